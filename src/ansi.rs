@@ -0,0 +1,216 @@
+//! Renderer representing litua text documents with ANSI escape sequences for terminals
+//!
+//! The renderer maps the standard call vocabulary of `markup` for a quick
+//! preview in a terminal: headings are bold (`h1` is also underlined), lists
+//! are indented with bullets or numbers, quotations are marked by a bar, and
+//! `pre` blocks are indented and highlighted (as litua syntax if `lang=litua`,
+//! with `syntax_highlight` if it supports the language, uniformly colored
+//! otherwise). Control characters of texts are removed, so a document cannot
+//! emit escape sequences of its own.
+
+use crate::highlight;
+use crate::markup::{arg, code_block, collapse_whitespace, plain_text};
+use crate::syntax_highlight;
+use crate::tree;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: (&str, &str) = ("\x1b[1m", "\x1b[22m");
+const DIM: (&str, &str) = ("\x1b[2m", "\x1b[22m");
+const ITALIC: (&str, &str) = ("\x1b[3m", "\x1b[23m");
+const UNDERLINE: (&str, &str) = ("\x1b[4m", "\x1b[24m");
+const CODE: (&str, &str) = ("\x1b[36m", "\x1b[39m");
+
+/// Returns the tree rendered for terminals
+pub fn to_ansi(tree: &tree::DocumentTree) -> String {
+    let blocks = match &tree.0 {
+        tree::DocumentElement::Function(root) => blocks(&root.content),
+        text @ tree::DocumentElement::Text(_) => blocks(std::slice::from_ref(text)),
+    };
+    let mut out = blocks.join("\n\n");
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+/// Remove control characters (except for newlines and tabs) from `text`
+fn sanitize(text: &str) -> String {
+    text.chars().filter(|chr| !chr.is_control() || *chr == '\n' || *chr == '\t').collect()
+}
+
+fn styled(content: &str, style: (&str, &str)) -> String {
+    format!("{}{content}{}", style.0, style.1)
+}
+
+fn is_block(call: &str) -> bool {
+    matches!(call, "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "p" | "ul" | "ol" | "li" | "blockquote" | "pre" | "hr")
+}
+
+fn is_inline(call: &str) -> bool {
+    matches!(call, "em" | "i" | "strong" | "b" | "code" | "a" | "img" | "br")
+}
+
+/// Does `func` (a transparent call) contain blocks?
+fn contains_blocks(func: &tree::DocumentFunction) -> bool {
+    func.content.iter().any(|element| match element {
+        tree::DocumentElement::Function(child) => is_block(&child.call) || (!is_inline(&child.call) && contains_blocks(child)),
+        tree::DocumentElement::Text(_) => false,
+    })
+}
+
+/// Represent the elements of `node` as sequence of blocks
+fn blocks(node: &[tree::DocumentElement]) -> Vec<String> {
+    let mut out = vec![];
+    let mut inline = vec![];
+    for element in node {
+        let func = match element {
+            tree::DocumentElement::Function(func) if is_block(&func.call) || (!is_inline(&func.call) && contains_blocks(func)) => func,
+            _ => {
+                inline.push(element.clone());
+                continue;
+            },
+        };
+        out.extend(paragraph(&inline));
+        inline.clear();
+
+        if is_block(&func.call) {
+            out.extend(block(func));
+        } else {
+            out.extend(blocks(&func.content));
+        }
+    }
+    out.extend(paragraph(&inline));
+    out
+}
+
+/// Render the block call `func`
+fn block(func: &tree::DocumentFunction) -> Option<String> {
+    match func.call.as_str() {
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let title = collapse_whitespace(&inline(&func.content).replace('\n', " ")).trim().to_owned();
+            if title.is_empty() {
+                return None;
+            }
+            Some(if func.call == "h1" {
+                format!("{}{}{title}{RESET}", BOLD.0, UNDERLINE.0)
+            } else {
+                format!("{}{title}{RESET}", BOLD.0)
+            })
+        },
+        "p" | "li" => paragraph(&func.content),
+        "ul" | "ol" => {
+            let start = arg(func, "start").and_then(|start| start.trim().parse::<usize>().ok()).unwrap_or(1);
+            let items: Vec<String> = func.content.iter()
+                .filter(|element| !matches!(element, tree::DocumentElement::Text(text) if text.trim().is_empty()))
+                .map(|element| match element {
+                    tree::DocumentElement::Function(item) if item.call == "li" => blocks(&item.content).join("\n\n"),
+                    _ => blocks(std::slice::from_ref(element)).join("\n\n"),
+                })
+                .collect();
+            if items.is_empty() {
+                return None;
+            }
+
+            let tight = items.iter().all(|item| !item.contains("\n\n"));
+            let rendered: Vec<String> = items.iter().enumerate().map(|(i, item)| {
+                let marker = if func.call == "ul" { "•".to_owned() } else { format!("{}.", start + i) };
+                let indent = " ".repeat(marker.chars().count() + 3);
+                format!("  {marker} {}", indent_lines(item, &indent).trim_start())
+            }).collect();
+            Some(rendered.join(if tight { "\n" } else { "\n\n" }))
+        },
+        "blockquote" => {
+            let body = blocks(&func.content).join("\n\n");
+            if body.is_empty() {
+                return None;
+            }
+            let bar = styled("│", DIM);
+            Some(body.lines().map(|line| format!("{bar} {line}")).collect::<Vec<_>>().join("\n"))
+        },
+        "pre" => {
+            let code = sanitize(&code_block(&func.content));
+            let highlighted = match arg(func, "lang").as_deref().map(str::trim) {
+                Some("litua") => highlight::to_ansi(&code).ok(),
+                Some(lang) if syntax_highlight::is_supported(lang) => syntax_highlight::highlight(&code, lang, syntax_highlight::Style::Ansi, None).ok(),
+                _ => None,
+            };
+            let highlighted = highlighted.unwrap_or_else(|| {
+                code.lines().map(|line| styled(line, CODE)).collect::<Vec<_>>().join("\n")
+            });
+            Some(indent_lines(&highlighted, "    "))
+        },
+        "hr" => Some(styled(&"─".repeat(40), DIM)),
+        _ => None,
+    }
+}
+
+/// Prefix every non-empty line of `text` with `indent`
+fn indent_lines(text: &str, indent: &str) -> String {
+    text.lines()
+        .map(|line| if line.is_empty() { String::new() } else { format!("{indent}{line}") })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Render the inline elements `node` as paragraph (if non-empty)
+fn paragraph(node: &[tree::DocumentElement]) -> Option<String> {
+    let content = inline(node);
+    let lines: Vec<&str> = content.split('\n').map(str::trim).filter(|line| !line.is_empty()).collect();
+    if lines.is_empty() {
+        return None;
+    }
+    Some(lines.join("\n"))
+}
+
+/// Render the inline elements `node` with whitespace collapsed
+/// and line breaks (`br`) as `\n`
+fn inline(node: &[tree::DocumentElement]) -> String {
+    let mut out = String::new();
+    for element in node {
+        let func = match element {
+            tree::DocumentElement::Text(text) => {
+                out.push_str(&collapse_whitespace(&sanitize(text)));
+                continue;
+            },
+            tree::DocumentElement::Function(func) => func,
+        };
+        match func.call.as_str() {
+            "em" | "i" => out.push_str(&styled(&inline(&func.content), ITALIC)),
+            "strong" | "b" => out.push_str(&styled(&inline(&func.content), BOLD)),
+            "code" => out.push_str(&styled(&collapse_whitespace(&sanitize(&plain_text(&func.content))), CODE)),
+            "a" => {
+                let label = inline(&func.content);
+                let href = sanitize(&arg(func, "href").unwrap_or_default());
+                match (label.trim().is_empty(), href.is_empty()) {
+                    (true, _) => out.push_str(&styled(&href, UNDERLINE)),
+                    (false, true) => out.push_str(&label),
+                    (false, false) => out.push_str(&format!("{} {}", styled(&label, UNDERLINE), styled(&format!("<{href}>"), DIM))),
+                }
+            },
+            "img" => {
+                let alt = collapse_whitespace(&sanitize(&arg(func, "alt").or_else(|| arg(func, "src")).unwrap_or_default()));
+                out.push_str(&styled(&format!("[image: {}]", alt.trim()), DIM));
+            },
+            "br" => out.push('\n'),
+            _ => out.push_str(&inline(&func.content)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_for_terminal() {
+        let src = "{h1 Title}\n{p Some {b bold} and {code x}\u{1b}[31m.}\n{ul {li one} {li two}}\n\
+            {blockquote quoted}\n{pre[lang=pseudo]\n  fn main() {< { >}\n}";
+        let tree = crate::parse_str(src).expect("valid document");
+        assert_eq!(to_ansi(&tree), "\x1b[1m\x1b[4mTitle\x1b[0m\n\n\
+            Some \x1b[1mbold\x1b[22m and \x1b[36mx\x1b[39m[31m.\n\n\
+            \x20 • one\n  • two\n\n\
+            \x1b[2m│\x1b[22m quoted\n\n\
+            \x20   \x1b[36mfn main() {\x1b[39m\n");
+    }
+}
@@ -0,0 +1,141 @@
+//! Catalog of the calls handled by hooks
+//!
+//! The catalog lists every call name a hook is registered for together
+//! with the argument keys the hooks read (found by looking for `args.key`
+//! and `args["key"]` in their source code) and the comment preceding the
+//! registration as documentation. Editor integrations use it to complete
+//! call names after `{` and argument keys after `[` and to show hover texts.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::ops;
+
+/// `HookSite` is the registration of one hook
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct HookSite {
+    /// name of the hook like `convert_node_to_string`
+    pub hook: String,
+    /// name of the hook file
+    pub file: String,
+    /// line of the registration (1-based)
+    pub line: usize,
+}
+
+/// `CallInfo` describes one call name handled by hooks
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct CallInfo {
+    /// hooks registered for the call
+    pub hooks: Vec<HookSite>,
+    /// argument keys read by the hooks
+    pub args: BTreeSet<String>,
+    /// documentation comments of the registrations (separated by empty lines)
+    pub doc: String,
+}
+
+/// `Catalog` maps call names to their description
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Catalog {
+    pub calls: BTreeMap<String, CallInfo>,
+}
+
+impl Catalog {
+    /// Create an empty catalog
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add hook `site` for call `call`. `src` is the source code of the hook file (if available)
+    /// and `lines` the range of lines (1-based) of the hook function.
+    pub fn add(&mut self, call: &str, site: HookSite, src: Option<&str>, lines: ops::RangeInclusive<usize>) {
+        let info = self.calls.entry(call.to_owned()).or_default();
+        if let Some(src) = src {
+            info.args.extend(arg_keys(src, lines));
+            if let Some(doc) = docstring(src, site.line).filter(|doc| !info.doc.contains(doc.as_str())) {
+                if !info.doc.is_empty() {
+                    info.doc.push_str("\n\n");
+                }
+                info.doc.push_str(&doc);
+            }
+        }
+        info.hooks.push(site);
+    }
+
+    /// Represent the catalog as JSON object with key `calls` (an array of objects
+    /// with keys `call`, `doc`, `args`, and `hooks`)
+    pub fn to_json(&self) -> serde_json::Value {
+        let calls: Vec<serde_json::Value> = self.calls.iter().map(|(call, info)| serde_json::json!({
+            "call": call,
+            "doc": info.doc,
+            "args": info.args,
+            "hooks": info.hooks.iter().map(|site| serde_json::json!({ "hook": site.hook, "file": site.file, "line": site.line })).collect::<Vec<_>>(),
+        })).collect();
+        serde_json::json!({ "calls": calls })
+    }
+}
+
+/// Returns the Lua comment lines directly preceding line `line` (1-based) of `src`
+/// without their comment markers
+pub fn docstring(src: &str, line: usize) -> Option<String> {
+    let lines: Vec<&str> = src.lines().take(line.saturating_sub(1)).collect();
+    let comments: Vec<&str> = lines.iter().rev()
+        .map(|line| line.trim())
+        .take_while(|line| line.starts_with("--") && !line.starts_with("--[["))
+        .map(|line| line.trim_start_matches('-').trim())
+        .collect();
+    let doc = comments.into_iter().rev().collect::<Vec<_>>().join("\n");
+    match doc.trim() {
+        "" => None,
+        doc => Some(doc.to_owned()),
+    }
+}
+
+/// Returns the keys `key` of expressions `args.key`, `args["key"]`, or `args['key']`
+/// in the lines `lines` (1-based) of `src`
+pub fn arg_keys(src: &str, lines: ops::RangeInclusive<usize>) -> BTreeSet<String> {
+    let mut keys = BTreeSet::new();
+    let is_identifier = |chr: char| chr.is_ascii_alphanumeric() || chr == '_';
+    for line in src.lines().skip(lines.start().saturating_sub(1)).take(lines.end().saturating_sub(*lines.start()) + 1) {
+        for (offset, _) in line.match_indices("args") {
+            if line[..offset].ends_with(is_identifier) {
+                continue;
+            }
+            let rest = &line[offset + 4..];
+            let key = if let Some(rest) = rest.strip_prefix('.') {
+                rest.split(|chr: char| !is_identifier(chr)).next()
+            } else if let Some(rest) = rest.strip_prefix("[\"").or_else(|| rest.strip_prefix("['")) {
+                rest.split(['"', '\'']).next()
+            } else {
+                None
+            };
+            if let Some(key) = key.filter(|key| !key.is_empty()) {
+                keys.insert(key.to_owned());
+            }
+        }
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalog_of_hook_file() {
+        let src = "local x = 1\n--- Highlighted code block\n-- with language `lang`\n\
+            Litua.convert_node_to_string(\"code\", function (node)\n  return node.args.lang[1] .. node.args[\"data-x\"][1] .. myargs.no\nend)\n\
+            Litua.modify_node(\"code\", function (node) return node end)\n";
+        let mut catalog = Catalog::new();
+        catalog.add("code", HookSite { hook: "convert_node_to_string".to_owned(), file: "hooks.a.lua".to_owned(), line: 4 }, Some(src), 4..=6);
+        catalog.add("code", HookSite { hook: "modify_node".to_owned(), file: "hooks.a.lua".to_owned(), line: 7 }, Some(src), 7..=7);
+
+        assert_eq!(catalog.to_json(), serde_json::json!({ "calls": [{
+            "call": "code",
+            "doc": "Highlighted code block\nwith language `lang`",
+            "args": ["data-x", "lang"],
+            "hooks": [
+                { "hook": "convert_node_to_string", "file": "hooks.a.lua", "line": 4 },
+                { "hook": "modify_node", "file": "hooks.a.lua", "line": 7 },
+            ],
+        }]}));
+    }
+}
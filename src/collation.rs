@@ -0,0 +1,132 @@
+//! Locale-aware ordering of texts like the terms of an index
+//!
+//! Texts are compared in three levels like in the Unicode collation algorithm:
+//! base letters first (ignoring diacritics, case, and punctuation, with
+//! whitespace sorting before any letter), then diacritics, and then case
+//! (lowercase first). Remaining ties are broken by the Unicode scalars. The
+//! language of the locale (like `sv` of `sv_FI`) tailors the alphabet: Swedish
+//! and Finnish sort `å`, `ä`, `ö` and Danish and Norwegian sort `æ`, `ø`, `å`
+//! as separate letters after `z` and Spanish sorts `ñ` after `n`. German and
+//! all other languages treat letters with diacritics like their base letters.
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Group of texts not starting with a letter
+pub const OTHER_GROUP: &str = "#";
+
+/// Returns the letters sorted as separate letters in the language of `locale`
+/// together with the letter they follow
+fn tailoring(locale: &str) -> &'static [(char, char)] {
+    let language = locale.split(['_', '-', '.', '@']).next().unwrap_or_default().to_ascii_lowercase();
+    match language.as_str() {
+        "sv" | "fi" => &[('å', 'z'), ('ä', 'z'), ('ö', 'z')],
+        "da" | "nb" | "nn" | "no" => &[('æ', 'z'), ('ø', 'z'), ('å', 'z')],
+        "es" => &[('ñ', 'n')],
+        _ => &[],
+    }
+}
+
+/// Returns the base letters of the lowercase letter `chr` and its diacritic (or 0)
+fn decompose(chr: char) -> (Vec<char>, u32) {
+    match chr {
+        'ß' => (vec!['s', 's'], 0),
+        'æ' => (vec!['a', 'e'], 0),
+        'œ' => (vec!['o', 'e'], 0),
+        'ø' => (vec!['o'], chr as u32),
+        _ => {
+            let mut chars = chr.to_string().nfd().collect::<Vec<char>>().into_iter();
+            let base = chars.next().unwrap_or(chr);
+            (vec![base], chars.next().map(|mark| mark as u32).unwrap_or(0))
+        },
+    }
+}
+
+/// `SortKey` determines the position of a text in sorted lists
+#[derive(Clone,Debug,Default,PartialEq,Eq,PartialOrd,Ord)]
+pub struct SortKey {
+    primary: Vec<u32>,
+    secondary: Vec<u32>,
+    tertiary: Vec<bool>,
+    text: String,
+}
+
+/// Returns the sort key of `text` in locale `locale` (like `de_AT`)
+pub fn sort_key(text: &str, locale: &str) -> SortKey {
+    let tailored = tailoring(locale);
+    let mut key = SortKey { text: text.to_owned(), ..SortKey::default() };
+    let mut whitespace = false;
+    for chr in text.trim().nfc() {
+        if chr.is_whitespace() {
+            whitespace = true;
+            continue;
+        }
+        if !chr.is_alphanumeric() {
+            continue;
+        }
+        if whitespace {
+            key.primary.push(1);
+            whitespace = false;
+        }
+
+        let lower = chr.to_lowercase().next().unwrap_or(chr);
+        key.tertiary.push(chr != lower);
+        match tailored.iter().position(|(letter, _)| *letter == lower) {
+            Some(index) => {
+                key.primary.push(tailored[index].1 as u32 * 8 + index as u32 + 1);
+                key.secondary.push(0);
+            },
+            None => {
+                let (bases, diacritic) = decompose(lower);
+                key.primary.extend(bases.iter().map(|base| *base as u32 * 8));
+                key.secondary.push(diacritic);
+            },
+        }
+    }
+    key
+}
+
+/// Compare `a` and `b` in locale `locale`
+pub fn compare(a: &str, b: &str, locale: &str) -> std::cmp::Ordering {
+    sort_key(a, locale).cmp(&sort_key(b, locale))
+}
+
+/// Sort `texts` in locale `locale`
+pub fn sort(texts: &mut [String], locale: &str) {
+    texts.sort_by_cached_key(|text| sort_key(text, locale));
+}
+
+/// Returns the heading of the group `text` belongs to in sorted lists like indexes,
+/// i.e. its first letter in uppercase without diacritics (unless it is a separate
+/// letter in the language of `locale`) or `OTHER_GROUP`
+pub fn group(text: &str, locale: &str) -> String {
+    let first = match text.nfc().find(|chr| chr.is_alphanumeric()) {
+        Some(chr) if chr.is_alphabetic() => chr.to_lowercase().next().unwrap_or(chr),
+        _ => return OTHER_GROUP.to_owned(),
+    };
+    if tailoring(locale).iter().any(|(letter, _)| *letter == first) {
+        return first.to_uppercase().collect();
+    }
+    decompose(first).0[0].to_uppercase().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_and_group_by_locale() {
+        let terms = ["zebra", "Öl", "Apfel", "Äpfel", "apfel", "Straße", "Strasse", "Ober", "New York", "Newark", "42"];
+        let mut sorted: Vec<String> = terms.iter().map(|term| term.to_string()).collect();
+        sort(&mut sorted, "de_AT");
+        assert_eq!(sorted, ["42", "apfel", "Apfel", "Äpfel", "New York", "Newark", "Ober", "Öl", "Straße", "Strasse", "zebra"]);
+        sort(&mut sorted, "sv_SE");
+        assert_eq!(sorted, ["42", "apfel", "Apfel", "New York", "Newark", "Ober", "Straße", "Strasse", "zebra", "Äpfel", "Öl"]);
+        assert_eq!(compare("mañana", "manzana", "es"), std::cmp::Ordering::Greater);
+        assert_eq!(compare("mañana", "manzana", "en"), std::cmp::Ordering::Less);
+
+        assert_eq!(group("Äpfel", "de"), "A");
+        assert_eq!(group("äpfel", "sv"), "Ä");
+        assert_eq!(group("ßx", "C"), "S");
+        assert_eq!(group("(42)", "C"), OTHER_GROUP);
+    }
+}
@@ -0,0 +1,103 @@
+//! Conditional content selected by the arguments of the root call
+//!
+//! `{if[flag=draft] …}` is replaced by its content if the root call has
+//! argument `draft` (e.g. given by `-D draft`) with a value other than an
+//! empty text, `false`, `no`, or `0`. Otherwise it is removed. `{ifnot[flag=draft] …}`
+//! is the opposite. With an additional argument `value=html`, the argument
+//! must be `html` instead. Thereby variants of a document are built from one
+//! source before any transformation hook runs.
+
+use std::collections::HashMap;
+
+use crate::errors;
+use crate::messages::Message;
+use crate::tree;
+
+/// Name of the call keeping its content if its condition holds
+pub const IF_CALL: &str = "if";
+/// Name of the call keeping its content if its condition does not hold
+pub const IFNOT_CALL: &str = "ifnot";
+
+/// Returns the text of argument `key` of `func` if it does not contain calls
+fn text_arg(func: &tree::DocumentFunction, key: &str) -> Option<String> {
+    func.args.get(key)?.iter().map(|element| match element {
+        tree::DocumentElement::Text(text) => Some(text.as_str()),
+        tree::DocumentElement::Function(_) => None,
+    }).collect()
+}
+
+/// Does the define `flag` (with value `value`, if given) hold?
+pub fn holds(defines: &HashMap<String, String>, flag: &str, value: Option<&str>) -> bool {
+    match (defines.get(flag), value) {
+        (Some(defined), Some(value)) => defined == value,
+        (Some(defined), None) => !matches!(defined.trim(), "" | "false" | "no" | "0"),
+        (None, _) => false,
+    }
+}
+
+/// Replace the `if` and `ifnot` calls of `tree` by their content if their condition holds
+/// for the text arguments of the root call or remove them otherwise
+pub fn evaluate(tree: &mut tree::DocumentTree) -> Result<(), errors::Error> {
+    let root = match &mut tree.0 {
+        tree::DocumentElement::Function(root) => root,
+        tree::DocumentElement::Text(_) => return Ok(()),
+    };
+    let defines: HashMap<String, String> = root.args.keys()
+        .filter(|key| !tree::is_reserved_arg(key))
+        .filter_map(|key| Some((key.to_owned(), text_arg(root, key)?)))
+        .collect();
+    evaluate_node(&mut root.content, &defines)
+}
+
+fn evaluate_node(node: &mut tree::DocumentNode, defines: &HashMap<String, String>) -> Result<(), errors::Error> {
+    let mut i = 0;
+    while i < node.len() {
+        let func = match &mut node[i] {
+            tree::DocumentElement::Function(func) => func,
+            tree::DocumentElement::Text(_) => {
+                i += 1;
+                continue;
+            },
+        };
+        for (_, value) in func.args.iter_mut().filter(|(key, _)| !tree::is_reserved_arg(key)) {
+            evaluate_node(value, defines)?;
+        }
+        evaluate_node(&mut func.content, defines)?;
+        if func.call != IF_CALL && func.call != IFNOT_CALL {
+            i += 1;
+            continue;
+        }
+
+        let flag = text_arg(func, "flag").filter(|flag| !flag.trim().is_empty()).ok_or_else(|| {
+            errors::Error::ConditionError(Message::new("C0001", vec![func.call.to_owned()]), func.span().unwrap_or(0..0))
+        })?;
+        let condition = holds(defines, flag.trim(), text_arg(func, "value").as_deref());
+        let content = match condition == (func.call == IF_CALL) {
+            true => std::mem::take(&mut func.content),
+            false => vec![],
+        };
+        let count = content.len();
+        node.splice(i..i + 1, content);
+        i += count;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_variant() {
+        let src = "{if[flag=draft] D}{ifnot[flag=draft] F}{if[flag=format][value=html] {ifnot[flag=print] H}}{if[flag=off] O}";
+        let parser = crate::parser::ParserBuilder::new()
+            .root_arg("draft", "true").root_arg("format", "html").root_arg("off", "false");
+        let mut tree = crate::pipeline::Pipeline::new().parser(parser)
+            .parse(std::path::Path::new(""), src).expect("valid document");
+        evaluate(&mut tree).expect("valid conditions");
+        assert_eq!(tree.to_litua_syntax(), "DH");
+
+        let mut tree = crate::parse_str("{if x}").expect("valid document");
+        assert!(matches!(evaluate(&mut tree), Err(errors::Error::ConditionError(msg, _)) if msg.code == "C0001"));
+    }
+}
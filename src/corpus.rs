@@ -0,0 +1,213 @@
+//! Conformance corpus of the lexer and parser (see `litua selftest`)
+//!
+//! A corpus is a directory of `*.lit` inputs (searched recursively). Next to
+//! every input, snapshot files record the expected result: `case.tokens` (one
+//! token per line like `Call(1..2)`), `case.tokens.json` (an array of objects
+//! like `pipeline::Event::to_json` without key `event`), `case.tree` (an
+//! outline of the parsed tree with one element per line, including reserved
+//! arguments like `=whitespace`), and `case.tree.json` (see
+//! `tree::DocumentTree::to_json`). If lexing or parsing fails, the snapshot
+//! ends with the message code of the error (`error P0107` or an object like
+//! `{"error": "P0107"}`). Inputs are lexed and parsed with the default options
+//! and an empty filepath, so snapshots do not depend on the location of the
+//! corpus and edge cases can be contributed as plain files.
+
+use std::fs;
+use std::io;
+use std::path;
+
+use crate::lexer;
+use crate::pipeline;
+use crate::tree;
+
+/// `Kind` is the stage of the result recorded by a snapshot
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Kind {
+    /// tokens emitted by the lexer
+    Tokens,
+    /// tree built by the parser
+    Tree,
+}
+
+/// `Format` is the representation of a snapshot
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Format {
+    /// human-readable text with one token (or element) per line
+    Text,
+    /// pretty-printed JSON
+    Json,
+}
+
+/// File extensions of snapshot files with the kind and format of their snapshot
+pub const SNAPSHOTS: [(&str, Kind, Format); 4] = [
+    ("tokens", Kind::Tokens, Format::Text),
+    ("tokens.json", Kind::Tokens, Format::Json),
+    ("tree", Kind::Tree, Format::Text),
+    ("tree.json", Kind::Tree, Format::Json),
+];
+
+/// `Check` is the comparison of one snapshot file with the actual result
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Check {
+    /// filepath of the snapshot file
+    pub snapshot: path::PathBuf,
+    /// content of the snapshot file
+    pub expected: String,
+    /// snapshot of the actual result
+    pub actual: String,
+}
+
+impl Check {
+    /// Does the actual result match the snapshot?
+    pub fn passed(&self) -> bool {
+        self.expected == self.actual
+    }
+}
+
+/// Returns the `*.lit` inputs in directory `dir` and its subdirectories in sorted order
+pub fn find_inputs(dir: &path::Path) -> Result<Vec<path::PathBuf>, io::Error> {
+    let mut inputs = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let filepath = entry.path();
+        if entry.file_type()?.is_dir() {
+            inputs.append(&mut find_inputs(&filepath)?);
+        } else if filepath.extension().is_some_and(|ext| ext == "lit") {
+            inputs.push(filepath);
+        }
+    }
+    inputs.sort();
+    Ok(inputs)
+}
+
+/// Returns the filepath of the snapshot file with extension `ext` of the input `input`
+pub fn snapshot_path(input: &path::Path, ext: &str) -> path::PathBuf {
+    input.with_extension(ext)
+}
+
+/// Append the outline of `elem` indented by `depth` levels to `out`
+fn outline(elem: &tree::DocumentElement, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    match elem {
+        tree::DocumentElement::Text(text) => out.push_str(&format!("{indent}text {text:?}\n")),
+        tree::DocumentElement::Function(func) => {
+            out.push_str(&format!("{indent}call {:?}\n", func.call));
+            let mut keys: Vec<&String> = func.args.keys().collect();
+            keys.sort();
+            for key in keys {
+                out.push_str(&format!("{indent}  arg {key:?}\n"));
+                for value in func.args[key].iter() {
+                    outline(value, depth + 2, out);
+                }
+            }
+            for child in func.content.iter() {
+                outline(child, depth + 1, out);
+            }
+        },
+    }
+}
+
+/// Returns the snapshot of kind `kind` in format `format` of the input `src`
+pub fn render(src: &str, kind: Kind, format: Format) -> String {
+    let mut lines = vec![];
+    let mut values = vec![];
+    let error = match kind {
+        Kind::Tokens => {
+            let mut error = None;
+            for token in lexer::LexingIterator::with_options(src, lexer::LexerOptions::default()) {
+                match token {
+                    Ok(token) => {
+                        lines.push(format!("{token:?}"));
+                        let mut json = pipeline::Event::Token(&token).to_json(src);
+                        if let Some(object) = json.as_object_mut() {
+                            object.remove("event");
+                        }
+                        values.push(json);
+                    },
+                    Err(e) => {
+                        error = Some(e);
+                        break;
+                    },
+                }
+            }
+            error
+        },
+        Kind::Tree => match pipeline::Pipeline::new().parse(path::Path::new(""), src) {
+            Ok(doc_tree) => {
+                let mut out = String::new();
+                outline(&doc_tree.0, 0, &mut out);
+                lines.push(out.trim_end().to_owned());
+                values.push(doc_tree.to_json());
+                None
+            },
+            Err(e) => Some(e),
+        },
+    };
+    if let Some(e) = error {
+        lines.push(format!("error {}", e.message().code));
+        values.push(serde_json::json!({ "error": e.message().code }));
+    }
+
+    match (format, kind) {
+        (Format::Text, _) => lines.iter().map(|line| format!("{line}\n")).collect(),
+        (Format::Json, Kind::Tree) if values.len() == 1 => format!("{:#}\n", values[0]),
+        (Format::Json, _) => format!("{:#}\n", serde_json::Value::Array(values)),
+    }
+}
+
+/// Compare the existing snapshot files of the input `input` with its actual
+/// snapshots (no checks are returned if the input has no snapshot file)
+pub fn check(input: &path::Path) -> Result<Vec<Check>, io::Error> {
+    let src = fs::read_to_string(input)?;
+    let mut checks = vec![];
+    for (ext, kind, format) in SNAPSHOTS {
+        let snapshot = snapshot_path(input, ext);
+        let expected = match fs::read_to_string(&snapshot) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        checks.push(Check { actual: render(&src, kind, format), snapshot, expected });
+    }
+    Ok(checks)
+}
+
+/// Write the actual snapshots of the input `input` to its existing snapshot
+/// files (or to new `*.tokens` and `*.tree` files if it has none) and return
+/// the filepaths written
+pub fn bless(input: &path::Path) -> Result<Vec<path::PathBuf>, io::Error> {
+    let src = fs::read_to_string(input)?;
+    let existing: Vec<_> = SNAPSHOTS.iter().filter(|(ext, _, _)| snapshot_path(input, ext).exists()).collect();
+    let selected = match existing.is_empty() {
+        true => SNAPSHOTS.iter().filter(|(_, _, format)| *format == Format::Text).collect(),
+        false => existing,
+    };
+    let mut written = vec![];
+    for (ext, kind, format) in selected {
+        let snapshot = snapshot_path(input, ext);
+        fs::write(&snapshot, render(&src, *kind, *format))?;
+        written.push(snapshot);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_snapshots() {
+        assert_eq!(render("{p a}", Kind::Tokens, Format::Text), "BeginFunction(0)\nCall(1..2)\nWhitespace(2..3)\nBeginContent(3)\nText(3..4)\nEndContent(4)\nEndFunction(4)\nEndOfFile(5)\n");
+        let tokens: serde_json::Value = serde_json::from_str(&render("{p a}", Kind::Tokens, Format::Json)).expect("valid JSON");
+        assert_eq!(tokens[1], serde_json::json!({ "token": "Call", "start": 1, "end": 2, "call": "p" }));
+
+        let outline = render("x{p[k=v] a}", Kind::Tree, Format::Text);
+        assert!(outline.starts_with("call \"document\"\n  arg \"filepath\"\n    text \"\"\n  text \"x\"\n  call \"p\"\n"));
+        assert!(outline.contains("    arg \"k\"\n      text \"v\"\n"));
+        let doc_tree: serde_json::Value = serde_json::from_str(&render("x{p a}", Kind::Tree, Format::Json)).expect("valid JSON");
+        assert_eq!(doc_tree["content"][1]["call"], "p");
+
+        assert!(render("{p a", Kind::Tree, Format::Text).starts_with("error P"));
+        assert!(render("{p a", Kind::Tree, Format::Json).contains("\"error\""));
+    }
+}
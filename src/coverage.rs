@@ -0,0 +1,80 @@
+//! Report of calls not handled by any hook across several text documents
+//!
+//! With `--report-unhandled-calls`, every call reaching the conversion to
+//! strings without a `convert_node_to_string` hook registered for its call name
+//! (or its namespace like `html` of `html:div`) is counted (hooks for every call like `Litua.convert_node_to_string("", …)`
+//! are fallbacks and do not count). Raw strings and the root call are ignored.
+//! The report lists the call names by descending frequency, which helps to
+//! migrate documents to a new set of hooks.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// `UnhandledCalls` counts the unhandled calls per call name
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct UnhandledCalls {
+    /// number of occurrences and documents containing them per call name
+    calls: BTreeMap<String, (usize, BTreeSet<String>)>,
+}
+
+impl UnhandledCalls {
+    /// Create an empty report
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record one unhandled call `call` in the text document `document`
+    pub fn record(&mut self, call: &str, document: &str) {
+        let (count, documents) = self.calls.entry(call.to_owned()).or_default();
+        *count += 1;
+        documents.insert(document.to_owned());
+    }
+
+    /// Is no unhandled call recorded?
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Returns the call names with their number of occurrences and documents,
+    /// sorted by descending number of occurrences (ties by call name)
+    pub fn sorted(&self) -> Vec<(&str, usize, usize)> {
+        let mut sorted: Vec<(&str, usize, usize)> = self.calls.iter()
+            .map(|(call, (count, documents))| (call.as_str(), *count, documents.len()))
+            .collect();
+        sorted.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        sorted
+    }
+
+    /// Represent the report as human-readable table
+    pub fn to_table(&self) -> String {
+        let mut out = String::new();
+        if self.is_empty() {
+            let _ = writeln!(out, "every call is handled by a convert_node_to_string hook");
+            return out;
+        }
+        let sorted = self.sorted();
+        let width = sorted.iter().map(|(call, _, _)| call.chars().count()).max().unwrap_or(0).max(4);
+        let _ = writeln!(out, "{:<width$}  {:>11}  {:>9}", "call", "occurrences", "documents");
+        for (call, count, documents) in sorted {
+            let _ = writeln!(out, "{call:<width$}  {count:>11}  {documents:>9}");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_by_frequency() {
+        let mut report = UnhandledCalls::new();
+        assert!(report.to_table().starts_with("every call"));
+        for (call, document) in [("aside", "a.lit"), ("kbd", "a.lit"), ("kbd", "b.lit"), ("abbr", "b.lit"), ("kbd", "b.lit")] {
+            report.record(call, document);
+        }
+        assert_eq!(report.sorted(), vec![("kbd", 3, 2), ("abbr", 1, 1), ("aside", 1, 1)]);
+        assert_eq!(report.to_table().lines().nth(1), Some("kbd              3          2"));
+    }
+}
@@ -0,0 +1,85 @@
+//! Timestamps and locale settings provided to hooks
+//!
+//! Hooks should use the build timestamp instead of the current time, because
+//! it can be fixed with the environment variable ``SOURCE_DATE_EPOCH``
+//! to make the output reproducible (see <https://reproducible-builds.org/specs/source-date-epoch/>).
+
+use std::time;
+
+/// Environment variable fixing the build timestamp (seconds since 1970-01-01T00:00:00Z)
+pub const SOURCE_DATE_EPOCH_ENV_VAR: &str = "SOURCE_DATE_EPOCH";
+
+/// Environment variables determining the locale in order of precedence
+pub const LOCALE_ENV_VARS: [&str; 3] = ["LC_ALL", "LC_TIME", "LANG"];
+
+/// Returns the build timestamp in seconds since the UNIX epoch, which is
+/// `source_date_epoch` (the value of ``SOURCE_DATE_EPOCH``) if given or `now` otherwise
+pub fn build_timestamp(source_date_epoch: Option<&str>, now: time::SystemTime) -> Result<i64, String> {
+    match source_date_epoch {
+        Some(value) => value.trim().parse::<i64>()
+            .map_err(|_| format!("environment variable {SOURCE_DATE_EPOCH_ENV_VAR} must be an integer, got '{value}'")),
+        None => Ok(unix_timestamp(now)),
+    }
+}
+
+/// Returns `t` in seconds since the UNIX epoch
+pub fn unix_timestamp(t: time::SystemTime) -> i64 {
+    match t.duration_since(time::UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs_f64().ceil() as i64),
+    }
+}
+
+/// Represent `timestamp` (seconds since the UNIX epoch) as UTC timestamp like `2023-04-01T12:34:56Z`
+pub fn iso8601(timestamp: i64) -> String {
+    let (days, secs_of_day) = (timestamp.div_euclid(86400), timestamp.rem_euclid(86400));
+
+    // convert days since 1970-01-01 to a civil date (proleptic Gregorian calendar)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z", secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60)
+}
+
+/// Returns the locale (like `de_AT`) given by the first non-empty environment
+/// variable of `LOCALE_ENV_VARS` with encoding and modifier removed.
+/// `var` returns the value of an environment variable. Defaults to `C`.
+pub fn locale<F: Fn(&str) -> Option<String>>(var: F) -> String {
+    LOCALE_ENV_VARS.iter()
+        .filter_map(|name| var(name))
+        .find(|value| !value.is_empty())
+        .map(|value| value.split(['.', '@']).next().unwrap_or_default().to_owned())
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| "C".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_and_locale() {
+        let now = time::UNIX_EPOCH + time::Duration::from_millis(1680352496789);
+        assert_eq!(build_timestamp(None, now), Ok(1680352496));
+        assert_eq!(build_timestamp(Some("1700000000\n"), now), Ok(1700000000));
+        assert!(build_timestamp(Some("yesterday"), now).is_err());
+        assert_eq!(unix_timestamp(time::UNIX_EPOCH - time::Duration::from_millis(1500)), -2);
+        assert_eq!(iso8601(1680352496), "2023-04-01T12:34:56Z");
+        assert_eq!(iso8601(-1), "1969-12-31T23:59:59Z");
+
+        let env = |name: &str| match name {
+            "LC_ALL" => Some(String::new()),
+            "LANG" => Some("de_AT.UTF-8@euro".to_owned()),
+            _ => None,
+        };
+        assert_eq!(locale(env), "de_AT");
+        assert_eq!(locale(|_| None), "C");
+    }
+}
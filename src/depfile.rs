@@ -0,0 +1,74 @@
+//! Dependencies of an output in Makefile syntax (see `--depfile`)
+//!
+//! A depfile consists of one rule `target: dependency …` listing every file
+//! the output was built from: the text document, included documents, hook
+//! files, Lua modules loaded by `require`, and files read by hooks with
+//! `Litua.read_file` or `Litua.read_include`. Make includes it with
+//! `-include doc.d`, Ninja reads it with `depfile = doc.d` and `deps = gcc`.
+
+use std::fmt::Write;
+
+/// `Depfile` collects the dependencies of one output in order of first occurrence
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Depfile {
+    dependencies: Vec<String>,
+}
+
+/// Escape `filepath` for Makefile rules (Ninja understands the same escapes)
+fn escape(filepath: &str) -> String {
+    let mut escaped = String::with_capacity(filepath.len());
+    for c in filepath.chars() {
+        match c {
+            ' ' | '#' => { escaped.push('\\'); escaped.push(c); },
+            '$' => escaped.push_str("$$"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Depfile {
+    /// Create a depfile without dependencies
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the dependency `filepath` (unless it is recorded already)
+    pub fn add(&mut self, filepath: &str) {
+        if !self.dependencies.iter().any(|dependency| dependency == filepath) {
+            self.dependencies.push(filepath.to_owned());
+        }
+    }
+
+    /// Returns the dependencies in order of first occurrence
+    pub fn dependencies(&self) -> &[String] {
+        &self.dependencies
+    }
+
+    /// Represent the depfile as Makefile rule for the output `target` with one dependency per line
+    pub fn to_makefile(&self, target: &str) -> String {
+        let mut out = format!("{}:", escape(target));
+        for dependency in self.dependencies.iter() {
+            let _ = write!(out, " \\\n  {}", escape(dependency));
+        }
+        out.push('\n');
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn makefile_rule_with_escapes() {
+        let mut depfile = Depfile::new();
+        depfile.add("my doc.lit");
+        depfile.add("hooks.lua");
+        depfile.add("my doc.lit");
+        depfile.add("assets/$price#1.txt");
+        assert_eq!(depfile.dependencies().len(), 3);
+        assert_eq!(depfile.to_makefile("out/my doc.html"), "out/my\\ doc.html: \\\n  my\\ doc.lit \\\n  hooks.lua \\\n  assets/$$price\\#1.txt\n");
+        assert_eq!(Depfile::new().to_makefile("a.out"), "a.out:\n");
+    }
+}
@@ -0,0 +1,286 @@
+//! Writer representing litua text documents as DocBook 5 XML
+//!
+//! A `Mapping` determines the DocBook element of every call. By default, it
+//! maps the standard call vocabulary of `markup` (`p`, `em`, `ul`, …). Some
+//! calls are handled by the writer itself: `h1` to `h6` open nested sections
+//! (or become `bridgehead` elements outside of the top level), `a[href=…]`
+//! becomes a `link`, `img[src=…][alt=…]` an `inlinemediaobject`, `pre[lang=…]`
+//! a `programlisting`, `hr` is omitted, and `br` becomes a space. Calls without
+//! element are transparent, i.e. only their content is written.
+
+use std::collections::HashMap;
+
+use crate::markup::{arg, code_block, collapse_whitespace};
+use crate::tree;
+
+/// XML namespace of DocBook 5
+pub const NAMESPACE: &str = "http://docbook.org/ns/docbook";
+
+/// XML namespace of the `xlink:href` attribute of links
+pub const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+
+/// DocBook elements containing blocks (texts in their content are wrapped in `para` elements)
+const CONTAINER_ELEMENTS: [&str; 13] = [
+    "blockquote", "caution", "example", "figure", "important", "itemizedlist", "listitem",
+    "note", "orderedlist", "section", "sidebar", "tip", "warning",
+];
+
+/// DocBook elements which are blocks, but contain inline content
+const BLOCK_ELEMENTS: [&str; 6] = ["literallayout", "para", "programlisting", "screen", "simpara", "title"];
+
+/// `Mapping` assigns DocBook elements to call names. An element is given
+/// by its name optionally followed by attributes, e.g. `emphasis role="strong"`.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Mapping {
+    elements: HashMap<String, String>,
+}
+
+impl Default for Mapping {
+    fn default() -> Self {
+        let mut elements = HashMap::new();
+        for (call, element) in [
+            ("p", "para"),
+            ("em", "emphasis"),
+            ("i", "emphasis"),
+            ("strong", "emphasis role=\"strong\""),
+            ("b", "emphasis role=\"strong\""),
+            ("code", "literal"),
+            ("ul", "itemizedlist"),
+            ("ol", "orderedlist"),
+            ("li", "listitem"),
+            ("blockquote", "blockquote"),
+        ] {
+            elements.insert(call.to_owned(), element.to_owned());
+        }
+        Self { elements }
+    }
+}
+
+impl Mapping {
+    /// Create the default mapping of the standard call vocabulary
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Map call `call` to `element` (an empty `element` makes the call transparent)
+    pub fn set(&mut self, call: &str, element: &str) {
+        self.elements.insert(call.to_owned(), element.trim().to_owned());
+    }
+
+    /// Extend the default mapping by the JSON object `value` mapping call names to elements
+    pub fn from_json(value: &serde_json::Value) -> Result<Self, String> {
+        let entries = value.as_object().ok_or_else(|| "the mapping must be a JSON object".to_owned())?;
+        let mut mapping = Self::new();
+        for (call, element) in entries.iter() {
+            match element.as_str() {
+                Some(element) => mapping.set(call, element),
+                None => return Err(format!("the element of call '{call}' must be a string")),
+            }
+        }
+        Ok(mapping)
+    }
+
+    /// Returns the element (name and attributes) of call `call` if it is not transparent
+    pub fn element(&self, call: &str) -> Option<&str> {
+        self.elements.get(call).map(String::as_str).filter(|element| !element.is_empty())
+    }
+}
+
+/// Returns the tree as DocBook 5 `article`
+pub fn to_docbook(tree: &tree::DocumentTree, mapping: &Mapping) -> String {
+    let mut out = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<article xmlns=\"{NAMESPACE}\" xmlns:xlink=\"{XLINK_NAMESPACE}\" version=\"5.0\">\n");
+    let writer = Writer { mapping };
+    match &tree.0 {
+        tree::DocumentElement::Function(root) => writer.blocks(&root.content, 1, true, &mut out),
+        text @ tree::DocumentElement::Text(_) => writer.blocks(std::slice::from_ref(text), 1, true, &mut out),
+    }
+    out.push_str("</article>\n");
+    out
+}
+
+/// Escape `text` for use in XML text content and attribute values
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Returns the element name of `element` without its attributes
+fn element_name(element: &str) -> &str {
+    element.split_whitespace().next().unwrap_or_default()
+}
+
+fn heading_level(call: &str) -> Option<usize> {
+    match call {
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => Some(usize::from(call.as_bytes()[1] - b'0')),
+        _ => None,
+    }
+}
+
+struct Writer<'m> {
+    mapping: &'m Mapping,
+}
+
+impl Writer<'_> {
+    /// Is `func` written as block (or does it contain blocks, if it is transparent)?
+    fn is_block(&self, func: &tree::DocumentFunction) -> bool {
+        if heading_level(&func.call).is_some() || func.call == "pre" || func.call == "hr" {
+            return true;
+        }
+        match self.mapping.element(&func.call) {
+            Some(element) => CONTAINER_ELEMENTS.contains(&element_name(element)) || BLOCK_ELEMENTS.contains(&element_name(element)),
+            None if func.call == "a" || func.call == "img" || func.call == "br" => false,
+            None => func.content.iter().any(|e| matches!(e, tree::DocumentElement::Function(child) if self.is_block(child))),
+        }
+    }
+
+    /// Write the elements of `node` as blocks indented by `depth` levels.
+    /// If `sections` is set, headings open nested `section` elements.
+    fn blocks(&self, node: &[tree::DocumentElement], depth: usize, sections: bool, out: &mut String) {
+        let mut open_sections: Vec<usize> = vec![];
+        let mut inline = vec![];
+        let mut depth = depth;
+
+        for element in node {
+            let func = match element {
+                tree::DocumentElement::Function(func) if self.is_block(func) => func,
+                _ => {
+                    inline.push(element.clone());
+                    continue;
+                },
+            };
+            self.paragraph(&inline, depth, out);
+            inline.clear();
+
+            match heading_level(&func.call) {
+                Some(level) if sections => {
+                    while open_sections.last().is_some_and(|open| *open >= level) {
+                        open_sections.pop();
+                        depth -= 1;
+                        out.push_str(&format!("{}</section>\n", "  ".repeat(depth)));
+                    }
+                    out.push_str(&format!("{}<section>\n", "  ".repeat(depth)));
+                    open_sections.push(level);
+                    depth += 1;
+                    out.push_str(&format!("{}<title>{}</title>\n", "  ".repeat(depth), self.inline(&func.content).trim()));
+                },
+                Some(level) => out.push_str(&format!("{}<bridgehead renderas=\"sect{level}\">{}</bridgehead>\n", "  ".repeat(depth), self.inline(&func.content).trim())),
+                None => self.block(func, depth, out),
+            }
+        }
+        self.paragraph(&inline, depth, out);
+
+        while open_sections.pop().is_some() {
+            depth -= 1;
+            out.push_str(&format!("{}</section>\n", "  ".repeat(depth)));
+        }
+    }
+
+    /// Write the inline elements `node` as `para` element unless they are whitespace only
+    fn paragraph(&self, node: &[tree::DocumentElement], depth: usize, out: &mut String) {
+        let content = self.inline(node);
+        if !content.trim().is_empty() {
+            out.push_str(&format!("{}<para>{}</para>\n", "  ".repeat(depth), content.trim()));
+        }
+    }
+
+    /// Write the block call `func` indented by `depth` levels
+    fn block(&self, func: &tree::DocumentFunction, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        if func.call == "hr" {
+            return;
+        }
+        if func.call == "pre" {
+            let code = code_block(&func.content);
+            let language = arg(func, "lang").map(|lang| format!(" language=\"{}\"", escape_xml(&lang))).unwrap_or_default();
+            out.push_str(&format!("{indent}<programlisting{language}>{}</programlisting>\n", escape_xml(&code)));
+            return;
+        }
+
+        let element = match self.mapping.element(&func.call) {
+            Some(element) => element,
+            None => return self.blocks(&func.content, depth, false, out),
+        };
+        let name = element_name(element);
+        let start = match (func.call.as_str(), arg(func, "start")) {
+            ("ol", Some(start)) => format!(" startingnumber=\"{}\"", escape_xml(start.trim())),
+            _ => String::new(),
+        };
+        if CONTAINER_ELEMENTS.contains(&name) {
+            out.push_str(&format!("{indent}<{element}{start}>\n"));
+            self.blocks(&func.content, depth + 1, false, out);
+            out.push_str(&format!("{indent}</{name}>\n"));
+        } else {
+            out.push_str(&format!("{indent}<{element}>{}</{name}>\n", self.inline(&func.content).trim()));
+        }
+    }
+
+    /// Represent the inline elements `node` with whitespace collapsed
+    fn inline(&self, node: &[tree::DocumentElement]) -> String {
+        let mut out = String::new();
+        for element in node {
+            let func = match element {
+                tree::DocumentElement::Text(text) => {
+                    out.push_str(&escape_xml(&collapse_whitespace(text)));
+                    continue;
+                },
+                tree::DocumentElement::Function(func) => func,
+            };
+            let content = self.inline(&func.content);
+            match (func.call.as_str(), self.mapping.element(&func.call)) {
+                (_, Some(element)) => out.push_str(&format!("<{element}>{content}</{}>", element_name(element))),
+                ("a", None) => {
+                    let href = escape_xml(&arg(func, "href").unwrap_or_default());
+                    out.push_str(&format!("<link xlink:href=\"{href}\">{content}</link>"));
+                },
+                ("img", None) => {
+                    let src = escape_xml(&arg(func, "src").unwrap_or_default());
+                    out.push_str(&format!("<inlinemediaobject><imageobject><imagedata fileref=\"{src}\"/></imageobject>"));
+                    if let Some(alt) = arg(func, "alt") {
+                        out.push_str(&format!("<textobject><phrase>{}</phrase></textobject>", escape_xml(&collapse_whitespace(&alt))));
+                    }
+                    out.push_str("</inlinemediaobject>");
+                },
+                ("br", None) => out.push(' '),
+                (_, None) => out.push_str(&content),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_docbook_with_custom_mapping() -> Result<(), String> {
+        let src = "Intro & {em more}.\n{h1 One}\n{p See {a[href=https://example.org/?a=1&b=2] this}.}\n{h2 Two}\n\
+            {note {ul {li item}}}\n{h1 Three}\n{pre[lang=rust]\n  x < y\n}";
+        let tree = crate::parse_str(src).expect("valid document");
+        let mapping = Mapping::from_json(&serde_json::json!({ "note": "note", "em": "" }))?;
+
+        assert_eq!(to_docbook(&tree, &mapping), format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <article xmlns=\"{NAMESPACE}\" xmlns:xlink=\"{XLINK_NAMESPACE}\" version=\"5.0\">\n\
+            \x20 <para>Intro &amp; more.</para>\n\
+            \x20 <section>\n\
+            \x20   <title>One</title>\n\
+            \x20   <para>See <link xlink:href=\"https://example.org/?a=1&amp;b=2\">this</link>.</para>\n\
+            \x20   <section>\n\
+            \x20     <title>Two</title>\n\
+            \x20     <note>\n\
+            \x20       <itemizedlist>\n\
+            \x20         <listitem>\n\
+            \x20           <para>item</para>\n\
+            \x20         </listitem>\n\
+            \x20       </itemizedlist>\n\
+            \x20     </note>\n\
+            \x20   </section>\n\
+            \x20 </section>\n\
+            \x20 <section>\n\
+            \x20   <title>Three</title>\n\
+            \x20   <programlisting language=\"rust\">x &lt; y</programlisting>\n\
+            \x20 </section>\n\
+            </article>\n"));
+        assert!(Mapping::from_json(&serde_json::json!({ "note": 1 })).is_err());
+        Ok(())
+    }
+}
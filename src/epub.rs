@@ -0,0 +1,272 @@
+//! EPUB 3 packaging of litua text documents
+//!
+//! Every chapter is an XHTML file. `to_xhtml` writes the standard call
+//! vocabulary of `markup` (`h1`, `p`, `em`, `a[href=…]`, …) as XHTML
+//! elements; other calls are transparent. `EpubWriter` writes the chapters
+//! to a zip archive one at a time and finally adds the OPF package document,
+//! the navigation document, and an NCX table of contents for EPUB 2 readers.
+//! Files referenced by the document (like images) are not packaged.
+//! Writing archives requires feature `epub`.
+
+use std::io;
+
+use crate::markup::{arg, code_block, plain_text};
+use crate::tree;
+
+/// Directory within the archive containing the package document and the chapters
+pub const CONTENT_DIR: &str = "OEBPS";
+
+/// `Metadata` describes the e-book in the package document
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Metadata {
+    /// title of the book (defaults to the title of the first chapter)
+    pub title: Option<String>,
+    /// language of the book as BCP 47 language tag like `en`
+    pub language: String,
+    /// unique identifier of the book like `urn:isbn:…`
+    pub identifier: String,
+    /// time of the last modification like `2023-04-01T12:34:56Z`
+    pub modified: String,
+}
+
+/// Escape `text` for use in XML text content and attribute values
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn is_block(call: &str) -> bool {
+    matches!(call, "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "p" | "ul" | "ol" | "li" | "blockquote" | "pre" | "hr")
+}
+
+/// Does the transparent call `func` contain blocks?
+fn contains_blocks(func: &tree::DocumentFunction) -> bool {
+    func.content.iter().any(|element| match element {
+        tree::DocumentElement::Function(child) => is_block(&child.call) || contains_blocks(child),
+        tree::DocumentElement::Text(_) => false,
+    })
+}
+
+/// Returns the title of the chapter started by `element` if it is an `h1` call
+pub fn chapter_title(element: &tree::DocumentElement) -> Option<String> {
+    match element {
+        tree::DocumentElement::Function(func) if func.call == "h1" => Some(plain_text(&func.content).split_whitespace().collect::<Vec<_>>().join(" ")),
+        _ => None,
+    }
+}
+
+/// Represent the elements `node` as content of an XHTML `body` element.
+/// Texts outside of blocks are wrapped in `p` elements.
+pub fn to_xhtml(node: &[tree::DocumentElement]) -> String {
+    let mut out = String::new();
+    let mut inline = String::new();
+    let flush = |out: &mut String, inline: &mut String| {
+        if !inline.trim().is_empty() {
+            out.push_str(&format!("<p>{}</p>\n", inline.trim()));
+        }
+        inline.clear();
+    };
+
+    for element in node {
+        match element {
+            tree::DocumentElement::Function(func) if is_block(&func.call) => {
+                flush(&mut out, &mut inline);
+                out.push_str(&element_to_xhtml(element));
+                out.push('\n');
+            },
+            tree::DocumentElement::Function(func) if !matches!(func.call.as_str(), "a" | "em" | "i" | "strong" | "b" | "code") && contains_blocks(func) => {
+                flush(&mut out, &mut inline);
+                out.push_str(&to_xhtml(&func.content));
+            },
+            _ => inline.push_str(&element_to_xhtml(element)),
+        }
+    }
+    flush(&mut out, &mut inline);
+    out
+}
+
+/// Represent `element` as XHTML
+fn element_to_xhtml(element: &tree::DocumentElement) -> String {
+    let func = match element {
+        tree::DocumentElement::Text(text) => return escape_xml(text),
+        tree::DocumentElement::Function(func) => func,
+    };
+    let content = || func.content.iter().map(element_to_xhtml).collect::<String>();
+    let attr = |key: &str, name: &str| arg(func, key).map(|value| format!(" {name}=\"{}\"", escape_xml(&value))).unwrap_or_default();
+    match func.call.as_str() {
+        "br" | "hr" => format!("<{}/>", func.call),
+        "img" => format!("<img{}{}/>", attr("src", "src"), match arg(func, "alt") {
+            Some(alt) => format!(" alt=\"{}\"", escape_xml(&alt)),
+            None => " alt=\"\"".to_owned(),
+        }),
+        "a" => format!("<a{}>{}</a>", attr("href", "href"), content()),
+        "ol" => format!("<ol{}>{}</ol>", attr("start", "start"), content()),
+        "pre" => {
+            let code = code_block(&func.content);
+            let class = arg(func, "lang").map(|lang| format!(" class=\"language-{}\"", escape_xml(&lang))).unwrap_or_default();
+            format!("<pre><code{class}>{}</code></pre>", escape_xml(&code))
+        },
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "p" | "ul" | "li" | "blockquote" | "em" | "i" | "strong" | "b" | "code" => {
+            format!("<{0}>{1}</{0}>", func.call, content())
+        },
+        _ => content(),
+    }
+}
+
+/// `EpubWriter` writes an EPUB archive one chapter at a time
+pub struct EpubWriter<W: io::Write + io::Seek> {
+    #[cfg(feature = "epub")]
+    zip: zip::ZipWriter<W>,
+    #[cfg(not(feature = "epub"))]
+    zip: std::marker::PhantomData<W>,
+    metadata: Metadata,
+    /// titles of the chapters written so far
+    chapters: Vec<String>,
+}
+
+impl<W: io::Write + io::Seek> EpubWriter<W> {
+    /// Start writing an EPUB archive to `writer`
+    #[cfg(feature = "epub")]
+    pub fn new(writer: W, metadata: Metadata) -> io::Result<Self> {
+        let mut zip = zip::ZipWriter::new(writer);
+        // NOTE: the uncompressed mimetype file must be the first file of the archive
+        zip.start_file("mimetype", zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored))?;
+        io::Write::write_all(&mut zip, b"application/epub+zip")?;
+        let mut epub = Self { zip, metadata, chapters: vec![] };
+        epub.add_file("META-INF/container.xml", &format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+            \x20 <rootfiles>\n\
+            \x20   <rootfile full-path=\"{CONTENT_DIR}/content.opf\" media-type=\"application/oebps-package+xml\"/>\n\
+            \x20 </rootfiles>\n\
+            </container>\n"))?;
+        Ok(epub)
+    }
+
+    /// Start writing an EPUB archive, which requires feature `epub`
+    #[cfg(not(feature = "epub"))]
+    pub fn new(_writer: W, _metadata: Metadata) -> io::Result<Self> {
+        let msg = "cannot write EPUB archives, because litua was built without feature 'epub'";
+        Err(io::Error::new(io::ErrorKind::Unsupported, msg))
+    }
+
+    #[cfg(feature = "epub")]
+    fn add_file(&mut self, name: &str, content: &str) -> io::Result<()> {
+        self.zip.start_file(name, zip::write::FileOptions::default())?;
+        io::Write::write_all(&mut self.zip, content.as_bytes())
+    }
+
+    #[cfg(not(feature = "epub"))]
+    fn add_file(&mut self, _name: &str, _content: &str) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    /// Returns the filename of chapter `index` (0-based) within `CONTENT_DIR`
+    fn chapter_filename(index: usize) -> String {
+        format!("chapter-{:03}.xhtml", index + 1)
+    }
+
+    /// Returns an XHTML document with title `title` and body `body`
+    fn xhtml_document(&self, title: &str, body: &str) -> String {
+        let language = escape_xml(&self.metadata.language);
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n\
+            <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" lang=\"{language}\" xml:lang=\"{language}\">\n\
+            <head><title>{}</title></head>\n<body>\n{body}</body>\n</html>\n", escape_xml(title))
+    }
+
+    /// Add a chapter with title `title` and the elements `content`
+    pub fn add_chapter(&mut self, title: &str, content: &[tree::DocumentElement]) -> io::Result<()> {
+        let name = format!("{CONTENT_DIR}/{}", Self::chapter_filename(self.chapters.len()));
+        let document = self.xhtml_document(title, &to_xhtml(content));
+        self.add_file(&name, &document)?;
+        self.chapters.push(title.to_owned());
+        Ok(())
+    }
+
+    /// Write the package document and the tables of contents and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.chapters.is_empty() {
+            self.add_chapter("", &[])?;
+        }
+        let title = self.metadata.title.clone()
+            .or_else(|| self.chapters.first().cloned())
+            .unwrap_or_default();
+
+        let nav_items: String = self.chapters.iter().enumerate()
+            .map(|(i, chapter)| format!("    <li><a href=\"{}\">{}</a></li>\n", Self::chapter_filename(i), escape_xml(chapter)))
+            .collect();
+        let nav = self.xhtml_document(&title, &format!("<nav epub:type=\"toc\" id=\"toc\">\n  <ol>\n{nav_items}  </ol>\n</nav>\n"));
+        self.add_file(&format!("{CONTENT_DIR}/nav.xhtml"), &nav)?;
+
+        let nav_points: String = self.chapters.iter().enumerate()
+            .map(|(i, chapter)| format!("    <navPoint id=\"chapter-{0}\" playOrder=\"{0}\"><navLabel><text>{1}</text></navLabel><content src=\"{2}\"/></navPoint>\n",
+                i + 1, escape_xml(chapter), Self::chapter_filename(i)))
+            .collect();
+        let ncx = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\" version=\"2005-1\">\n\
+            \x20 <head><meta name=\"dtb:uid\" content=\"{}\"/></head>\n\
+            \x20 <docTitle><text>{}</text></docTitle>\n\
+            \x20 <navMap>\n{nav_points}  </navMap>\n\
+            </ncx>\n", escape_xml(&self.metadata.identifier), escape_xml(&title));
+        self.add_file(&format!("{CONTENT_DIR}/toc.ncx"), &ncx)?;
+
+        let manifest: String = (0..self.chapters.len())
+            .map(|i| format!("    <item id=\"chapter-{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n", i + 1, Self::chapter_filename(i)))
+            .collect();
+        let spine: String = (0..self.chapters.len())
+            .map(|i| format!("    <itemref idref=\"chapter-{}\"/>\n", i + 1))
+            .collect();
+        let opf = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\">\n\
+            \x20 <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+            \x20   <dc:identifier id=\"book-id\">{}</dc:identifier>\n\
+            \x20   <dc:title>{}</dc:title>\n\
+            \x20   <dc:language>{}</dc:language>\n\
+            \x20   <meta property=\"dcterms:modified\">{}</meta>\n\
+            \x20 </metadata>\n\
+            \x20 <manifest>\n\
+            \x20   <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+            \x20   <item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\"/>\n\
+            {manifest}  </manifest>\n\
+            \x20 <spine toc=\"ncx\">\n{spine}  </spine>\n\
+            </package>\n",
+            escape_xml(&self.metadata.identifier), escape_xml(&title), escape_xml(&self.metadata.language), escape_xml(&self.metadata.modified));
+        self.add_file(&format!("{CONTENT_DIR}/content.opf"), &opf)?;
+
+        #[cfg(feature = "epub")]
+        return Ok(self.zip.finish()?);
+        #[cfg(not(feature = "epub"))]
+        return Err(io::Error::from(io::ErrorKind::Unsupported));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xhtml_of_standard_calls() {
+        let tree = crate::parse_str("Intro {em & more}\n{section {h2 A<B} {img[src=a.png]}}{pre\n  x\n}").expect("valid document");
+        let content = match &tree.0 {
+            tree::DocumentElement::Function(root) => root.content.clone(),
+            tree::DocumentElement::Text(_) => panic!("root must be a call"),
+        };
+        assert_eq!(to_xhtml(&content), "<p>Intro <em>&amp; more</em></p>\n<h2>A&lt;B</h2>\n<p><img src=\"a.png\" alt=\"\"/></p>\n<pre><code>x</code></pre>\n");
+    }
+
+    #[cfg(feature = "epub")]
+    #[test]
+    fn write_epub_archive() -> io::Result<()> {
+        let metadata = Metadata { title: None, language: "en".to_owned(), identifier: "urn:uuid:1".to_owned(), modified: "2023-04-01T12:34:56Z".to_owned() };
+        let mut epub = EpubWriter::new(io::Cursor::new(vec![]), metadata)?;
+        epub.add_chapter("One & Two", &[tree::DocumentElement::Text("text".to_owned())])?;
+        let archive = epub.finish()?.into_inner();
+
+        let mut zip = zip::ZipArchive::new(io::Cursor::new(archive))?;
+        let names: Vec<&str> = zip.file_names().collect();
+        assert!(names.contains(&"OEBPS/chapter-001.xhtml") && names.contains(&"OEBPS/nav.xhtml"));
+        assert_eq!(zip.by_index(0)?.name(), "mimetype");
+        let mut opf = String::new();
+        io::Read::read_to_string(&mut zip.by_name("OEBPS/content.opf")?, &mut opf)?;
+        assert!(opf.contains("<dc:title>One &amp; Two</dc:title>"));
+        Ok(())
+    }
+}
@@ -1,124 +1,310 @@
 use std::error;
 use std::fmt;
+use std::ops;
 use std::path;
 
 use crate::lexer;
-use crate::lines_with_indices::StrLinesWithByteIndices;
+use crate::messages::{Lang, Message};
+use crate::text::{LineIndex, StrLinesWithByteIndices};
 
+/// Maximum number of characters of a source line shown in an excerpt
+pub const MAX_EXCERPT_WIDTH: usize = 100;
+
+/// Columns are counted up to this number of characters. Positions further
+/// right (e.g. in minified documents on a single line) are reported by their
+/// byte offset within the line instead.
+pub const MAX_COLUMN: usize = 10_000;
 
 #[derive(Debug,Clone)]
+#[non_exhaustive]
 pub enum Error {
     /// lexing error regarding unbalanced parentheses with message and byte offset
-    UnbalancedParentheses(String, usize),
+    UnbalancedParentheses(Message, usize),
     /// lexing error regarding syntax violation with message and byte offset
-    InvalidSyntax(String, usize),
+    InvalidSyntax(Message, usize),
     /// parsing error where the lexer yields an invalid sequence of tokens
-    /// with messages what we actual got and what we expected
-    UnexpectedToken(lexer::Token, String),
+    /// with the token we actually got and a message what we expected
+    UnexpectedToken(lexer::Token, Message),
     /// parsing error where the content suddenly finished
-    UnexpectedEOF(String),
+    UnexpectedEOF(Message),
     /// lexing error which was resolved into a complete message
-    /// including line number and line column. Consists of
-    /// (filepath, message, line number, character index within line, byte offset within line).
+    /// including line and column. Consists of (filepath, message,
+    /// line index, character index within line, byte offset within line)
+    /// where indices are zero-based.
     /// NOTE: must not be used directly by the lexer
-    LexingError(path::PathBuf, String, usize, usize, usize),
+    LexingError(path::PathBuf, Message, usize, usize, usize),
     /// lexing error which was resolved into a complete message
-    /// including line number and line column. Consists of (filepath, message, X)
-    /// where X is (line number, character index within line, byte offset within line)
-    /// twice for start and end.
+    /// including line and column. Consists of (filepath, message, X)
+    /// where X is (line index, character index within line, byte offset within line)
+    /// twice for the first and the last character of the range (zero-based indices).
     /// NOTE: must not be used directly by the lexer
-    RangedLexingError(path::PathBuf, String, [(usize, usize, usize); 2]),
+    RangedLexingError(path::PathBuf, Message, [(usize, usize, usize); 2]),
+    /// error raised by a hook with message and the byte range of the call
+    /// the hook was invoked for
+    HookError(Message, ops::Range<usize>),
+    /// error regarding an `include` call with message and the byte range of the call
+    IncludeError(Message, ops::Range<usize>),
+    /// error regarding an `if` or `ifnot` call with message and the byte range of the call
+    ConditionError(Message, ops::Range<usize>),
+    /// error regarding the included text document at the filepath
+    /// (byte offsets of the error refer to this document)
+    InIncludedFile(path::PathBuf, Box<Error>),
 }
 
 impl Error {
-    /// Return (lineno, linecol, byte offset within line) for a given `byte_offset`
-    /// within some text content `src`
-    fn get_line_identifier_at_byte(byte_offset: usize, src: &str) -> (usize, usize, usize) {
-        let mut prev_byte_offset = 0;
-        let mut prev_line_index = 0;
-        let mut prev_column_index = 0;
-
-        for (line_index, (start_byte_offset, line)) in src.lines_indices().enumerate() {
-            for (column_index, (column_byte_offset, _)) in line.char_indices().enumerate() {
-                if prev_byte_offset <= byte_offset && byte_offset < start_byte_offset + column_byte_offset {
-                    return (prev_line_index, prev_column_index, column_byte_offset);
-                }
-
-                prev_byte_offset = start_byte_offset + column_byte_offset;
-                prev_line_index = line_index;
-                prev_column_index = column_index;
-            }
+    /// Return (line index, character index within line, byte offset within line)
+    /// with zero-based indices for a given `byte_offset` within some text content `src`
+    /// indexed by `lines`. The character index is clamped to `MAX_COLUMN`.
+    fn get_line_identifier_at_byte(byte_offset: usize, src: &str, lines: &LineIndex) -> (usize, usize, usize) {
+        let line_index = lines.line_of(byte_offset);
+        let line = lines.line_range(line_index).unwrap_or(0..0);
+        let line_byte_index = byte_offset.saturating_sub(line.start).min(line.len());
+        let line_char_index = src[line.clone()].char_indices()
+            .take_while(|(i, _)| *i < line_byte_index)
+            .take(MAX_COLUMN)
+            .count();
+        (line_index, line_char_index, line_byte_index)
+    }
+
+    /// Resolve the byte offsets of this error within `src` into lines and columns
+    /// (for `InIncludedFile`, `src` must be the content of the included document)
+    pub fn format_with_source(&self, filepath: &path::Path, src: &str) -> Error {
+        self.format_with_line_index(filepath, src, &LineIndex::new(src))
+    }
+
+    /// Like `format_with_source`, but reuses the line index `lines` of `src`
+    /// (e.g. to resolve many diagnostics of the same document)
+    pub fn format_with_line_index(&self, filepath: &path::Path, src: &str, lines: &LineIndex) -> Error {
+        if let Error::InIncludedFile(included, err) = self {
+            return err.format_with_line_index(included, src, lines);
         }
+        let range = match self.byte_range(src) {
+            Some(range) => range,
+            None => return self.clone(),
+        };
 
-        (prev_line_index, prev_column_index, prev_byte_offset)
+        let first = Self::get_line_identifier_at_byte(range.start, src, lines);
+        if range.is_empty() {
+            Error::LexingError(filepath.to_owned(), self.message().to_owned(), first.0, first.1, first.2)
+        } else {
+            let last = Self::get_line_identifier_at_byte(range.end - 1, src, lines);
+            Error::RangedLexingError(filepath.to_owned(), self.message().to_owned(), [first, last])
+        }
     }
+}
 
-    pub fn format_with_source(&self, filepath: &path::Path, src: &str) -> Error {
+impl error::Error for Error {}
+
+impl Error {
+    /// Returns the byte range within the text document `src` this error refers to
+    /// (empty for a position and `None` if it was already resolved by `format_with_source`)
+    pub fn byte_range(&self, src: &str) -> Option<ops::Range<usize>> {
         use Error::*;
 
         match self {
-            UnbalancedParentheses(msg, byte_offset) |
-            InvalidSyntax(msg, byte_offset) => {
-                let (line_index, line_char_index, line_byte_index) = Self::get_line_identifier_at_byte(*byte_offset, src);
-                let lineno = line_index + 1;  // humans prefer one-based indices, we get zero-based indices
-                let linecol = line_char_index + 1;  // humans prefer one-based indices, we get zero-based indices
-
-                LexingError(filepath.to_owned(), msg.to_owned(), lineno, linecol, line_byte_index)
-            },
-            UnexpectedEOF(msg) => {
-                let lines_count = src.lines().count();
-                LexingError(filepath.to_owned(), msg.to_owned(), lines_count, 0, src.len())
+            UnbalancedParentheses(_, byte_offset) |
+            InvalidSyntax(_, byte_offset) => Some(*byte_offset..*byte_offset),
+            UnexpectedToken(got_token, _) => match got_token.byte_offsets() {
+                (start, Some(end)) => Some(start..end),
+                (start, None) => Some(start..start),
             },
-            UnexpectedToken(got_token, expected) => {
-                let byte_offsets = got_token.byte_offsets();
-                let (start_index, start_char_index, start_byte_index) = Self::get_line_identifier_at_byte(byte_offsets.0, src);
-
-                match byte_offsets.1 {
-                    Some(end_byteoffset) => {
-                        let (end_index, end_char_index, end_byte_index) = Self::get_line_identifier_at_byte(end_byteoffset, src);
-                        RangedLexingError(
-                            filepath.to_owned(),
-                            format!("expected {}, but got token {:?}", expected, got_token.name()),
-                            [(start_index, start_char_index, start_byte_index), (end_index, end_char_index, end_byte_index)]
-                        )
-                    },
-                    None => {
-                        let msg = format!("expected {}, but got token {:?}", expected, got_token.name());
-                        LexingError(filepath.to_owned(), msg, start_index, start_char_index, start_byte_index)
-                    },
-                }
+            UnexpectedEOF(_) => Some(src.len()..src.len()),
+            HookError(_, range) |
+            IncludeError(_, range) |
+            ConditionError(_, range) => Some(range.start.min(src.len())..range.end.min(src.len())),
+            InIncludedFile(_, err) => err.byte_range(src),
+            LexingError(..) | RangedLexingError(..) => None,
+        }
+    }
 
+    /// Returns the message of this error
+    pub fn message(&self) -> &Message {
+        use Error::*;
 
-            },
-            LexingError(..) => self.clone(),
-            RangedLexingError(..) => self.clone(),
+        match self {
+            UnbalancedParentheses(msg, _) |
+            InvalidSyntax(msg, _) |
+            UnexpectedToken(_, msg) |
+            UnexpectedEOF(msg) |
+            LexingError(_, msg, ..) |
+            RangedLexingError(_, msg, _) |
+            HookError(msg, _) |
+            IncludeError(msg, _) |
+            ConditionError(msg, _) => msg,
+            InIncludedFile(_, err) => err.message(),
         }
     }
-}
 
-impl error::Error for Error {}
+    /// Returns the line and column (one-based) of errors resolved by `format_with_source`
+    /// (columns beyond `MAX_COLUMN` are clamped)
+    pub fn line_column(&self) -> Option<(usize, usize)> {
+        match self {
+            Error::LexingError(_, _, line_index, column_index, _) |
+            Error::RangedLexingError(_, _, [(line_index, column_index, _), _]) => Some((line_index + 1, column_index + 1)),
+            _ => None,
+        }
+    }
+
+    /// Represent this error as human-readable message in language `lang`
+    pub fn localized(&self, lang: Lang) -> String {
+        use Error::*;
+
+        let message = self.message().render(lang);
+        let frame = match self {
+            UnbalancedParentheses(_, byte) |
+            InvalidSyntax(_, byte) |
+            HookError(_, ops::Range { start: byte, .. }) |
+            IncludeError(_, ops::Range { start: byte, .. }) |
+            ConditionError(_, ops::Range { start: byte, .. }) => Message::new("E0003", vec![message, byte.to_string()]),
+            InIncludedFile(filepath, err) => Message::new("E0007", vec![err.localized(lang), filepath.display().to_string()]),
+            UnexpectedEOF(_) |
+            UnexpectedToken(..) => return message,
+            // NOTE: columns are clamped at MAX_COLUMN, so refer to byte offsets within the line
+            LexingError(filepath, _, line_index, column_index, column_byteoffset) if *column_index >= MAX_COLUMN => Message::new("E0008", vec![
+                message, filepath.display().to_string(), (line_index + 1).to_string(), column_byteoffset.to_string(),
+            ]),
+            RangedLexingError(filepath, _, range) if range[0].1 >= MAX_COLUMN || range[1].1 >= MAX_COLUMN => Message::new("E0009", vec![
+                message, filepath.display().to_string(),
+                (range[0].0 + 1).to_string(), range[0].2.to_string(), (range[1].0 + 1).to_string(), range[1].2.to_string(),
+            ]),
+            LexingError(filepath, _, line_index, column_index, column_byteoffset) => Message::new("E0001", vec![
+                message, filepath.display().to_string(),
+                (line_index + 1).to_string(), (column_index + 1).to_string(), column_byteoffset.to_string(),
+            ]),
+            RangedLexingError(filepath, _, range) => Message::new("E0002", vec![
+                message, filepath.display().to_string(),
+                (range[0].0 + 1).to_string(), (range[0].1 + 1).to_string(), (range[1].0 + 1).to_string(), (range[1].1 + 1).to_string(),
+            ]),
+        };
+        frame.render(lang)
+    }
+}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use Error::*;
+        f.write_str(&self.localized(Lang::English))
+    }
+}
 
-        match self {
-            UnbalancedParentheses(msg, byte) |
-            InvalidSyntax(msg, byte) => write!(f, "{msg} at byte {byte}"),
-            UnexpectedEOF(msg) => write!(f, "{msg}"),
-            UnexpectedToken(got, expected) => write!(f, "expected {expected}, but got token {:?}", got),
-            LexingError(filepath, message, line_index, column_index, column_byteoffset) =>
-                write!(
-                    f, "{message} in file {}, line {} at column {} (byte offset {} within line)",
-                    filepath.display(), line_index + 1, column_index + 1, column_byteoffset
-                ),
-            RangedLexingError(filepath, message, range) =>
-                write!(
-                    f, "{message} in file {} from line {} at column {} until line {} at column {}",
-                    filepath.display(), range[0].0 + 1, range[0].1 + 1, range[1].0, range[1].1
-                ),
+/// Represent the lines of `src` containing `range` and underline the range
+/// (an empty range is marked by a single caret), preceded and followed by up
+/// to `context_lines` lines. Lines are prefixed with their line number. Long
+/// lines are clamped to a window of `MAX_EXCERPT_WIDTH` characters around
+/// the start of the range.
+pub fn excerpt(src: &str, range: ops::Range<usize>, context_lines: usize) -> String {
+    let mut lines: Vec<(usize, &str)> = src.lines_indices().collect();
+    if lines.is_empty() {
+        lines.push((0, ""));
+    }
+    let line_of = |byte_offset: usize| lines.partition_point(|(start, _)| *start <= byte_offset).saturating_sub(1);
+    let column_of = |line_index: usize, byte_offset: usize| {
+        let (start, line) = lines[line_index];
+        line.char_indices().take_while(|(i, _)| start + i < byte_offset).count()
+    };
+
+    let start_line = line_of(range.start);
+    let end_line = line_of(range.end.saturating_sub(1).max(range.start));
+    let first = start_line.saturating_sub(context_lines);
+    let last = (end_line + context_lines).min(lines.len() - 1);
+    let number_width = (last + 1).to_string().len();
+
+    // determine the window of characters shown (shared by all lines to keep columns aligned)
+    let start_column = column_of(start_line, range.start);
+    let line_width = lines[start_line].1.chars().take(start_column + MAX_EXCERPT_WIDTH).count();
+    let window_start = (start_column.saturating_sub(MAX_EXCERPT_WIDTH / 2) + MAX_EXCERPT_WIDTH)
+        .min(line_width.max(start_column + 1))
+        .saturating_sub(MAX_EXCERPT_WIDTH);
+    let window_end = window_start + MAX_EXCERPT_WIDTH;
+    let ellipsis_width = if window_start > 0 { 1 } else { 0 };
+
+    let mut out = String::new();
+    for (index, (_, line)) in lines.iter().enumerate().take(last + 1).skip(first) {
+        // NOTE: count at most up to the end of the window, since lines of minified documents are enormous
+        let shown_width = line.chars().take(window_end + 1).count();
+        let mut text: String = if window_start > 0 && shown_width > window_start { "…".to_owned() } else { String::new() };
+        text.extend(line.chars().skip(window_start).take(window_end - window_start).map(|c| if c == '\t' { ' ' } else { c }));
+        if shown_width > window_end {
+            text.push('…');
+        }
+        out.push_str(&format!("{:>number_width$} | {}\n", index + 1, text.trim_end()));
+
+        if (start_line..=end_line).contains(&index) {
+            let from = if index == start_line { start_column } else { 0 };
+            let to = if index == end_line { column_of(end_line, range.end) } else { shown_width };
+            let from = from.clamp(window_start, window_end);
+            let to = to.min(window_end).max(from + 1);
+            let indent = " ".repeat(from - window_start + ellipsis_width);
+            out.push_str(&format!("{:>number_width$} | {indent}{}\n", "", "^".repeat(to - from)));
         }
     }
+    out
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excerpt_with_context_and_clamping() {
+        let src = "first\nsecond {}\nthird\nfourth\n";
+        assert_eq!(excerpt(src, 14..14, 1), "1 | first\n2 | second {}\n  |         ^\n3 | third\n");
+        assert_eq!(excerpt(src, 14..14, 0), "2 | second {}\n  |         ^\n");
+        assert_eq!(excerpt("{x\n", 3..3, 1), "1 | {x\n2 | \n  | ^\n");
 
+        let long = format!("{}{{}}{}", "a".repeat(500), "b".repeat(500));
+        let lines: Vec<String> = excerpt(&long, 500..500, 2).lines().map(|l| l.to_owned()).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("1 | …a") && lines[0].ends_with("b…"));
+        assert_eq!(lines[0].chars().count(), "1 | ".len() + MAX_EXCERPT_WIDTH + 2);
+        assert_eq!(lines[1].chars().nth(lines[1].find('^').unwrap()), Some('^'));
+        assert_eq!(lines[0].chars().nth(lines[1].find('^').unwrap()), Some('{'));
+    }
+
+    #[test]
+    fn ranged_errors_underline_the_token() {
+        let src = "first\nsecond {x[a=1]bad}\n";
+        let err = Error::UnexpectedToken(lexer::Token::Text(20..23), Message::new("P0103", vec!["Text".to_owned()]));
+        let range = err.byte_range(src).expect("unresolved error");
+        assert_eq!(excerpt(src, range, 0), "2 | second {x[a=1]bad}\n  |               ^^^\n");
+
+        let resolved = err.format_with_source(path::Path::new("doc.lit"), src);
+        assert!(matches!(resolved, Error::RangedLexingError(_, _, [(1, 14, 14), (1, 16, 16)])));
+        assert!(resolved.to_string().ends_with("from line 2 at column 15 until line 2 at column 17"));
+
+        let multiline = excerpt("ab\ncd\nef", 1..5, 0);
+        assert_eq!(multiline, "1 | ab\n  |  ^\n2 | cd\n  | ^^\n");
+
+        let resolved = Error::InvalidSyntax(Message::new("L0005", vec!["1".to_owned()]), 8).format_with_source(path::Path::new("doc.lit"), src);
+        assert!(resolved.to_string().ends_with("line 2 at column 3 (byte offset 2 within line)"));
+    }
+
+    #[test]
+    fn enormous_single_line_documents() {
+        let src = format!("{}{{x}}{}", "é".repeat(MAX_COLUMN), "a".repeat(MAX_COLUMN));
+        let offset = 2 * MAX_COLUMN;
+        let resolved = Error::InvalidSyntax(Message::new("L0005", vec!["1".to_owned()]), offset).format_with_source(path::Path::new("doc.lit"), &src);
+        assert_eq!(resolved.line_column(), Some((1, MAX_COLUMN + 1)));
+        assert!(resolved.to_string().ends_with(&format!("line 1 at byte offset {offset} within line")));
+
+        let err = Error::UnexpectedToken(lexer::Token::Text(offset..offset + 3), Message::new("P0103", vec!["Text".to_owned()]));
+        assert!(err.format_with_source(path::Path::new("doc.lit"), &src).to_string()
+            .ends_with(&format!("from line 1 at byte offset {offset} until line 1 at byte offset {} within line", offset + 2)));
+
+        let excerpt = excerpt(&src, offset..offset + 3, 0);
+        let lines: Vec<&str> = excerpt.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("é{x}a"));
+        assert_eq!(lines[1].trim_start_matches([' ', '|']), "^^^");
+    }
+
+    #[test]
+    fn hook_errors_refer_to_the_call() {
+        let src = "first\n  {p hello {q world}}\n";
+        let message = Message::new("T0001", vec!["hook from 'hooks.lua'".to_owned(), "q".to_owned(), "boom".to_owned()]);
+        let err = Error::HookError(message, 16..25);
+        assert_eq!(err.to_string(), "hook from 'hooks.lua' failed for call 'q': boom at byte 16");
+
+        let resolved = err.format_with_source(path::Path::new("doc.lit"), src);
+        assert_eq!(resolved.to_string(), "hook from 'hooks.lua' failed for call 'q': boom in file doc.lit from line 2 at column 11 until line 2 at column 19");
+    }
+}
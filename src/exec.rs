@@ -0,0 +1,96 @@
+//! Execution of external commands (like graphviz or plantuml) from hooks
+//!
+//! Executing commands is opt-in (``--allow-exec``), thus this module is only
+//! used if the user allowed it. The output of commands is captured and
+//! commands exceeding their time limit are killed.
+
+use std::io;
+use std::process;
+use std::thread;
+use std::time;
+
+/// Default time limit for a command to finish
+pub const DEFAULT_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+/// Interval to check whether a command has finished
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(5);
+
+/// `Output` is the exit status and captured output of a finished command
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Output {
+    /// exit code (`None` if the command was terminated by a signal)
+    pub status: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Run command `cmd` with arguments `args` (without a shell), write `stdin`
+/// to its standard input and capture its standard output and error.
+/// Fails with `io::ErrorKind::TimedOut` if the command does not finish within `timeout`.
+pub fn run(cmd: &str, args: &[String], stdin: &[u8], timeout: time::Duration) -> io::Result<Output> {
+    let mut child = process::Command::new(cmd)
+        .args(args)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot run command '{cmd}': {e}")))?;
+
+    // NOTE: pipes are served by threads, because a command blocks if a pipe buffer is full
+    let mut child_stdin = child.stdin.take();
+    let input = stdin.to_vec();
+    let writer = thread::spawn(move || {
+        if let Some(pipe) = child_stdin.as_mut() {
+            // NOTE: a command might not read its input, which is not an error
+            let _ = io::Write::write_all(pipe, &input);
+        }
+    });
+    let mut child_stdout = child.stdout.take();
+    let stdout_reader = thread::spawn(move || read_pipe(child_stdout.as_mut()));
+    let mut child_stderr = child.stderr.take();
+    let stderr_reader = thread::spawn(move || read_pipe(child_stderr.as_mut()));
+
+    let start = time::Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            let msg = format!("command '{cmd}' did not finish within {} seconds and was killed", timeout.as_secs_f64());
+            return Err(io::Error::new(io::ErrorKind::TimedOut, msg));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let _ = writer.join();
+    let stdout = stdout_reader.join().map_err(|_| io::Error::other("reading standard output failed"))??;
+    let stderr = stderr_reader.join().map_err(|_| io::Error::other("reading standard error failed"))??;
+    Ok(Output { status: status.code(), stdout, stderr })
+}
+
+fn read_pipe<R: io::Read>(pipe: Option<&mut R>) -> io::Result<Vec<u8>> {
+    let mut content = vec![];
+    if let Some(pipe) = pipe {
+        pipe.read_to_end(&mut content)?;
+    }
+    Ok(content)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_output_and_enforce_timeout() -> io::Result<()> {
+        let args = vec!["-c".to_owned(), "tr a-z A-Z; echo warning >&2; exit 3".to_owned()];
+        let output = run("sh", &args, b"digraph", DEFAULT_TIMEOUT)?;
+        assert_eq!(output, Output { status: Some(3), stdout: b"DIGRAPH".to_vec(), stderr: b"warning\n".to_vec() });
+
+        let err = run("sleep", &["5".to_owned()], b"", time::Duration::from_millis(50)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(run("litua-nonexistent-command", &[], b"", DEFAULT_TIMEOUT).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+//! Tracing a single call through the transformation (see `--explain`)
+//!
+//! A call is selected by its path as reported by hook tracing, like
+//! `/document/p[2]/em[1]`, where the numbers are 1-based indices within the
+//! content of the parent call and `[@href]` selects the value of argument `href`
+//! (followed by an optional index, by default 1). Alternatively, `#intro`
+//! selects the first call with argument `id` or `label` being `intro` and `p`
+//! selects the first call named `p` in document order. During the transformation,
+//! the call is identified by its source span (argument `=span`), so nodes
+//! created by hooks which copy the arguments of the call are traced as well.
+
+use std::fmt::Write;
+
+use crate::errors;
+use crate::links;
+use crate::tree;
+
+/// Hooks invoked for individual calls in the order they run
+/// (other hooks are not listed as matching the explained call)
+pub const NODE_HOOKS: [&str; 4] = ["read_new_node", "modify_node", "read_modified_node", "convert_node_to_string"];
+
+/// `Step` is one hook invocation for the explained call
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Step {
+    /// name of the hook like `modify_node`
+    pub hook: String,
+    /// source location of the hook implementation
+    pub src: String,
+    /// path of the node the hook was invoked for
+    pub path: String,
+    /// string representation of the value returned by the hook (if any)
+    pub value: Option<String>,
+}
+
+/// `Explanation` collects everything known about the explained call
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Explanation {
+    /// the selector given by the user
+    pub selector: String,
+    /// the call in litua input syntax as parsed
+    pub parsed: String,
+    /// excerpt of the source document containing the call (if it was not included from another document)
+    pub excerpt: Option<String>,
+    /// source locations of the hooks registered for the call name of the call
+    pub matching_hooks: Vec<String>,
+    /// hook invocations in the order they happened
+    pub steps: Vec<Step>,
+    /// string representations of the call used for the output
+    pub contributions: Vec<String>,
+}
+
+/// Returns the call selected by `selector` in `tree`
+pub fn resolve<'t>(tree: &'t tree::DocumentTree, selector: &str) -> Result<&'t tree::DocumentFunction, String> {
+    if let Some(target) = selector.strip_prefix('#') {
+        return tree.find_all(|func| links::TARGET_ARGS.iter().any(|key| func.text_arg(key).map(str::trim) == Some(target)))
+            .into_iter().next()
+            .ok_or_else(|| format!("no call has argument 'id' or 'label' with value '{target}'"));
+    }
+    if !selector.starts_with('/') {
+        return tree.find_all(|func| func.call == selector).into_iter().next()
+            .ok_or_else(|| format!("no call is named '{selector}'"));
+    }
+
+    let root = match &tree.0 {
+        tree::DocumentElement::Function(root) => root,
+        tree::DocumentElement::Text(_) => return Err("the document has no root call".to_owned()),
+    };
+    let mut segments = selector[1..].split('/');
+    let root_call = segments.next().unwrap_or_default();
+    if root_call != root.call {
+        return Err(format!("the root call is named '{}', not '{root_call}'", root.call));
+    }
+    let mut func = root;
+    for segment in segments {
+        let (call, rest) = segment.split_once('[').ok_or_else(|| format!("segment '{segment}' has no index like '{segment}[1]'"))?;
+        let mut selectors = rest.trim_end_matches(']').split("][");
+        let index_of = |text: Option<&str>| -> Result<usize, String> {
+            match text {
+                None => Ok(0),
+                Some(text) => text.parse::<usize>().ok().filter(|index| *index > 0).map(|index| index - 1)
+                    .ok_or_else(|| format!("'{text}' in segment '{segment}' is not an index starting at 1")),
+            }
+        };
+        let first = selectors.next();
+        let element = match first.and_then(|key| key.strip_prefix('@')) {
+            Some(key) => {
+                let index = index_of(selectors.next())?;
+                func.args.get(key).and_then(|value| value.get(index))
+                    .ok_or_else(|| format!("call '{}' has no element {} in argument '{key}'", func.call, index + 1))?
+            },
+            None => {
+                let index = index_of(first)?;
+                func.content.get(index)
+                    .ok_or_else(|| format!("call '{}' has no element {} in its content", func.call, index + 1))?
+            },
+        };
+        func = match element {
+            tree::DocumentElement::Function(child) if child.call == call => child,
+            tree::DocumentElement::Function(child) => return Err(format!("segment '{segment}' refers to a call named '{}'", child.call)),
+            tree::DocumentElement::Text(_) => return Err(format!("segment '{segment}' refers to text")),
+        };
+    }
+    Ok(func)
+}
+
+impl Explanation {
+    /// Start the explanation of call `func` selected by `selector` within the source document `src`
+    pub fn new(selector: &str, func: &tree::DocumentFunction, src: &str) -> Self {
+        let wrapper = tree::DocumentFunction { content: vec![tree::DocumentElement::Function(func.clone())], ..tree::DocumentFunction::new() };
+        let included = func.args.contains_key(tree::FILE_ARG);
+        Self {
+            selector: selector.to_owned(),
+            parsed: tree::DocumentTree(tree::DocumentElement::Function(wrapper)).to_litua_syntax(),
+            excerpt: func.span().filter(|_| !included).map(|span| errors::excerpt(src, span, 0)),
+            ..Self::default()
+        }
+    }
+
+    /// Represent the explanation as human-readable text
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "=== {} as parsed ===", self.selector);
+        if let Some(excerpt) = &self.excerpt {
+            let _ = writeln!(out, "{}", excerpt.trim_end());
+        }
+        let _ = writeln!(out, "{}", self.parsed);
+
+        let _ = writeln!(out, "=== registered hooks for its call name ===");
+        if self.matching_hooks.is_empty() {
+            let _ = writeln!(out, "(none)");
+        }
+        for hook in self.matching_hooks.iter() {
+            let _ = writeln!(out, "{hook}");
+        }
+
+        let _ = writeln!(out, "=== hook invocations ===");
+        if self.steps.is_empty() {
+            let _ = writeln!(out, "(none)");
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            let _ = writeln!(out, "{}. {} at {} ({})", i + 1, step.hook, step.path, step.src);
+            match &step.value {
+                Some(value) => { let _ = writeln!(out, "   {}", value.replace('\n', "\n   ")); },
+                None => { let _ = writeln!(out, "   (no result)"); },
+            }
+        }
+
+        let _ = writeln!(out, "=== contribution to the output ===");
+        if self.contributions.is_empty() {
+            let _ = writeln!(out, "(none, the call was removed or replaced)");
+        }
+        for contribution in self.contributions.iter() {
+            let _ = writeln!(out, "{contribution}");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_paths_and_selectors() -> Result<(), String> {
+        let tree = crate::parse_str("{h1[id=intro] A}\n{p x {em[title={b t}] y}}").map_err(|_| "invalid document")?;
+        assert_eq!(resolve(&tree, "#intro")?.call, "h1");
+        assert_eq!(resolve(&tree, "em")?.call, "em");
+        assert_eq!(resolve(&tree, "/document/p[3]/em[2]")?.content, vec![tree::DocumentElement::Text("y".to_owned())]);
+        assert_eq!(resolve(&tree, "/document/p[3]/em[2]/b[@title]")?.call, "b");
+        assert_eq!(resolve(&tree, "/document")?.call, "document");
+        assert!(resolve(&tree, "/document/p[1]").is_err());
+        assert!(resolve(&tree, "/document/p[0]").is_err());
+        assert!(resolve(&tree, "/root").is_err());
+        assert!(resolve(&tree, "#missing").is_err());
+
+        let explanation = Explanation::new("em", resolve(&tree, "em")?, "{h1[id=intro] A}\n{p x {em[title={b t}] y}}");
+        assert_eq!(explanation.parsed, "{em[title={b t}] y}");
+        assert!(explanation.excerpt.is_some_and(|excerpt| excerpt.contains("^^^")));
+        Ok(())
+    }
+}
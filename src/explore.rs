@@ -0,0 +1,430 @@
+//! Interactive explorer of the tree of a litua text document
+//!
+//! `Explorer` holds the state of `litua explore`: the parsed tree, the calls
+//! expanded so far, and the selected row. Only the content of calls is shown
+//! as rows; their arguments are listed in the details of the selected call.
+//! Searching selects the next call (in document order) whose name contains
+//! the query and expands its ancestors. The terminal interface of `run`
+//! requires feature `explore`.
+
+use std::collections::HashSet;
+
+use crate::text::LineIndex;
+use crate::tree::{self, NodePath, NodeStep};
+
+/// Maximum number of characters of texts shown in rows
+const MAX_LABEL_CHARS: usize = 60;
+
+/// `Row` is one visible line of the tree
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Row {
+    /// location of the element in the tree
+    pub path: NodePath,
+    /// nesting depth (0 for the root call)
+    pub depth: usize,
+    /// short description like `{p}` or `"some text"`
+    pub label: String,
+    /// is it a call with content?
+    pub expandable: bool,
+}
+
+/// `Explorer` is the state of the tree explorer
+#[derive(Clone,Debug)]
+pub struct Explorer {
+    tree: tree::DocumentTree,
+    expanded: HashSet<NodePath>,
+    rows: Vec<Row>,
+    selected: usize,
+}
+
+/// Returns `text` quoted with escaped line breaks and shortened to `MAX_LABEL_CHARS` characters
+fn quote(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r").replace('\t', "\\t");
+    match escaped.char_indices().nth(MAX_LABEL_CHARS) {
+        Some((end, _)) => format!("\"{}…\"", &escaped[..end]),
+        None => format!("\"{escaped}\""),
+    }
+}
+
+/// Returns the text of `node` as written in litua syntax
+fn node_syntax(node: &tree::DocumentNode) -> String {
+    node.iter().map(|element| match element {
+        tree::DocumentElement::Text(text) => text.to_owned(),
+        tree::DocumentElement::Function(func) => tree::DocumentTree(tree::DocumentElement::Function(func.clone())).to_litua_syntax(),
+    }).collect()
+}
+
+impl Explorer {
+    /// Create an explorer of `tree` with the root call expanded and selected
+    pub fn new(tree: tree::DocumentTree) -> Self {
+        let mut explorer = Explorer { tree, expanded: HashSet::new(), rows: vec![], selected: 0 };
+        explorer.expanded.insert(NodePath::default());
+        explorer.refresh();
+        explorer
+    }
+
+    /// Returns the visible rows
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    /// Returns the index of the selected row
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Returns the selected row
+    pub fn selected_row(&self) -> &Row {
+        &self.rows[self.selected]
+    }
+
+    fn refresh(&mut self) {
+        let selected = self.rows.get(self.selected).map(|row| row.path.clone());
+        self.rows.clear();
+        let root = self.tree.0.clone();
+        self.add_rows(&root, NodePath::default(), 0);
+        if let Some(path) = selected {
+            self.select(&path);
+        }
+    }
+
+    fn add_rows(&mut self, element: &tree::DocumentElement, path: NodePath, depth: usize) {
+        let (label, children) = match element {
+            tree::DocumentElement::Text(text) => (quote(text), None),
+            tree::DocumentElement::Function(func) => {
+                let mut keys: Vec<&String> = func.args.keys().filter(|key| !tree::is_reserved_arg(key)).collect();
+                keys.sort();
+                let args: Vec<String> = keys.iter().map(|key| format!("{key}={}", quote(&node_syntax(&func.args[*key])))).collect();
+                let label = match args.is_empty() {
+                    true => format!("{{{}}}", func.call),
+                    false => format!("{{{}}} [{}]", func.call, args.join(", ")),
+                };
+                (label, Some(&func.content))
+            },
+        };
+        let expandable = children.is_some_and(|content| !content.is_empty());
+        let expanded = expandable && self.expanded.contains(&path);
+        self.rows.push(Row { path: path.clone(), depth, label, expandable });
+        if let (true, Some(content)) = (expanded, children) {
+            for (index, child) in content.iter().enumerate() {
+                let mut child_path = path.clone();
+                child_path.0.push(NodeStep::Content(index));
+                self.add_rows(child, child_path, depth + 1);
+            }
+        }
+    }
+
+    /// Select the row of `path` (if visible)
+    fn select(&mut self, path: &NodePath) {
+        if let Some(index) = self.rows.iter().position(|row| row.path == *path) {
+            self.selected = index;
+        }
+    }
+
+    /// Move the selection by `delta` rows
+    pub fn move_by(&mut self, delta: isize) {
+        self.selected = self.selected.saturating_add_signed(delta).min(self.rows.len() - 1);
+    }
+
+    /// Expand the selected call or select its first child if it is already expanded
+    pub fn expand(&mut self) {
+        let row = self.selected_row().clone();
+        if !row.expandable {
+            return;
+        }
+        if self.expanded.insert(row.path) {
+            self.refresh();
+        } else {
+            self.move_by(1);
+        }
+    }
+
+    /// Collapse the selected call or select its parent if it is already collapsed
+    pub fn collapse(&mut self) {
+        let path = self.selected_row().path.clone();
+        if self.expanded.remove(&path) && self.selected_row().expandable {
+            self.refresh();
+        } else if let Some(parent) = path.0.split_last().map(|(_, parent)| NodePath(parent.to_vec())) {
+            self.select(&parent);
+        }
+    }
+
+    /// Expand the selected call if it is collapsed and collapse it otherwise
+    pub fn toggle(&mut self) {
+        match self.expanded.contains(&self.selected_row().path) {
+            true => self.collapse(),
+            false => self.expand(),
+        }
+    }
+
+    /// Select the next call after the selected row (in document order, wrapping around)
+    /// whose name contains `query` and expand its ancestors. Returns false if there is none.
+    pub fn search(&mut self, query: &str) -> bool {
+        let mut paths = vec![];
+        collect_calls(&self.tree.0, NodePath::default(), query, &mut paths);
+        // NOTE: comparing the content indices of paths compares their positions in document order
+        let current = content_indices(&self.selected_row().path);
+        let found = match paths.iter().find(|path| content_indices(path) > current).or(paths.first()) {
+            Some(path) => path.clone(),
+            None => return false,
+        };
+        for depth in 0..found.0.len() {
+            self.expanded.insert(NodePath(found.0[..depth].to_vec()));
+        }
+        self.refresh();
+        self.select(&found);
+        true
+    }
+
+    /// Returns the lines describing the selected element: its path, call name,
+    /// span (with line and column in `src`), and arguments
+    pub fn details(&self, src: &str) -> Vec<String> {
+        let path = &self.selected_row().path;
+        let steps: Vec<String> = path.0.iter().map(|step| match step {
+            NodeStep::Content(index) => index.to_string(),
+            NodeStep::Arg(key, index) => format!("{key}:{index}"),
+        }).collect();
+        let mut lines = vec![format!("path: /{}", steps.join("/"))];
+        match self.tree.get_path(path) {
+            Some(tree::DocumentElement::Function(func)) => {
+                lines.push(format!("call: {}", func.call));
+                if let Some(span) = func.span() {
+                    let line_index = LineIndex::new(src);
+                    let (start_line, start_column) = line_index.line_column(src, span.start);
+                    let (end_line, end_column) = line_index.line_column(src, span.end);
+                    lines.push(format!("span: bytes {}..{} (line {start_line}, column {start_column} to line {end_line}, column {end_column})", span.start, span.end));
+                }
+                let mut keys: Vec<&String> = func.args.keys().filter(|key| !tree::is_reserved_arg(key)).collect();
+                keys.sort();
+                for key in keys {
+                    lines.push(format!("arg {key} = {}", node_syntax(&func.args[key])));
+                }
+                lines.push(format!("content: {} element{}", func.content.len(), if func.content.len() == 1 { "" } else { "s" }));
+            },
+            Some(tree::DocumentElement::Text(text)) => {
+                let count = text.chars().count();
+                lines.push(format!("text: {count} character{}", if count == 1 { "" } else { "s" }));
+                lines.extend(text.lines().map(str::to_owned));
+            },
+            None => {},
+        }
+        lines
+    }
+
+    /// Returns a tree with the root call of the document (and its arguments)
+    /// containing only the selected element, so transforming it yields the
+    /// output of the selected subtree
+    pub fn subtree(&self) -> tree::DocumentTree {
+        let selected = self.tree.get_path(&self.selected_row().path).cloned().unwrap_or_else(tree::DocumentFunction::empty_element);
+        match (&self.tree.0, self.selected_row().path.0.is_empty()) {
+            (_, true) => self.tree.clone(),
+            (tree::DocumentElement::Function(root), false) => {
+                tree::DocumentTree(tree::DocumentElement::Function(tree::DocumentFunction { call: root.call.clone(), args: root.args.clone(), content: vec![selected] }))
+            },
+            (tree::DocumentElement::Text(_), false) => tree::DocumentTree(selected),
+        }
+    }
+}
+
+/// Returns the content indices of the steps of `path`
+fn content_indices(path: &NodePath) -> Vec<usize> {
+    path.0.iter().map(|step| match step {
+        NodeStep::Content(index) | NodeStep::Arg(_, index) => *index,
+    }).collect()
+}
+
+/// Collect the paths of the calls in the content of `element` (in document order) whose name contains `query`
+fn collect_calls(element: &tree::DocumentElement, path: NodePath, query: &str, paths: &mut Vec<NodePath>) {
+    if let tree::DocumentElement::Function(func) = element {
+        if func.call.contains(query) {
+            paths.push(path.clone());
+        }
+        for (index, child) in func.content.iter().enumerate() {
+            let mut child_path = path.clone();
+            child_path.0.push(NodeStep::Content(index));
+            collect_calls(child, child_path, query, paths);
+        }
+    }
+}
+
+#[cfg(feature = "explore")]
+mod terminal {
+    use std::io;
+    use std::io::Write;
+
+    use crossterm::{cursor, event, execute, queue, style, terminal};
+    use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+
+    use super::Explorer;
+    use crate::tree;
+
+    const HELP: &str = "↑↓ move  → expand  ← collapse  space toggle  / search  n next  p preview  q quit";
+
+    /// What the bottom pane shows
+    enum Pane {
+        Details,
+        Preview(Vec<String>),
+        Search(String),
+    }
+
+    /// Returns the first `width` characters of `line`
+    fn truncate(line: &str, width: usize) -> String {
+        line.chars().filter(|chr| !chr.is_control()).take(width).collect()
+    }
+
+    fn draw<W: Write>(out: &mut W, explorer: &Explorer, src: &str, pane: &Pane, status: &str, offset: &mut usize) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, (height as usize).max(4));
+        let tree_height = (height * 3 / 5).max(1);
+        if explorer.selected() < *offset {
+            *offset = explorer.selected();
+        } else if explorer.selected() >= *offset + tree_height {
+            *offset = explorer.selected() + 1 - tree_height;
+        }
+
+        queue!(out, terminal::Clear(terminal::ClearType::All))?;
+        for (line, (index, row)) in explorer.rows().iter().enumerate().skip(*offset).take(tree_height).enumerate() {
+            let marker = match (row.expandable, explorer.rows().get(index + 1).is_some_and(|next| next.depth > row.depth)) {
+                (true, true) => "▾ ",
+                (true, false) => "▸ ",
+                (false, _) => "  ",
+            };
+            let text = truncate(&format!("{}{marker}{}", "  ".repeat(row.depth), row.label), width);
+            queue!(out, cursor::MoveTo(0, line as u16))?;
+            if index == explorer.selected() {
+                queue!(out, style::SetAttribute(style::Attribute::Reverse), style::Print(text), style::SetAttribute(style::Attribute::Reset))?;
+            } else {
+                queue!(out, style::Print(text))?;
+            }
+        }
+
+        let status = match pane {
+            Pane::Search(query) => format!("/{query}"),
+            _ if !status.is_empty() => status.to_owned(),
+            _ => HELP.to_owned(),
+        };
+        queue!(out, cursor::MoveTo(0, tree_height as u16),
+            style::SetAttribute(style::Attribute::Reverse), style::Print(format!("{:width$}", truncate(&status, width))), style::SetAttribute(style::Attribute::Reset))?;
+        let lines = match pane {
+            Pane::Preview(lines) => lines.clone(),
+            _ => explorer.details(src),
+        };
+        for (line, text) in lines.iter().take(height - tree_height - 1).enumerate() {
+            queue!(out, cursor::MoveTo(0, (tree_height + 1 + line) as u16), style::Print(truncate(text, width)))?;
+        }
+        out.flush()
+    }
+
+    fn event_loop<W: Write, F: FnMut(&tree::DocumentTree) -> Result<String, String>>(out: &mut W, mut explorer: Explorer, src: &str, mut preview: F) -> io::Result<()> {
+        let mut pane = Pane::Details;
+        let mut query = String::new();
+        let mut status = String::new();
+        let mut offset = 0;
+        loop {
+            draw(out, &explorer, src, &pane, &status, &mut offset)?;
+            let key = match event::read()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => key,
+                _ => continue,
+            };
+            status.clear();
+            if let Pane::Search(input) = &mut pane {
+                match key.code {
+                    KeyCode::Enter => {
+                        query = input.clone();
+                        pane = Pane::Details;
+                        if !explorer.search(&query) {
+                            status = format!("no call matching '{query}'");
+                        }
+                    },
+                    KeyCode::Esc => pane = Pane::Details,
+                    KeyCode::Backspace => { input.pop(); },
+                    KeyCode::Char(chr) => input.push(chr),
+                    _ => {},
+                }
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => explorer.move_by(-1),
+                KeyCode::Down | KeyCode::Char('j') => explorer.move_by(1),
+                KeyCode::PageUp => explorer.move_by(-10),
+                KeyCode::PageDown => explorer.move_by(10),
+                KeyCode::Right | KeyCode::Char('l') | KeyCode::Enter => explorer.expand(),
+                KeyCode::Left | KeyCode::Char('h') => explorer.collapse(),
+                KeyCode::Char(' ') => explorer.toggle(),
+                KeyCode::Char('/') => {
+                    pane = Pane::Search(String::new());
+                    continue;
+                },
+                // NOTE: the guard searches, so a successful search falls through to the last arm
+                KeyCode::Char('n') if !query.is_empty() && !explorer.search(&query) => {
+                    status = format!("no call matching '{query}'");
+                },
+                KeyCode::Char('p') => {
+                    let (title, result) = match preview(&explorer.subtree()) {
+                        Ok(output) => ("transform result:", output),
+                        Err(e) => ("transform failed:", e),
+                    };
+                    pane = Pane::Preview(std::iter::once(title.to_owned()).chain(result.lines().map(str::to_owned)).collect());
+                    continue;
+                },
+                _ => {},
+            }
+            pane = Pane::Details;
+        }
+    }
+
+    /// Run the explorer in the terminal until the user quits. `src` is the source
+    /// of the document and `preview` returns the transform result of a subtree.
+    pub fn run<F: FnMut(&tree::DocumentTree) -> Result<String, String>>(explorer: Explorer, src: &str, preview: F) -> io::Result<()> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        let result = event_loop(&mut out, explorer, src, preview);
+        // NOTE: restore the terminal even if the loop failed
+        let _ = execute!(out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+        result
+    }
+}
+
+#[cfg(feature = "explore")]
+pub use terminal::run;
+
+/// Run the explorer in the terminal, which requires feature `explore`
+#[cfg(not(feature = "explore"))]
+pub fn run<F: FnMut(&tree::DocumentTree) -> Result<String, String>>(_explorer: Explorer, _src: &str, _preview: F) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "cannot explore documents, because litua was built without feature 'explore'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn navigate_search_and_extract_subtree() {
+        let src = "{h1 Title}\n{section[id=intro] {p first {em word}}\n{p second}}";
+        let tree = crate::pipeline::Pipeline::new().parse(std::path::Path::new("doc.lit"), src).expect("valid document");
+        let mut explorer = Explorer::new(tree);
+        let labels = |explorer: &Explorer| explorer.rows().iter().map(|row| row.label.clone()).collect::<Vec<_>>();
+        assert_eq!(labels(&explorer), ["{document} [filepath=\"doc.lit\"]", "{h1}", "\"\\n\"", "{section} [id=\"intro\"]"]);
+
+        assert!(explorer.search("em"));
+        assert_eq!(explorer.selected_row().label, "{em}");
+        assert_eq!(explorer.selected_row().depth, 3);
+        assert_eq!(explorer.details(src)[1..3], ["call: em".to_owned(), "span: bytes 39..48 (line 2, column 29 to line 2, column 38)".to_owned()]);
+        let subtree = explorer.subtree();
+        assert!(matches!(&subtree.0, tree::DocumentElement::Function(root) if root.call == "document" && root.text_arg("filepath") == Some("doc.lit")));
+        assert_eq!(subtree.to_litua_syntax(), "{em word}");
+
+        explorer.collapse();
+        explorer.collapse();
+        assert_eq!(explorer.selected_row().label, "{p}");
+        explorer.collapse();
+        assert_eq!((explorer.selected(), labels(&explorer).len()), (3, 7));
+        explorer.toggle();
+        assert_eq!(labels(&explorer).len(), 4);
+        assert!(!explorer.search("table"));
+    }
+}
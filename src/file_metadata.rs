@@ -0,0 +1,118 @@
+//! Metadata of the source file provided as arguments of the root call
+//!
+//! Besides `filepath`, the root call gets the arguments `filesize` (in bytes),
+//! `modified` (UTC timestamp of the last modification), `content-hash` (64-bit
+//! FNV-1a hash of the content as 16 hex digits), and `git-commit` (the commit
+//! checked out in the git repository containing the file). The commit is read
+//! from the `.git` directory, so git does not need to be installed.
+
+use std::fs;
+use std::path;
+
+use crate::datetime;
+use crate::tree;
+
+/// `FileMetadata` is the metadata of a source file
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct FileMetadata {
+    pub size: u64,
+    /// seconds since the UNIX epoch (if the platform provides it)
+    pub modified: Option<i64>,
+    pub content_hash: u64,
+    /// hash of the commit checked out (if the file is part of a git repository)
+    pub git_commit: Option<String>,
+}
+
+impl FileMetadata {
+    /// Determine the metadata of the file at `filepath` with content `content`
+    pub fn of_file(filepath: &path::Path, content: &[u8]) -> Self {
+        let modified = fs::metadata(filepath).and_then(|metadata| metadata.modified()).ok();
+        let dir = match filepath.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => path::Path::new("."),
+        };
+        Self {
+            size: content.len() as u64,
+            modified: modified.map(datetime::unix_timestamp),
+            content_hash: tree::stable_hash(content),
+            git_commit: fs::canonicalize(dir).ok().and_then(|dir| git_commit(&dir)),
+        }
+    }
+
+    /// Returns the arguments of the root call as (key, value) pairs
+    pub fn root_args(&self) -> Vec<(String, String)> {
+        let mut args = vec![
+            ("filesize".to_owned(), self.size.to_string()),
+            ("content-hash".to_owned(), format!("{:016x}", self.content_hash)),
+        ];
+        if let Some(modified) = self.modified {
+            args.push(("modified".to_owned(), datetime::iso8601(modified)));
+        }
+        if let Some(commit) = &self.git_commit {
+            args.push(("git-commit".to_owned(), commit.to_owned()));
+        }
+        args
+    }
+}
+
+/// Returns the hash of the commit checked out in the git repository containing directory `dir`
+pub fn git_commit(dir: &path::Path) -> Option<String> {
+    let git_dir = dir.ancestors().map(|ancestor| ancestor.join(".git")).find(|git_dir| git_dir.exists())?;
+    // NOTE: in worktrees and submodules, `.git` is a file referring to the actual git directory
+    let git_dir = match fs::read_to_string(&git_dir) {
+        Ok(content) => git_dir.parent()?.join(content.trim().strip_prefix("gitdir:")?.trim()),
+        Err(_) => git_dir,
+    };
+
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let reference = match head.trim().strip_prefix("ref:") {
+        Some(reference) => reference.trim().to_owned(),
+        None => return Some(head.trim().to_owned()).filter(|commit| is_commit_hash(commit)),
+    };
+    // NOTE: linked worktrees store shared refs in the common directory
+    let common_dir = fs::read_to_string(git_dir.join("commondir")).ok()
+        .map(|common| git_dir.join(common.trim()))
+        .unwrap_or_else(|| git_dir.clone());
+
+    for dir in [&git_dir, &common_dir] {
+        if let Ok(commit) = fs::read_to_string(dir.join(&reference)) {
+            return Some(commit.trim().to_owned()).filter(|commit| is_commit_hash(commit));
+        }
+    }
+    let packed_refs = fs::read_to_string(common_dir.join("packed-refs")).ok()?;
+    packed_refs.lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(_, name)| *name == reference)
+        .map(|(commit, _)| commit.to_owned())
+        .filter(|commit| is_commit_hash(commit))
+}
+
+fn is_commit_hash(text: &str) -> bool {
+    matches!(text.len(), 40 | 64) && text.chars().all(|chr| chr.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_of_file_in_git_repository() -> std::io::Result<()> {
+        let repo = std::env::temp_dir().join(format!("litua-file-metadata-{}", std::process::id()));
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        fs::create_dir_all(repo.join(".git/refs/heads"))?;
+        fs::create_dir_all(repo.join("docs"))?;
+        fs::write(repo.join(".git/HEAD"), "ref: refs/heads/main\n")?;
+        fs::write(repo.join(".git/packed-refs"), format!("# pack-refs with: peeled\n{commit} refs/heads/main\n"))?;
+        fs::write(repo.join("docs/doc.lit"), "{p x}")?;
+
+        let metadata = FileMetadata::of_file(&repo.join("docs/doc.lit"), b"{p x}");
+        let args = metadata.root_args();
+        fs::remove_dir_all(&repo)?;
+
+        assert_eq!(metadata.git_commit.as_deref(), Some(commit));
+        assert_eq!(args[0], ("filesize".to_owned(), "5".to_owned()));
+        assert_eq!(args[1].1.len(), 16);
+        assert!(args.iter().any(|(key, value)| key == "modified" && value.ends_with('Z')));
+        Ok(())
+    }
+}
@@ -0,0 +1,336 @@
+//! Formatter representing litua text documents in a canonical way
+//!
+//! The formatter keeps the header lines and the text of a document, but
+//! sorts the arguments of every call by key and replaces the whitespace
+//! separating a call name (or its arguments) from its content by a single
+//! space unless it contains a line break.
+//!
+//! `FormatOptions` (read from section `[fmt]` of a `litua.toml` file) adjust
+//! this representation: lines of text content can be wrapped at a maximum
+//! width, raw strings can use a preferred delimiter length, and arguments
+//! can keep their order of the source document.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path;
+
+use crate::errors;
+use crate::grammar;
+use crate::header;
+use crate::lexer;
+use crate::parser;
+use crate::pipeline;
+use crate::tree;
+
+/// Name of the configuration file looked up by `litua fmt`
+pub const CONFIG_FILE: &str = "litua.toml";
+
+/// `ArgOrder` determines the order of the arguments of a call
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum ArgOrder {
+    /// arguments are sorted by key
+    Sorted,
+    /// arguments keep their order of the source document
+    Source,
+}
+
+/// `FormatOptions` configure the formatter
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct FormatOptions {
+    /// maximum number of characters per line if `wrap` is set
+    pub max_width: usize,
+    /// break lines of text content longer than `max_width` at spaces
+    /// (except for raw strings, `pre` calls, and calls of `preserve-whitespace` and `verbatim` directives)
+    pub wrap: bool,
+    /// preferred number of `<` delimiting raw strings (more if the raw string requires it)
+    pub raw_delimiter_length: Option<usize>,
+    /// order of the arguments of a call
+    pub arg_order: ArgOrder,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self { max_width: 80, wrap: false, raw_delimiter_length: None, arg_order: ArgOrder::Sorted }
+    }
+}
+
+impl FormatOptions {
+    /// Read the options from section `[fmt]` of the TOML document `src`
+    /// (with keys `max-width`, `wrap`, `raw-delimiter-length`, and `arg-order`)
+    pub fn from_toml(src: &str) -> Result<Self, String> {
+        let table = src.parse::<toml::Table>().map_err(|e| e.message().to_owned())?;
+        let mut options = Self::default();
+        let section = match table.get("fmt") {
+            Some(toml::Value::Table(section)) => section,
+            Some(_) => return Err("expected a table for key 'fmt'".to_owned()),
+            None => return Ok(options),
+        };
+
+        let max_raw = usize::from(lexer::MAX_RAW_DELIMITER_LENGTH);
+        for (key, value) in section.iter() {
+            match (key.as_str(), value) {
+                ("max-width", toml::Value::Integer(width)) if *width > 0 => options.max_width = *width as usize,
+                ("wrap", toml::Value::Boolean(wrap)) => options.wrap = *wrap,
+                ("raw-delimiter-length", toml::Value::Integer(length)) if (1..=max_raw as i64).contains(length) => {
+                    options.raw_delimiter_length = Some(*length as usize);
+                },
+                ("arg-order", toml::Value::String(order)) if order == "sorted" => options.arg_order = ArgOrder::Sorted,
+                ("arg-order", toml::Value::String(order)) if order == "source" => options.arg_order = ArgOrder::Source,
+                ("max-width", _) => return Err("fmt.max-width expects a positive integer".to_owned()),
+                ("wrap", _) => return Err("fmt.wrap expects a boolean".to_owned()),
+                ("raw-delimiter-length", _) => return Err(format!("fmt.raw-delimiter-length expects an integer between 1 and {max_raw}")),
+                ("arg-order", _) => return Err("fmt.arg-order expects 'sorted' or 'source'".to_owned()),
+                (key, _) => return Err(format!("unknown key 'fmt.{key}'")),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Returns the path of the configuration file `CONFIG_FILE` in directory `dir`
+/// or its closest ancestor containing one
+pub fn find_config(dir: &path::Path) -> Option<path::PathBuf> {
+    dir.ancestors()
+        .map(|ancestor| ancestor.join(CONFIG_FILE))
+        .find(|filepath| filepath.is_file())
+}
+
+/// Returns the text document `src` in canonical representation
+pub fn format_document(src: &str) -> Result<String, errors::Error> {
+    format_document_with(src, &FormatOptions::default())
+}
+
+/// Returns the text document `src` in canonical representation adjusted by `options`
+pub fn format_document_with(src: &str, options: &FormatOptions) -> Result<String, errors::Error> {
+    let directives = header::directives(src)?;
+    let verbatim = directives.iter().filter(|directive| directive.key == "verbatim").map(|directive| directive.value.as_str());
+    let builder = verbatim.clone().fold(parser::ParserBuilder::new(), |builder, call| builder.verbatim(call))
+        .lossless(true)
+        .normalize_names(false)
+        .merge_texts(true);
+    let pipeline = pipeline::Pipeline::for_document(src)?.parser(builder);
+    let mut tree = pipeline.parse(path::Path::new(""), src)?;
+    normalize_whitespace(&mut tree.0);
+
+    let mut out = src[..header::header_length(src)].to_owned();
+    if *options == FormatOptions::default() {
+        out.push_str(&tree.to_litua_syntax_with(pipeline.syntax()));
+        return Ok(out);
+    }
+
+    let mut writer = Writer {
+        options,
+        arg_orders: match options.arg_order {
+            ArgOrder::Sorted => HashMap::new(),
+            ArgOrder::Source => arg_orders(src, &pipeline),
+        },
+        preserved: directives.iter()
+            .filter(|directive| directive.key == "preserve-whitespace")
+            .map(|directive| directive.value.as_str())
+            .chain(verbatim)
+            .chain(["pre"])
+            .map(str::to_owned)
+            .collect(),
+        syntax: pipeline.syntax(),
+        out,
+    };
+    match &tree.0 {
+        tree::DocumentElement::Function(root) => root.content.iter().for_each(|element| writer.write_element(element, true)),
+        text => writer.write_element(text, true),
+    }
+    Ok(writer.out)
+}
+
+/// Returns the argument keys in source order for every call of `src` lexed by `pipeline`
+/// (identified by the byte offset where the call starts)
+fn arg_orders(src: &str, pipeline: &pipeline::Pipeline) -> HashMap<usize, Vec<String>> {
+    let mut orders = HashMap::new();
+    let mut calls: Vec<(usize, Vec<String>)> = vec![];
+    for token in pipeline.tokens(src).flatten() {
+        match token {
+            lexer::Token::BeginFunction(byte_offset) => calls.push((byte_offset, vec![])),
+            lexer::Token::ArgKey(range) => if let Some((_, keys)) = calls.last_mut() {
+                keys.push(src[range].to_owned());
+            },
+            lexer::Token::EndFunction(_) => if let Some((byte_offset, keys)) = calls.pop() {
+                orders.insert(byte_offset, keys);
+            },
+            _ => {},
+        }
+    }
+    orders
+}
+
+/// Returns the number of closing delimiters `close_raw` required to delimit
+/// raw string `func` given the preferred number `preferred`
+fn raw_delimiter_length(func: &tree::DocumentFunction, preferred: usize, close_raw: char) -> usize {
+    let mut raw = func.text_arg(tree::WHITESPACE_ARG).unwrap_or(" ").to_owned();
+    for element in func.content.iter() {
+        if let tree::DocumentElement::Text(text) = element {
+            raw.push_str(text);
+        }
+    }
+    preferred.max(grammar::raw_delimiter_length(&raw, close_raw))
+}
+
+/// `Writer` represents a tree in litua syntax according to `FormatOptions`
+struct Writer<'o> {
+    options: &'o FormatOptions,
+    arg_orders: HashMap<usize, Vec<String>>,
+    /// calls whose text content must not be wrapped
+    preserved: HashSet<String>,
+    /// syntax configuration determining the raw string delimiters
+    syntax: &'o lexer::LexerOptions,
+    out: String,
+}
+
+impl<'o> Writer<'o> {
+    /// Number of characters of the last line written
+    fn column(&self) -> usize {
+        let line_start = self.out.rfind('\n').map_or(0, |i| i + 1);
+        self.out[line_start..].chars().count()
+    }
+
+    fn write_element(&mut self, element: &tree::DocumentElement, wrap: bool) {
+        match element {
+            tree::DocumentElement::Function(func) => self.write_call(func, wrap),
+            tree::DocumentElement::Text(text) if wrap && self.options.wrap => self.write_wrapped(text),
+            tree::DocumentElement::Text(text) => self.out.push_str(text),
+        }
+    }
+
+    /// Write `text` replacing spaces by line breaks where the following word exceeds `max_width`
+    fn write_wrapped(&mut self, text: &str) {
+        let mut column = self.column();
+        for (i, word) in text.split(' ').enumerate() {
+            let word_width = word.split('\n').next().unwrap_or_default().chars().count();
+            if i > 0 {
+                if column > 0 && column + 1 + word_width > self.options.max_width {
+                    self.out.push('\n');
+                    column = 0;
+                } else {
+                    self.out.push(' ');
+                    column += 1;
+                }
+            }
+            self.out.push_str(word);
+            column = match word.rfind('\n') {
+                Some(i) => word[i + 1..].chars().count(),
+                None => column + word_width,
+            };
+        }
+    }
+
+    fn write_call(&mut self, func: &tree::DocumentFunction, wrap: bool) {
+        let whitespace = func.text_arg(tree::WHITESPACE_ARG).unwrap_or(" ");
+
+        if func.call.starts_with(lexer::OPEN_RAW) {
+            let length = match self.options.raw_delimiter_length {
+                Some(preferred) => raw_delimiter_length(func, preferred, self.syntax.close_raw),
+                None => func.call.chars().count(),
+            };
+            let length = if length > usize::from(lexer::MAX_RAW_DELIMITER_LENGTH) { func.call.chars().count() } else { length };
+            self.out.push(lexer::OPEN_FUNCTION);
+            self.out.extend(std::iter::repeat_n(self.syntax.open_raw, length));
+            self.out.push_str(func.text_arg(tree::LANGUAGE_ARG).unwrap_or_default());
+            self.out.push_str(whitespace);
+            for element in func.content.iter() {
+                self.write_element(element, false);
+            }
+            self.out.push_str(func.text_arg(tree::WHITESPACE_AFTER_ARG).unwrap_or(" "));
+            self.out.extend(std::iter::repeat_n(self.syntax.close_raw, length));
+            self.out.push(lexer::CLOSE_FUNCTION);
+            return;
+        }
+
+        self.out.push(lexer::OPEN_FUNCTION);
+        self.out.push_str(&func.call);
+
+        let mut keys: Vec<&String> = func.args.keys().filter(|key| !tree::is_reserved_arg(key)).collect();
+        keys.sort();
+        if let Some(order) = func.span().and_then(|span| self.arg_orders.get(&span.start)) {
+            keys.sort_by_key(|key| order.iter().position(|k| k == *key).unwrap_or(order.len()));
+        }
+        for key in keys {
+            self.out.push(lexer::OPEN_ARG);
+            self.out.push_str(key);
+            if let Some(arg_type) = func.arg_type(key) {
+                self.out.push(tree::TYPE_SEPARATOR);
+                self.out.push_str(arg_type.name());
+            }
+            self.out.push(lexer::ASSIGN);
+            for element in func.args[key].iter() {
+                self.write_element(element, false);
+            }
+            self.out.push(lexer::CLOSE_ARG);
+        }
+
+        let wrap = wrap && !self.preserved.contains(&func.call);
+        if !func.content.is_empty() || func.text_arg(tree::WHITESPACE_ARG).is_some() {
+            self.out.push_str(whitespace);
+            for element in func.content.iter() {
+                self.write_element(element, wrap);
+            }
+        }
+        self.out.push_str(func.text_arg(tree::WHITESPACE_AFTER_ARG).unwrap_or(""));
+        self.out.push(lexer::CLOSE_FUNCTION);
+    }
+}
+
+/// Replace `=whitespace` of all calls except raw strings by a single space
+/// unless it contains a line break
+fn normalize_whitespace(element: &mut tree::DocumentElement) {
+    let func = match element {
+        tree::DocumentElement::Function(func) => func,
+        tree::DocumentElement::Text(_) => return,
+    };
+    if func.call.starts_with(lexer::OPEN_RAW) {
+        return;
+    }
+
+    if let Some(whitespace) = func.args.get_mut(tree::WHITESPACE_ARG) {
+        if let [tree::DocumentElement::Text(text)] = whitespace.as_mut_slice() {
+            if !text.contains('\n') {
+                *text = " ".to_owned();
+            }
+        }
+    }
+    for (key, value) in func.args.iter_mut() {
+        if !tree::is_reserved_arg(key) {
+            value.iter_mut().for_each(normalize_whitespace);
+        }
+    }
+    func.content.iter_mut().for_each(normalize_whitespace);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_representation() -> Result<(), errors::Error> {
+        let src = "%!litua whitespace-texts=keep\n{p[z=1][a={b  x}]\t\tsome {i  text }}\n{pre\n  keep}{<  raw  >}";
+        let formatted = format_document(src)?;
+        assert_eq!(formatted, "%!litua whitespace-texts=keep\n{p[a={b x}][z=1] some {i text }}\n{pre\n  keep}{<  raw  >}");
+        assert_eq!(format_document(&formatted)?, formatted);
+        assert!(format_document("{p").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn format_with_options() -> Result<(), errors::Error> {
+        let options = FormatOptions::from_toml("[fmt]\nmax-width = 12\nwrap = true\nraw-delimiter-length = 2\narg-order = \"source\"\n")
+            .expect("valid configuration");
+        assert_eq!(options, FormatOptions { max_width: 12, wrap: true, raw_delimiter_length: Some(2), arg_order: ArgOrder::Source });
+
+        let src = "%!litua preserve-whitespace=verse\n{p[z=1][a=2] one two three four} {verse five six seven eight}\n{<<<< a >> b >>>>}{< c >}";
+        let formatted = format_document_with(src, &options)?;
+        assert_eq!(formatted, "%!litua preserve-whitespace=verse\n\
+            {p[z=1][a=2] one\ntwo three\nfour} {verse five six seven eight}\n{<<< a >> b >>>}{<< c >>}");
+        assert_eq!(format_document_with(&formatted, &options)?, formatted);
+        assert!(FormatOptions::from_toml("[fmt]\narg-order = \"random\"").is_err());
+        assert!(FormatOptions::from_toml("[fmt]\nwidth = 3").is_err());
+        assert_eq!(FormatOptions::from_toml("[other]\nx = 1"), Ok(FormatOptions::default()));
+        Ok(())
+    }
+}
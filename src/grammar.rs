@@ -0,0 +1,473 @@
+//! Grammar descriptions of the litua input syntax for other tools
+//!
+//! Besides grammars for other tools, this module escapes texts for generated
+//! litua source code according to a syntax configuration (see `lexer::LexerOptions`).
+
+use crate::lexer;
+
+/// Call representing `lexer::OPEN_FUNCTION` in text
+pub const ESCAPED_OPEN_FUNCTION: &str = "{left-curly-brace}";
+/// Call representing `lexer::CLOSE_FUNCTION` in text
+pub const ESCAPED_CLOSE_FUNCTION: &str = "{right-curly-brace}";
+
+/// Returns the minimum number of delimiters of a raw string with text `text` if the
+/// closing delimiter is `close_raw`: whitespace followed by as many `close_raw`
+/// as the raw string has opening delimiters terminates it
+pub fn raw_delimiter_length(text: &str, close_raw: char) -> usize {
+    let mut longest = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(chr) = chars.next() {
+        if chr.is_whitespace() {
+            let mut run = 0;
+            while chars.next_if_eq(&close_raw).is_some() {
+                run += 1;
+            }
+            longest = longest.max(run);
+        }
+    }
+    longest + 1
+}
+
+/// Represent `text` as raw string in the syntax configured by `options`
+pub fn raw_string(text: &str, options: &lexer::LexerOptions) -> Result<String, String> {
+    let length = raw_delimiter_length(text, options.close_raw);
+    if length > usize::from(lexer::MAX_RAW_DELIMITER_LENGTH) {
+        return Err(format!("text requires more than {} raw string delimiters", lexer::MAX_RAW_DELIMITER_LENGTH));
+    }
+    if options.trim_raw_whitespace && text.ends_with(char::is_whitespace) {
+        return Err("raw string text must not end with whitespace if trailing whitespace is trimmed".to_owned());
+    }
+    let open = options.open_raw.to_string().repeat(length);
+    let close = options.close_raw.to_string().repeat(length);
+    Ok(format!("{}{open} {text} {close}{}", lexer::OPEN_FUNCTION, lexer::CLOSE_FUNCTION))
+}
+
+/// Represent `text` as text content in the syntax configured by `options`.
+/// Call delimiters are replaced by `ESCAPED_OPEN_FUNCTION` and `ESCAPED_CLOSE_FUNCTION`,
+/// and `lexer::LINE_CALL` at the start of a line (or of `text`) by a raw string
+/// if line calls are enabled.
+pub fn escape_text(text: &str, options: &lexer::LexerOptions) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut line_start = true;
+    for chr in text.chars() {
+        match chr {
+            lexer::OPEN_FUNCTION => escaped.push_str(ESCAPED_OPEN_FUNCTION),
+            lexer::CLOSE_FUNCTION => escaped.push_str(ESCAPED_CLOSE_FUNCTION),
+            lexer::LINE_CALL if options.line_calls && line_start => {
+                escaped.push_str(&raw_string(&chr.to_string(), options).unwrap_or_default());
+            },
+            _ => escaped.push(chr),
+        }
+        line_start = matches!(chr, '\n' | '\r') || (line_start && matches!(chr, ' ' | '\t'));
+    }
+    escaped
+}
+
+/// Represent `text` as argument value in the syntax configured by `options`.
+/// `lexer::OPEN_FUNCTION` is replaced by `ESCAPED_OPEN_FUNCTION` and
+/// `lexer::CLOSE_ARG` (which has no escape call) by a raw string.
+pub fn escape_arg(text: &str, options: &lexer::LexerOptions) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for chr in text.chars() {
+        match chr {
+            lexer::OPEN_FUNCTION => escaped.push_str(ESCAPED_OPEN_FUNCTION),
+            lexer::CLOSE_ARG => escaped.push_str(&raw_string(&chr.to_string(), options).unwrap_or_default()),
+            _ => escaped.push(chr),
+        }
+    }
+    escaped
+}
+
+/// `TreeSitterGrammar` contains the files of a tree-sitter grammar
+/// for litua text documents. Raw strings require counting delimiters
+/// and thus are recognized by an external scanner written in C.
+#[derive(Clone,Debug,PartialEq)]
+pub struct TreeSitterGrammar {
+    /// content of `grammar.js`
+    pub grammar_js: String,
+    /// content of `src/scanner.c`
+    pub scanner_c: String,
+    /// content of `queries/highlights.scm`
+    pub highlights_scm: String,
+}
+
+impl TreeSitterGrammar {
+    /// Returns the list of (relative filepath, file content) pairs
+    /// in the layout expected by the tree-sitter CLI
+    pub fn files(&self) -> Vec<(&'static str, &str)> {
+        vec![
+            ("grammar.js", &self.grammar_js),
+            ("src/scanner.c", &self.scanner_c),
+            ("queries/highlights.scm", &self.highlights_scm),
+        ]
+    }
+}
+
+/// Represent `chr` as JavaScript string literal
+fn js_string(chr: char) -> String {
+    match chr {
+        '\'' | '\\' => format!("'\\{chr}'"),
+        _ => format!("'{chr}'"),
+    }
+}
+
+/// Represent `chr` as string literal in a tree-sitter query
+fn query_string(chr: char) -> String {
+    match chr {
+        '"' | '\\' => format!("\"\\{chr}\""),
+        _ => format!("\"{chr}\""),
+    }
+}
+
+/// Represent `chr` as member of a character class in a JavaScript regex
+fn regex_class_member(chr: char) -> String {
+    match chr {
+        '\\' | ']' | '[' | '^' | '-' | '/' => format!("\\{chr}"),
+        _ => chr.to_string(),
+    }
+}
+
+/// Represent `chr` as C character literal
+fn c_char(chr: char) -> String {
+    match chr {
+        '\'' | '\\' => format!("'\\{chr}'"),
+        _ if chr.is_ascii() => format!("'{chr}'"),
+        _ => format!("0x{:X}", chr as u32),
+    }
+}
+
+/// Generate a tree-sitter grammar recognizing the same syntax as `lexer::Lexer`
+/// configured by `options`. Header lines and line calls are recognized by the
+/// external scanner if `options.skip_header` and `options.line_calls` are set.
+/// `options.trim_raw_whitespace` only affects the text of raw strings and thus
+/// does not change the grammar.
+pub fn tree_sitter(options: &lexer::LexerOptions) -> TreeSitterGrammar {
+    let open_function = lexer::OPEN_FUNCTION;
+    let close_function = lexer::CLOSE_FUNCTION;
+    let open_arg = lexer::OPEN_ARG;
+    let close_arg = lexer::CLOSE_ARG;
+    let assign = lexer::ASSIGN;
+    let open_raw = options.open_raw;
+    let close_raw = options.close_raw;
+
+    let of = regex_class_member(open_function);
+    let cf = regex_class_member(close_function);
+    let oa = regex_class_member(open_arg);
+    let ca = regex_class_member(close_arg);
+    let asg = regex_class_member(assign);
+    let call_name = match options.call_names {
+        lexer::CallNamePolicy::Identifier => {
+            let punctuation: String = lexer::CALL_NAME_PUNCTUATION.chars().map(regex_class_member).collect();
+            format!(r"[\p{{L}}\p{{N}}\p{{M}}{punctuation}]+")
+        },
+        lexer::CallNamePolicy::Permissive => format!(r"[^\s{cf}{oa}]+"),
+    };
+
+    // NOTE: the order of external tokens must match `enum TokenType` of the scanner
+    let mut externals = vec!["$.raw_string"];
+    let mut token_types = vec!["RAW_STRING"];
+    let mut elements = vec!["$.text", "$.call", "$.raw_string"];
+    let mut extra_rules = String::new();
+    let mut extra_highlights = String::new();
+    let text = match options.line_calls {
+        true => format!("[^{of}{cf}\\r\\n]+"),
+        false => format!("[^{of}{cf}]+"),
+    };
+    if options.line_calls {
+        externals.push("$.line_call_start");
+        token_types.push("LINE_CALL_START");
+        elements.extend(["$.line_call", "$._newline"]);
+        extra_rules.push_str(r"
+    // a line call ends at the end of the line, the line terminator is text of the enclosing content
+    line_call: $ => prec.right(seq(
+      $.line_call_start,
+      field('name', $.call_name),
+      repeat($.argument),
+      optional(seq(token(prec(1, /[ \t]+/)), optional($.line_content))),
+    )),
+
+    line_content: $ => prec.right(repeat1(choice($.text, $.call, $.raw_string))),
+
+    _newline: $ => /\r?\n|\r/,
+");
+        extra_highlights.push_str("(line_call_start) @punctuation.special\n");
+    }
+    let document = match options.skip_header {
+        true => {
+            externals.push("$.header");
+            token_types.push("HEADER");
+            extra_highlights.push_str("(header) @comment\n");
+            format!("seq(optional($.header), repeat(choice({})))", elements.join(", "))
+        },
+        false => format!("repeat(choice({}))", elements.join(", ")),
+    };
+
+    let grammar_js = format!(r#"// tree-sitter grammar for litua text documents
+// NOTE: generated by `litua grammar --format tree-sitter`, do not edit
+
+module.exports = grammar({{
+  name: 'litua',
+
+  externals: $ => [{externals}],
+
+  // NOTE: whitespace is significant in litua, so there are no extras
+  extras: $ => [],
+
+  rules: {{
+    document: $ => {document},
+
+    call: $ => seq(
+      {open_function},
+      field('name', $.call_name),
+      repeat($.argument),
+      optional(seq($._whitespace, optional($.content))),
+      {close_function},
+    ),
+
+    argument: $ => seq(
+      {open_arg},
+      field('key', $.arg_key),
+      {assign},
+      optional(field('value', $.arg_value)),
+      {close_arg},
+    ),
+
+    arg_value: $ => repeat1(choice($.arg_text, $.call, $.raw_string)),
+
+    content: $ => repeat1(choice({elements})),
+{extra_rules}
+    call_name: $ => /{call_name}/,
+
+    arg_key: $ => /[^{asg}]+/,
+
+    arg_text: $ => /[^{of}{ca}]+/,
+
+    text: $ => /{text}/,
+
+    _whitespace: $ => /\s+/,
+  }},
+}});
+"#,
+        externals = externals.join(", "),
+        elements = elements.join(", "),
+        open_function = js_string(open_function),
+        close_function = js_string(close_function),
+        open_arg = js_string(open_arg),
+        close_arg = js_string(close_arg),
+        assign = js_string(assign),
+    );
+
+    let mut scan_prologue = String::new();
+    let mut scanner_functions = String::new();
+    if options.skip_header {
+        scanner_functions.push_str(&format!(r#"
+// The header consists of a first line starting with `{shebang}` and
+// lines starting with `{directive}` followed by whitespace.
+static bool scan_header(TSLexer *lexer) {{
+  bool found = false;
+  for (bool first = true;; first = false) {{
+    const char *prefix = first && lexer->lookahead == '#' ? "{shebang}" : "{directive}";
+    for (const char *c = prefix; *c; c++) {{
+      if (lexer->lookahead != *c) return found;
+      lexer->advance(lexer, false);
+    }}
+    if (prefix[0] != '#' && !lexer->eof(lexer) && !iswspace(lexer->lookahead)) return found;
+    while (!lexer->eof(lexer) && lexer->lookahead != '\n') lexer->advance(lexer, false);
+    if (!lexer->eof(lexer)) lexer->advance(lexer, false);
+    lexer->mark_end(lexer);
+    found = true;
+  }}
+}}
+"#,
+            shebang = crate::header::SHEBANG,
+            directive = crate::header::DIRECTIVE_PREFIX,
+        ));
+        scan_prologue.push_str(r"  if (valid_symbols[HEADER] && lexer->get_column(lexer) == 0 && (lexer->lookahead == '#' || lexer->lookahead == '%')) {
+    lexer->result_symbol = HEADER;
+    return scan_header(lexer);
+  }
+");
+    }
+    if options.line_calls {
+        scanner_functions.push_str(&format!(r#"
+// A line call starts with {line_call} after spaces and tabs at the start of a line.
+static bool scan_line_call_start(TSLexer *lexer, bool *indented) {{
+  while (lexer->lookahead == ' ' || lexer->lookahead == '\t') {{
+    *indented = true;
+    lexer->advance(lexer, false);
+  }}
+  if (lexer->lookahead != {c_line_call}) return false;
+  lexer->advance(lexer, false);
+  lexer->result_symbol = LINE_CALL_START;
+  return true;
+}}
+"#,
+            line_call = lexer::LINE_CALL,
+            c_line_call = c_char(lexer::LINE_CALL),
+        ));
+        scan_prologue.push_str(r"  if (valid_symbols[LINE_CALL_START] && lexer->get_column(lexer) == 0) {
+    bool indented = false;
+    if (scan_line_call_start(lexer, &indented)) return true;
+    if (indented) return false;
+  }
+");
+    }
+
+    let scanner_c = format!(r#"// external scanner recognizing litua raw strings
+// NOTE: generated by `litua grammar --format tree-sitter`, do not edit
+
+#include "tree_sitter/parser.h"
+#include <wchar.h>
+#include <wctype.h>
+
+enum TokenType {{
+{token_types}}};
+
+void *tree_sitter_litua_external_scanner_create(void) {{ return NULL; }}
+void tree_sitter_litua_external_scanner_destroy(void *payload) {{}}
+unsigned tree_sitter_litua_external_scanner_serialize(void *payload, char *buffer) {{ return 0; }}
+void tree_sitter_litua_external_scanner_deserialize(void *payload, const char *buffer, unsigned length) {{}}
+{scanner_functions}
+// A raw string starts with {open_function} and N {open_raw} characters followed by an optional
+// language tag (like `lua`) and one whitespace.
+// It ends with one whitespace followed by N {close_raw} characters and {close_function}.
+bool tree_sitter_litua_external_scanner_scan(void *payload, TSLexer *lexer, const bool *valid_symbols) {{
+{scan_prologue}  if (!valid_symbols[RAW_STRING] || lexer->lookahead != {c_open_function}) return false;
+  lexer->advance(lexer, false);
+
+  unsigned delimiter_length = 0;
+  while (lexer->lookahead == {c_open_raw}) {{
+    delimiter_length++;
+    lexer->advance(lexer, false);
+  }}
+  if (delimiter_length == 0 || delimiter_length > {max_length}) return false;
+  while (lexer->lookahead != 0 && lexer->lookahead != {c_open_raw} && (iswalnum(lexer->lookahead) || wcschr(L"{call_name_punctuation}{language_punctuation}", (wchar_t)lexer->lookahead))) {{
+    lexer->advance(lexer, false);
+  }}
+  if (!iswspace(lexer->lookahead)) return false;
+  lexer->advance(lexer, false);
+
+  unsigned delimiter_read = 0;
+  bool after_whitespace = false;
+  while (!lexer->eof(lexer)) {{
+    int32_t chr = lexer->lookahead;
+    if (delimiter_read == delimiter_length) {{
+      if (chr != {c_close_function}) return false;
+      lexer->advance(lexer, false);
+      lexer->result_symbol = RAW_STRING;
+      return true;
+    }}
+
+    if (iswspace(chr)) {{
+      after_whitespace = true;
+      delimiter_read = 0;
+    }} else if (chr == {c_close_raw} && after_whitespace) {{
+      delimiter_read++;
+    }} else {{
+      after_whitespace = false;
+      delimiter_read = 0;
+    }}
+    lexer->advance(lexer, false);
+  }}
+
+  return false;
+}}
+"#,
+        token_types = token_types.iter().map(|name| format!("  {name},\n")).collect::<String>(),
+        open_function = open_function,
+        close_function = close_function,
+        open_raw = open_raw,
+        close_raw = close_raw,
+        c_open_function = c_char(open_function),
+        c_close_function = c_char(close_function),
+        c_open_raw = c_char(open_raw),
+        c_close_raw = c_char(close_raw),
+        max_length = lexer::MAX_RAW_DELIMITER_LENGTH,
+        call_name_punctuation = lexer::CALL_NAME_PUNCTUATION,
+        language_punctuation = lexer::RAW_LANGUAGE_PUNCTUATION,
+    );
+
+    let highlights_scm = format!(r#"; highlight queries for litua text documents
+; NOTE: generated by `litua grammar --format tree-sitter`, do not edit
+
+(call_name) @function
+(arg_key) @property
+(arg_text) @string
+(raw_string) @string.special
+(text) @text
+{extra_highlights}
+[{open_function} {close_function} {open_arg} {close_arg}] @punctuation.bracket
+{assign} @operator
+"#,
+        open_function = query_string(open_function),
+        close_function = query_string(close_function),
+        open_arg = query_string(open_arg),
+        close_arg = query_string(close_arg),
+        assign = query_string(assign),
+    );
+
+    TreeSitterGrammar { grammar_js, scanner_c, highlights_scm }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree;
+
+    #[test]
+    fn escape_for_syntax_configuration() -> Result<(), String> {
+        let options = lexer::LexerOptions { line_calls: true, open_raw: '|', close_raw: '|', ..Default::default() };
+        assert_eq!(escape_text("@a {b}\n  @c d@e", &options), "{| @ |}a {left-curly-brace}b{right-curly-brace}\n  {| @ |}c d@e");
+        assert_eq!(escape_text("@a", &lexer::LexerOptions::default()), "@a");
+        assert_eq!(escape_arg("x]{y}", &lexer::LexerOptions::default()), "x{< ] >}{left-curly-brace}y}");
+        assert_eq!(raw_string("a >> b >", &lexer::LexerOptions::default())?, "{<<< a >> b > >>>}");
+
+        let escaped = escape_text("@h {x} ]", &options);
+        let tree = crate::pipeline::Pipeline::new().lexer_options(options)
+            .parse(std::path::Path::new("escaped.lit"), &escaped).map_err(|e| e.to_string())?;
+        let calls: Vec<&str> = match &tree.0 {
+            tree::DocumentElement::Function(root) => root.content.iter().filter_map(|element| match element {
+                tree::DocumentElement::Function(func) => Some(func.call.as_str()),
+                tree::DocumentElement::Text(_) => None,
+            }).collect(),
+            tree::DocumentElement::Text(_) => vec![],
+        };
+        assert_eq!(calls, ["<", "left-curly-brace", "right-curly-brace"]);
+        Ok(())
+    }
+
+    #[test]
+    fn tree_sitter_uses_lexer_delimiters() {
+        let grammar = tree_sitter(&lexer::LexerOptions::default());
+        assert!(grammar.grammar_js.contains("call_name: $ => /[\\p{L}\\p{N}\\p{M}\\-_.\\/:$]+/,"));
+        assert!(grammar.grammar_js.contains("text: $ => /[^{}]+/,"));
+        assert!(grammar.scanner_c.contains("lexer->lookahead != '{'"));
+        assert!(grammar.scanner_c.contains("wcschr(L\"-_./:$+#\""));
+        assert!(grammar.highlights_scm.contains("[\"{\" \"}\" \"[\" \"]\"] @punctuation.bracket"));
+        assert!(grammar.grammar_js.contains("externals: $ => [$.raw_string, $.header],"));
+        assert!(!grammar.grammar_js.contains("line_call"));
+    }
+
+    #[test]
+    fn tree_sitter_uses_lexer_options() {
+        let options = lexer::LexerOptions {
+            call_names: lexer::CallNamePolicy::Permissive,
+            open_raw: '|',
+            close_raw: '!',
+            skip_header: false,
+            line_calls: true,
+            ..Default::default()
+        };
+        let grammar = tree_sitter(&options);
+        assert!(grammar.grammar_js.contains("externals: $ => [$.raw_string, $.line_call_start],"));
+        assert!(grammar.grammar_js.contains("document: $ => repeat(choice($.text, $.call, $.raw_string, $.line_call, $._newline)),"));
+        assert!(grammar.grammar_js.contains("call_name: $ => /[^\\s}\\[]+/,"));
+        assert!(grammar.grammar_js.contains("text: $ => /[^{}\\r\\n]+/,"));
+        assert!(grammar.scanner_c.contains("enum TokenType {\n  RAW_STRING,\n  LINE_CALL_START,\n};"));
+        assert!(grammar.scanner_c.contains("while (lexer->lookahead == '|') {"));
+        assert!(grammar.scanner_c.contains("chr == '!' && after_whitespace"));
+        assert!(!grammar.scanner_c.contains("'<'") && !grammar.scanner_c.contains("'>'"));
+        assert!(!grammar.scanner_c.contains("scan_header"));
+        assert!(grammar.highlights_scm.contains("(line_call_start) @punctuation.special"));
+    }
+}
@@ -0,0 +1,173 @@
+//! Header lines of litua text documents
+//!
+//! A text document might start with a shebang line (``#!/usr/bin/env litua``)
+//! to make it executable, followed by directive lines like
+//! ``%!litua raw-delimiters=| whitespace-texts=drop`` setting options
+//! from within the document. The lexer skips these header lines.
+
+use crate::errors;
+use crate::lexer;
+use crate::messages::Message;
+
+/// prefix of a shebang line (only admissible as first line)
+pub const SHEBANG: &str = "#!";
+/// prefix of a directive line
+pub const DIRECTIVE_PREFIX: &str = "%!litua";
+
+/// `Directive` is one `key=value` pair of a directive line
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Directive {
+    pub key: String,
+    pub value: String,
+    /// byte offset of the key within the text document
+    pub byte_offset: usize,
+}
+
+/// Iterate over (byte offset, line including its line terminator) of the header lines
+fn header_lines(src: &str) -> impl Iterator<Item = (usize, &str)> {
+    src.split_inclusive('\n')
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some((start, line))
+        })
+        .take_while(|(start, line)| {
+            (*start == 0 && line.starts_with(SHEBANG)) || is_directive_line(line)
+        })
+}
+
+/// Is `line` a directive line (i.e. `DIRECTIVE_PREFIX` followed by whitespace)?
+fn is_directive_line(line: &str) -> bool {
+    match line.strip_prefix(DIRECTIVE_PREFIX) {
+        Some(rest) => rest.is_empty() || rest.starts_with(char::is_whitespace),
+        None => false,
+    }
+}
+
+/// Returns the number of bytes of the header lines at the start of `src`
+pub fn header_length(src: &str) -> usize {
+    header_lines(src).map(|(_, line)| line.len()).sum()
+}
+
+/// Returns the directives of the header lines at the start of `src`
+pub fn directives(src: &str) -> Result<Vec<Directive>, errors::Error> {
+    let mut directives = vec![];
+    for (line_offset, line) in header_lines(src) {
+        if !is_directive_line(line) {
+            continue;
+        }
+
+        let mut offset = line_offset + DIRECTIVE_PREFIX.len();
+        for word in line[DIRECTIVE_PREFIX.len()..].split_inclusive(char::is_whitespace) {
+            let byte_offset = offset;
+            offset += word.len();
+            let word = word.trim_end();
+            if word.is_empty() {
+                continue;
+            }
+
+            match word.split_once('=') {
+                Some((key, value)) if !key.is_empty() => directives.push(Directive {
+                    key: key.to_owned(),
+                    value: value.to_owned(),
+                    byte_offset,
+                }),
+                _ => {
+                    let msg = Message::new("H0001", vec![word.to_owned()]);
+                    return Err(errors::Error::InvalidSyntax(msg, byte_offset));
+                },
+            }
+        }
+    }
+    Ok(directives)
+}
+
+/// Parse a boolean value of a directive
+pub fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("expects true or false, got '{value}'")),
+    }
+}
+
+/// Parse raw string delimiters given as one character (for opening
+/// and closing) or two characters (opening and closing)
+pub fn parse_raw_delimiters(chars: &str) -> Result<(char, char), String> {
+    match chars.chars().collect::<Vec<char>>()[..] {
+        [both] => Ok((both, both)),
+        [open, close] => Ok((open, close)),
+        _ => Err(format!("expects one or two characters, got '{chars}'")),
+    }
+}
+
+/// Update the lexer options `options` by the directive with key `key` and
+/// value `value`. Returns `None` if the directive does not configure the lexer.
+pub fn apply_lexer_directive(options: &mut lexer::LexerOptions, key: &str, value: &str) -> Option<Result<(), String>> {
+    let result = match key {
+        "raw-delimiters" => parse_raw_delimiters(value).map(|(open, close)| {
+            options.open_raw = open;
+            options.close_raw = close;
+        }),
+        "call-names" => match value {
+            "identifier" => Ok(lexer::CallNamePolicy::Identifier),
+            "permissive" => Ok(lexer::CallNamePolicy::Permissive),
+            _ => Err(format!("expects identifier or permissive, got '{value}'")),
+        }.map(|policy| options.call_names = policy),
+        "trim-raw-whitespace" => parse_bool(value).map(|b| options.trim_raw_whitespace = b),
+        "line-calls" => parse_bool(value).map(|b| options.line_calls = b),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Error for the invalid directive `directive` with message `msg`
+pub fn invalid_directive(directive: &Directive, msg: String) -> errors::Error {
+    let msg = Message::new("H0002", vec![directive.key.to_owned(), msg]);
+    errors::Error::InvalidSyntax(msg, directive.byte_offset)
+}
+
+/// Returns the lexer options `options` updated by the lexer `directives` (like `raw-delimiters`).
+/// Directives not configuring the lexer are ignored.
+pub fn apply_lexer_directives(options: &lexer::LexerOptions, directives: &[Directive]) -> Result<lexer::LexerOptions, errors::Error> {
+    let mut options = options.clone();
+    for directive in directives.iter() {
+        let result = apply_lexer_directive(&mut options, &directive.key, &directive.value).unwrap_or(Ok(()));
+        if let Err(msg) = result.and_then(|_| options.validate()) {
+            return Err(invalid_directive(directive, msg));
+        }
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shebang_and_directives() -> Result<(), errors::Error> {
+        let src = "#!/usr/bin/env litua\n%!litua raw-delimiters=| format=html\n%!litua\tdefine=title=T\n{text}\n%!litua x=y\n";
+        assert_eq!(&src[header_length(src)..], "{text}\n%!litua x=y\n");
+
+        let directives = directives(src)?;
+        let pairs: Vec<(&str, &str)> = directives.iter().map(|d| (d.key.as_str(), d.value.as_str())).collect();
+        assert_eq!(pairs, vec![("raw-delimiters", "|"), ("format", "html"), ("define", "title=T")]);
+        assert_eq!(&src[directives[1].byte_offset..directives[1].byte_offset + 6], "format");
+
+        assert_eq!(header_length("text\n#!not a shebang\n"), 0);
+        assert_eq!(header_length("%!lituax=y\n"), 0);
+        assert!(super::directives("%!litua novalue\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn lexer_directives() -> Result<(), errors::Error> {
+        let src = "%!litua raw-delimiters=|! format=html line-calls=yes\n";
+        let options = apply_lexer_directives(&lexer::LexerOptions::default(), &directives(src)?)?;
+        assert_eq!((options.open_raw, options.close_raw, options.line_calls), ('|', '!', true));
+
+        let invalid = directives("%!litua trim-raw-whitespace=maybe\n")?;
+        assert!(matches!(apply_lexer_directives(&lexer::LexerOptions::default(), &invalid), Err(errors::Error::InvalidSyntax(_, 8))));
+        Ok(())
+    }
+}
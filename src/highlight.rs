@@ -0,0 +1,223 @@
+//! Syntax highlighting of litua text documents based on the lexer
+
+use std::ops;
+
+use crate::errors;
+use crate::lexer;
+
+/// `SpanKind` classifies a byte range of a litua text document
+#[derive(Clone,Copy,Debug,Hash,PartialEq,Eq)]
+pub enum SpanKind {
+    /// syntax characters like `{`, `[`, `=`, `]`, `}` or raw string delimiters
+    Delimiter,
+    /// name of a call
+    CallName,
+    /// key of an argument
+    ArgKey,
+    /// text inside an argument value
+    ArgValue,
+    /// content of a raw string
+    RawString,
+    /// whitespace separating a call name or arguments from the content
+    Whitespace,
+    /// text of the document or inside the content of some call
+    Text,
+}
+
+impl SpanKind {
+    /// CSS class used for this kind of span in HTML output
+    pub fn css_class(&self) -> &'static str {
+        match self {
+            SpanKind::Delimiter => "litua-delimiter",
+            SpanKind::CallName => "litua-call",
+            SpanKind::ArgKey => "litua-arg-key",
+            SpanKind::ArgValue => "litua-arg-value",
+            SpanKind::RawString => "litua-raw",
+            SpanKind::Whitespace => "litua-whitespace",
+            SpanKind::Text => "litua-text",
+        }
+    }
+
+    /// ANSI escape sequence (Select Graphic Rendition) used for this kind of span in terminal output
+    pub fn ansi_style(&self) -> &'static str {
+        match self {
+            SpanKind::Delimiter => "\x1b[35m",
+            SpanKind::CallName => "\x1b[1;34m",
+            SpanKind::ArgKey => "\x1b[36m",
+            SpanKind::ArgValue => "\x1b[32m",
+            SpanKind::RawString => "\x1b[33m",
+            SpanKind::Whitespace | SpanKind::Text => "",
+        }
+    }
+}
+
+/// Lex `src` with the default options and return a sequence of classified byte ranges
+/// covering the entire document. Adjacent byte ranges of the same kind are merged.
+pub fn classify(src: &str) -> Result<Vec<(ops::Range<usize>, SpanKind)>, errors::Error> {
+    classify_with(src, &lexer::LexerOptions::default())
+}
+
+/// Like `classify`, but lexes `src` with the syntax configured by `options`
+pub fn classify_with(src: &str, options: &lexer::LexerOptions) -> Result<Vec<(ops::Range<usize>, SpanKind)>, errors::Error> {
+    // admissible scopes for text tokens
+    enum Scope {
+        ArgValue,
+        Content,
+        RawString,
+    }
+
+    let lex = lexer::Lexer::with_options(src, options.clone());
+    let mut spans = vec![];
+    let mut scopes = vec![Scope::Content];
+
+    for tok_or_err in lex.iter() {
+        let token = tok_or_err?;
+        match token {
+            lexer::Token::Call(range) => spans.push((range, SpanKind::CallName)),
+            lexer::Token::ArgKey(range) => spans.push((range, SpanKind::ArgKey)),
+            lexer::Token::Whitespace(range) => {
+                let kind = match scopes.last() {
+                    Some(Scope::RawString) => SpanKind::RawString,
+                    _ => SpanKind::Whitespace,
+                };
+                spans.push((range, kind));
+            },
+            lexer::Token::Text(range) => {
+                let kind = match scopes.last() {
+                    Some(Scope::ArgValue) => SpanKind::ArgValue,
+                    Some(Scope::RawString) => SpanKind::RawString,
+                    _ => SpanKind::Text,
+                };
+                spans.push((range, kind));
+            },
+            lexer::Token::BeginArgValue(_) => scopes.push(Scope::ArgValue),
+            lexer::Token::BeginContent(_) => scopes.push(Scope::Content),
+            lexer::Token::BeginRaw(_) => scopes.push(Scope::RawString),
+            lexer::Token::EndArgValue(_) |
+            lexer::Token::EndContent(_) |
+            lexer::Token::EndRaw(_) => { scopes.pop(); },
+            _ => {},
+        }
+    }
+
+    // NOTE: bytes not covered by any token are syntax characters
+    //       like braces which do not have a token of their own
+    spans.sort_by_key(|(range, _)| range.start);
+    let mut covered = vec![];
+    let mut position = 0;
+    for (range, kind) in spans {
+        if range.is_empty() || range.end <= position {
+            continue;
+        }
+        if position < range.start {
+            push_span(&mut covered, position..range.start, SpanKind::Delimiter);
+        }
+        push_span(&mut covered, range.start.max(position)..range.end, kind);
+        position = range.end;
+    }
+    if position < src.len() {
+        push_span(&mut covered, position..src.len(), SpanKind::Delimiter);
+    }
+
+    Ok(covered)
+}
+
+/// Append the span to `spans` or extend the last span if it is adjacent and of the same kind
+fn push_span(spans: &mut Vec<(ops::Range<usize>, SpanKind)>, range: ops::Range<usize>, kind: SpanKind) {
+    if let Some((last_range, last_kind)) = spans.last_mut() {
+        if *last_kind == kind && last_range.end == range.start {
+            last_range.end = range.end;
+            return;
+        }
+    }
+    spans.push((range, kind));
+}
+
+/// Escape `text` for use in HTML text content and attribute values
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Represent `src` as HTML `pre` element with one `span` element per classified byte range
+pub fn to_html(src: &str) -> Result<String, errors::Error> {
+    to_html_with(src, &lexer::LexerOptions::default())
+}
+
+/// Like `to_html`, but lexes `src` with the syntax configured by `options`
+pub fn to_html_with(src: &str, options: &lexer::LexerOptions) -> Result<String, errors::Error> {
+    let mut out = String::from("<pre class=\"litua\"><code>");
+    for (range, kind) in classify_with(src, options)? {
+        out.push_str(&format!("<span class=\"{}\">{}</span>", kind.css_class(), escape_html(&src[range])));
+    }
+    out.push_str("</code></pre>\n");
+    Ok(out)
+}
+
+/// Represent `src` with ANSI escape sequences for terminal output
+pub fn to_ansi(src: &str) -> Result<String, errors::Error> {
+    to_ansi_with(src, &lexer::LexerOptions::default())
+}
+
+/// Like `to_ansi`, but lexes `src` with the syntax configured by `options`
+pub fn to_ansi_with(src: &str, options: &lexer::LexerOptions) -> Result<String, errors::Error> {
+    let mut out = String::new();
+    for (range, kind) in classify_with(src, options)? {
+        let style = kind.ansi_style();
+        if style.is_empty() {
+            out.push_str(&src[range]);
+        } else {
+            out.push_str(style);
+            out.push_str(&src[range]);
+            out.push_str("\x1b[0m");
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_call_with_args_and_raw_string() -> Result<(), errors::Error> {
+        let input = "A{b[k=v][l=w] {< r >}}";
+        let spans = classify(input)?;
+        let represented: Vec<(&str, SpanKind)> = spans.iter().map(|(r, k)| (&input[r.clone()], *k)).collect();
+        assert_eq!(represented, vec![
+            ("A", SpanKind::Text),
+            ("{", SpanKind::Delimiter),
+            ("b", SpanKind::CallName),
+            ("[", SpanKind::Delimiter),
+            ("k", SpanKind::ArgKey),
+            ("=", SpanKind::Delimiter),
+            ("v", SpanKind::ArgValue),
+            ("][", SpanKind::Delimiter),
+            ("l", SpanKind::ArgKey),
+            ("=", SpanKind::Delimiter),
+            ("w", SpanKind::ArgValue),
+            ("]", SpanKind::Delimiter),
+            (" ", SpanKind::Whitespace),
+            ("{<", SpanKind::Delimiter),
+            (" r ", SpanKind::RawString),
+            (">}}", SpanKind::Delimiter),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn classify_with_raw_delimiters() -> Result<(), errors::Error> {
+        let input = "{p {| a |}}";
+        let options = lexer::LexerOptions { open_raw: '|', close_raw: '|', ..Default::default() };
+        let spans = classify_with(input, &options)?;
+        assert!(spans.iter().any(|(r, k)| &input[r.clone()] == " a " && *k == SpanKind::RawString));
+        assert!(classify(input).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn html_is_escaped() -> Result<(), errors::Error> {
+        let html = to_html("a<b")?;
+        assert_eq!(html, "<pre class=\"litua\"><code><span class=\"litua-text\">a&lt;b</span></code></pre>\n");
+        Ok(())
+    }
+}
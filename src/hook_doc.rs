@@ -0,0 +1,171 @@
+//! Reference documentation of the call vocabulary supported by hook files
+//!
+//! Hook files document calls with LDoc-style comment blocks (lines starting
+//! with `---`). A block contains a tag `@call name` (or precedes a hook
+//! registration like `Litua.convert_node_to_string("name", …)`), tags
+//! `@arg key type description` for the arguments, and free text describing
+//! the call. The reference is itself a litua text document using the
+//! standard call vocabulary of `markup`.
+
+use std::collections::BTreeMap;
+
+/// `ArgDoc` documents one argument of a call
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct ArgDoc {
+    /// type of the argument like `string` (empty if unspecified)
+    pub kind: String,
+    pub description: String,
+}
+
+/// `CallDoc` documents one call
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct CallDoc {
+    /// description of the call (paragraphs separated by empty lines)
+    pub description: String,
+    pub args: BTreeMap<String, ArgDoc>,
+    /// hook files documenting the call
+    pub files: Vec<String>,
+}
+
+/// `Reference` maps call names to their documentation
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Reference {
+    pub calls: BTreeMap<String, CallDoc>,
+}
+
+/// Returns the call name registered by a line like `Litua.modify_node("name", function (node)`
+fn registered_call(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("Litua.")?;
+    let (_, rest) = rest.split_once('(')?;
+    let rest = rest.trim_start();
+    let quote = rest.chars().next().filter(|chr| *chr == '"' || *chr == '\'')?;
+    rest[1..].split(quote).next()
+}
+
+impl Reference {
+    /// Create an empty reference
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the documented calls of hook file `file` with Lua source code `src`
+    pub fn add_hook_file(&mut self, file: &str, src: &str) {
+        let lines: Vec<&str> = src.lines().collect();
+        let mut i = 0;
+        while i < lines.len() {
+            if !lines[i].trim_start().starts_with("---") {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < lines.len() && lines[i].trim_start().starts_with("--") {
+                i += 1;
+            }
+            let block: Vec<&str> = lines[start..i].iter()
+                .map(|line| line.trim_start().trim_start_matches('-'))
+                .map(|line| line.strip_prefix(' ').unwrap_or(line))
+                .collect();
+            self.add_block(file, &block, lines.get(i).and_then(|line| registered_call(line)));
+        }
+    }
+
+    /// Add the comment block `block` of hook file `file` (followed by a registration for call `registered`)
+    fn add_block(&mut self, file: &str, block: &[&str], registered: Option<&str>) {
+        let mut call = registered;
+        let mut args = vec![];
+        let mut description: Vec<&str> = vec![];
+        for line in block.iter() {
+            if let Some(name) = line.strip_prefix("@call") {
+                call = Some(name.trim()).filter(|name| !name.is_empty()).or(call);
+            } else if let Some(arg) = line.strip_prefix("@arg") {
+                let mut words = arg.trim().splitn(3, ' ');
+                if let Some(key) = words.next().filter(|key| !key.is_empty()) {
+                    let kind = words.next().unwrap_or_default().to_owned();
+                    let description = words.next().unwrap_or_default().trim().to_owned();
+                    args.push((key.to_owned(), ArgDoc { kind, description }));
+                }
+            } else if !line.starts_with('@') {
+                description.push(line.trim_end());
+            }
+        }
+        let call = match call {
+            Some(call) => call,
+            None => return,
+        };
+
+        let doc = self.calls.entry(call.to_owned()).or_default();
+        let description = description.join("\n");
+        let description = description.trim();
+        if !description.is_empty() && !doc.description.contains(description) {
+            if !doc.description.is_empty() {
+                doc.description.push_str("\n\n");
+            }
+            doc.description.push_str(description);
+        }
+        doc.args.extend(args);
+        if !doc.files.iter().any(|f| f == file) {
+            doc.files.push(file.to_owned());
+        }
+    }
+
+    /// Represent the reference as litua text document with title `title`
+    pub fn to_litua(&self, title: &str) -> String {
+        let mut out = format!("{{h1 {}}}\n", text(title));
+        if self.calls.is_empty() {
+            out.push_str("\n{p No documented calls found.}\n");
+        }
+        for (call, doc) in self.calls.iter() {
+            out.push_str(&format!("\n{{h2 {{code {}}}}}\n", text(call)));
+            for paragraph in doc.description.split("\n\n") {
+                out.push_str(&format!("\n{{p {}}}\n", text(paragraph)));
+            }
+            if !doc.args.is_empty() {
+                out.push_str("\n{ul\n");
+                for (key, arg) in doc.args.iter() {
+                    out.push_str(&format!("  {{li {{code {}}}", text(key)));
+                    if !arg.kind.is_empty() {
+                        out.push_str(&format!(" ({{em {}}})", text(&arg.kind)));
+                    }
+                    if !arg.description.is_empty() {
+                        out.push_str(&format!(": {}", text(&arg.description)));
+                    }
+                    out.push_str("}\n");
+                }
+                out.push_str("}\n");
+            }
+            out.push_str(&format!("\n{{p Defined in {}.}}\n", doc.files.iter().map(|file| format!("{{code {}}}", text(file))).collect::<Vec<_>>().join(", ")));
+        }
+        out
+    }
+}
+
+/// Represent `content` as text of a litua document (as raw string if it contains delimiters)
+fn text(content: &str) -> String {
+    if !content.contains(['{', '}']) {
+        return content.to_owned();
+    }
+    let mut length = 1;
+    while content.contains(&format!(" {}", ">".repeat(length))) || content.starts_with(&">".repeat(length)) {
+        length += 1;
+    }
+    format!("{{{} {content} {}}}", "<".repeat(length), ">".repeat(length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn document_hook_file() {
+        let src = "--- A section of the document\n---@call section\n---@arg title string heading of the section\n\
+            ---@arg level integer\nLitua.convert_node_to_string(\"section\", function (node) end)\n\n\
+            --- Code like {x}\nLitua.modify_node('code', function (node) return node end)\n-- no doc comment\n";
+        let mut reference = Reference::new();
+        reference.add_hook_file("hooks.a.lua", src);
+        let doc = reference.to_litua("Calls");
+        assert_eq!(doc, "{h1 Calls}\n\n{h2 {code code}}\n\n{p {< Code like {x} >}}\n\n{p Defined in {code hooks.a.lua}.}\n\n\
+            {h2 {code section}}\n\n{p A section of the document}\n\n{ul\n  {li {code level} ({em integer})}\n  \
+            {li {code title} ({em string}): heading of the section}\n}\n\n{p Defined in {code hooks.a.lua}.}\n");
+        assert!(crate::parse_str(&doc).is_ok());
+    }
+}
@@ -0,0 +1,163 @@
+//! Hook packages are zip or tar archives containing hook files
+//!
+//! A hook package makes a hook library distributable as a single file.
+//! The hook files (``hook*.lua``) are read from the top-level directory
+//! of the archive. All other Lua files can be loaded with ``require``,
+//! e.g. ``require("util.text")`` loads ``util/text.lua`` or ``util/text/init.lua``.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path;
+
+/// File extensions of the archive formats accepted as hook packages
+pub const PACKAGE_EXTENSIONS: [&str; 4] = [".zip", ".tar", ".tar.gz", ".tgz"];
+
+/// `HookPackage` is the set of files of a hook package archive
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct HookPackage {
+    /// filepath of the archive
+    pub path: path::PathBuf,
+    /// file contents by their path within the archive (separated by `/`)
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+/// Does the filepath have the file extension of a hook package archive?
+pub fn is_package_path(filepath: &path::Path) -> bool {
+    match filepath.file_name().and_then(|name| name.to_str()) {
+        Some(name) => {
+            let name = name.to_ascii_lowercase();
+            PACKAGE_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+        },
+        None => false,
+    }
+}
+
+/// Normalize the path of a file within an archive (e.g. `./a//b.lua` becomes `a/b.lua`)
+#[cfg(feature = "hook-packages")]
+fn normalize_entry_name(name: &str) -> String {
+    name.split(['/', '\\'])
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+impl HookPackage {
+    /// Read the hook package archive at `filepath`. The archive format
+    /// is determined by the file extension.
+    #[cfg(feature = "hook-packages")]
+    pub fn open(filepath: &path::Path) -> io::Result<HookPackage> {
+        let name = filepath.to_string_lossy().to_ascii_lowercase();
+        let file = std::fs::File::open(filepath)?;
+        let files = if name.ends_with(".zip") {
+            read_zip(io::BufReader::new(file))?
+        } else if name.ends_with(".tar") {
+            read_tar(io::BufReader::new(file))?
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            read_tar_gz(io::BufReader::new(file))?
+        } else {
+            let msg = format!("'{}' is not a hook package, supported file extensions are {}", filepath.display(), PACKAGE_EXTENSIONS.join(", "));
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        };
+        Ok(HookPackage { path: filepath.to_owned(), files })
+    }
+
+    /// Read the hook package archive at `filepath`, which requires feature `hook-packages`
+    #[cfg(not(feature = "hook-packages"))]
+    pub fn open(filepath: &path::Path) -> io::Result<HookPackage> {
+        let msg = format!("cannot read hook package '{}', because litua was built without feature 'hook-packages'", filepath.display());
+        Err(io::Error::new(io::ErrorKind::Unsupported, msg))
+    }
+
+    /// Returns (file name, content) of the hook files in the top-level
+    /// directory of the archive sorted by file name
+    pub fn hook_files(&self) -> Vec<(&str, &[u8])> {
+        self.files.iter()
+            .filter(|(name, _)| !name.contains('/') && name.starts_with("hook") && name.ends_with(".lua"))
+            .map(|(name, content)| (name.as_str(), content.as_slice()))
+            .collect()
+    }
+
+    /// Returns (path within archive, content) of the Lua file providing module `module`
+    /// (e.g. `util.text` is provided by `util/text.lua` or `util/text/init.lua`)
+    pub fn find_module(&self, module: &str) -> Option<(&str, &[u8])> {
+        let base = module.replace('.', "/");
+        [format!("{base}.lua"), format!("{base}/init.lua")].iter()
+            .find_map(|candidate| self.files.get_key_value(candidate))
+            .map(|(name, content)| (name.as_str(), content.as_slice()))
+    }
+}
+
+#[cfg(feature = "hook-packages")]
+fn read_zip<R: io::Read + io::Seek>(reader: R) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let mut files = BTreeMap::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let mut content = vec![];
+        io::Read::read_to_end(&mut entry, &mut content)?;
+        files.insert(normalize_entry_name(entry.name()), content);
+    }
+    Ok(files)
+}
+
+#[cfg(feature = "hook-packages")]
+fn read_tar<R: io::Read>(reader: R) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut files = BTreeMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = normalize_entry_name(&entry.path()?.to_string_lossy());
+        let mut content = vec![];
+        io::Read::read_to_end(&mut entry, &mut content)?;
+        files.insert(name, content);
+    }
+    Ok(files)
+}
+
+#[cfg(feature = "hook-packages")]
+fn read_tar_gz<R: io::Read>(reader: R) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    read_tar(flate2::read::GzDecoder::new(reader))
+}
+
+#[cfg(all(test, feature = "hook-packages"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_hooks_and_modules_from_archives() -> io::Result<()> {
+        let mut zipped = io::Cursor::new(vec![]);
+        {
+            let mut writer = zip::ZipWriter::new(&mut zipped);
+            let options = zip::write::FileOptions::default();
+            for (name, content) in [("hooks.lua", "-- hooks"), ("./util/text.lua", "-- text"), ("lib/hooks_nested.lua", "-- nested")] {
+                writer.start_file(name, options)?;
+                io::Write::write_all(&mut writer, content.as_bytes())?;
+            }
+            writer.finish()?;
+        }
+        zipped.set_position(0);
+
+        let package = HookPackage { path: "pkg.zip".into(), files: read_zip(zipped)? };
+        assert_eq!(package.hook_files(), vec![("hooks.lua", "-- hooks".as_bytes())]);
+        assert_eq!(package.find_module("util.text"), Some(("util/text.lua", "-- text".as_bytes())));
+        assert_eq!(package.find_module("util"), None);
+
+        let mut tarred = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(8);
+        header.set_mode(0o644);
+        tarred.append_data(&mut header, "util/init.lua", "-- index".as_bytes())?;
+        let package = HookPackage { path: "pkg.tar".into(), files: read_tar(tarred.into_inner()?.as_slice())? };
+        assert_eq!(package.find_module("util"), Some(("util/init.lua", "-- index".as_bytes())));
+
+        assert!(is_package_path(path::Path::new("dir/Hooks.TAR.GZ")));
+        assert!(!is_package_path(path::Path::new("hooks")));
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+//! Writer representing litua text documents as standalone HTML documents
+//!
+//! The body is written like the chapters of `epub`, i.e. the standard call
+//! vocabulary of `markup` (`h1`, `p`, `em`, `a[href=…]`, …) becomes HTML
+//! elements and other calls are transparent. The title of the document is
+//! the text of its first `h1` call.
+
+use crate::epub;
+use crate::tree;
+
+/// Returns the tree as HTML5 document
+pub fn to_html(tree: &tree::DocumentTree) -> String {
+    let node = match &tree.0 {
+        tree::DocumentElement::Function(root) => root.content.as_slice(),
+        text @ tree::DocumentElement::Text(_) => std::slice::from_ref(text),
+    };
+    let title = node.iter().find_map(epub::chapter_title).unwrap_or_default();
+    let title = title.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\"/>\n<title>{title}</title>\n</head>\n<body>\n{}</body>\n</html>\n", epub::to_xhtml(node))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_html_document() {
+        let tree = crate::parse_str("{h1 A & B}\ntext {em here}").expect("valid document");
+        assert_eq!(to_html(&tree), "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\"/>\n<title>A &amp; B</title>\n</head>\n<body>\n\
+            <h1>A &amp; B</h1>\n<p>text <em>here</em></p>\n</body>\n</html>\n");
+    }
+}
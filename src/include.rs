@@ -0,0 +1,171 @@
+//! Project builds combining a master document with the documents it includes
+//!
+//! Every call `{include chapter.lit}` is replaced by the content of the text
+//! document at the given filepath (relative to the including document), which
+//! may include further documents itself. The calls of included documents get
+//! argument `=file` with their filepath, so their `=span` (and errors raised
+//! for them) can be attributed to the right file. A document including itself
+//! (directly or indirectly) is an error.
+
+use std::io;
+use std::path;
+
+use crate::errors;
+use crate::messages::Message;
+use crate::pipeline;
+use crate::tree;
+
+/// Name of the call including another text document
+pub const INCLUDE_CALL: &str = "include";
+
+/// Returns `filepath` with `.` components removed and `..` components resolved lexically
+pub fn normalize(filepath: &path::Path) -> path::PathBuf {
+    let mut normalized = path::PathBuf::new();
+    for component in filepath.components() {
+        match component {
+            path::Component::CurDir => {},
+            path::Component::ParentDir if matches!(normalized.components().next_back(), Some(path::Component::Normal(_))) => {
+                normalized.pop();
+            },
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Replace the `include` calls of `tree` (the text document at `filepath`) recursively
+/// by the content of the included documents, which are read with `read` and parsed
+/// with `pipeline`. Returns the filepaths of the included documents in order of inclusion.
+pub fn resolve_includes<R>(tree: &mut tree::DocumentTree, filepath: &path::Path, pipeline: &pipeline::Pipeline, read: &mut R) -> Result<Vec<path::PathBuf>, errors::Error>
+    where R: FnMut(&path::Path) -> io::Result<String>
+{
+    let mut resolver = Resolver { pipeline, read, stack: vec![normalize(filepath)], included: vec![] };
+    if let tree::DocumentElement::Function(root) = &mut tree.0 {
+        resolver.resolve(&mut root.content, filepath)?;
+    }
+    Ok(resolver.included)
+}
+
+struct Resolver<'p, R> {
+    pipeline: &'p pipeline::Pipeline,
+    read: &'p mut R,
+    /// normalized filepaths of the documents currently being resolved
+    stack: Vec<path::PathBuf>,
+    included: Vec<path::PathBuf>,
+}
+
+impl<'p, R: FnMut(&path::Path) -> io::Result<String>> Resolver<'p, R> {
+    /// Resolve the `include` calls of `node` read from `filepath`
+    fn resolve(&mut self, node: &mut tree::DocumentNode, filepath: &path::Path) -> Result<(), errors::Error> {
+        let mut i = 0;
+        while i < node.len() {
+            let func = match &mut node[i] {
+                tree::DocumentElement::Function(func) => func,
+                tree::DocumentElement::Text(_) => {
+                    i += 1;
+                    continue;
+                },
+            };
+            if func.call != INCLUDE_CALL {
+                for value in func.args.iter_mut().filter(|(key, _)| !tree::is_reserved_arg(key)).map(|(_, value)| value) {
+                    self.resolve(value, filepath)?;
+                }
+                self.resolve(&mut func.content, filepath)?;
+                i += 1;
+                continue;
+            }
+
+            let content = self.include(func, filepath).map_err(|e| self.locate(e, filepath))?;
+            let count = content.len();
+            node.splice(i..i + 1, content);
+            i += count;
+        }
+        Ok(())
+    }
+
+    /// Returns the content of the document included by call `func` of the document at `filepath`
+    fn include(&mut self, func: &tree::DocumentFunction, filepath: &path::Path) -> Result<tree::DocumentNode, errors::Error> {
+        let span = func.span().unwrap_or(0..0);
+        let reference = match func.content.as_slice() {
+            [tree::DocumentElement::Text(reference)] if !reference.trim().is_empty() => reference.trim(),
+            _ => return Err(errors::Error::IncludeError(Message::new("I0001", vec![func.call.to_owned()]), span)),
+        };
+        let included = filepath.parent().unwrap_or(path::Path::new("")).join(reference);
+        let normalized = normalize(&included);
+        if let Some(start) = self.stack.iter().position(|file| *file == normalized) {
+            let cycle: Vec<String> = self.stack[start..].iter().chain([&normalized]).map(|file| file.display().to_string()).collect();
+            return Err(errors::Error::IncludeError(Message::new("I0002", vec![cycle.join(" → ")]), span));
+        }
+
+        let src = (self.read)(&included)
+            .map_err(|e| errors::Error::IncludeError(Message::new("I0003", vec![included.display().to_string(), e.to_string()]), span))?;
+        let mut tree = self.pipeline.parse(&included, &src)
+            .map_err(|e| errors::Error::InIncludedFile(included.to_owned(), Box::new(e)))?;
+        let mut content = match &mut tree.0 {
+            tree::DocumentElement::Function(root) => std::mem::take(&mut root.content),
+            text @ tree::DocumentElement::Text(_) => vec![text.clone()],
+        };
+        mark_file(&mut content, &included);
+
+        self.included.push(included.to_owned());
+        self.stack.push(normalized);
+        self.resolve(&mut content, &included)?;
+        self.stack.pop();
+        Ok(content)
+    }
+
+    /// Attribute error `e` of the document at `filepath` to this document unless it is the master document
+    fn locate(&self, e: errors::Error, filepath: &path::Path) -> errors::Error {
+        match e {
+            e @ errors::Error::InIncludedFile(..) => e,
+            e if self.stack.len() == 1 => e,
+            e => errors::Error::InIncludedFile(filepath.to_owned(), Box::new(e)),
+        }
+    }
+}
+
+/// Set argument `=file` of all calls of `node` to `filepath`
+fn mark_file(node: &mut tree::DocumentNode, filepath: &path::Path) {
+    for element in node.iter_mut() {
+        if let tree::DocumentElement::Function(func) = element {
+            func.args.insert(tree::FILE_ARG.to_owned(), vec![tree::DocumentElement::Text(filepath.display().to_string())]);
+            for value in func.args.iter_mut().filter(|(key, _)| !tree::is_reserved_arg(key)).map(|(_, value)| value) {
+                mark_file(value, filepath);
+            }
+            mark_file(&mut func.content, filepath);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_nested_includes() {
+        let files = [("book/ch1.lit", "{h1 One}{include parts/a.lit}"), ("book/parts/a.lit", "{p A}"), ("book/loop.lit", "{include ./loop.lit}"), ("book/bad.lit", "{p")];
+        let mut read = |filepath: &path::Path| files.iter()
+            .find(|(name, _)| path::Path::new(name) == filepath)
+            .map(|(_, src)| src.to_string())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not found"));
+        let pipeline = pipeline::Pipeline::new();
+        let master = path::Path::new("book/main.lit");
+
+        let mut tree = pipeline.parse(master, "{include ch1.lit}\n{p end}").expect("valid document");
+        let included = resolve_includes(&mut tree, master, &pipeline, &mut read).expect("includes can be resolved");
+        assert_eq!(included, vec![path::PathBuf::from("book/ch1.lit"), path::PathBuf::from("book/parts/a.lit")]);
+        assert_eq!(tree.to_litua_syntax(), "{h1 One}{p A}\n{p end}");
+        let root = match &tree.0 { tree::DocumentElement::Function(root) => root, _ => unreachable!() };
+        let file_of = |i: usize| match &root.content[i] { tree::DocumentElement::Function(func) => func.args.get(tree::FILE_ARG).cloned(), _ => None };
+        assert_eq!(file_of(1), Some(vec![tree::DocumentElement::Text("book/parts/a.lit".to_owned())]));
+        assert_eq!(file_of(3), None);
+
+        let mut tree = pipeline.parse(master, "{include loop.lit}").expect("valid document");
+        let err = resolve_includes(&mut tree, master, &pipeline, &mut read).expect_err("include cycle");
+        assert!(matches!(&err, errors::Error::InIncludedFile(file, inner) if file == path::Path::new("book/loop.lit") && inner.message().code == "I0002"));
+
+        let mut tree = pipeline.parse(master, "{include bad.lit}").expect("valid document");
+        let err = resolve_includes(&mut tree, master, &pipeline, &mut read).expect_err("syntax error");
+        assert!(matches!(err, errors::Error::InIncludedFile(file, _) if file == path::Path::new("book/bad.lit")));
+    }
+}
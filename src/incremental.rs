@@ -0,0 +1,173 @@
+//! Cache of the output of chunks for incremental builds
+//!
+//! With `--chunk-cache`, the output of every chunk of a document processed
+//! with `--chunked` is stored in a cache file and reused by the next run if
+//! neither the chunk nor the hooks it depends on changed. The key of a chunk
+//! combines the structural hash of its tree (see `tree::DocumentElement::content_hash`),
+//! its index, a hash of the context (configuration, variables, and registered
+//! hooks), and the state persisting across chunks before the chunk. An entry
+//! stores the output, the state after the chunk (restored if the entry is
+//! reused), and the content hashes of the hook files whose hooks ran for the
+//! chunk. An entry is only reused if these hook files are unchanged.
+//! Entries not used by a run are dropped when the cache is written.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+
+use crate::tree;
+
+/// Version of the cache file format (caches of other versions are ignored)
+pub const FORMAT_VERSION: u64 = 1;
+
+/// Arguments of the root call describing the source file (see `file_metadata`).
+/// They change with every edit of the document, so the key ignores them.
+pub const VOLATILE_ROOT_ARGS: [&str; 4] = ["filesize", "modified", "content-hash", "git-commit"];
+
+/// `Entry` is the cached result of transforming one chunk
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Entry {
+    /// output of the chunk (after the `modify_final_string` hooks)
+    pub output: String,
+    /// state persisting across chunks after the chunk (see `recording::lua_to_json`)
+    pub state: serde_json::Value,
+    /// content hashes of the hook files whose hooks ran for the chunk
+    pub hooks: BTreeMap<String, u64>,
+}
+
+/// `Cache` maps the keys of chunks to their cached results
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Cache {
+    entries: BTreeMap<u64, Entry>,
+    /// keys looked up successfully or inserted by this run
+    used: BTreeSet<u64>,
+}
+
+impl Cache {
+    /// Create an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the entry for `key` if the hook files it depends on still have
+    /// the same content hash according to `hook_hash`
+    pub fn lookup<F: FnMut(&str) -> u64>(&mut self, key: u64, mut hook_hash: F) -> Option<&Entry> {
+        let entry = self.entries.get(&key)?;
+        if entry.hooks.iter().any(|(file, hash)| hook_hash(file) != *hash) {
+            return None;
+        }
+        self.used.insert(key);
+        Some(entry)
+    }
+
+    /// Store `entry` for `key` unless its state contains values which cannot
+    /// be restored (like functions). Returns whether the entry was stored.
+    pub fn insert(&mut self, key: u64, entry: Entry) -> bool {
+        if contains_unsupported(&entry.state) {
+            return false;
+        }
+        self.entries.insert(key, entry);
+        self.used.insert(key);
+        true
+    }
+
+    /// Represent the entries used by this run as JSON object with keys
+    /// `version` and `entries` (an object mapping keys as 16 hex digits to
+    /// objects with keys `output`, `state`, and `hooks`)
+    pub fn to_json(&self) -> serde_json::Value {
+        let entries: serde_json::Map<String, serde_json::Value> = self.used.iter()
+            .filter_map(|key| self.entries.get(key).map(|entry| (format!("{key:016x}"), serde_json::json!({
+                "output": entry.output,
+                "state": entry.state,
+                "hooks": entry.hooks.iter().map(|(file, hash)| (file.to_owned(), serde_json::Value::from(format!("{hash:016x}")))).collect::<serde_json::Map<_, _>>(),
+            }))))
+            .collect();
+        serde_json::json!({ "version": FORMAT_VERSION, "entries": entries })
+    }
+
+    /// Read a cache represented as JSON by `to_json`
+    pub fn from_json(value: &serde_json::Value) -> Result<Cache, String> {
+        match value.get("version").and_then(serde_json::Value::as_u64) {
+            Some(FORMAT_VERSION) => {},
+            Some(version) => return Err(format!("cache has format version {version}, but only version {FORMAT_VERSION} is supported")),
+            None => return Err("cache has no format version".to_owned()),
+        }
+        let parse_hash = |text: Option<&str>| text.and_then(|text| u64::from_str_radix(text, 16).ok());
+        let mut cache = Cache::new();
+        for (key, entry) in value.get("entries").and_then(serde_json::Value::as_object).ok_or("cache has no entries")? {
+            let key = parse_hash(Some(key)).ok_or_else(|| format!("cache key '{key}' is not a hexadecimal number"))?;
+            let mut hooks = BTreeMap::new();
+            for (file, hash) in entry.get("hooks").and_then(serde_json::Value::as_object).into_iter().flatten() {
+                let hash = parse_hash(hash.as_str()).ok_or_else(|| format!("hash of hook file '{file}' is not a hexadecimal number"))?;
+                hooks.insert(file.to_owned(), hash);
+            }
+            cache.entries.insert(key, Entry {
+                output: entry.get("output").and_then(serde_json::Value::as_str).ok_or_else(|| format!("cache entry {key:016x} has no output"))?.to_owned(),
+                state: entry.get("state").cloned().unwrap_or_default(),
+                hooks,
+            });
+        }
+        Ok(cache)
+    }
+}
+
+/// Does `value` contain a value which cannot be represented (see `recording::lua_to_json`)?
+fn contains_unsupported(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Object(object) => object.contains_key("unsupported") || object.values().any(contains_unsupported),
+        serde_json::Value::Array(array) => array.iter().any(contains_unsupported),
+        _ => false,
+    }
+}
+
+/// Returns the key of the chunk `chunk` with 0-based index `index` transformed
+/// in the context with hash `context` and the state `state` persisting across chunks
+pub fn chunk_key(chunk: &tree::DocumentTree, index: usize, context: u64, state: &serde_json::Value) -> u64 {
+    let mut bytes = vec![];
+    match &chunk.0 {
+        tree::DocumentElement::Function(root) => {
+            let args = root.args.iter()
+                .filter(|(key, _)| !VOLATILE_ROOT_ARGS.contains(&key.as_str()))
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect();
+            let bare_root = tree::DocumentFunction { call: root.call.clone(), args, content: vec![] };
+            bytes.extend(tree::DocumentElement::Function(bare_root).content_hash().to_le_bytes());
+            for element in root.content.iter() {
+                bytes.extend(element.content_hash().to_le_bytes());
+            }
+        },
+        element => bytes.extend(element.content_hash().to_le_bytes()),
+    }
+    bytes.extend((index as u64).to_le_bytes());
+    bytes.extend(context.to_le_bytes());
+    bytes.extend(state.to_string().as_bytes());
+    tree::stable_hash(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse_unchanged_chunks() {
+        let chunk = |src: &str| crate::parse_str(src).expect("valid document");
+        let state = serde_json::json!({ "table": { "footnote_count": 1 } });
+        let key = chunk_key(&chunk("{p a}"), 0, 42, &state);
+        assert_eq!(chunk_key(&chunk("{p   a}"), 0, 42, &state), key);
+        assert_ne!(chunk_key(&chunk("{p b}"), 0, 42, &state), key);
+        assert_ne!(chunk_key(&chunk("{p a}"), 1, 42, &state), key);
+        assert_ne!(chunk_key(&chunk("{p a}"), 0, 43, &state), key);
+        assert_ne!(chunk_key(&chunk("{p a}"), 0, 42, &serde_json::Value::Null), key);
+
+        let mut cache = Cache::new();
+        let hooks = BTreeMap::from([("hooks.lua".to_owned(), 7)]);
+        assert!(cache.insert(key, Entry { output: "<p>a</p>".to_owned(), state: state.clone(), hooks }));
+        assert!(!cache.insert(1, Entry { state: serde_json::json!({ "unsupported": "function" }), ..Entry::default() }));
+
+        let mut cache = Cache::from_json(&cache.to_json()).expect("valid cache");
+        assert!(cache.lookup(key, |_| 8).is_none());
+        assert_eq!(cache.lookup(key, |_| 7).map(|entry| entry.output.as_str()), Some("<p>a</p>"));
+        assert_eq!(cache.to_json()["entries"].as_object().map(|entries| entries.len()), Some(1));
+        assert_eq!(Cache::new().to_json()["entries"], serde_json::json!({}));
+        assert!(Cache::from_json(&serde_json::json!({ "version": 0, "entries": {} })).is_err());
+    }
+}
@@ -2,11 +2,16 @@
 
 use std::collections::VecDeque;
 use std::fmt;
+use std::iter;
 use std::mem;
 use std::ops;
 use std::str;
 
+use unicode_normalization::char::is_combining_mark;
+
 use crate::errors;
+use crate::header;
+use crate::messages::Message;
 
 // characters part of the litua text document syntax
 
@@ -24,6 +29,91 @@ pub const ASSIGN: char = '=';
 pub const OPEN_RAW: char = '<';
 /// U+003E  GREATER-THAN SIGN
 pub const CLOSE_RAW: char = '>';
+/// U+0040  COMMERCIAL AT (starts a line call if `LexerOptions::line_calls` is set)
+pub const LINE_CALL: char = '@';
+/// maximum number of `OPEN_RAW` characters starting a raw string
+/// (also applies to `LexerOptions::open_raw`)
+pub const MAX_RAW_DELIMITER_LENGTH: u8 = 126;
+
+/// characters admissible in call names besides letters and digits
+/// according to `CallNamePolicy::Identifier`
+pub const CALL_NAME_PUNCTUATION: &str = "-_./:$";
+/// characters admissible in the language tag of a raw string (like `c++` in
+/// `{<<c++ …>>}`) besides the characters of identifier call names
+pub const RAW_LANGUAGE_PUNCTUATION: &str = "+#";
+
+/// `CallNamePolicy` defines which Unicode scalars can occur in call names
+#[derive(Clone,Copy,Debug,Default,Hash,PartialEq,Eq)]
+pub enum CallNamePolicy {
+    /// letters (including combining marks), digits, and `CALL_NAME_PUNCTUATION`
+    #[default]
+    Identifier,
+    /// any character except for whitespace and the delimiters
+    /// terminating a call name (legacy behavior)
+    Permissive,
+}
+
+impl CallNamePolicy {
+    /// Is `chr` admissible in a call name according to this policy?
+    pub fn admits(&self, chr: char) -> bool {
+        match self {
+            CallNamePolicy::Identifier => chr.is_alphanumeric() || is_combining_mark(chr) || CALL_NAME_PUNCTUATION.contains(chr),
+            CallNamePolicy::Permissive => true,
+        }
+    }
+}
+
+/// `LexerOptions` configures the syntax accepted by the lexer
+#[derive(Clone,Debug,PartialEq)]
+pub struct LexerOptions {
+    /// characters admissible in call names
+    pub call_names: CallNamePolicy,
+    /// if set, all whitespace before the terminating `CLOSE_RAW` sequence
+    /// of a raw string is excluded from its text (e.g. a newline and indentation
+    /// if the terminator is on a line of its own). By default, only the single
+    /// mandatory whitespace character is excluded.
+    pub trim_raw_whitespace: bool,
+    /// character starting a raw string (repeated, like `OPEN_RAW`)
+    pub open_raw: char,
+    /// character terminating a raw string (repeated, like `CLOSE_RAW`)
+    pub close_raw: char,
+    /// if set, the shebang and directive lines at the start of the text
+    /// document are skipped (see module `header`)
+    pub skip_header: bool,
+    /// if set, `LINE_CALL` at the start of a line (after optional indentation)
+    /// starts a call like `OPEN_FUNCTION` whose content ends at the end of the line,
+    /// e.g. `@section[level=2] Heading text` is lexed like `{section[level=2] Heading text}`
+    pub line_calls: bool,
+}
+
+impl Default for LexerOptions {
+    fn default() -> Self {
+        Self {
+            call_names: CallNamePolicy::default(),
+            trim_raw_whitespace: false,
+            open_raw: OPEN_RAW,
+            close_raw: CLOSE_RAW,
+            skip_header: true,
+            line_calls: false,
+        }
+    }
+}
+
+impl LexerOptions {
+    /// Verify that the raw string delimiters can be distinguished
+    /// from whitespace, the remaining delimiters and call names
+    pub fn validate(&self) -> Result<(), String> {
+        for (desc, chr) in [("opening", self.open_raw), ("closing", self.close_raw)] {
+            if chr.is_whitespace() || [OPEN_FUNCTION, CLOSE_FUNCTION, OPEN_ARG, CLOSE_ARG, ASSIGN].contains(&chr) {
+                return Err(format!("{desc} raw string delimiter '{chr}' must not be whitespace or one of '{OPEN_FUNCTION}{CLOSE_FUNCTION}{OPEN_ARG}{CLOSE_ARG}{ASSIGN}'"));
+            }
+        }
+        if CallNamePolicy::Identifier.admits(self.open_raw) {
+            return Err(format!("opening raw string delimiter '{}' must not be admissible in call names", self.open_raw));
+        }
+        Ok(())
+    }
+}
 
 /// `Lexer` is an object holding a reference to the source code
 /// of the text document to lex. Method `iter()` returns an
@@ -33,15 +123,21 @@ pub const CLOSE_RAW: char = '>';
 pub struct Lexer<'l> {
     /// reference to source code
     pub source: &'l str,
+    /// syntax configuration
+    pub options: LexerOptions,
 }
 
 impl<'l> Lexer<'l> {
     pub fn new(src: &'l str) -> Self {
-        Self { source: src }
+        Self { source: src, options: LexerOptions::default() }
+    }
+
+    pub fn with_options(src: &'l str, options: LexerOptions) -> Self {
+        Self { source: src, options }
     }
 
-    pub fn iter(&'l self) -> LexingIterator {
-        LexingIterator::new(self.source)
+    pub fn iter(&'l self) -> LexingIterator<'l> {
+        LexingIterator::with_options(self.source, self.options.clone())
     }
 }
 
@@ -67,7 +163,8 @@ enum LexingScope {
 /// first or more characters” whereas Found prefixes mean
 /// “I just read the first character”. For details, please
 /// refer to the state diagrams in the `design/` folder.
-#[derive(Clone,Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+#[non_exhaustive]
 pub enum LexingState {
     ReadingContent,
     ReadingContentText,
@@ -75,10 +172,12 @@ pub enum LexingState {
     ReadingArgumentValueText,
     FoundCallOpening,
     StartRaw,
+    ReadingRawLanguage,
     ReadingRaw,
     FoundWhitespaceRaw,
     EndRaw,
     ReadingCallName,
+    ReadingWhitespace,
     FoundArgumentOpening,
     FoundArgumentClosing,
     Terminated,
@@ -93,10 +192,12 @@ impl fmt::Display for LexingState {
             LexingState::ReadingArgumentValueText => write!(f, "reading text inside an argument value"),
             LexingState::FoundCallOpening => write!(f, "reading the start of a function call"),
             LexingState::StartRaw => write!(f, "starting a raw string"),
+            LexingState::ReadingRawLanguage => write!(f, "reading the language tag of a raw string"),
             LexingState::ReadingRaw => write!(f, "reading raw string"),
             LexingState::FoundWhitespaceRaw => write!(f, "reading whitespace in raw string"),
             LexingState::EndRaw => write!(f, "terminating raw string"),
             LexingState::ReadingCallName => write!(f, "reading the name of a function call"),
+            LexingState::ReadingWhitespace => write!(f, "reading whitespace before content"),
             LexingState::FoundArgumentOpening => write!(f, "reading a function argument"),
             LexingState::FoundArgumentClosing => write!(f, "finishing one function argument"),
             LexingState::Terminated => write!(f, "terminating"),
@@ -105,10 +206,10 @@ impl fmt::Display for LexingState {
 }
 
 /// `LexingIteratior` is the object you receive when calling `.iter()` on the `Lexer` object.
-#[derive(Debug)]
+#[derive(Clone,Debug)]
 pub struct LexingIterator<'l> {
     /// State of this iterator
-    pub state: LexingState,
+    state: LexingState,
     /// Number of bytes to be read by this lexer.
     /// Only used to handle EOF properly.
     source_byte_length: usize,
@@ -129,6 +230,13 @@ pub struct LexingIterator<'l> {
     token_rawcontent_start: usize,
     /// Last whitespace character read (only used in raw strings)
     token_whitespace: char,
+    /// Byte offset where the latest sequence of whitespace characters
+    /// within the raw string content started (only used with `trim_raw_whitespace`)
+    raw_whitespace_start: usize,
+    /// Byte offset where the whitespace separating the call name or arguments
+    /// from the content started.
+    /// e.g. while lexing 'X' in ``{item   X``, `whitespace_start` points to the first ' '.
+    whitespace_start: usize,
     /// raw strings end with a repetition of “>” where the number matches
     /// the number of “<” of the beginning. Thus we store the number of
     /// characters here.
@@ -148,10 +256,39 @@ pub struct LexingIterator<'l> {
     /// `progress()` is one token, but sometimes several tokens are generated.
     /// In this case, the tokens are `push_back`ed and consecutively
     /// `pop_front`ed to process them.
-    pub next_tokens: VecDeque<Token>,
+    next_tokens: VecDeque<Token>,
     /// if an error occured, the error is returned once
     /// and the lexer switches to the infinite EOF state
-    pub occured_error: Option<errors::Error>,
+    occured_error: Option<errors::Error>,
+    /// syntax configuration
+    options: LexerOptions,
+    /// if enabled, the state transitions recorded since the last `take_trace`
+    trace: Option<Vec<LexingTransition>>,
+    /// Were only spaces and tabs read since the last line break (or the start)?
+    line_start: bool,
+    /// depths in `stack` of the `Function` scopes of the open line calls
+    line_calls: Vec<usize>,
+}
+
+/// `LexingTransition` records how the lexer processed one Unicode scalar
+#[derive(Clone,Debug,PartialEq)]
+pub struct LexingTransition {
+    /// UTF-8 byte offset of the Unicode scalar
+    pub byte_offset: usize,
+    /// state before reading the Unicode scalar
+    pub old_state: LexingState,
+    /// the Unicode scalar read
+    pub chr: char,
+    /// state after reading the Unicode scalar
+    pub new_state: LexingState,
+    /// tokens emitted because of this Unicode scalar
+    pub emitted_tokens: Vec<Token>,
+}
+
+impl fmt::Display for LexingTransition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\t{:?}\t{:?} -> {:?}\t{:?}", self.byte_offset, self.chr, self.old_state, self.new_state, self.emitted_tokens)
+    }
 }
 
 impl<'l> LexingIterator<'l> {
@@ -160,20 +297,115 @@ impl<'l> LexingIterator<'l> {
 
     /// Create a `LexingIterator` instance based on the source code `src`
     /// of the text document provided.
-    pub fn new(src: &str) -> LexingIterator {
+    pub fn new(src: &str) -> LexingIterator<'_> {
+        Self::with_options(src, LexerOptions::default())
+    }
+
+    /// Create a `LexingIterator` instance based on the source code `src`
+    /// of the text document provided and the syntax configuration `options`.
+    pub fn with_options(src: &str, options: LexerOptions) -> LexingIterator<'_> {
+        let mut chars = src.char_indices();
+        let mut token_start = 0;
+        if options.skip_header {
+            let header_length = header::header_length(src);
+            if header_length > 0 {
+                // NOTE: skip the header, but keep byte offsets relative to `src`
+                chars.nth(src[..header_length].chars().count() - 1);
+                token_start = Self::START_TOKEN_AT_NEXT_BYTEOFFSET;
+            }
+        }
+
         LexingIterator {
             state: LexingState::ReadingContent,
             source_byte_length: src.len(),
-            token_start: 0,
+            token_start,
             token_function_start: 0,
             token_rawcontent_start: 0,
             token_whitespace: ' ',
+            raw_whitespace_start: 0,
+            whitespace_start: 0,
             raw_delimiter_length: 0,
             raw_delimiter_read: 0,
-            chars: src.char_indices(),
+            chars,
             stack: vec![LexingScope::Content],
             next_tokens: VecDeque::new(),
             occured_error: None,
+            options,
+            trace: None,
+            line_start: true,
+            line_calls: Vec::new(),
+        }
+    }
+
+    /// Is the innermost open call a line call whose call name, arguments,
+    /// or content (outside of nested calls) are being read?
+    fn in_line_call(&self) -> bool {
+        let depth = match self.stack.last() {
+            Some(LexingScope::Function) => self.stack.len() - 1,
+            Some(LexingScope::Content) => self.stack.len().saturating_sub(2),
+            _ => return false,
+        };
+        self.line_calls.last() == Some(&depth)
+    }
+
+    /// Is `chr` admissible in the language tag of a raw string?
+    fn is_raw_language_char(&self, chr: char) -> bool {
+        chr != self.options.open_raw && (CallNamePolicy::Identifier.admits(chr) || RAW_LANGUAGE_PUNCTUATION.contains(chr))
+    }
+
+    /// Start the text of a raw string after its delimiters (and language tag)
+    /// and the mandatory whitespace `chr` at `byte_offset`
+    fn begin_raw_content(&mut self, chr: char, byte_offset: usize) {
+        self.raw_delimiter_read = 0;
+        self.next_tokens.push_back(Token::BeginRaw(self.token_function_start + OPEN_FUNCTION.len_utf8()..byte_offset));
+        self.next_tokens.push_back(Token::Whitespace(byte_offset..byte_offset + chr.len_utf8()));
+        self.push_scope(LexingScope::RawString, byte_offset);
+        self.token_start = Self::START_TOKEN_AT_NEXT_BYTEOFFSET;
+        self.token_rawcontent_start = Self::START_TOKEN_AT_NEXT_BYTEOFFSET;
+        self.state = LexingState::ReadingRaw;
+    }
+
+    /// Terminate the innermost line call before `byte_offset`
+    /// (the line break, `CLOSE_FUNCTION`, or the end of the document)
+    fn end_line_call(&mut self, byte_offset: usize) {
+        use LexingState::*;
+
+        match self.state {
+            ReadingCallName => {
+                self.next_tokens.push_back(Token::Call(self.token_start..byte_offset));
+                self.next_tokens.push_back(Token::EndFunction(byte_offset));
+                self.pop_scope(byte_offset);
+            },
+            FoundArgumentClosing => {
+                self.next_tokens.push_back(Token::EndArgs(self.token_start));
+                self.next_tokens.push_back(Token::EndFunction(byte_offset));
+                self.pop_scope(byte_offset);
+            },
+            _ => {
+                if self.state == ReadingWhitespace {
+                    self.next_tokens.push_back(Token::Whitespace(self.whitespace_start..byte_offset));
+                }
+                if self.state == ReadingWhitespace || self.token_start == Self::START_AND_EMIT_TOKEN_AT_NEXT_BYTEOFFSET {
+                    self.next_tokens.push_back(Token::BeginContent(byte_offset));
+                } else if self.state == ReadingContentText {
+                    self.next_tokens.push_back(Token::Text(self.token_start..byte_offset));
+                }
+                self.next_tokens.push_back(Token::EndContent(byte_offset));
+                self.pop_scope(byte_offset);
+            },
+        }
+        self.line_calls.pop();
+        self.state = ReadingContent;
+        self.token_start = byte_offset;
+        self.token_function_start = Self::START_TOKEN_AT_NEXT_BYTEOFFSET;
+    }
+
+    /// Terminate with an error if `chr` is not admissible in call names
+    fn check_call_name_char(&mut self, chr: char, byte_offset: usize) {
+        if !self.options.call_names.admits(chr) {
+            let msg = Message::new("L0001", vec![chr.escape_debug().to_string(), format!("{:04X}", chr as u32), CALL_NAME_PUNCTUATION.to_owned()]);
+            self.occured_error = Some(errors::Error::InvalidSyntax(msg, byte_offset));
+            self.state = LexingState::Terminated;
         }
     }
 
@@ -189,7 +421,7 @@ impl<'l> LexingIterator<'l> {
             Some(t) => t,
             None => {
                 self.state = LexingState::Terminated;
-                self.occured_error = Some(errors::Error::UnbalancedParentheses(format!("scope ended at byte {} but it never started", byte_offset), byte_offset));
+                self.occured_error = Some(errors::Error::UnbalancedParentheses(Message::new("L0002", vec![byte_offset.to_string()]), byte_offset));
                 return;
             }
         };
@@ -198,7 +430,7 @@ impl<'l> LexingIterator<'l> {
             Some(t) => t,
             None => {
                 self.state = LexingState::Terminated;
-                self.occured_error = Some(errors::Error::UnbalancedParentheses(format!("scope {:?} ended at byte {} but it never started", old_top, byte_offset), byte_offset));
+                self.occured_error = Some(errors::Error::UnbalancedParentheses(Message::new("L0003", vec![format!("{:?}", old_top), byte_offset.to_string()]), byte_offset));
                 return;
             }
         };
@@ -254,6 +486,15 @@ impl<'l> LexingIterator<'l> {
         let (byte_offset, chr) = match self.chars.next() {
             Some((bo, ch)) => (bo, ch),
             None => {
+                if self.in_line_call() && matches!(self.state, ReadingCallName | FoundArgumentClosing | ReadingWhitespace | ReadingContent | ReadingContentText) {
+                    self.end_line_call(self.source_byte_length);
+                    return None;
+                }
+                if self.state == ReadingWhitespace {
+                    self.next_tokens.push_back(Token::Whitespace(self.whitespace_start..self.source_byte_length));
+                    self.state = ReadingContent;
+                    return None;
+                }
                 if self.token_start != self.source_byte_length &&
                    self.token_start != Self::START_TOKEN_AT_NEXT_BYTEOFFSET &&
                    self.token_start != Self::START_AND_EMIT_TOKEN_AT_NEXT_BYTEOFFSET
@@ -267,7 +508,28 @@ impl<'l> LexingIterator<'l> {
             },
         };
 
-        // eprintln!("state {:?} and now char '{}'", self.state, chr);
+        let old_state = self.trace.as_ref().map(|_| self.state);
+        let line_start = self.line_start;
+        self.line_start = match chr {
+            '\n' | '\r' => true,
+            ' ' | '\t' => line_start,
+            _ => false,
+        };
+
+        // NOTE: the whitespace run separating call name or arguments from the content
+        //       ends with the first non-whitespace character, which is part of the content
+        if self.state == ReadingWhitespace && !chr.is_whitespace() {
+            self.next_tokens.push_back(Token::Whitespace(self.whitespace_start..byte_offset));
+            self.state = ReadingContent;
+        }
+
+        // NOTE: a line break ends the content of a line call, which continues
+        //       in the enclosing content. `CLOSE_FUNCTION` ends both.
+        if matches!(chr, '\n' | '\r' | CLOSE_FUNCTION) && self.in_line_call()
+            && matches!(self.state, ReadingCallName | FoundArgumentClosing | ReadingWhitespace | ReadingContent | ReadingContentText)
+        {
+            self.end_line_call(byte_offset);
+        }
 
         match self.state {
             ReadingContent => {
@@ -286,6 +548,12 @@ impl<'l> LexingIterator<'l> {
                         self.token_function_start = byte_offset;
                         self.state = FoundCallOpening;
                     },
+                    LINE_CALL if self.options.line_calls && line_start => {
+                        self.token_start = byte_offset;
+                        self.token_function_start = byte_offset;
+                        self.line_calls.push(self.stack.len());
+                        self.state = FoundCallOpening;
+                    },
                     CLOSE_FUNCTION => {
                         self.next_tokens.push_back(Token::EndContent(byte_offset));
                         self.token_start = byte_offset;
@@ -305,6 +573,13 @@ impl<'l> LexingIterator<'l> {
                         self.token_function_start = byte_offset;
                         self.state = FoundCallOpening;
                     },
+                    LINE_CALL if self.options.line_calls && line_start => {
+                        self.next_tokens.push_back(Token::Text(self.token_start..byte_offset));
+                        self.token_start = byte_offset;
+                        self.token_function_start = byte_offset;
+                        self.line_calls.push(self.stack.len());
+                        self.state = FoundCallOpening;
+                    },
                     CLOSE_FUNCTION => {
                         self.next_tokens.push_back(Token::Text(self.token_start..byte_offset));
                         self.next_tokens.push_back(Token::EndContent(byte_offset));
@@ -369,11 +644,12 @@ impl<'l> LexingIterator<'l> {
                 match chr {
                     CLOSE_FUNCTION => {
                         self.next_tokens.push_back(Token::BeginFunction(self.token_start));
-                        let msg = format!("call '{OPEN_FUNCTION}' was immediately closed by '{CLOSE_FUNCTION}', but empty calls are not allowed");
+                        let msg = Message::new("L0004", vec![OPEN_FUNCTION.to_string(), CLOSE_FUNCTION.to_string()]);
                         self.occured_error = Some(errors::Error::InvalidSyntax(msg, byte_offset));
                         self.state = Terminated;
                     },
-                    OPEN_RAW => {
+                    // NOTE: line calls cannot be raw strings
+                    c if c == self.options.open_raw && self.line_calls.last() != Some(&self.stack.len()) => {
                         self.token_start = byte_offset;
                         self.raw_delimiter_length = 1;
                         self.state = StartRaw;
@@ -383,29 +659,36 @@ impl<'l> LexingIterator<'l> {
                         self.next_tokens.push_back(Token::BeginFunction(self.token_start));
                         self.token_start = byte_offset;
                         self.state = ReadingCallName;
+                        self.check_call_name_char(chr, byte_offset);
                     },
                 }
             },
             StartRaw => {
                 match chr {
-                    OPEN_RAW => {
+                    c if c == self.options.open_raw => {
                         self.raw_delimiter_length += 1;
-                        if self.raw_delimiter_length == 127 {
-                            self.occured_error = Some(errors::Error::InvalidSyntax("raw string delimiter must not exceed length 126".to_string(), byte_offset));
+                        if self.raw_delimiter_length > MAX_RAW_DELIMITER_LENGTH {
+                            let msg = Message::new("L0005", vec![MAX_RAW_DELIMITER_LENGTH.to_string()]);
+                            self.occured_error = Some(errors::Error::InvalidSyntax(msg, byte_offset));
                             self.state = Terminated;
                         }
                     },
-                    c if c.is_whitespace() => {
-                        self.raw_delimiter_read = 0;
-                        self.next_tokens.push_back(Token::BeginRaw(self.token_function_start + OPEN_FUNCTION.len_utf8()..byte_offset));
-                        self.next_tokens.push_back(Token::Whitespace(byte_offset, c));
-                        self.push_scope(LexingScope::RawString, byte_offset);
-                        self.token_start = Self::START_TOKEN_AT_NEXT_BYTEOFFSET;
-                        self.token_rawcontent_start = Self::START_TOKEN_AT_NEXT_BYTEOFFSET;
-                        self.state = ReadingRaw;
+                    c if c.is_whitespace() => self.begin_raw_content(c, byte_offset),
+                    // NOTE: a language tag like `lua` in `{<<lua …>>}` is part of the BeginRaw token
+                    c if self.is_raw_language_char(c) => self.state = ReadingRawLanguage,
+                    c => {
+                        let msg = Message::new("L0006", vec![c.to_string()]);
+                        self.occured_error = Some(errors::Error::InvalidSyntax(msg, byte_offset));
+                        self.state = Terminated;
                     },
+                }
+            },
+            ReadingRawLanguage => {
+                match chr {
+                    c if c.is_whitespace() => self.begin_raw_content(c, byte_offset),
+                    c if self.is_raw_language_char(c) => {},
                     c => {
-                        let msg = format!("unexpected character '{c}' while reading raw string start");
+                        let msg = Message::new("L0006", vec![c.to_string()]);
                         self.occured_error = Some(errors::Error::InvalidSyntax(msg, byte_offset));
                         self.state = Terminated;
                     },
@@ -421,6 +704,7 @@ impl<'l> LexingIterator<'l> {
                         self.state = FoundWhitespaceRaw;
                         self.token_whitespace = c;
                         self.token_start = byte_offset;
+                        self.raw_whitespace_start = byte_offset;
                     },
                     _ => {
                         self.raw_delimiter_read = 0;
@@ -429,7 +713,7 @@ impl<'l> LexingIterator<'l> {
             },
             FoundWhitespaceRaw => {
                 match chr {
-                    CLOSE_RAW => {
+                    c if c == self.options.close_raw => {
                         self.raw_delimiter_read += 1;
                         if self.raw_delimiter_read == self.raw_delimiter_length {
                             self.state = EndRaw;
@@ -437,6 +721,12 @@ impl<'l> LexingIterator<'l> {
                     },
                     c if c.is_whitespace() => {
                         // NOTE: special case if a whitespace occurs after a whitespace
+                        if self.raw_delimiter_read > 0 {
+                            // NOTE: the whitespace sequence was interrupted by an incomplete
+                            //       CLOSE_RAW sequence, which is part of the content
+                            self.raw_whitespace_start = byte_offset;
+                            self.raw_delimiter_read = 0;
+                        }
                         self.state = FoundWhitespaceRaw;
                         self.token_whitespace = c;
                         self.token_start = byte_offset;
@@ -450,8 +740,13 @@ impl<'l> LexingIterator<'l> {
             EndRaw => {
                 match chr {
                     CLOSE_FUNCTION => {
-                        self.next_tokens.push_back(Token::Text(self.token_rawcontent_start..self.token_start));
-                        self.next_tokens.push_back(Token::Whitespace(self.token_start, self.token_whitespace));
+                        let text_end = if self.options.trim_raw_whitespace {
+                            self.raw_whitespace_start
+                        } else {
+                            self.token_start
+                        };
+                        self.next_tokens.push_back(Token::Text(self.token_rawcontent_start..text_end));
+                        self.next_tokens.push_back(Token::Whitespace(self.token_start..self.token_start + self.token_whitespace.len_utf8()));
                         self.next_tokens.push_back(Token::EndRaw(self.token_start + self.token_whitespace.len_utf8()..byte_offset));
                         self.token_start = Self::START_TOKEN_AT_NEXT_BYTEOFFSET;
                         self.token_function_start = Self::START_TOKEN_AT_NEXT_BYTEOFFSET;
@@ -459,7 +754,7 @@ impl<'l> LexingIterator<'l> {
                         self.pop_scope(byte_offset);
                     },
                     _ => {
-                        let msg = format!("unexpected character '{chr}' - only '}}' after a '>' sequence terminates a raw string");
+                        let msg = Message::new("L0007", vec![chr.to_string(), self.options.close_raw.to_string()]);
                         self.occured_error = Some(errors::Error::InvalidSyntax(msg, byte_offset));
                         self.state = Terminated;
                     }
@@ -476,10 +771,10 @@ impl<'l> LexingIterator<'l> {
                     },
                     c if c.is_whitespace() => {
                         self.next_tokens.push_back(Token::Call(self.token_start..byte_offset));
-                        self.next_tokens.push_back(Token::Whitespace(byte_offset, c));
                         self.push_scope(LexingScope::Content, byte_offset);
                         self.token_start = Self::START_AND_EMIT_TOKEN_AT_NEXT_BYTEOFFSET;
-                        self.state = ReadingContent;
+                        self.whitespace_start = byte_offset;
+                        self.state = ReadingWhitespace;
                     },
                     OPEN_ARG => {
                         self.next_tokens.push_back(Token::Call(self.token_start..byte_offset));
@@ -487,13 +782,13 @@ impl<'l> LexingIterator<'l> {
                         self.token_start = Self::START_TOKEN_AT_NEXT_BYTEOFFSET;
                         self.state = FoundArgumentOpening;
                     },
-                    _ => {},
+                    _ => self.check_call_name_char(chr, byte_offset),
                 }
             },
             FoundArgumentOpening => {
                 match chr {
                     ASSIGN if self.token_start == Self::START_TOKEN_AT_NEXT_BYTEOFFSET => {
-                        self.occured_error = Some(errors::Error::InvalidSyntax("argument key must not be an empty string".to_string(), byte_offset));
+                        self.occured_error = Some(errors::Error::InvalidSyntax(Message::new("L0008", vec![ASSIGN.to_string()]), byte_offset));
                         self.state = Terminated;
                     },
                     ASSIGN => {
@@ -523,35 +818,85 @@ impl<'l> LexingIterator<'l> {
                     },
                     c if c.is_whitespace() => {
                         self.next_tokens.push_back(Token::EndArgs(self.token_start));
-                        self.next_tokens.push_back(Token::Whitespace(byte_offset, c));
                         self.push_scope(LexingScope::Content, byte_offset);
                         self.token_start = Self::START_AND_EMIT_TOKEN_AT_NEXT_BYTEOFFSET;
                         self.token_rawcontent_start = Self::START_TOKEN_AT_NEXT_BYTEOFFSET;
-                        self.state = ReadingContent;
+                        self.whitespace_start = byte_offset;
+                        self.state = ReadingWhitespace;
                     },
                     _ => {
                         self.state = Terminated;
-                        let msg = format!("after ending arguments with '{CLOSE_ARG}', I require a whitespace character to continue with content");
+                        let msg = Message::new("L0009", vec![CLOSE_ARG.to_string()]);
                         self.occured_error = Some(errors::Error::InvalidSyntax(msg, byte_offset));
                     }
                 }
             },
+            ReadingWhitespace => {},
             Terminated => {},
         }
 
+        if let (Some(trace), Some(old_state)) = (&mut self.trace, old_state) {
+            trace.push(LexingTransition {
+                byte_offset,
+                old_state,
+                chr,
+                new_state: self.state,
+                emitted_tokens: self.next_tokens.iter().cloned().collect(),
+            });
+        }
+
         self.next_tokens.pop_front()
     }
 
     pub(crate) fn emit_occured_error(&mut self) -> Option<errors::Error> {
         mem::take(&mut self.occured_error)
     }
+
+    /// Returns the current state of this iterator
+    pub fn state(&self) -> LexingState {
+        self.state
+    }
+
+    /// Capture the current position of this iterator.
+    /// Pass the result to `restore` to continue lexing from this
+    /// position again without re-lexing from the start.
+    pub fn checkpoint(&self) -> LexingCheckpoint<'l> {
+        LexingCheckpoint(self.clone())
+    }
+
+    /// Reset this iterator to the position captured by `checkpoint`
+    pub fn restore(&mut self, checkpoint: LexingCheckpoint<'l>) {
+        *self = checkpoint.0;
+    }
+
+    /// Start recording a `LexingTransition` for every Unicode scalar read
+    pub fn enable_trace(&mut self) {
+        if self.trace.is_none() {
+            self.trace = Some(Vec::new());
+        }
+    }
+
+    /// Return the transitions recorded since the last call
+    /// (empty unless `enable_trace` was called)
+    pub fn take_trace(&mut self) -> Vec<LexingTransition> {
+        match &mut self.trace {
+            Some(trace) => mem::take(trace),
+            None => Vec::new(),
+        }
+    }
 }
 
+/// `LexingCheckpoint` is an opaque position of a `LexingIterator`
+/// returned by `LexingIterator::checkpoint`
+#[derive(Clone,Debug)]
+pub struct LexingCheckpoint<'l>(LexingIterator<'l>);
+
 /// Tokens as interface between lexer and parser. The arguments of some
 /// variant refer to a byte position within the source document where
 /// this token happens (1-ary) or goes from-to (`ops::Range` instances).
-/// `Whitespace` is an exception since it provides the whitespace character
-/// directly.
+/// `Whitespace` covers the entire run of whitespace characters separating
+/// a call name or arguments from the content, but only the single mandatory
+/// whitespace character delimiting raw string content.
 /// 
 /// The admissible sequences of `Token`s is not specified here. It is an
 /// implicit contract between lexer and parser.
@@ -559,7 +904,7 @@ impl<'l> LexingIterator<'l> {
 pub enum Token {
     BeginFunction(usize),
     Call(ops::Range<usize>),
-    Whitespace(usize, char),
+    Whitespace(ops::Range<usize>),
     BeginArgs(usize),
     ArgKey(ops::Range<usize>),
     BeginArgValue(usize),
@@ -568,6 +913,7 @@ pub enum Token {
     BeginContent(usize),
     EndContent(usize),
     EndFunction(usize),
+    /// the opening delimiters of a raw string followed by its optional language tag
     BeginRaw(ops::Range<usize>),
     EndRaw(ops::Range<usize>),
     Text(ops::Range<usize>),
@@ -590,8 +936,8 @@ impl Token {
             Token::EndContent(byte_offset) |
             Token::EndFunction(byte_offset) |
             Token::EndOfFile(byte_offset) => (*byte_offset, None),
-            Token::Whitespace(byte_offset, _) => (*byte_offset, None),
             Token::Call(range) |
+            Token::Whitespace(range) |
             Token::ArgKey(range) |
             Token::BeginRaw(range) |
             Token::EndRaw(range) |
@@ -610,7 +956,7 @@ impl Token {
             Token::EndContent(_) => "EndContent",
             Token::EndFunction(_) => "EndFunction",
             Token::EndOfFile(_) => "EndOfFile",
-            Token::Whitespace(_, _) => "Whitespace",
+            Token::Whitespace(_) => "Whitespace",
             Token::Call(_) => "Call",
             Token::ArgKey(_) => "ArgKey",
             Token::BeginRaw(_) => "BeginRaw",
@@ -659,13 +1005,140 @@ impl<'l> Iterator for LexingIterator<'l> {
             }
         }
     }
+
+    /// Unless the lexer terminated, at least one more item (some token,
+    /// `Token::EndOfFile` or an error) follows the pending tokens.
+    /// The number of tokens per Unicode scalar is not bounded by a
+    /// small constant, thus no upper bound is given before termination.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let pending = self.next_tokens.len() + usize::from(self.occured_error.is_some());
+        if self.state == LexingState::Terminated {
+            (pending, Some(pending))
+        } else {
+            (self.next_tokens.len() + 1, None)
+        }
+    }
 }
 
+impl<'l> iter::FusedIterator for LexingIterator<'l> {}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn lex_trace() -> Result<(), errors::Error> {
+        let input = "{{i}";
+        let lex = Lexer::with_options(input, LexerOptions { call_names: CallNamePolicy::Permissive, ..Default::default() });
+        let mut iter = lex.iter();
+        iter.enable_trace();
+        assert_eq!(iter.next().unwrap()?, Token::BeginFunction(0));
+
+        let trace = iter.take_trace();
+        assert_eq!(trace.len(), 2);
+        assert_eq!(trace[0].old_state, LexingState::ReadingContent);
+        assert_eq!(trace[0].new_state, LexingState::FoundCallOpening);
+        assert!(trace[0].emitted_tokens.is_empty());
+        assert_eq!((trace[1].byte_offset, trace[1].chr), (1, '{'));
+        assert_eq!(trace[1].new_state, LexingState::ReadingCallName);
+        assert_eq!(trace[1].emitted_tokens, vec![Token::BeginFunction(0)]);
+        assert!(iter.take_trace().is_empty());
+
+        assert_eq!(iter.next().unwrap()?, Token::Call(1..3));
+        assert_eq!(iter.take_trace().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn lex_whitespace_runs() -> Result<(), errors::Error> {
+        let input = "{a \n\t b}{c[d=e]  }{f  ";
+        let lex = Lexer::new(input);
+        let mut iter = lex.iter();
+        assert_eq!(iter.next().unwrap()?, Token::BeginFunction(0));
+        assert_eq!(iter.next().unwrap()?, Token::Call(1..2));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(2..6));
+        assert_eq!(iter.next().unwrap()?, Token::BeginContent(6));
+        assert_eq!(iter.next().unwrap()?, Token::Text(6..7));
+        assert_eq!(iter.next().unwrap()?, Token::EndContent(7));
+        assert_eq!(iter.next().unwrap()?, Token::EndFunction(7));
+        assert_eq!(iter.next().unwrap()?, Token::BeginFunction(8));
+        assert_eq!(iter.next().unwrap()?, Token::Call(9..10));
+        assert_eq!(iter.next().unwrap()?, Token::BeginArgs(10));
+        assert_eq!(iter.next().unwrap()?, Token::ArgKey(11..12));
+        assert_eq!(iter.next().unwrap()?, Token::BeginArgValue(13));
+        assert_eq!(iter.next().unwrap()?, Token::Text(13..14));
+        assert_eq!(iter.next().unwrap()?, Token::EndArgValue(14));
+        assert_eq!(iter.next().unwrap()?, Token::EndArgs(14));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(15..17));
+        assert_eq!(iter.next().unwrap()?, Token::BeginContent(17));
+        assert_eq!(iter.next().unwrap()?, Token::EndContent(17));
+        assert_eq!(iter.next().unwrap()?, Token::EndFunction(17));
+        assert_eq!(iter.next().unwrap()?, Token::BeginFunction(18));
+        assert_eq!(iter.next().unwrap()?, Token::Call(19..20));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(20..22));
+        Ok(())
+    }
+
+    #[test]
+    fn lex_skip_header() -> Result<(), errors::Error> {
+        let input = "#!/usr/bin/env litua\n%!litua a=b\nx{y}";
+        let lex = Lexer::new(input);
+        let mut iter = lex.iter();
+        assert_eq!(iter.next().unwrap()?, Token::Text(33..34));
+        assert_eq!(iter.next().unwrap()?, Token::BeginFunction(34));
+
+        let lex = Lexer::with_options(input, LexerOptions { skip_header: false, call_names: CallNamePolicy::Permissive, ..Default::default() });
+        let mut iter = lex.iter();
+        assert_eq!(iter.next().unwrap()?, Token::Text(0..34));
+
+        let input = "%!litua a=b\n";
+        let tokens: Vec<Token> = Lexer::new(input).iter().collect::<Result<_, _>>()?;
+        assert_eq!(tokens, vec![Token::EndOfFile(input.len())]);
+        Ok(())
+    }
+
+    #[test]
+    fn lex_line_calls() -> Result<(), errors::Error> {
+        let input = "@h1 A\r\n@br\n@p[x=y]";
+        let lex = Lexer::with_options(input, LexerOptions { line_calls: true, ..Default::default() });
+        let tokens: Vec<Token> = lex.iter().collect::<Result<_, _>>()?;
+        assert_eq!(tokens, vec![
+            Token::BeginFunction(0), Token::Call(1..3), Token::Whitespace(3..4),
+            Token::BeginContent(4), Token::Text(4..5), Token::EndContent(5), Token::EndFunction(5),
+            Token::Text(5..7),
+            Token::BeginFunction(7), Token::Call(8..10), Token::EndFunction(10),
+            Token::Text(10..11),
+            Token::BeginFunction(11), Token::Call(12..13), Token::BeginArgs(13), Token::ArgKey(14..15),
+            Token::BeginArgValue(16), Token::Text(16..17), Token::EndArgValue(17), Token::EndArgs(17), Token::EndFunction(input.len()),
+            Token::EndOfFile(input.len()),
+        ]);
+
+        let tokens: Vec<Token> = Lexer::new("@h1 A").iter().collect::<Result<_, _>>()?;
+        assert_eq!(tokens, vec![Token::Text(0..5), Token::EndOfFile(5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn lex_checkpoint_restore() -> Result<(), errors::Error> {
+        let input = "{a[b=c] d}";
+        let lex = Lexer::new(input);
+        let mut iter = lex.iter();
+        assert_eq!(iter.size_hint(), (1, None));
+        assert_eq!(iter.next().unwrap()?, Token::BeginFunction(0));
+        let checkpoint = iter.checkpoint();
+        let remaining: Vec<Token> = iter.by_ref().collect::<Result<_, _>>()?;
+        assert_eq!(remaining.last(), Some(&Token::EndOfFile(input.len())));
+        assert_eq!(iter.size_hint(), (0, Some(0)));
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+
+        iter.restore(checkpoint);
+        let again: Vec<Token> = iter.collect::<Result<_, _>>()?;
+        assert_eq!(remaining, again);
+        Ok(())
+    }
+
     #[test]
     fn lex_only_text() -> Result<(), errors::Error> {
         let input = "hello";
@@ -720,7 +1193,7 @@ mod tests {
         assert_eq!(iter.next().unwrap()?, Token::Text(22..24));
         assert_eq!(iter.next().unwrap()?, Token::EndArgValue(24));
         assert_eq!(iter.next().unwrap()?, Token::EndArgs(24));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(25, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(25..26));
         assert_eq!(iter.next().unwrap()?, Token::BeginContent(26));
         assert_eq!(iter.next().unwrap()?, Token::Text(26..37));
         assert_eq!(iter.next().unwrap()?, Token::EndContent(37));
@@ -735,9 +1208,9 @@ mod tests {
         let mut iter = lex.iter();
         assert_eq!(iter.next().unwrap()?, Token::Text(0..1));
         assert_eq!(iter.next().unwrap()?, Token::BeginRaw(2..5));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(5, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(5..6));
         assert_eq!(iter.next().unwrap()?, Token::Text(6..10));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(10, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(10..11));
         assert_eq!(iter.next().unwrap()?, Token::EndRaw(11..14));
         assert_eq!(iter.next().unwrap()?, Token::Text(15..16));
         Ok(())
@@ -754,28 +1227,28 @@ mod tests {
         assert_eq!(iter.next().unwrap()?, Token::ArgKey(5..6));
         assert_eq!(iter.next().unwrap()?, Token::BeginArgValue(7));
         assert_eq!(iter.next().unwrap()?, Token::BeginRaw(8..9));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(9, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(9..10));
         assert_eq!(iter.next().unwrap()?, Token::Text(10..11));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(11, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(11..12));
         assert_eq!(iter.next().unwrap()?, Token::EndRaw(12..13));
         assert_eq!(iter.next().unwrap()?, Token::EndArgValue(14));
         assert_eq!(iter.next().unwrap()?, Token::ArgKey(16..18));
         assert_eq!(iter.next().unwrap()?, Token::BeginArgValue(19));
         assert_eq!(iter.next().unwrap()?, Token::BeginRaw(20..23));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(23, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(23..24));
         assert_eq!(iter.next().unwrap()?, Token::Text(24..26));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(26, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(26..27));
         assert_eq!(iter.next().unwrap()?, Token::EndRaw(27..30));
         assert_eq!(iter.next().unwrap()?, Token::Text(31..32));
         assert_eq!(iter.next().unwrap()?, Token::EndArgValue(32));
         assert_eq!(iter.next().unwrap()?, Token::EndArgs(32));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(33, '\n'));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(33..34));
         assert_eq!(iter.next().unwrap()?, Token::BeginContent(34));
         assert_eq!(iter.next().unwrap()?, Token::Text(34..36));
         assert_eq!(iter.next().unwrap()?, Token::BeginRaw(37..39));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(39, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(39..40));
         assert_eq!(iter.next().unwrap()?, Token::Text(40..45));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(45, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(45..46));
         assert_eq!(iter.next().unwrap()?, Token::EndRaw(46..48));
         assert_eq!(iter.next().unwrap()?, Token::Text(49..51));
         assert_eq!(iter.next().unwrap()?, Token::EndContent(51));
@@ -822,7 +1295,7 @@ mod tests {
         assert_eq!(iter.next().unwrap()?, Token::EndArgValue(32));
         
         assert_eq!(iter.next().unwrap()?, Token::EndArgs(32));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(33, '\n'));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(33..34));
         assert_eq!(iter.next().unwrap()?, Token::BeginContent(34));
 
         assert_eq!(iter.next().unwrap()?, Token::BeginFunction(34));
@@ -850,7 +1323,7 @@ mod tests {
         let mut iter = lex.iter();
         assert_eq!(iter.next().unwrap()?, Token::BeginFunction(0));
         assert_eq!(iter.next().unwrap()?, Token::Call(1..5));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(5, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(5..6));
         assert_eq!(iter.next().unwrap()?, Token::BeginContent(6));
         assert_eq!(iter.next().unwrap()?, Token::Text(6..10));
         assert_eq!(iter.next().unwrap()?, Token::EndContent(10));
@@ -877,7 +1350,7 @@ mod tests {
     #[test]
     fn lex_evil_names() -> Result<(), errors::Error> {
         let input = "{a/b[x=V{z=y}W] con[te{nt}} {left-curly-brace\t}{y[z[y=x] content}";
-        let lex = Lexer::new(input);
+        let lex = Lexer::with_options(input, LexerOptions { call_names: CallNamePolicy::Permissive, ..Default::default() });
         let mut iter = lex.iter();
 
         // a/b
@@ -895,7 +1368,7 @@ mod tests {
         assert_eq!(iter.next().unwrap()?, Token::EndArgValue(14));
         assert_eq!(iter.next().unwrap()?, Token::EndArgs(14));
 
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(15, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(15..16));
         assert_eq!(iter.next().unwrap()?, Token::BeginContent(16));
         assert_eq!(iter.next().unwrap()?, Token::Text(16..22));
         assert_eq!(iter.next().unwrap()?, Token::BeginFunction(22));
@@ -910,7 +1383,7 @@ mod tests {
         // left-curly-brace
         assert_eq!(iter.next().unwrap()?, Token::BeginFunction(28));
         assert_eq!(iter.next().unwrap()?, Token::Call(29..45));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(45, '\t'));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(45..46));
         assert_eq!(iter.next().unwrap()?, Token::BeginContent(46));
         // NOTE: empty content, subject to debate
         assert_eq!(iter.next().unwrap()?, Token::EndContent(46));
@@ -926,7 +1399,7 @@ mod tests {
         assert_eq!(iter.next().unwrap()?, Token::Text(54..55));
         assert_eq!(iter.next().unwrap()?, Token::EndArgValue(55));
         assert_eq!(iter.next().unwrap()?, Token::EndArgs(55));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(56, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(56..57));
         assert_eq!(iter.next().unwrap()?, Token::BeginContent(57));
         assert_eq!(iter.next().unwrap()?, Token::Text(57..64));
         assert_eq!(iter.next().unwrap()?, Token::EndContent(64));
@@ -941,9 +1414,9 @@ mod tests {
         let lex = Lexer::new(input);
         let mut iter = lex.iter();
         assert_eq!(iter.next().unwrap()?, Token::BeginRaw(1..4));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(4, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(4..5));
         assert_eq!(iter.next().unwrap()?, Token::Text(5..20));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(20, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(20..21));
         assert_eq!(iter.next().unwrap()?, Token::EndRaw(21..24));
         Ok(())
     }
@@ -969,9 +1442,9 @@ mod tests {
         let mut iter = lex.iter();
 
         assert_eq!(iter.next().unwrap()?, Token::BeginRaw(1..127));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(127, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(127..128));
         assert_eq!(iter.next().unwrap()?, Token::Text(128..133));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(133, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(133..134));
         assert_eq!(iter.next().unwrap()?, Token::EndRaw(134..260));
 
         assert_eq!(iter.next().unwrap()?, Token::Text(261..262));
@@ -1026,15 +1499,89 @@ mod tests {
 
         assert_eq!(iter.next().unwrap()?, Token::BeginFunction(0));
         assert_eq!(iter.next().unwrap()?, Token::Call(1..5));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(5, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(5..6));
         assert_eq!(iter.next().unwrap()?, Token::BeginContent(6));
         assert_eq!(iter.next().unwrap()?, Token::BeginRaw(7..10));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(10, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(10..11));
         assert_eq!(iter.next().unwrap()?, Token::Text(11..50));
-        assert_eq!(iter.next().unwrap()?, Token::Whitespace(50, ' '));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(50..51));
         assert_eq!(iter.next().unwrap()?, Token::EndRaw(51..54));
         assert_eq!(iter.next().unwrap()?, Token::EndContent(55));
         assert_eq!(iter.next().unwrap()?, Token::EndFunction(55));
         Ok(())
     }
+
+    #[test]
+    fn lex_call_name_policy() -> Result<(), errors::Error> {
+        let input = "{a/b:c.d-e_f2} {caf\u{e9}} {cafe\u{301}}";
+        let lex = Lexer::new(input);
+        assert!(lex.iter().all(|tok| tok.is_ok()));
+
+        let input = "{x{z=y}";
+        let lex = Lexer::new(input);
+        match lex.iter().last() {
+            Some(Err(errors::Error::InvalidSyntax(msg, byte_offset))) => {
+                assert_eq!(msg.code, "L0001");
+                assert!(msg.to_string().contains("'{'"));
+                assert_eq!(byte_offset, 2);
+            },
+            _ => panic!("expected invalid call name to be rejected"),
+        }
+
+        let lex = Lexer::with_options(input, LexerOptions { call_names: CallNamePolicy::Permissive, ..Default::default() });
+        assert!(lex.iter().all(|tok| tok.is_ok()));
+        Ok(())
+    }
+
+    #[test]
+    fn lex_trim_raw_whitespace() -> Result<(), errors::Error> {
+        let input = "{<< a > b\n  >>}";
+        let lex = Lexer::new(input);
+        let mut iter = lex.iter();
+        assert_eq!(iter.next().unwrap()?, Token::BeginRaw(1..3));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(3..4));
+        assert_eq!(iter.next().unwrap()?, Token::Text(4..11));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(11..12));
+        assert_eq!(iter.next().unwrap()?, Token::EndRaw(12..14));
+
+        let lex = Lexer::with_options(input, LexerOptions { trim_raw_whitespace: true, ..Default::default() });
+        let mut iter = lex.iter();
+        assert_eq!(iter.next().unwrap()?, Token::BeginRaw(1..3));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(3..4));
+        assert_eq!(iter.next().unwrap()?, Token::Text(4..9));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(11..12));
+        assert_eq!(iter.next().unwrap()?, Token::EndRaw(12..14));
+        Ok(())
+    }
+
+    #[test]
+    fn lex_alternative_raw_delimiters() -> Result<(), errors::Error> {
+        let input = "{## <p> # ##} {<x}";
+        let options = LexerOptions { open_raw: '#', close_raw: '#', call_names: CallNamePolicy::Permissive, ..Default::default() };
+        assert!(options.validate().is_ok());
+        let lex = Lexer::with_options(input, options);
+        let mut iter = lex.iter();
+        assert_eq!(iter.next().unwrap()?, Token::BeginRaw(1..3));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(3..4));
+        assert_eq!(iter.next().unwrap()?, Token::Text(4..9));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(9..10));
+        assert_eq!(iter.next().unwrap()?, Token::EndRaw(10..12));
+        assert_eq!(iter.next().unwrap()?, Token::Text(13..14));
+        assert_eq!(iter.next().unwrap()?, Token::BeginFunction(14));
+        assert_eq!(iter.next().unwrap()?, Token::Call(15..17));
+
+        // NOTE: an incomplete terminator followed by text must not count towards the next one
+        let input = "{|| a !b !} !!}";
+        let lex = Lexer::with_options(input, LexerOptions { open_raw: '|', close_raw: '!', ..Default::default() });
+        let mut iter = lex.iter();
+        assert_eq!(iter.next().unwrap()?, Token::BeginRaw(1..3));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(3..4));
+        assert_eq!(iter.next().unwrap()?, Token::Text(4..11));
+        assert_eq!(iter.next().unwrap()?, Token::Whitespace(11..12));
+        assert_eq!(iter.next().unwrap()?, Token::EndRaw(12..14));
+
+        assert!(LexerOptions { open_raw: 'a', ..Default::default() }.validate().is_err());
+        assert!(LexerOptions { close_raw: '}', ..Default::default() }.validate().is_err());
+        Ok(())
+    }
 }
@@ -1,8 +1,140 @@
 //! rust components of litua - a tool to read a text document,
 //! receive its tree in Lua and manipulate it before representing it as string.
+//!
+//! Downstream users should import the supported types with ``use litua::prelude::*;``.
+//!
+//! # Stability
+//!
+//! The items of `prelude` follow semantic versioning. The modules defining
+//! them (`errors`, `lexer`, `parser`, `pipeline`, `token_filter`, and `tree`)
+//! are documented, but their other public items may change in minor releases.
+//! All other modules are hidden from the documentation: they implement the
+//! litua executable and may change or disappear in any release.
+//!
+//! # Thread safety
+//!
+//! Lexing and parsing do not share state between documents. Their types (like
+//! `tree::DocumentTree`, `parser::Parser`, and `errors::Error`) are `Send + Sync`,
+//! so documents can be parsed on worker threads concurrently and the trees can
+//! be passed between threads. Running
+//! hooks requires a Lua runtime, which must not be shared between threads;
+//! create one runtime per thread instead.
 
+// NOTE: the façade of the library, which defines the items re-exported by `prelude`
 pub mod errors;
 pub mod lexer;
 pub mod parser;
+pub mod pipeline;
+pub mod prelude;
+pub mod token_filter;
 pub mod tree;
-pub(crate) mod lines_with_indices;
+
+// NOTE: the remaining modules implement the litua executable. They are public
+// only because the executable is a separate crate using them.
+#[doc(hidden)]
+pub mod ansi;
+#[doc(hidden)]
+pub mod catalog;
+#[doc(hidden)]
+pub mod collation;
+#[doc(hidden)]
+pub mod conditional;
+#[doc(hidden)]
+pub mod corpus;
+#[doc(hidden)]
+pub mod coverage;
+#[doc(hidden)]
+pub mod datetime;
+#[doc(hidden)]
+pub mod depfile;
+#[doc(hidden)]
+pub mod docbook;
+#[doc(hidden)]
+pub mod epub;
+#[doc(hidden)]
+pub mod exec;
+#[doc(hidden)]
+pub mod explain;
+#[doc(hidden)]
+pub mod explore;
+#[doc(hidden)]
+pub mod file_metadata;
+#[doc(hidden)]
+pub mod format;
+#[doc(hidden)]
+pub mod grammar;
+#[doc(hidden)]
+pub mod header;
+#[doc(hidden)]
+pub mod highlight;
+#[doc(hidden)]
+pub mod hook_doc;
+#[doc(hidden)]
+pub mod hook_package;
+#[doc(hidden)]
+pub mod html;
+#[doc(hidden)]
+pub mod include;
+#[doc(hidden)]
+pub mod incremental;
+#[doc(hidden)]
+pub mod links;
+#[doc(hidden)]
+pub mod lint;
+#[doc(hidden)]
+pub mod lsp;
+#[doc(hidden)]
+pub mod lua_modules;
+#[doc(hidden)]
+pub mod man;
+#[doc(hidden)]
+pub mod manifest;
+#[doc(hidden)]
+pub mod markup;
+#[doc(hidden)]
+pub mod messages;
+#[doc(hidden)]
+pub mod net;
+#[doc(hidden)]
+pub mod profile;
+#[doc(hidden)]
+pub mod recording;
+#[doc(hidden)]
+pub mod serve;
+#[doc(hidden)]
+pub mod sourcemap;
+#[doc(hidden)]
+pub mod stats;
+#[doc(hidden)]
+pub mod structure;
+#[doc(hidden)]
+pub mod syntax_highlight;
+#[doc(hidden)]
+pub mod text;
+#[doc(hidden)]
+pub mod xml;
+
+use std::path;
+
+/// Compile-time assertion that types of the pure parsing path are `Send + Sync`
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<errors::Error>();
+    assert_send_sync::<lexer::Lexer>();
+    assert_send_sync::<lexer::LexingIterator>();
+    assert_send_sync::<lexer::Token>();
+    assert_send_sync::<parser::Parser>();
+    assert_send_sync::<parser::ParserBuilder>();
+    assert_send_sync::<pipeline::Pipeline>();
+    assert_send_sync::<tree::DocumentTree>();
+    assert_send_sync::<lint::Diagnostic>();
+    assert_send_sync::<sourcemap::SourceMap>();
+};
+
+/// Lex and parse the litua text document `src` and return its tree.
+/// The `filepath` argument of the root call is empty. Convenient for
+/// downstream users and fuzz targets. At the moment, lexing and parsing
+/// stops at the first error, thus the error vector contains one element.
+pub fn parse_str(src: &str) -> Result<tree::DocumentTree, Vec<errors::Error>> {
+    pipeline::Pipeline::new().parse(path::Path::new(""), src).map_err(|e| vec![e])
+}
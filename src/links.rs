@@ -0,0 +1,172 @@
+//! Validation of links between the text documents of a directory
+//!
+//! Calls with argument `id` or `label` declare targets. Targets share one
+//! namespace across all documents, so a target declared twice is reported.
+//! Calls `{ref intro}` (or `{ref[target=intro] …}`) and `{link[href=…] …}`
+//! (or `{link chapter2.lit}`) refer to targets: `intro` refers to the target
+//! `intro` of any document, `#intro` to the target declared in the referring
+//! document, `chapter2.lit#intro` to the target declared in `chapter2.lit`
+//! (relative to the referring document), and `chapter2.lit` to the document
+//! itself. A document may also be referred to by its output like
+//! `chapter2.html`. URLs like `https://example.org` are not checked.
+
+use std::collections::BTreeMap;
+use std::ops;
+use std::path;
+
+use crate::include;
+use crate::lint;
+use crate::messages::Message;
+use crate::tree;
+
+/// Argument keys declaring targets
+pub const TARGET_ARGS: [&str; 2] = ["id", "label"];
+/// Call name of references to targets
+pub const REF_CALL: &str = "ref";
+/// Call name of links to targets or documents
+pub const LINK_CALL: &str = "link";
+
+/// `Location` is a byte range of a text document
+type Location = (path::PathBuf, ops::Range<usize>);
+
+/// `LinkChecker` collects the targets and references of several text documents
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct LinkChecker {
+    /// normalized filepaths of the documents
+    documents: Vec<path::PathBuf>,
+    /// declarations of every target in the order of the documents
+    targets: BTreeMap<String, Vec<Location>>,
+    /// references with the referring call
+    references: Vec<(String, Location)>,
+}
+
+/// Returns the text of `node` if it consists of one text element
+fn single_text(node: &tree::DocumentNode) -> Option<&str> {
+    match node.as_slice() {
+        [tree::DocumentElement::Text(text)] => Some(text.trim()),
+        _ => None,
+    }
+}
+
+/// Is `target` a URL (not referring to a document of the directory)?
+fn is_url(target: &str) -> bool {
+    target.contains("://") || target.starts_with("mailto:")
+}
+
+impl LinkChecker {
+    /// Create a checker without documents
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collect the targets and references of `tree`, the text document at `filepath`
+    pub fn add_document(&mut self, filepath: &path::Path, tree: &tree::DocumentTree) {
+        let filepath = include::normalize(filepath);
+        let root = match &tree.0 {
+            tree::DocumentElement::Function(root) => root,
+            tree::DocumentElement::Text(_) => {
+                self.documents.push(filepath);
+                return;
+            },
+        };
+        self.add_node(&filepath, &root.content);
+        self.documents.push(filepath);
+    }
+
+    fn add_node(&mut self, filepath: &path::Path, node: &tree::DocumentNode) {
+        for element in node {
+            let func = match element {
+                tree::DocumentElement::Function(func) => func,
+                tree::DocumentElement::Text(_) => continue,
+            };
+            let location = (filepath.to_owned(), func.span().unwrap_or(0..0));
+            for key in TARGET_ARGS {
+                if let Some(target) = func.args.get(key).and_then(single_text).filter(|target| !target.is_empty()) {
+                    self.targets.entry(target.to_owned()).or_default().push(location.clone());
+                }
+            }
+            let reference = match func.call.as_str() {
+                REF_CALL => func.args.get("target").and_then(single_text).or_else(|| single_text(&func.content)),
+                LINK_CALL => func.args.get("href").and_then(single_text).or_else(|| single_text(&func.content)),
+                _ => None,
+            };
+            if let Some(reference) = reference.filter(|reference| !reference.is_empty() && !is_url(reference)) {
+                self.references.push((reference.to_owned(), location));
+            }
+
+            for (key, value) in func.args.iter() {
+                if !tree::is_reserved_arg(key) {
+                    self.add_node(filepath, value);
+                }
+            }
+            self.add_node(filepath, &func.content);
+        }
+    }
+
+    /// Returns the document `reference` (relative to `referrer`) refers to
+    fn document(&self, referrer: &path::Path, reference: &str) -> Option<&path::Path> {
+        let referred = include::normalize(&referrer.parent().unwrap_or(path::Path::new("")).join(reference));
+        self.documents.iter()
+            .find(|document| **document == referred || document.with_extension("") == referred.with_extension(""))
+            .map(path::PathBuf::as_path)
+    }
+
+    /// Is the reference `reference` of document `referrer` declared? `exists` determines
+    /// whether a file not belonging to the documents exists.
+    fn resolves<F: Fn(&path::Path) -> bool>(&self, referrer: &path::Path, reference: &str, exists: &F) -> bool {
+        let declared_in = |target: &str, document: &path::Path| {
+            self.targets.get(target).is_some_and(|locations| locations.iter().any(|(file, _)| file == document))
+        };
+        match reference.split_once('#') {
+            None => self.targets.contains_key(reference) || self.document(referrer, reference).is_some()
+                || exists(&referrer.parent().unwrap_or(path::Path::new("")).join(reference)),
+            Some(("", target)) => declared_in(target, referrer),
+            Some((document, "")) => self.document(referrer, document).is_some(),
+            Some((document, target)) => self.document(referrer, document).is_some_and(|document| declared_in(target, document)),
+        }
+    }
+
+    /// Returns the diagnostics for references to undeclared targets and targets
+    /// declared more than once with the filepath of the document they refer to
+    pub fn diagnostics<F: Fn(&path::Path) -> bool>(&self, exists: F) -> Vec<(path::PathBuf, lint::Diagnostic)> {
+        let mut diagnostics = vec![];
+        for (target, locations) in self.targets.iter() {
+            let first = &locations[0].0;
+            for (file, range) in locations.iter().skip(1) {
+                let message = Message::new("R0002", vec![target.to_owned(), first.display().to_string()]);
+                diagnostics.push((file.to_owned(), lint::Diagnostic { message, range: range.clone() }));
+            }
+        }
+        for (reference, (file, range)) in self.references.iter() {
+            if !self.resolves(file, reference, &exists) {
+                let message = Message::new("R0001", vec![reference.to_owned()]);
+                diagnostics.push((file.to_owned(), lint::Diagnostic { message, range: range.clone() }));
+            }
+        }
+        diagnostics.sort_by(|(a, diag_a), (b, diag_b)| a.cmp(b).then(diag_a.range.start.cmp(&diag_b.range.start)));
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_broken_and_duplicate_targets() {
+        let mut links = LinkChecker::new();
+        let a = crate::parse_str("{h1[id=intro] A}{ref intro}{ref missing}{link[href=b.html#usage] b}{link[href=https://example.org] x}").expect("valid document");
+        let b = crate::parse_str("{h1[id=usage] B}{p[label=intro] again}{link ./a.lit#usage}{link a.lit}{ref #usage}").expect("valid document");
+        links.add_document(path::Path::new("docs/a.lit"), &a);
+        links.add_document(path::Path::new("docs/./b.lit"), &b);
+
+        let found: Vec<(path::PathBuf, &str, Vec<String>)> = links.diagnostics(|_| false).into_iter()
+            .map(|(file, diagnostic)| (file, diagnostic.message.code, diagnostic.message.args))
+            .collect();
+        assert_eq!(found, vec![
+            (path::PathBuf::from("docs/a.lit"), "R0001", vec!["missing".to_owned()]),
+            (path::PathBuf::from("docs/b.lit"), "R0002", vec!["intro".to_owned(), "docs/a.lit".to_owned()]),
+            (path::PathBuf::from("docs/b.lit"), "R0001", vec!["./a.lit#usage".to_owned()]),
+        ]);
+    }
+}
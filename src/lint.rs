@@ -0,0 +1,197 @@
+//! Lint rules reporting questionable constructs in litua text documents
+//!
+//! Besides the built-in rules, projects can define their own rules in Lua
+//! files `lint_*.lua` next to their hook files. Such a file returns a function
+//! receiving the root node of the document (parsed losslessly, so every call
+//! has a `=span` argument) and a table `diagnostics`. The rule calls
+//! `diagnostics.report(node, message)` for every violation it finds.
+
+use std::cell;
+use std::fs;
+use std::io;
+use std::ops;
+use std::path;
+
+use crate::errors;
+use crate::text::StrLinesWithByteIndices;
+use crate::messages::Message;
+use crate::parser;
+use crate::pipeline;
+use crate::tree;
+
+/// `Diagnostic` is a questionable construct at byte range `range` of the text document
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Diagnostic {
+    pub message: Message,
+    pub range: ops::Range<usize>,
+}
+
+/// Prefix of the filenames of lint rules implemented in Lua
+pub const RULE_FILE_PREFIX: &str = "lint_";
+
+/// `LuaRule` is a lint rule implemented in Lua
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct LuaRule {
+    /// name of the rule (the filename without prefix `lint_` and suffix `.lua`)
+    pub name: String,
+    /// Lua source code returning the function checking the document
+    pub src: String,
+}
+
+/// Returns the lint rules of the files `lint_*.lua` in directory `dir` sorted by name
+pub fn find_rules(dir: &path::Path) -> io::Result<Vec<LuaRule>> {
+    let mut rules = vec![];
+    for entry in fs::read_dir(dir)? {
+        let filepath = entry?.path();
+        let name = filepath.file_name().and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(RULE_FILE_PREFIX)?.strip_suffix(".lua"));
+        if let Some(name) = name.filter(|name| !name.is_empty()) {
+            rules.push(LuaRule { name: name.to_owned(), src: fs::read_to_string(&filepath)? });
+        }
+    }
+    rules.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(rules)
+}
+
+/// Parse the text document `src` losslessly with the syntax configured by its header directives
+fn parse(src: &str) -> Result<tree::DocumentTree, errors::Error> {
+    pipeline::Pipeline::for_document(src)?
+        .parser(parser::ParserBuilder::new().lossless(true))
+        .parse(path::Path::new(""), src)
+}
+
+/// Push the diagnostics of the built-in rules for text document `src` and its `tree` to `diagnostics`
+fn builtin_rules(src: &str, tree: &tree::DocumentTree, diagnostics: &mut Vec<Diagnostic>) {
+    trailing_whitespace(src, diagnostics);
+    if let tree::DocumentElement::Function(root) = &tree.0 {
+        for element in root.content.iter() {
+            empty_arguments(element, diagnostics);
+        }
+    }
+}
+
+/// Returns the diagnostics of text document `src` sorted by position
+/// or the error if `src` cannot be parsed
+pub fn lint(src: &str) -> Result<Vec<Diagnostic>, errors::Error> {
+    let tree = parse(src)?;
+    let mut diagnostics = vec![];
+    builtin_rules(src, &tree, &mut diagnostics);
+    diagnostics.sort_by_key(|d| (d.range.start, d.range.end));
+    Ok(diagnostics)
+}
+
+/// Returns the diagnostics of text document `src` reported by the built-in rules
+/// and by `rules` sorted by position. If `src` cannot be parsed, the error
+/// is an `mlua::Error::ExternalError` wrapping the `errors::Error`.
+pub fn lint_with_rules(src: &str, rules: &[LuaRule]) -> mlua::Result<Vec<Diagnostic>> {
+    let tree = parse(src).map_err(mlua::Error::external)?;
+    let mut diagnostics = vec![];
+    builtin_rules(src, &tree, &mut diagnostics);
+
+    for rule in rules.iter() {
+        let lua = mlua::Lua::new();
+        let found = cell::RefCell::new(vec![]);
+        lua.scope(|scope| {
+            let report = scope.create_function(|_, (node, message): (mlua::Value, String)| {
+                let range = match node {
+                    mlua::Value::Table(node) => lua_span(&node)?.unwrap_or(0..0),
+                    _ => 0..0,
+                };
+                found.borrow_mut().push(Diagnostic { message: Message::new("W0003", vec![message, rule.name.clone()]), range });
+                Ok(())
+            })?;
+            let api = lua.create_table()?;
+            api.set("report", report)?;
+            let check: mlua::Function = lua.load(&rule.src).set_name(format!("{RULE_FILE_PREFIX}{}.lua", rule.name))?.eval()?;
+            check.call::<_, ()>((&tree, api))
+        })?;
+        diagnostics.extend(found.into_inner());
+    }
+
+    diagnostics.sort_by_key(|d| (d.range.start, d.range.end));
+    Ok(diagnostics)
+}
+
+/// Returns the byte range stored in argument `=span` of the Lua representation of a call
+fn lua_span(node: &mlua::Table) -> mlua::Result<Option<ops::Range<usize>>> {
+    let span = match node.get::<_, Option<mlua::Table>>("args")? {
+        Some(args) => args.get::<_, Option<mlua::Table>>(tree::SPAN_ARG)?,
+        None => None,
+    };
+    let span = match span {
+        Some(span) => span,
+        None => return Ok(None),
+    };
+    let start = span.get::<_, String>(1)?.parse().ok();
+    let end = span.get::<_, String>(2)?.parse().ok();
+    Ok(start.zip(end).map(|(start, end)| start..end))
+}
+
+/// Rule W0001: lines must not end with whitespace
+fn trailing_whitespace(src: &str, diagnostics: &mut Vec<Diagnostic>) {
+    for (start, line) in src.lines_indices() {
+        let trimmed = line.trim_end();
+        if trimmed.len() < line.len() {
+            diagnostics.push(Diagnostic {
+                message: Message::new("W0001", vec![]),
+                range: start + trimmed.len()..start + line.len(),
+            });
+        }
+    }
+}
+
+/// Rule W0002: arguments should have a value
+fn empty_arguments(element: &tree::DocumentElement, diagnostics: &mut Vec<Diagnostic>) {
+    let func = match element {
+        tree::DocumentElement::Function(func) => func,
+        tree::DocumentElement::Text(_) => return,
+    };
+
+    let mut keys: Vec<&String> = func.args.keys().filter(|k| !tree::is_reserved_arg(k)).collect();
+    keys.sort();
+    for key in keys {
+        let value = &func.args[key];
+        if value.iter().all(|e| matches!(e, tree::DocumentElement::Text(t) if t.is_empty())) {
+            if let Some(span) = func.span() {
+                diagnostics.push(Diagnostic {
+                    message: Message::new("W0002", vec![key.to_owned(), func.call.to_owned()]),
+                    range: span,
+                });
+            }
+        }
+        value.iter().for_each(|e| empty_arguments(e, diagnostics));
+    }
+    func.content.iter().for_each(|e| empty_arguments(e, diagnostics));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_rule_violations() -> Result<(), errors::Error> {
+        let src = "first  \n{p[a=][b=x] text}\n";
+        let diagnostics = lint(src)?;
+        let found: Vec<(&str, ops::Range<usize>)> = diagnostics.iter().map(|d| (d.message.code, d.range.clone())).collect();
+        assert_eq!(found, vec![("W0001", 5..7), ("W0002", 8..25)]);
+        assert_eq!(diagnostics[1].message.to_string(), "argument 'a' of call 'p' has an empty value");
+        assert!(lint("{p x}\n")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn report_lua_rule_violations() -> mlua::Result<()> {
+        let rule = LuaRule { name: "no-very".to_owned(), src: "return function (tree, diagnostics)\n\
+            for _, node in ipairs(tree.content) do\n\
+              if type(node) == 'table' and node.content[1]:find('very') then diagnostics.report(node, 'avoid \"very\"') end\n\
+            end\n\
+          end".to_owned() };
+        let diagnostics = lint_with_rules("{p a very long text} \n{p fine}", &[rule])?;
+        let found: Vec<(String, ops::Range<usize>)> = diagnostics.iter().map(|d| (d.message.to_string(), d.range.clone())).collect();
+        assert_eq!(found, vec![("avoid \"very\" (rule 'no-very')".to_owned(), 0..20), ("trailing whitespace".to_owned(), 20..21)]);
+
+        let failing = LuaRule { name: "broken".to_owned(), src: "return function () error('oops') end".to_owned() };
+        assert!(lint_with_rules("text", &[failing]).is_err());
+        Ok(())
+    }
+}
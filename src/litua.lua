@@ -8,12 +8,33 @@ Litua = {
         ["read_new_node"] = {},
         ["modify_node"] = {},
         ["read_modified_node"] = {},
+        ["pass"] = {},
         ["convert_node_to_string"] = {},
         ["modify_final_string"] = { [""] = {} },
         ["on_teardown"] = { [""] = {} },
     },
+    -- names of the passes in order of registration (see Litua.register_pass)
+    ["pass_order"] = {},
     ["global"] = {},
+    -- global user variables which persist across the chunks of a document processed with --chunked
+    ["global_state"] = {},
+    -- variables substituted for {var name} and {$name} before the transformation
+    ["vars"] = {},
+    -- terms of {index term} nodes in document order (persist across chunks)
+    ["index_entries"] = {},
+    -- number of the last {footnote …} node (persists across chunks)
+    ["footnote_count"] = 0,
     ["config"] = {},
+    ["fragments"] = {},
+    -- calls of texts marked by Litua.span (the index is the id of the marker)
+    ["spans"] = {},
+    -- default values of arguments per call name (see Litua.apply_defaults)
+    ["defaults"] = {},
+    -- version of the hook API as "major.minor". Within the same major
+    -- version, newer minor versions only add functionality (see the
+    -- API version history in the README).
+    ["api_version"] = "2.24",
+    ["required_api_versions"] = {},
 }
 
 --- A table implementation which logs any accesses to its items
@@ -33,6 +54,199 @@ end
 --- Turn Litua.global into an AccessLoggingTable
 setmetatable(Litua.global, AccessLoggingTable)
 
+--- Create the defaults of a call on first access, so hook files
+--- can declare them like ``Litua.defaults.img.width = "auto"``
+setmetatable(Litua.defaults, {
+    __index = function (defaults, call)
+        local call_defaults = {}
+        rawset(defaults, call, call_defaults)
+        return call_defaults
+    end,
+})
+
+--- Normalize a call name the same way the parser does
+-- The runtime replaces this function by Unicode NFC normalization
+-- unless normalization has been disabled
+-- @param name  call name as string
+-- @return normalized call name as string
+Litua.normalize_name = function (name) return name end
+
+--- Separator of namespace and local name in call names like `html:div`
+Litua.namespace_separator = ":"
+
+--- Split a call name into namespace and local name (like ``tree::split_call_name``)
+-- Call names without separator, or with an empty namespace or local name, have no namespace.
+-- @param call  call name as string like "html:div"
+-- @return  namespace (or nil) and local name like "html", "div"
+Litua.split_call_name = function (call)
+    -- NOTE: the separator is escaped, since it is matched as Lua pattern
+    local separator = (Litua.namespace_separator:gsub("%p", "%%%0"))
+    local namespace, local_name = call:match("^(.-)" .. separator .. "(.+)$")
+    if namespace == nil or namespace == "" then
+        return nil, call
+    end
+    return namespace, local_name
+end
+
+--- Filter under which hooks for every call of namespace `namespace` are registered
+-- @param namespace  namespace as string like "html"
+-- @return  filter as string like "html:*"
+Litua.namespace_filter = function (namespace)
+    if type(namespace) ~= "string" then
+        return namespace -- NOTE: rejected by Litua.register_hook
+    end
+    return namespace .. Litua.namespace_separator .. "*"
+end
+
+--- Filters of the hooks applying to call `call` in the order they run:
+--- the call name, its namespace (if any), and every call
+-- @param call  call name as string
+-- @return  list of filters
+Litua.dispatch_filters = function (call)
+    local namespace = Litua.split_call_name(call)
+    if namespace == nil then
+        return { call, "" }
+    end
+    return { call, Litua.namespace_filter(namespace), "" }
+end
+
+--- Is `node` the call selected by --explain?
+-- The runtime sets Litua.explain_target (table with fields start, end, and file)
+-- to the source span of the selected call if --explain is given
+-- @param node  any value passed to a hook
+-- @return  true if node has the source span of the selected call
+Litua.is_explained = function (node)
+    local target = Litua.explain_target
+    if target == nil or type(node) ~= "table" or type(node.args) ~= "table" then
+        return false
+    end
+    local span = node.args["=span"]
+    local file = node.args["=file"]
+    return type(span) == "table" and tonumber(span[1]) == target["start"] and tonumber(span[2]) == target["end"]
+        and (type(file) == "table" and file[1] or nil) == target["file"]
+end
+
+--- Invoke a hook and report the invocation to Litua.trace_hook (if set)
+-- The runtime sets Litua.trace_hook and Litua.clock if hook tracing is enabled,
+-- Litua.Debugger if the debugger is enabled, Litua.record_invocation and
+-- Litua.record_results if invocations are recorded (see --record),
+-- Litua.replay_invocation if invocations are replayed (see --replay), and
+-- Litua.explain_step if invocations for the call selected by --explain are reported
+-- @param hook_name  a hook name like modify_node or on_setup
+-- @param hook  the hook entry (table with fields src and impl)
+-- @param call  call name of the node the hook is invoked for ("" for global hooks)
+-- @param path  path of the node within the tree ("" for global hooks)
+-- @param ...  arguments to pass to the hook implementation
+-- @return  the return values of the hook implementation
+Litua.invoke_hook = function (hook_name, hook, call, path, ...)
+    local impl = hook.impl
+    if Litua.invoked_hook_files ~= nil then
+        Litua.invoked_hook_files[hook.file or hook.src] = true
+    end
+    if Litua.replay_invocation ~= nil then
+        impl = function (...)
+            local results = Litua.replay_invocation(hook_name, hook.src, call, path, ...)
+            if results.error ~= nil then
+                error(results.error, 0)
+            end
+            return table.unpack(results, 1, results.n)
+        end
+    elseif Litua.Debugger ~= nil and Litua.Debugger.call ~= nil then
+        impl = function (...) return Litua.Debugger.call(hook, ...) end
+    end
+    if Litua.record_invocation ~= nil then
+        -- NOTE: the inputs are recorded before hooks like modify_node modify them
+        local recorded_impl = impl
+        impl = function (...)
+            local index = Litua.record_invocation(hook_name, hook.src, call, path, ...)
+            local results = table.pack(pcall(recorded_impl, ...))
+            if not results[1] then
+                Litua.record_results(index, tostring(results[2]))
+                error(results[2], 0)
+            end
+            Litua.record_results(index, nil, table.unpack(results, 2, results.n))
+            return table.unpack(results, 2, results.n)
+        end
+    end
+
+    if call ~= "" then
+        -- NOTE: errors raised by node hooks are annotated with the node's position
+        local node_impl = impl
+        impl = function (node, ...)
+            local results = table.pack(pcall(node_impl, node, ...))
+            if not results[1] then
+                Litua.raise_hook_error(hook, call, path, node, results[2])
+            end
+            return table.unpack(results, 2, results.n)
+        end
+    end
+
+    if call ~= "" and Litua.explain_step ~= nil and Litua.is_explained((...)) then
+        -- NOTE: the node is checked before the hook runs, because hooks may drop its arguments
+        local explained_impl = impl
+        impl = function (...)
+            local results = table.pack(explained_impl(...))
+            Litua.explain_step(hook_name, hook.src, path, results[1])
+            return table.unpack(results, 1, results.n)
+        end
+    end
+
+    if Litua.trace_hook == nil then
+        return impl(...)
+    end
+
+    local start = Litua.clock()
+    local results = table.pack(impl(...))
+    local duration = Litua.clock() - start
+
+    local output_length
+    if type(results[1]) == "string" then
+        output_length = #results[1]
+    end
+    Litua.trace_hook({
+        ["hook"] = hook_name,
+        ["src"] = hook.src,
+        ["call"] = call,
+        ["path"] = path,
+        ["duration"] = duration,
+        ["output_length"] = output_length,
+    })
+
+    return table.unpack(results, 1, results.n)
+end
+
+--- Raise an error of a node hook annotated with the hook, the call name, and the position of the node
+-- The runtime sets Litua.hook_error, which raises an error referring to the source span of the node
+-- @param hook  the hook entry (table with fields src and impl)
+-- @param call  call name of the node the hook was invoked for
+-- @param path  path of the node within the tree
+-- @param node  the node the hook was invoked for
+-- @param err  the error raised by the hook (a table with field `node` refers to that node instead)
+Litua.raise_hook_error = function (hook, call, path, node, err)
+    local msg = tostring(err)
+    if type(err) == "table" and type(err.node) == "table" then
+        node = err.node
+    end
+    local span = type(node) == "table" and type(node.args) == "table" and node.args["=span"] or nil
+    local file = span ~= nil and node.args["=file"] or nil
+    if span ~= nil and Litua.hook_error ~= nil then
+        Litua.hook_error(hook.src, call, msg, tonumber(span[1]), tonumber(span[2]), file and file[1])
+    end
+    error(hook.src .. " failed for call '" .. call .. "' at " .. path .. ": " .. msg, 0)
+end
+
+--- Declare the version of the hook API the hook file was written for
+-- The runtime checks whether the API provided by this litua version
+-- is compatible and aborts with an error message otherwise.
+-- @param version  API version like "2" or "2.0"
+Litua.require_api = function (version)
+    local info = debug.getinfo(2, "Sl")
+    table.insert(Litua.required_api_versions, {
+        ["version"] = tostring(version),
+        ["src"] = Litua.format("%1 at line %2", info.source, info.currentline),
+    })
+end
+
 --- Register a new hook
 -- Store the hook function `hook_impl` in the Litua hooks table
 -- to trigger it for every call named `filter` (or every call if
@@ -89,12 +303,18 @@ Litua.register_hook = function (hook_name, filter, hook_impl)
     end
 
     -- everything fine, let's insert the hook!
+    filter = Litua.normalize_name(filter)
     if type(Litua.hooks[hook_name][filter]) == "nil" then
         Litua.hooks[hook_name][filter] = {}
     end
+    local impl_info = debug.getinfo(hook_impl, "S")
     table.insert(Litua.hooks[hook_name][filter], {
         ["src"] = call_repr,
         ["impl"] = hook_impl,
+        ["file"] = source_file,
+        ["line"] = line_number,
+        ["first_line"] = impl_info.linedefined,
+        ["last_line"] = impl_info.lastlinedefined,
     })
     Litua.log("register_hook", call_repr .. " registered")
 
@@ -136,6 +356,41 @@ Litua.read_modified_node = function (filter, hook) Litua.register_hook("read_mod
 -- @param hook  hook like ``function (node, depth, filter) return "…", nil end`` to invoke
 Litua.convert_node_to_string = function (filter, hook) Litua.register_hook("convert_node_to_string", filter, hook) end
 
+--- Register a new read_new_node hook for every call of a namespace like `html:div`
+-- @param namespace  namespace to filter for like "html"
+-- @param hook  hook like ``function (node_copy, depth) return nil end`` to invoke
+Litua.read_new_namespace = function (namespace, hook) Litua.register_hook("read_new_node", Litua.namespace_filter(namespace), hook) end
+
+--- Register a new modify_node hook for every call of a namespace like `html:div`
+-- @param namespace  namespace to filter for like "html"
+-- @param hook  hook like ``function (node, depth, filter) return node, nil end`` to invoke
+Litua.modify_namespace = function (namespace, hook) Litua.register_hook("modify_node", Litua.namespace_filter(namespace), hook) end
+
+--- Register a new read_modified_node hook for every call of a namespace like `html:div`
+-- @param namespace  namespace to filter for like "html"
+-- @param hook  hook like ``function (node_copy, depth) return nil end`` to invoke
+Litua.read_modified_namespace = function (namespace, hook) Litua.register_hook("read_modified_node", Litua.namespace_filter(namespace), hook) end
+
+--- Register a new convert_node_to_string hook for every call of a namespace like `html:div`
+-- @param namespace  namespace to filter for like "html"
+-- @param hook  hook like ``function (node, depth, filter) return "…", nil end`` to invoke
+Litua.convert_namespace_to_string = function (namespace, hook) Litua.register_hook("convert_node_to_string", Litua.namespace_filter(namespace), hook) end
+
+--- Register a new pass, invoked once for the whole tree after read_modified_node
+--- hooks and before convert_node_to_string hooks. Passes run in order of registration.
+-- @param name  unique name of the pass like ``"resolve-refs"``
+-- @param pass  hook like ``function (root, name) return root end`` to invoke
+Litua.register_pass = function (name, pass)
+    if type(name) == "string" and Litua.hooks.pass[Litua.normalize_name(name)] ~= nil then
+        Litua.error(Litua.format("pass %1 must only be registered once", name), {
+            ["source"] = Litua.hooks.pass[Litua.normalize_name(name)][1].src,
+            ["fix"] = "choose a different name for one of the passes",
+        })
+    end
+    Litua.register_hook("pass", name, pass)
+    table.insert(Litua.pass_order, Litua.normalize_name(name))
+end
+
 --- Register a new modify_final_string hook, invoked after the tree has been turned into a string again
 -- @param hook  hook like ``function (text) return text end`` to invoke
 Litua.modify_final_string = function (hook) Litua.register_hook("modify_final_string", "", hook) end
@@ -144,6 +399,82 @@ Litua.modify_final_string = function (hook) Litua.register_hook("modify_final_st
 -- @param hook  hook like ``function () return nil end`` to invoke
 Litua.on_teardown = function (hook) Litua.register_hook("on_teardown", "", hook) end
 
+--- Names of all hooks in the order the runtime runs them
+Litua.hook_names = {
+    "on_setup", "modify_initial_string", "read_new_node", "modify_node",
+    "read_modified_node", "pass", "convert_node_to_string", "modify_final_string", "on_teardown",
+}
+
+--- Describe all registered hooks in the order they run
+-- For every node, the hooks registered for its call name run before the hooks
+-- registered for its namespace and the hooks registered for every call (in order of registration). Only the first
+-- convert_node_to_string hook applies.
+-- @return  human-readable description as string
+Litua.describe_hooks = function ()
+    local global_hooks = { ["on_setup"] = true, ["modify_initial_string"] = true, ["modify_final_string"] = true, ["on_teardown"] = true }
+    local out = ""
+
+    for _, hook_name in ipairs(Litua.hook_names) do
+        local filters = {}
+        for filter, hooks in pairs(Litua.hooks[hook_name]) do
+            if filter ~= "" and #hooks > 0 then
+                table.insert(filters, filter)
+            end
+        end
+        table.sort(filters)
+        if Litua.hooks[hook_name][""] ~= nil and #Litua.hooks[hook_name][""] > 0 then
+            table.insert(filters, "")
+        end
+        if hook_name == "pass" then
+            filters = Litua.pass_order
+        end
+
+        out = out .. hook_name .. (#filters == 0 and " (no hooks)" or "") .. "\n"
+        for _, filter in ipairs(filters) do
+            if global_hooks[hook_name] then
+                out = out .. "  global\n"
+            elseif hook_name == "pass" then
+                out = out .. "  pass '" .. filter .. "'\n"
+            elseif filter == "" then
+                out = out .. "  every call (after the hooks for its call name)\n"
+            elseif filter:sub(-2) == Litua.namespace_separator .. "*" then
+                out = out .. "  namespace '" .. filter:sub(1, -3) .. "' (after the hooks for its call names)\n"
+            else
+                out = out .. "  call '" .. filter .. "'\n"
+            end
+            for i, hook in ipairs(Litua.hooks[hook_name][filter]) do
+                local unused = (hook_name == "convert_node_to_string" and i > 1) and " (unused, only the first hook applies)" or ""
+                out = out .. "    " .. tostring(i) .. ". " .. hook.src .. unused .. "\n"
+            end
+        end
+    end
+
+    return out
+end
+
+--- List the hooks registered for specific call names (used by `litua dump calls`)
+-- @return  list of tables with keys call, hook, file, line, first_line, and last_line
+Litua.describe_calls = function ()
+    local sites = {}
+    for _, hook_name in ipairs(Litua.hook_names) do
+        for filter, hooks in pairs(Litua.hooks[hook_name]) do
+            if filter ~= "" and hook_name ~= "pass" then
+                for _, hook in ipairs(hooks) do
+                    table.insert(sites, {
+                        ["call"] = filter,
+                        ["hook"] = hook_name,
+                        ["file"] = hook.file,
+                        ["line"] = hook.line,
+                        ["first_line"] = hook.first_line,
+                        ["last_line"] = hook.last_line,
+                    })
+                end
+            end
+        end
+    end
+    return sites
+end
+
 --- Pre-processing functions are all hooks which run
 --- without requiring the input as tree.
 -- @param text  the text document content
@@ -156,7 +487,7 @@ Litua.preprocess = function (text)
     Litua.log("preprocess", "run " .. hook_name .. " hooks")
     for i=1,#Litua.hooks[hook_name][""] do
         Litua.log("preprocess", "ran " .. Litua.hooks[hook_name][""][i].src)
-        result = Litua.hooks[hook_name][""][i].impl()
+        result = Litua.invoke_hook(hook_name, Litua.hooks[hook_name][""][i], "", "")
         if result ~= nil then
             Litua.error(Litua.format("%1 hook returned non-nil value", hook_name), {
                 ["expected"] = Litua.format("%1 hooks must return nil", hook_name),
@@ -170,7 +501,7 @@ Litua.preprocess = function (text)
     hook_name = "modify_initial_string"
     Litua.log("preprocess", "run " .. hook_name .. " hooks")
     for i=1,#Litua.hooks[hook_name][""] do
-        text = Litua.hooks[hook_name][""][i].impl(text)
+        text = Litua.invoke_hook(hook_name, Litua.hooks[hook_name][""][i], "", "", text)
         if type(text) ~= "string" then
             Litua.error(Litua.format("%1 hook returned non-string value as first return value", hook_name), {
                 ["context"] = Litua.format("%1 hooks must return two values (string representation and error)", hook_name),
@@ -0,0 +1,126 @@
+--- Debugger support for hook authors
+-- This file is only loaded if litua runs with --lua-debug.
+-- If the module "mobdebug" can be required (e.g. the remote debugger
+-- of ZeroBrane Studio), it is started and takes over. Otherwise a hook
+-- raising an error pauses in an interactive prompt on stdin/stderr.
+
+Litua.Debugger = {}
+
+Litua.Debugger.help = [[
+commands:
+  bt         show the traceback of the error
+  locals     list local variables of the function which raised the error
+  p <expr>   evaluate Lua expression <expr> (local variables are accessible)
+  c          continue (i.e. propagate the error)
+  h          show this help]]
+
+--- Write a message of the debugger to stderr
+-- @param msg  the message to write
+Litua.Debugger.write = function (msg)
+    io.stderr:write("DEBUG:\t" .. tostring(msg) .. "\n")
+end
+
+--- Collect the local variables of the function at stack level `level`
+-- @param level  stack level like in debug.getlocal
+-- @return  table of (name, value) pairs and table of names in declaration order
+Litua.Debugger.collect_locals = function (level)
+    local values = {}
+    local names = {}
+    local i = 1
+    while true do
+        local name, value = debug.getlocal(level + 1, i)
+        if name == nil then break end
+        if name:sub(1, 1) ~= "(" then
+            if values[name] == nil then
+                table.insert(names, name)
+            end
+            values[name] = value
+        end
+        i = i + 1
+    end
+    return values, names
+end
+
+--- Message handler for xpcall. Pauses in an interactive prompt.
+-- @param err  the error raised by the hook
+-- @param hook  the hook entry (table with fields src and impl)
+-- @return  the error `err`
+Litua.Debugger.on_error = function (err, hook)
+    -- NOTE: level 1 is this function (tail-called by the message handler),
+    --       level 2 and above the error site (skipping C functions like error
+    --       and litua's standard library functions like Litua.error)
+    local level = 2
+    while true do
+        local info = debug.getinfo(level, "S")
+        if info == nil or (info.what ~= "C" and info.source ~= "litua_stdlib.lua") then
+            break
+        end
+        level = level + 1
+    end
+
+    local values, names = Litua.Debugger.collect_locals(level)
+    local traceback = debug.traceback(tostring(err), level)
+    local env = setmetatable({}, {
+        __index = function (_, key)
+            if values[key] ~= nil then return values[key] end
+            return _G[key]
+        end,
+    })
+
+    Litua.Debugger.write(hook.src .. " raised an error: " .. tostring(err))
+    Litua.Debugger.write("paused, enter 'h' for help")
+    while true do
+        io.stderr:write("(litua-debug) ")
+        local line = io.read("l")
+        if line == nil or line == "c" then
+            break
+        elseif line == "h" then
+            Litua.Debugger.write(Litua.Debugger.help)
+        elseif line == "bt" then
+            Litua.Debugger.write(traceback)
+        elseif line == "locals" then
+            for _, name in ipairs(names) do
+                Litua.Debugger.write(name .. " = " .. Litua.format("%1", values[name]))
+            end
+        elseif line:sub(1, 2) == "p " then
+            local chunk, msg = load("return " .. line:sub(3), "=(litua-debug)", "t", env)
+            if chunk == nil then
+                Litua.Debugger.write(msg)
+            else
+                local ok, result = pcall(chunk)
+                Litua.Debugger.write((Litua.format("%1", result)))
+                if not ok then
+                    Litua.Debugger.write("evaluation failed")
+                end
+            end
+        elseif line ~= "" then
+            Litua.Debugger.write("unknown command '" .. line .. "', enter 'h' for help")
+        end
+    end
+
+    return err
+end
+
+--- Invoke the hook implementation with the given arguments,
+-- but pause in the interactive prompt if the hook raises an error
+-- @param hook  the hook entry (table with fields src and impl)
+-- @param ...  arguments to pass to the hook implementation
+-- @return  the return values of the hook implementation
+Litua.Debugger.call = function (hook, ...)
+    local handler = function (err) return Litua.Debugger.on_error(err, hook) end
+    local results = table.pack(xpcall(hook.impl, handler, ...))
+    if not results[1] then
+        error(results[2], 0)
+    end
+    return table.unpack(results, 2, results.n)
+end
+
+-- prefer a remote debugger if available
+local found, mobdebug = pcall(require, "mobdebug")
+if found then
+    Litua.Debugger.write("remote debugger 'mobdebug' found, starting it")
+    mobdebug.start()
+    Litua.Debugger.call = nil
+else
+    Litua.Debugger.write("no remote debugger 'mobdebug' found, hook errors will pause in an interactive prompt")
+end
@@ -0,0 +1,85 @@
+--- Human-readable representation of Lua values (module "litua.inspect")
+-- In contrast to `Litua.represent_table`, nested tables are represented
+-- recursively with sorted keys. Tables seen before are represented as `<cycle>`.
+
+local inspect = {}
+
+local function is_identifier(key)
+    return type(key) == "string" and key:match("^[%a_][%w_]*$") ~= nil
+end
+
+local function sort_keys(a, b)
+    local ta, tb = type(a), type(b)
+    if ta == tb and (ta == "string" or ta == "number") then
+        return a < b
+    end
+    if ta ~= tb then
+        return ta < tb
+    end
+    return tostring(a) < tostring(b)
+end
+
+local represent
+
+local function represent_table(tbl, opts, depth, seen)
+    if seen[tbl] then
+        return "<cycle>"
+    end
+    if depth >= opts.depth then
+        return "{...}"
+    end
+    seen[tbl] = true
+
+    local items = {}
+    local count = #tbl
+    for i = 1, count do
+        table.insert(items, represent(tbl[i], opts, depth + 1, seen))
+    end
+
+    local keys = {}
+    for key in pairs(tbl) do
+        if math.type(key) ~= "integer" or key < 1 or key > count then
+            table.insert(keys, key)
+        end
+    end
+    table.sort(keys, sort_keys)
+    for _, key in ipairs(keys) do
+        local repr_key = is_identifier(key) and key or "[" .. represent(key, opts, depth + 1, seen) .. "]"
+        table.insert(items, repr_key .. " = " .. represent(tbl[key], opts, depth + 1, seen))
+    end
+
+    seen[tbl] = nil
+    if #items == 0 then
+        return "{}"
+    end
+    if opts.indent == nil then
+        return "{ " .. table.concat(items, ", ") .. " }"
+    end
+    local inner = string.rep(" ", opts.indent * (depth + 1))
+    local outer = string.rep(" ", opts.indent * depth)
+    return "{\n" .. inner .. table.concat(items, ",\n" .. inner) .. "\n" .. outer .. "}"
+end
+
+represent = function (value, opts, depth, seen)
+    local t = type(value)
+    if t == "string" then
+        return (string.format("%q", value):gsub("\\\n", "\\n"))
+    elseif t == "table" then
+        return represent_table(value, opts, depth, seen)
+    elseif t == "function" or t == "thread" or t == "userdata" then
+        return "<" .. t .. ">"
+    end
+    return tostring(value)
+end
+
+--- Represent `value` as string
+-- @param value  the value to represent
+-- @tparam table opts  options `depth` (maximum nesting level of tables, default: unlimited)
+--                     and `indent` (number of spaces per nesting level, default: single line)
+-- @treturn string representation
+inspect.inspect = function (value, opts)
+    opts = opts or {}
+    return represent(value, { depth = opts.depth or math.huge, indent = opts.indent }, 0, {})
+end
+
+return setmetatable(inspect, { __call = function (_, value, opts) return inspect.inspect(value, opts) end })
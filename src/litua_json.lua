@@ -0,0 +1,266 @@
+--- JSON encoder and decoder (module "litua.json")
+-- Objects are represented as tables with string keys, arrays as sequences.
+-- Use `json.null` for JSON null values within tables and `json.empty_array`
+-- for an empty array (an empty table is encoded as empty object).
+
+local json = {}
+
+--- Sentinel representing JSON null
+json.null = setmetatable({}, { __tostring = function () return "null" end })
+
+--- Sentinel representing an empty JSON array
+json.empty_array = setmetatable({}, { __tostring = function () return "[]" end })
+
+local escapes = {
+    ['"'] = '\\"', ["\\"] = "\\\\", ["\b"] = "\\b", ["\f"] = "\\f",
+    ["\n"] = "\\n", ["\r"] = "\\r", ["\t"] = "\\t",
+}
+
+local function encode_string(str)
+    return '"' .. str:gsub('[%c"\\]', function (c)
+        return escapes[c] or string.format("\\u%04x", c:byte())
+    end) .. '"'
+end
+
+--- Is `tbl` a sequence with keys 1, …, n (for n > 0)?
+local function is_array(tbl)
+    local count = 0
+    for _ in pairs(tbl) do
+        count = count + 1
+    end
+    return count > 0 and count == #tbl
+end
+
+local encode_value
+
+local function encode_table(tbl, indent, depth, seen)
+    if seen[tbl] then
+        error("cannot encode a table with cyclic references as JSON")
+    end
+    seen[tbl] = true
+
+    local newline, inner, outer, colon = "", "", "", ":"
+    if indent ~= nil then
+        newline = "\n"
+        inner = string.rep(" ", indent * (depth + 1))
+        outer = string.rep(" ", indent * depth)
+        colon = ": "
+    end
+
+    local items = {}
+    local open, close
+    if tbl == json.empty_array then
+        open, close = "[", "]"
+    elseif is_array(tbl) then
+        open, close = "[", "]"
+        for i = 1, #tbl do
+            table.insert(items, inner .. encode_value(tbl[i], indent, depth + 1, seen))
+        end
+    else
+        open, close = "{", "}"
+        local keys = {}
+        for key in pairs(tbl) do
+            if type(key) ~= "string" and type(key) ~= "number" then
+                error("cannot encode a table with key of type " .. type(key) .. " as JSON")
+            end
+            table.insert(keys, tostring(key))
+        end
+        table.sort(keys)
+        for _, key in ipairs(keys) do
+            local value = tbl[key]
+            if value == nil then
+                value = tbl[tonumber(key)]
+            end
+            table.insert(items, inner .. encode_string(key) .. colon .. encode_value(value, indent, depth + 1, seen))
+        end
+    end
+
+    seen[tbl] = nil
+    if #items == 0 then
+        return open .. close
+    end
+    return open .. newline .. table.concat(items, "," .. newline) .. newline .. outer .. close
+end
+
+encode_value = function (value, indent, depth, seen)
+    local t = type(value)
+    if value == nil or value == json.null then
+        return "null"
+    elseif t == "boolean" then
+        return tostring(value)
+    elseif t == "number" then
+        if value ~= value or value == math.huge or value == -math.huge then
+            error("cannot encode " .. tostring(value) .. " as JSON")
+        end
+        if math.type(value) == "integer" then
+            return tostring(value)
+        end
+        local repr = string.format("%.14g", value)
+        if tonumber(repr) ~= value then
+            repr = string.format("%.17g", value)
+        end
+        return repr
+    elseif t == "string" then
+        return encode_string(value)
+    elseif t == "table" then
+        return encode_table(value, indent, depth, seen)
+    end
+    error("cannot encode a value of type " .. t .. " as JSON")
+end
+
+--- Represent a Lua value as JSON text. Object keys are sorted.
+-- @param value  the value to represent (nil, boolean, number, string, or table)
+-- @tparam integer indent  number of spaces to indent nested values with (default: no whitespace)
+-- @treturn string JSON text
+json.encode = function (value, indent)
+    return encode_value(value, indent, 0, {})
+end
+
+--- Parse JSON text into a Lua value. JSON null is represented as `json.null`.
+-- @tparam string text  the JSON text
+-- @return the value
+json.decode = function (text)
+    local pos = 1
+
+    local function fail(msg)
+        error(string.format("invalid JSON at byte %d: %s", pos, msg))
+    end
+
+    local function skip_whitespace()
+        pos = text:find("[^ \t\r\n]", pos) or #text + 1
+    end
+
+    local decode_value
+
+    local function decode_string()
+        local parts = {}
+        pos = pos + 1
+        while true do
+            local c = text:sub(pos, pos)
+            if c == "" then
+                fail("unterminated string")
+            elseif c == '"' then
+                pos = pos + 1
+                return table.concat(parts)
+            elseif c == "\\" then
+                local e = text:sub(pos + 1, pos + 1)
+                local simple = { ['"'] = '"', ["\\"] = "\\", ["/"] = "/", b = "\b", f = "\f", n = "\n", r = "\r", t = "\t" }
+                if simple[e] then
+                    table.insert(parts, simple[e])
+                    pos = pos + 2
+                elseif e == "u" then
+                    local code = tonumber(text:sub(pos + 2, pos + 5), 16) or fail("invalid unicode escape")
+                    pos = pos + 6
+                    if code >= 0xD800 and code <= 0xDBFF and text:sub(pos, pos + 1) == "\\u" then
+                        local low = tonumber(text:sub(pos + 2, pos + 5), 16)
+                        if low and low >= 0xDC00 and low <= 0xDFFF then
+                            code = 0x10000 + (code - 0xD800) * 0x400 + (low - 0xDC00)
+                            pos = pos + 6
+                        end
+                    end
+                    table.insert(parts, utf8.char(code))
+                else
+                    fail("invalid escape sequence")
+                end
+            elseif c:byte() < 0x20 then
+                fail("control character in string")
+            else
+                local stop = text:find('["\\%c]', pos) or #text + 1
+                table.insert(parts, text:sub(pos, stop - 1))
+                pos = stop
+            end
+        end
+    end
+
+    local function decode_number()
+        local number = text:match("^-?%d+%.?%d*[eE]?[-+]?%d*", pos)
+        local value = number and tonumber(number)
+        if value == nil then
+            fail("invalid number")
+        end
+        pos = pos + #number
+        return value
+    end
+
+    local function decode_array()
+        local arr = {}
+        pos = pos + 1
+        skip_whitespace()
+        if text:sub(pos, pos) == "]" then
+            pos = pos + 1
+            return json.empty_array
+        end
+        while true do
+            table.insert(arr, decode_value())
+            skip_whitespace()
+            local c = text:sub(pos, pos)
+            pos = pos + 1
+            if c == "]" then
+                return arr
+            elseif c ~= "," then
+                fail("expected ',' or ']'")
+            end
+        end
+    end
+
+    local function decode_object()
+        local obj = {}
+        pos = pos + 1
+        skip_whitespace()
+        if text:sub(pos, pos) == "}" then
+            pos = pos + 1
+            return obj
+        end
+        while true do
+            skip_whitespace()
+            if text:sub(pos, pos) ~= '"' then
+                fail("expected string as object key")
+            end
+            local key = decode_string()
+            skip_whitespace()
+            if text:sub(pos, pos) ~= ":" then
+                fail("expected ':'")
+            end
+            pos = pos + 1
+            obj[key] = decode_value()
+            skip_whitespace()
+            local c = text:sub(pos, pos)
+            pos = pos + 1
+            if c == "}" then
+                return obj
+            elseif c ~= "," then
+                fail("expected ',' or '}'")
+            end
+        end
+    end
+
+    decode_value = function ()
+        skip_whitespace()
+        local c = text:sub(pos, pos)
+        if c == "{" then
+            return decode_object()
+        elseif c == "[" then
+            return decode_array()
+        elseif c == '"' then
+            return decode_string()
+        elseif c == "-" or c:match("%d") then
+            return decode_number()
+        end
+        for literal, value in pairs({ ["true"] = true, ["false"] = false, ["null"] = json.null }) do
+            if text:sub(pos, pos + #literal - 1) == literal then
+                pos = pos + #literal
+                return value
+            end
+        end
+        fail("unexpected " .. (c == "" and "end of text" or "character '" .. c .. "'"))
+    end
+
+    local value = decode_value()
+    skip_whitespace()
+    if pos <= #text then
+        fail("trailing characters")
+    end
+    return value
+end
+
+return json
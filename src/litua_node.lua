@@ -23,21 +23,37 @@ Litua.Node.identity_representation = function (node)
     --       deterministic behavior
     table.sort(args_keys)
 
+    -- NOTE: declared types like "width:int" of "=types" are represented as part of the key
+    local arg_types = {}
+    for _, declared in ipairs(node.args["=types"] or {}) do
+        local argkey, argtype = tostring(declared):match("^(.*):(%a+)$")
+        if argkey ~= nil then
+            arg_types[argkey] = argtype
+        end
+    end
+
     for i = 1,#args_keys do
         local argkey = tostring(args_keys[i])
         local argvalues = node.args[argkey]
+        local typed_key = argkey
+        if arg_types[argkey] ~= nil then
+            typed_key = argkey .. ":" .. arg_types[argkey]
+        end
 
-        args_string = args_string .. "[" .. argkey .. "=" .. Litua.concat_table_values(argvalues) .. "]"
+        args_string = args_string .. "[" .. typed_key .. "=" .. Litua.concat_table_values(argvalues) .. "]"
     end
 
     -- read special arguments
     local whitespace = ""
     local whitespace_after = ""
+    local language = ""
     for argkey, argvalues in pairs(node.args) do
         if argkey == "=whitespace" then
             whitespace = Litua.concat_table_values(argvalues)
         elseif argkey == "=whitespace-after" then
             whitespace_after = Litua.concat_table_values(argvalues)
+        elseif argkey == "=language" then
+            language = Litua.concat_table_values(argvalues)
         end
     end
 
@@ -53,7 +69,7 @@ Litua.Node.identity_representation = function (node)
     -- reconstruct entire function
     if node.call:match("<+") ~= nil then
         local length = #node.call
-        return "{" .. node.call .. whitespace .. content_string .. whitespace_after .. (">"):rep(length) .. "}"
+        return "{" .. node.call .. language .. whitespace .. content_string .. whitespace_after .. (">"):rep(length) .. "}"
     else
         return "{" .. node.call .. args_string .. whitespace .. content_string .. whitespace_after .. "}"
     end
@@ -86,9 +102,94 @@ Litua.Node.text_only_representation = function (node)
     return content_string
 end
 
+--- Argument keys reserved for litua
+-- All argument keys starting with "=" are reserved for internal bookkeeping.
+-- Hooks may read and modify the following ones, but must not introduce others.
+Litua.Node.reserved_args = { ["=whitespace"] = true, ["=whitespace-after"] = true, ["=span"] = true, ["=file"] = true, ["=language"] = true, ["=types"] = true }
+
+--- Verify that a node does not use unknown reserved argument keys
+-- @param node  a Litua.Node or a table with fields call and args
+-- @param source  the hook source file responsible for the node (optional)
+Litua.Node.check_args = function (node, source)
+    for argkey, _ in pairs(node.args) do
+        if tostring(argkey):sub(1, 1) == "=" and not Litua.Node.reserved_args[argkey] then
+            Litua.error("argument key '" .. tostring(argkey) .. "' of node '" .. tostring(node.call) .. "' is reserved for litua", {
+                ["expected"] = "argument key not starting with '='",
+                ["actual"] = tostring(argkey),
+                ["fix"] = "rename the argument",
+                ["source"] = source,
+            })
+        end
+    end
+end
+
 --- The set of admissible API call
 Litua.Node.Api = { "call", "args", "content", "copy", "is_node", "tostring", "totext" }
 
+--- Does the content element `element` match the filter `filter`?
+-- @param element  a Litua.Node or string of the content of a node
+-- @param filter  call name, predicate function taking the element, or nil to match every node
+-- @return  true if element is a node matching the filter
+local function matches_filter(element, filter)
+    if type(element) ~= "table" or not element.is_node then
+        return false
+    elseif filter == nil then
+        return true
+    elseif type(filter) == "function" then
+        return filter(element) and true or false
+    end
+    return element.call == Litua.normalize_name(tostring(filter))
+end
+
+--- Methods of nodes to pick specific children out of `node.content`
+Litua.Node.methods = {
+    --- Returns the child nodes in the content, like ``node:children("item")``
+    -- @param self  the node
+    -- @param filter  call name, predicate function taking the child node, or nil for every child node
+    -- @return  list of Litua.Node instances
+    ["children"] = function (self, filter)
+        local children = {}
+        for _, element in ipairs(self.content) do
+            if matches_filter(element, filter) then
+                table.insert(children, element)
+            end
+        end
+        return children
+    end,
+
+    --- Returns the first child node in the content, like ``node:first("title")``
+    -- @param self  the node
+    -- @param filter  call name, predicate function taking the child node, or nil for any child node
+    -- @return  Litua.Node instance and its index in the content, or nil
+    ["first"] = function (self, filter)
+        for i, element in ipairs(self.content) do
+            if matches_filter(element, filter) then
+                return element, i
+            end
+        end
+        return nil
+    end,
+
+    --- Splits the content into the elements satisfying `predicate` and the others,
+    --- like ``local captions, rest = node:partition(function (e) return e.is_node and e.call == "caption" end)``
+    -- @param self  the node
+    -- @param predicate  function taking a content element (Litua.Node or string)
+    -- @return  list of matching elements and list of other elements (both in content order)
+    ["partition"] = function (self, predicate)
+        if type(predicate) ~= "function" then
+            Litua.error("partition requires a predicate function", {
+                ["expected"] = "a function taking a content element and returning a boolean",
+                ["actual"] = Litua.format("%1", predicate),
+            })
+        end
+        local matching, others = {}, {}
+        for _, element in ipairs(self.content) do
+            table.insert(predicate(element) and matching or others, element)
+        end
+        return matching, others
+    end,
+}
+
 --- Constructor for a new node
 -- It takes the `call` name, arguments `args`, and a table `content`.
 -- Here, `content` can be Nodes or strings themselves.
@@ -102,14 +203,18 @@ Litua.Node.init = function (call, args, content)
         ["args"] = args,
         ["content"] = content,
     }
+    Litua.Node.check_args(node)
 
     node.copy = function (self)
         local new_args = {}
         for argkey, argvalues in pairs(self.args) do
             new_args[argkey] = {}
             for _, argvalue in ipairs(argvalues) do
-                if argvalue.is_node then
+                if type(argvalue) == "table" and argvalue.is_node then
                     table.insert(new_args[argkey], argvalue:copy())
+                elseif type(argvalue) == "number" or type(argvalue) == "boolean" then
+                    -- NOTE: values of typed arguments like [width:int=300]
+                    table.insert(new_args[argkey], argvalue)
                 else
                     table.insert(new_args[argkey], tostring(argvalue))
                 end
@@ -138,6 +243,14 @@ Litua.Node.__name = function (self, name)
 end
 
 Litua.Node.__index = function (self, index)
+    -- NOTE: derived from the call name, so they cannot be modified
+    if index == "namespace" then
+        return (Litua.split_call_name(rawget(self, "call")))
+    elseif index == "local_name" then
+        return select(2, Litua.split_call_name(rawget(self, "call")))
+    elseif Litua.Node.methods[index] ~= nil then
+        return Litua.Node.methods[index]
+    end
     for _, key in ipairs(Litua.Node.Api) do
         if index == key then
             return rawget(self, index)
@@ -0,0 +1,85 @@
+--- Manipulation of filepaths separated by `/` (module "litua.path")
+-- The functions operate on strings only and never access the filesystem.
+
+local path = {}
+
+--- Join path components. An absolute component discards all previous components.
+-- @tparam string ...  the path components
+-- @treturn string joined path
+path.join = function (...)
+    local result = ""
+    for _, component in ipairs({ ... }) do
+        if component:sub(1, 1) == "/" or result == "" then
+            result = component
+        elseif component ~= "" then
+            result = result:gsub("/+$", "") .. "/" .. component
+        end
+    end
+    return result
+end
+
+--- Does the path start at the root directory?
+-- @tparam string p  the path
+-- @treturn boolean
+path.is_absolute = function (p)
+    return p:sub(1, 1) == "/"
+end
+
+--- Remove empty and `.` components and resolve `..` components where possible
+-- @tparam string p  the path
+-- @treturn string normalized path (`.` for an empty relative path)
+path.normalize = function (p)
+    local components = {}
+    for component in p:gmatch("[^/]+") do
+        if component == ".." then
+            if #components > 0 and components[#components] ~= ".." then
+                table.remove(components)
+            elseif not path.is_absolute(p) then
+                table.insert(components, component)
+            end
+        elseif component ~= "." then
+            table.insert(components, component)
+        end
+    end
+    local normalized = table.concat(components, "/")
+    if path.is_absolute(p) then
+        return "/" .. normalized
+    end
+    return normalized == "" and "." or normalized
+end
+
+--- Last component of the path (e.g. `c.lit` for `a/b/c.lit`)
+-- @tparam string p  the path
+-- @treturn string
+path.basename = function (p)
+    return (p:gsub("/+$", "")):match("[^/]*$")
+end
+
+--- All components of the path except the last one (e.g. `a/b` for `a/b/c.lit`)
+-- @tparam string p  the path
+-- @treturn string directory (`.` if there is none, `/` for the root directory)
+path.dirname = function (p)
+    local trimmed = p:gsub("/+$", "")
+    local dir = trimmed:match("^(.*)/[^/]*$")
+    if dir == nil then
+        return path.is_absolute(p) and "/" or "."
+    end
+    return dir == "" and "/" or dir
+end
+
+--- File extension of the last component including the dot (e.g. `.lit` for `a/b/c.lit`)
+-- @tparam string p  the path
+-- @treturn string extension or the empty string
+path.extension = function (p)
+    return path.basename(p):match("^.+(%.[^.]*)$") or ""
+end
+
+--- Last component of the path without its file extension (e.g. `c` for `a/b/c.lit`)
+-- @tparam string p  the path
+-- @treturn string
+path.stem = function (p)
+    local base = path.basename(p)
+    return base:sub(1, #base - #path.extension(p))
+end
+
+return path
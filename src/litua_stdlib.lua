@@ -23,11 +23,37 @@ Litua.error = function (errmsg, info)
     error(out)
 end
 
+--- Template of the prefix of log lines, where "{component}" is replaced
+--- by the component (the runtime sets it if --log-prefix is given)
+Litua.log_prefix = "LOG[{component}]:\t"
+
+--- Represent a message as log line with the prefix of `Litua.log_prefix`
+-- @tparam string component  the component where this issue occurs
+-- @tparam string msg  the string explaining the issue
+-- @treturn string the log line
+Litua.log_line = function (component, msg)
+    local prefix = Litua.log_prefix:gsub("{component}", function () return tostring(component) end)
+    return prefix .. tostring(msg)
+end
+
 --- Generate a message and log it to stdout for human consumption
 -- @tparam string component  the component where this issue occurs
 -- @tparam string msg  the string explaining the issue
 Litua.log = function (component, msg)
-    print("LOG[" .. component .. "]:\t" .. tostring(msg))
+    print(Litua.log_line(component, msg))
+end
+
+--- Return the keys of a table sorted by their string representation.
+--- Iterating over them instead of `pairs` makes the order independent of Lua's hash seed.
+-- @tparam table tbl  the table
+-- @treturn table list of keys
+Litua.sorted_keys = function (tbl)
+    local keys = {}
+    for key in pairs(tbl) do
+        table.insert(keys, key)
+    end
+    table.sort(keys, function (a, b) return tostring(a) < tostring(b) end)
+    return keys
 end
 
 --- Represent a table as a string without traversing recursively.
@@ -35,8 +61,8 @@ end
 -- @tparam table tbl  the table to represent
 Litua.represent_table = function (tbl)
     local repr = "{ "
-    for key, value in pairs(tbl) do
-        repr = repr .. "[" .. tostring(key) .. "] = " .. tostring(value) .. ", "
+    for _, key in ipairs(Litua.sorted_keys(tbl)) do
+        repr = repr .. "[" .. tostring(key) .. "] = " .. tostring(tbl[key]) .. ", "
     end
     return repr:sub(#repr - 1) .. " }"
 end
@@ -92,3 +118,136 @@ Litua.format = function (format_string, ...)
     -- replace placeholders
     return format_string:gsub("%%(%d)", func_args)
 end
+
+--- Date and time helpers. Use `Litua.datetime.build_time()` instead of `os.time()`,
+--- because the build timestamp can be fixed with SOURCE_DATE_EPOCH for reproducible output.
+--- `build_time`, `document_mtime`, and `locale` are provided by litua's rust side.
+Litua.datetime = {}
+
+--- Format a timestamp in UTC like `os.date`
+-- @tparam string pattern  strftime-like pattern (e.g. "%Y-%m-%d")
+-- @tparam integer timestamp  seconds since the UNIX epoch (default: the build timestamp)
+-- @treturn string formatted timestamp
+Litua.datetime.format = function (pattern, timestamp)
+    return os.date("!" .. pattern, timestamp or Litua.datetime.build_time())
+end
+
+--- Format a timestamp in UTC as ISO-8601 string like "2023-04-01T12:34:56Z"
+-- @tparam integer timestamp  seconds since the UNIX epoch (default: the build timestamp)
+-- @treturn string formatted timestamp
+Litua.datetime.iso8601 = function (timestamp)
+    return Litua.datetime.format("%Y-%m-%dT%H:%M:%SZ", timestamp)
+end
+
+--- Locale-aware sorting of texts like index terms. `compare(a, b, locale)` returns
+--- -1, 0, or 1, `sort(texts, locale)` returns the sorted list, and `group(text, locale)`
+--- returns the heading letter of `text` in an index. The locale (like "sv_SE") defaults to
+--- `Litua.datetime.locale()`. The functions are provided by litua's rust side.
+Litua.collation = {}
+
+--- Prepend `prefix` to every line of `text` which is not blank.
+--- The string utilities `Litua.trim`, `Litua.split`, `Litua.dedent`, `Litua.slugify`,
+--- and `Litua.wrap` as well as `Litua.highlight` are provided by litua's rust side.
+-- @tparam string text  the text to indent
+-- @param prefix  the string to prepend or the number of spaces to prepend
+-- @treturn string indented text
+Litua.indent = function (text, prefix)
+    if type(prefix) == "number" then
+        prefix = string.rep(" ", prefix)
+    end
+    local indented = text:gsub("[^\n]+", function (line)
+        if line:match("^%s*$") then
+            return line
+        end
+        return prefix .. line
+    end)
+    return indented
+end
+
+--- Raise an error if `node` violates the mini-schema `schema`, a table with
+--- optional lists `args` (argument keys like "src" or "alt?") and `children`
+--- (call names of child calls like "caption?", "item*", or "item+"; "*" admits others).
+--- The error refers to the source position of `node`. `Litua.check_structure`
+--- returning the list of violations is provided by litua's rust side.
+-- @tparam table node  the node to check
+-- @tparam table schema  the schema like `{ args = { "src" }, children = { "caption?" } }`
+-- @treturn table the node
+Litua.assert_structure = function (node, schema)
+    local violations = Litua.check_structure(node, schema)
+    if #violations > 0 then
+        local msg = table.concat(violations, "; ")
+        -- NOTE: Litua.raise_hook_error positions errors with a field `node` at that node
+        error(setmetatable({ ["message"] = msg, ["node"] = node }, { __tostring = function () return msg end }), 0)
+    end
+    return node
+end
+
+--- Filters applicable to template placeholders like `${title|html}`.
+--- Hooks can add filters by assigning functions mapping a string to a string.
+Litua.template_filters = {
+    html = function (text)
+        return (text:gsub('[&<>"\']', { ["&"] = "&amp;", ["<"] = "&lt;", [">"] = "&gt;", ['"'] = "&quot;", ["'"] = "&#39;" }))
+    end,
+    latex = function (text)
+        return (text:gsub("[\\{}$&#^_%%~]", {
+            ["\\"] = "\\textbackslash{}", ["{"] = "\\{", ["}"] = "\\}", ["$"] = "\\$", ["&"] = "\\&",
+            ["#"] = "\\#", ["^"] = "\\textasciicircum{}", ["_"] = "\\_", ["%"] = "\\%", ["~"] = "\\textasciitilde{}",
+        }))
+    end,
+    upper = function (text) return text:upper() end,
+    lower = function (text) return text:lower() end,
+    trim = function (text) return Litua.trim(text) end,
+    slugify = function (text) return Litua.slugify(text) end,
+}
+
+--- Fill in a template. A placeholder `${name}` is replaced by `tostring(values.name)`
+--- and `${a.b}` by the value of `values.a.b`. Filters of `Litua.template_filters` are
+--- applied from left to right, e.g. `${title|trim|html}`. `$${` represents a literal `${`.
+-- @tparam string tmpl  the template
+-- @tparam table values  values of the placeholders
+-- @treturn string filled-in template
+Litua.template = function (tmpl, values)
+    local function fill(placeholder)
+        local filters = {}
+        for item in placeholder:gmatch("[^|]+") do
+            table.insert(filters, Litua.trim(item))
+        end
+        local name = table.remove(filters, 1) or ""
+
+        local value = values
+        for key in name:gmatch("[^.]+") do
+            if type(value) ~= "table" then
+                value = nil
+                break
+            end
+            value = value[key]
+        end
+        if value == nil or name == "" then
+            Litua.error("template placeholder '${" .. placeholder .. "}' has no value", {
+                context = "template '" .. tmpl .. "'",
+                fix = "provide a value for '" .. name .. "'",
+            })
+        end
+
+        local text = tostring(value)
+        for _, filter in ipairs(filters) do
+            local apply = Litua.template_filters[filter]
+            if apply == nil then
+                Litua.error("unknown template filter '" .. filter .. "'", {
+                    context = "template '" .. tmpl .. "'",
+                    fix = "add the filter to Litua.template_filters",
+                })
+            end
+            text = apply(text)
+        end
+        return text
+    end
+
+    local filled = tmpl:gsub("(%$?)%${(.-)}", function (escape, placeholder)
+        if escape == "$" then
+            return "${" .. placeholder .. "}"
+        end
+        return fill(placeholder)
+    end)
+    return filled
+end
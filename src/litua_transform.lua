@@ -13,6 +13,9 @@ Litua.tree_to_nodes = function (tree)
         for _, argvalue in ipairs(argvalues) do
             if type(argvalue) == "table" then
                 table.insert(new_arg, Litua.tree_to_nodes(argvalue))
+            elseif type(argvalue) == "number" or type(argvalue) == "boolean" then
+                -- NOTE: values of typed arguments like [width:int=300]
+                table.insert(new_arg, argvalue)
             else
                 table.insert(new_arg, tostring(argvalue))
             end
@@ -31,22 +34,43 @@ Litua.tree_to_nodes = function (tree)
     return Litua.Node.init(new_call, new_args, new_content)
 end
 
+--- Determine the path of a child node for hook tracing
+-- Paths look like ``/document/p[2]/a[@href][1]`` where the number is the
+-- index within the content (or the argument value) of the parent node
+-- @param path  path of the parent node
+-- @param child  the child node
+-- @param index  index of the child within the content or argument value (optional)
+-- @param argkey  key of the argument containing the child (optional)
+-- @return  path of the child node as string
+Litua.child_path = function (path, child, index, argkey)
+    local segment = "/" .. tostring(child.call)
+    if argkey ~= nil then
+        segment = segment .. "[@" .. tostring(argkey) .. "]"
+    end
+    if index ~= nil then
+        segment = segment .. "[" .. tostring(index) .. "]"
+    end
+    return path .. segment
+end
+
 --- Implementation of the read-new-node hooks
 -- This function invokes the hook for the node and then recurses into any content
 -- or arg nodes (unless it's a string and thus has no children nodes)
 -- @param node  the current node to process
 -- @param depth  the current recursion depth
 -- @param hook_name  "read-new-node"
+-- @param path  path of the node within the tree (default: "/" followed by the call name)
 -- @return  error or nil
-Litua.recurse_reading = function (node, depth, hook_name)
+Litua.recurse_reading = function (node, depth, hook_name, path)
     local err
+    path = path or ("/" .. node.call)
 
-    local calls = { node.call, "" }
+    local calls = Litua.dispatch_filters(node.call)
     for _, call in ipairs(calls) do
         if Litua.hooks[hook_name][call] ~= nil then
             for i, hook in ipairs(Litua.hooks[hook_name][call]) do
                 Litua.log("transform", "ran " .. Litua.hooks[hook_name][call][i].src .. " for call '" .. node.call .. "'")
-                err = hook.impl(node:copy(), depth)
+                err = Litua.invoke_hook(hook_name, hook, node.call, path, node:copy(), depth)
                 if err ~= nil then
                     Litua.error(Litua.format("%1 hook #%2 returned non-nil value", hook_name, i), {
                         ["expected"] = "return value nil",
@@ -63,7 +87,7 @@ Litua.recurse_reading = function (node, depth, hook_name)
     for _, argvalues in ipairs(node.args) do
         for _, argvalue in ipairs(argvalues) do
             if argvalue.is_node then
-                err = Litua.recurse_reading(argvalue, depth + 1, hook_name)
+                err = Litua.recurse_reading(argvalue, depth + 1, hook_name, Litua.child_path(path, argvalue))
                 if err ~= nil then
                     return err
                 end
@@ -71,9 +95,9 @@ Litua.recurse_reading = function (node, depth, hook_name)
         end
     end
 
-    for _, value in ipairs(node.content) do
+    for i, value in ipairs(node.content) do
         if value.is_node then
-            err = Litua.recurse_reading(value, depth + 1, hook_name)
+            err = Litua.recurse_reading(value, depth + 1, hook_name, Litua.child_path(path, value, i))
             if err ~= nil then
                 return err
             end
@@ -87,16 +111,18 @@ end
 -- @param node  the current node to process
 -- @param depth  the current recursion depth
 -- @param hook_name  "modify-node"
+-- @param path  path of the node within the tree (default: "/" followed by the call name)
 -- @return  (modified node, error or nil)
-Litua.recurse_modify_node = function (node, depth, hook_name)
+Litua.recurse_modify_node = function (node, depth, hook_name, path)
     local err
+    path = path or ("/" .. node.call)
 
-    local calls = { node.call, "" }
+    local calls = Litua.dispatch_filters(node.call)
     for _, call in ipairs(calls) do
         if Litua.hooks[hook_name][call] ~= nil then
             for i, hook in ipairs(Litua.hooks[hook_name][call]) do
                 Litua.log("transform", "ran " .. Litua.hooks[hook_name][call][i].src .. " for call '" .. node.call .. "'")
-                node, err = hook.impl(node, depth, call)
+                node, err = Litua.invoke_hook(hook_name, hook, node.call, path, node, depth, call)
                 if node == nil or (not node.is_node and type(node) ~= "string") then
                     Litua.error(Litua.format("%1 hook #%2 returned nil value", hook_name, i), {
                         ["expected"] = "return value node",
@@ -114,15 +140,18 @@ Litua.recurse_modify_node = function (node, depth, hook_name)
                     })
                     return err
                 end
+                if node.is_node then
+                    Litua.Node.check_args(node, hook.src)
+                end
             end
         end
     end
 
     if type(node) ~= "string" then
-        for argkey, argvalues in pairs(node.args) do
-            for i, argvalue in ipairs(argvalues) do
-                if argvalue.is_node then
-                    node.args[argkey][i], err = Litua.recurse_modify_node(argvalue, depth + 1, hook_name)
+        for _, argkey in ipairs(Litua.sorted_keys(node.args)) do
+            for i, argvalue in ipairs(node.args[argkey]) do
+                if type(argvalue) == "table" and argvalue.is_node then
+                    node.args[argkey][i], err = Litua.recurse_modify_node(argvalue, depth + 1, hook_name, Litua.child_path(path, argvalue, i, argkey))
                     if err ~= nil then
                         return nil, err
                     end
@@ -132,7 +161,7 @@ Litua.recurse_modify_node = function (node, depth, hook_name)
 
         for i, value in ipairs(node.content) do
             if value.is_node then
-                node.content[i], err = Litua.recurse_modify_node(value, depth + 1, hook_name)
+                node.content[i], err = Litua.recurse_modify_node(value, depth + 1, hook_name, Litua.child_path(path, value, i))
                 if err ~= nil then
                     return nil, err
                 end
@@ -143,22 +172,182 @@ Litua.recurse_modify_node = function (node, depth, hook_name)
     return node, nil
 end
 
+--- Remember the string representation of a node for the source map
+-- Only active if Litua.config.sourcemap is set. The string representation
+-- is stored together with the source span of the node (argument "=span").
+-- The representation of the call selected by --explain is reported to
+-- Litua.explain_output (set by the runtime).
+-- @param node  the node which was converted to a string
+-- @param depth  the current recursion depth
+-- @param repr  the string representation of the node
+-- @return  the string representation `repr`
+Litua.record_fragment = function (node, depth, repr)
+    if Litua.explain_output ~= nil and Litua.is_explained(node) then
+        Litua.explain_output(repr)
+    end
+    if not Litua.config.sourcemap or node.args["=span"] == nil then
+        return repr
+    end
+
+    table.insert(Litua.fragments, {
+        ["call"] = node.call,
+        ["start"] = tonumber(node.args["=span"][1]),
+        ["end"] = tonumber(node.args["=span"][2]),
+        ["depth"] = depth,
+        ["output"] = repr,
+    })
+    return repr
+end
+
+--- Mark a text as the output of a node
+-- Hooks building strings from the string representations of several nodes
+-- (or rewriting them) can mark the part originating from a node, so the source
+-- map relates it to the node even if it does not occur verbatim in the output.
+-- Without --sourcemap, the text is returned unchanged. Otherwise it is
+-- surrounded by markers (see Litua.span_markers), which are removed from
+-- the final output after the modify_final_string hooks.
+-- @param text  the output of the node
+-- @param node  the node which produced the text
+-- @return  the marked text
+Litua.span = function (text, node)
+    text = tostring(text)
+    if type(node) ~= "table" or type(node.args) ~= "table" then
+        Litua.error("Litua.span expects a node as second argument", {
+            ["expected"] = "a node",
+            ["actual"] = type(node),
+            ["fix"] = "call Litua.span(text, node)",
+        })
+    end
+    if Litua.explain_output ~= nil and Litua.is_explained(node) then
+        Litua.explain_output(text)
+    end
+    if not Litua.config.sourcemap or node.args["=span"] == nil then
+        return text
+    end
+
+    table.insert(Litua.spans, {
+        ["call"] = node.call,
+        ["start"] = tonumber(node.args["=span"][1]),
+        ["end"] = tonumber(node.args["=span"][2]),
+    })
+    local markers = Litua.span_markers
+    return markers.start .. #Litua.spans .. markers.separator .. text .. markers["end"]
+end
+
+--- Maximum nesting of nodes returned by convert-node-to-string hooks
+-- (exceeded e.g. if a hook for call "a" returns a node with call "a")
+Litua.max_redispatch_depth = 100
+Litua.redispatch_depth = 0
+
+--- Run the read-new-node, modify-node, and read-modified-node hooks for a
+--- node returned by a convert-node-to-string hook. Passes and the preparation
+--- of the tree (variables, index, footnotes, argument defaults) do not run again.
+-- @param node  the node returned by the hook
+-- @param depth  the recursion depth of the node the hook was invoked for
+-- @param path  path of the returned node
+-- @return  (node or string returned by the modify-node hooks, error or nil)
+Litua.prepare_returned_node = function (node, depth, path)
+    local err = Litua.recurse_reading(node, depth, "read_new_node", path)
+    if err ~= nil then
+        return node, err
+    end
+    node, err = Litua.recurse_modify_node(node, depth, "modify_node", path)
+    if err ~= nil or type(node) ~= "table" or not node.is_node then
+        return node, err
+    end
+    return node, Litua.recurse_reading(node, depth, "read_modified_node", path)
+end
+
+--- Convert the items of a list returned by a convert-node-to-string hook
+--- (see Litua.redispatch_to_string)
+-- @return  (string representation, error or nil)
+Litua.convert_returned_items = function (items, depth, hook_name, path, hook)
+    local repr = ""
+    for i, item in ipairs(items) do
+        if type(item) == "table" and item.is_node then
+            Litua.Node.check_args(item, hook.src)
+            local item_path = Litua.child_path(path, item, i)
+            local prepared, err = Litua.prepare_returned_node(item, depth, item_path)
+            if err ~= nil then
+                return prepared, err
+            end
+            if type(prepared) == "string" then
+                repr = repr .. prepared
+            else
+                local item_repr
+                item_repr, err = Litua.recurse_node_to_string(prepared, depth, hook_name, item_path)
+                if err ~= nil then
+                    return item_repr, err
+                end
+                repr = repr .. item_repr
+            end
+        elseif type(item) == "string" then
+            repr = repr .. item
+        else
+            Litua.error(Litua.format("%1 hook returned a list with a %2 value", hook_name, type(item)), {
+                ["expected"] = "a string, a node, or a list of strings and nodes",
+                ["actual"] = Litua.format("item %1 is %2", i, item),
+                ["fix"] = "make hook return a string or nodes created with Litua.Node.init",
+                ["source"] = hook.src,
+            })
+        end
+    end
+    return repr, nil
+end
+
+--- Convert a node or a list of nodes and strings returned by a
+--- convert-node-to-string hook to a string. The nodes run through the
+--- read-new-node, modify-node, and read-modified-node hooks and are
+--- dispatched through the convert-node-to-string hooks again.
+-- @param result  the node or list returned by the hook
+-- @param depth  the recursion depth of the node the hook was invoked for
+-- @param hook_name  "convert-node-to-string"
+-- @param path  path of the node the hook was invoked for
+-- @param hook  the hook entry (table with fields src and impl)
+-- @return  (string representation, error or nil)
+Litua.redispatch_to_string = function (result, depth, hook_name, path, hook)
+    local items = result
+    if result.is_node then
+        items = { result }
+    end
+
+    if Litua.redispatch_depth >= Litua.max_redispatch_depth then
+        Litua.error(Litua.format("%1 hook returned nodes nested more than %2 times", hook_name, Litua.max_redispatch_depth), {
+            ["context"] = "nodes returned by " .. tostring(hook_name) .. " hooks are converted by " .. tostring(hook_name) .. " hooks again",
+            ["fix"] = "make sure the hook for a call does not return a node with the same call",
+            ["source"] = hook.src,
+        })
+    end
+
+    -- NOTE: the depth is restored even if a hook raises an error,
+    --       so the error does not leak into later documents or chunks
+    Litua.redispatch_depth = Litua.redispatch_depth + 1
+    local ok, repr, err = pcall(Litua.convert_returned_items, items, depth, hook_name, path, hook)
+    Litua.redispatch_depth = Litua.redispatch_depth - 1
+    if not ok then
+        error(repr, 0)
+    end
+    return repr, err
+end
+
 --- Implementation of the convert-node-to-string hooks
 -- This function invokes the hook for the args nodes,
 -- then content nodes and finally for the node itself
 -- @param node  the current node to process
 -- @param depth  the current recursion depth
 -- @param hook_name  "convert-node-to-string"
+-- @param path  path of the node within the tree (default: "/" followed by the call name)
 -- @return  (string representation, error or nil)
-Litua.recurse_node_to_string = function (node, depth, hook_name)
+Litua.recurse_node_to_string = function (node, depth, hook_name, path)
     local err
+    path = path or ("/" .. node.call)
     if node.call == "left-curly-brace" then return "{", nil end
     if node.call == "right-curly-brace" then return "}", nil end
 
     -- (1) resolve args to string
     for i, arg in ipairs(node.args) do
         if arg.is_node then
-            node.args[i], err = Litua.recurse_node_to_string(arg, depth + 1, hook_name)
+            node.args[i], err = Litua.recurse_node_to_string(arg, depth + 1, hook_name, Litua.child_path(path, arg))
             if err ~= nil then
                 return node.args[i], err
             end
@@ -170,7 +359,7 @@ Litua.recurse_node_to_string = function (node, depth, hook_name)
     -- (2) resolve content to string
     for i = 1,#node.content do
         if node.content[i].is_node then
-            node.content[i], err = Litua.recurse_node_to_string(node.content[i], depth + 1, hook_name)
+            node.content[i], err = Litua.recurse_node_to_string(node.content[i], depth + 1, hook_name, Litua.child_path(path, node.content[i], i))
             if err ~= nil then
                 return node.content[i], err
             end
@@ -180,7 +369,14 @@ Litua.recurse_node_to_string = function (node, depth, hook_name)
     end
 
     -- (3) call hooks for this node
-    local calls = { node.call, "" }
+    -- NOTE: the runtime sets Litua.unhandled_call to collect calls without a hook (see --report-unhandled-calls)
+    local calls = Litua.dispatch_filters(node.call)
+    local own_hooks = Litua.hooks[hook_name][node.call]
+    local namespace_hooks = calls[3] ~= nil and Litua.hooks[hook_name][calls[2]] or nil
+    local handled = (own_hooks ~= nil and own_hooks[1] ~= nil) or (namespace_hooks ~= nil and namespace_hooks[1] ~= nil)
+    if Litua.unhandled_call ~= nil and depth > 0 and not node.call:match("^<+$") and not handled then
+        Litua.unhandled_call(node.call)
+    end
     for _, call in ipairs(calls) do
         local hooks = Litua.hooks[hook_name][call]
         if hooks ~= nil and hooks[1] ~= nil then
@@ -188,7 +384,7 @@ Litua.recurse_node_to_string = function (node, depth, hook_name)
             Litua.log("transform", "ran " .. hook.src .. " for call '" .. node.call .. "'")
 
             local result
-            result, err = hook.impl(node, depth, call)
+            result, err = Litua.invoke_hook(hook_name, hook, node.call, path, node, depth, call)
             if err ~= nil then
                 Litua.error(Litua.format("%1 hook returned non-nil value as second value", hook_name), {
                     ["context"] = tostring(hook_name) .. " hooks must return two values (node and error)",
@@ -199,6 +395,12 @@ Litua.recurse_node_to_string = function (node, depth, hook_name)
                 })
                 return nil, err
             end
+            if type(result) == "table" then
+                result, err = Litua.redispatch_to_string(result, depth, hook_name, path, hook)
+                if err ~= nil then
+                    return result, err
+                end
+            end
             if type(result) ~= "string" then
                 Litua.error(Litua.format("%1 hook returned non-string value as first return value", hook_name), {
                     ["context"] = Litua.format("%1 hooks must return two values (string representation and error)", hook_name),
@@ -210,11 +412,416 @@ Litua.recurse_node_to_string = function (node, depth, hook_name)
                 return "error", err
             end
 
-            return result, nil
+            return Litua.record_fragment(node, depth, result), nil
         end
     end
 
-    return tostring(node), nil
+    return Litua.record_fragment(node, depth, tostring(node)), nil
+end
+
+--- Is any read_new_node, modify_node, read_modified_node, or convert_node_to_string
+--- hook registered for call name `call`? The built-in passes leave calls with such
+--- hooks alone, so hook files handling calls like ``footnote`` themselves keep working.
+-- @param call  call name as string
+-- @return  true if a hook is registered for `call` (hooks for every call do not count)
+Litua.has_node_hooks = function (call)
+    for _, hook_name in ipairs({ "read_new_node", "modify_node", "read_modified_node", "convert_node_to_string" }) do
+        local hooks = Litua.hooks[hook_name][Litua.normalize_name(call)]
+        if hooks ~= nil and #hooks > 0 then
+            return true
+        end
+    end
+    return false
+end
+
+--- Call name of nodes replaced by the value of the variable named by their content
+Litua.var_call = "var"
+--- Prefix of call names like ``$title`` replaced by the value of the variable ``title``
+Litua.var_prefix = "$"
+
+--- Returns the name of the variable a node refers to (or nil if it is no variable node)
+-- @param node  the node ``{var name}`` or ``{$name}``
+-- @return  the variable name or nil
+Litua.var_name = function (node)
+    if node.call == Litua.var_call then
+        return Litua.concat_table_values(node.content):match("^%s*(.-)%s*$")
+    elseif node.call:sub(1, #Litua.var_prefix) == Litua.var_prefix and #node.call > #Litua.var_prefix then
+        return node.call:sub(#Litua.var_prefix + 1)
+    end
+    return nil
+end
+
+--- Implementation of the variable substitution pass
+-- Replaces every node ``{var name}`` and ``{$name}`` by the value of the variable
+-- ``name``. The variable is looked up in ``Litua.vars`` (set by on_setup and
+-- modify_initial_string hooks), the arguments of the root node (``--define``),
+-- and the directives of the document header in this order. Values of
+-- ``Litua.vars`` are strings (other values are converted by tostring), nodes,
+-- or lists of strings and nodes. Unknown variables raise an error. Nodes
+-- with hooks registered for their call name (like ``var`` for HTML ``<var>``)
+-- are left alone.
+-- @param root  the root node
+-- @return  the root node with variables substituted
+Litua.substitute_vars = function (root)
+    local handled = {}
+    local function is_handled(call)
+        if handled[call] == nil then
+            handled[call] = Litua.has_node_hooks(call)
+        end
+        return handled[call]
+    end
+
+    local function lookup(name, node, path)
+        local value = Litua.vars[name]
+        if value == nil and name:sub(1, 1) ~= "=" then
+            value = root.args[name]
+        end
+        if value == nil then
+            value = Litua.config.directives[name]
+        end
+
+        if value == nil then
+            Litua.raise_hook_error({ ["src"] = "variable substitution" }, node.call, path, node, Litua.format("unknown variable %1", name))
+        elseif type(value) == "table" and value.is_node then
+            return { value:copy() }
+        elseif type(value) == "table" then
+            local elements = {}
+            for _, element in ipairs(value) do
+                if type(element) == "table" and element.is_node then
+                    table.insert(elements, element:copy())
+                else
+                    table.insert(elements, tostring(element))
+                end
+            end
+            return elements
+        end
+        return { tostring(value) }
+    end
+
+    local recurse
+    local function substitute(elements, path, argkey)
+        local result = {}
+        for i, element in ipairs(elements) do
+            if type(element) == "table" and element.is_node then
+                local child_path = Litua.child_path(path, element, i, argkey)
+                local name = Litua.var_name(element)
+                if name ~= nil and is_handled(element.call) then
+                    name = nil
+                end
+                if name == nil then
+                    recurse(element, child_path)
+                    table.insert(result, element)
+                else
+                    for _, value in ipairs(lookup(name, element, child_path)) do
+                        table.insert(result, value)
+                    end
+                end
+            else
+                table.insert(result, element)
+            end
+        end
+        return result
+    end
+
+    recurse = function (node, path)
+        for _, argkey in ipairs(Litua.sorted_keys(node.args)) do
+            if argkey:sub(1, 1) ~= "=" then
+                node.args[argkey] = substitute(node.args[argkey], path, argkey)
+            end
+        end
+        node.content = substitute(node.content, path)
+    end
+
+    recurse(root, "/" .. root.call)
+    return root
+end
+
+--- Adds the default values of ``Litua.defaults`` to every node lacking these
+--- arguments, e.g. ``Litua.defaults.img.width = "auto"`` turns ``{img[src=a.png]}``
+--- into ``{img[src=a.png][width=auto]}``. Default values are strings, numbers,
+--- booleans, nodes, or lists of strings and nodes. Reserved arguments (starting
+--- with ``=``) cannot have defaults.
+--- @param root  the root node
+--- @return  the root node with default arguments
+Litua.apply_defaults = function (root)
+    local defaults = {}
+    for call, call_defaults in pairs(Litua.defaults) do
+        if type(call_defaults) ~= "table" then
+            Litua.error(Litua.format("defaults of call %1 must be a table", call), {
+                ["expected"] = "a table mapping argument keys to default values",
+                ["actual"] = Litua.format("%1", call_defaults),
+                ["fix"] = Litua.format("declare defaults like Litua.defaults[%1].key = \"value\"", call),
+            })
+        end
+        for _, key in ipairs(Litua.sorted_keys(call_defaults)) do
+            if type(key) ~= "string" or key:sub(1, 1) == "=" then
+                Litua.error(Litua.format("argument %1 of call %2 cannot have a default value", key, call), {
+                    ["context"] = "argument keys are strings and keys starting with '=' are reserved for litua",
+                    ["fix"] = "remove the default value from Litua.defaults",
+                })
+            end
+        end
+        defaults[Litua.normalize_name(call)] = call_defaults
+    end
+    if next(defaults) == nil then
+        return root
+    end
+
+    local function default_value(call, key, value)
+        if type(value) == "string" or type(value) == "number" or type(value) == "boolean" then
+            return { value }
+        elseif type(value) == "table" and value.is_node then
+            return { value:copy() }
+        elseif type(value) == "table" then
+            local elements = {}
+            for _, element in ipairs(value) do
+                if type(element) == "table" and element.is_node then
+                    table.insert(elements, element:copy())
+                else
+                    table.insert(elements, tostring(element))
+                end
+            end
+            return elements
+        end
+        Litua.error(Litua.format("default value of argument %1 of call %2 must be a string, number, boolean, or node", key, call), {
+            ["actual"] = Litua.format("%1", value),
+        })
+    end
+
+    local function apply(node)
+        local call_defaults = defaults[node.call]
+        if call_defaults ~= nil then
+            for _, key in ipairs(Litua.sorted_keys(call_defaults)) do
+                if node.args[key] == nil then
+                    node.args[key] = default_value(node.call, key, call_defaults[key])
+                end
+            end
+        end
+        for _, argvalues in pairs(node.args) do
+            for _, argvalue in ipairs(argvalues) do
+                if type(argvalue) == "table" and argvalue.is_node then
+                    apply(argvalue)
+                end
+            end
+        end
+        for _, value in ipairs(node.content) do
+            if type(value) == "table" and value.is_node then
+                apply(value)
+            end
+        end
+    end
+
+    apply(root)
+    return root
+end
+
+--- Call name of nodes marking an index term
+Litua.index_call = "index"
+--- Call name of nodes replaced by the index
+Litua.printindex_call = "printindex"
+
+--- Build the index of the terms collected in Litua.index_entries
+-- Every term becomes a node ``{index-entry[term=…] term {index-ref[target=index-1] 1}…}``
+-- with one back-reference per occurrence (numbered from 1). The entries are sorted
+-- with Litua.collation and grouped in nodes ``{index-group[letter=A] …}``.
+-- @param locale  locale determining the order (default: Litua.datetime.locale())
+-- @return  list of index-group nodes
+Litua.build_index = function (locale)
+    local terms, occurrences = {}, {}
+    for _, entry in ipairs(Litua.index_entries) do
+        if occurrences[entry.term] == nil then
+            occurrences[entry.term] = {}
+            table.insert(terms, entry.term)
+        end
+        table.insert(occurrences[entry.term], entry.id)
+    end
+
+    local groups, group = {}, nil
+    for _, term in ipairs(Litua.collation.sort(terms, locale)) do
+        local letter = Litua.collation.group(term, locale)
+        if group == nil or group.args.letter[1] ~= letter then
+            group = Litua.Node.init("index-group", { ["letter"] = { letter } }, {})
+            table.insert(groups, group)
+        end
+        local entry = Litua.Node.init("index-entry", { ["term"] = { term } }, { term })
+        for i, id in ipairs(occurrences[term]) do
+            table.insert(entry.content, Litua.Node.init("index-ref", { ["target"] = { id } }, { tostring(i) }))
+        end
+        table.insert(group.content, entry)
+    end
+    return groups
+end
+
+--- Implementation of the index pass
+-- Collects the terms of all nodes ``{index term}`` in document order into
+-- Litua.index_entries and assigns them an argument ``id`` like ``index-1``
+-- (unless they have one), then replaces every node ``{printindex}`` by the
+-- index built by Litua.build_index (with its argument ``locale`` if given).
+-- With --chunked, the index contains the terms of the current and previous chunks.
+-- The pass is skipped if hooks are registered for ``index`` or ``printindex``.
+-- @param root  the root node
+-- @return  the root node with printindex nodes expanded
+Litua.expand_index = function (root)
+    for _, call in ipairs({ Litua.index_call, Litua.printindex_call }) do
+        if Litua.has_node_hooks(call) then
+            Litua.log("transform", Litua.format("the index is not expanded, since hooks for call %1 are registered", call))
+            return root
+        end
+    end
+
+    local function collect(node)
+        if node.call == Litua.index_call then
+            if node.args.id == nil then
+                node.args.id = { "index-" .. tostring(#Litua.index_entries + 1) }
+            end
+            table.insert(Litua.index_entries, {
+                ["term"] = Litua.trim(node:totext()),
+                ["id"] = Litua.concat_table_values(node.args.id),
+            })
+        end
+        for _, value in ipairs(node.content) do
+            if value.is_node then
+                collect(value)
+            end
+        end
+    end
+
+    local function expand(node)
+        local content = {}
+        for _, value in ipairs(node.content) do
+            if value.is_node and value.call == Litua.printindex_call then
+                local locale
+                if value.args.locale ~= nil then
+                    locale = Litua.trim(Litua.concat_table_values(value.args.locale))
+                end
+                for _, group in ipairs(Litua.build_index(locale)) do
+                    table.insert(content, group)
+                end
+            else
+                if value.is_node then
+                    expand(value)
+                end
+                table.insert(content, value)
+            end
+        end
+        node.content = content
+    end
+
+    collect(root)
+    expand(root)
+    return root
+end
+
+--- Call name of nodes with the body of a footnote
+Litua.footnote_call = "footnote"
+--- Call name of nodes replaced by the bodies of the preceding footnotes
+Litua.footnotes_call = "footnotes"
+
+--- Implementation of the footnote pass
+-- Numbers all nodes ``{footnote body}`` in document order (continuing
+-- Litua.footnote_count) and replaces them by markers
+-- ``{footnote-ref[number=1][id=footnote-ref-1][target=footnote-1] 1}``.
+-- Every node ``{footnotes}`` (e.g. at the end of a section) is replaced by
+-- ``{footnote-list {footnote-body[number=1][id=footnote-1][target=footnote-ref-1] body}…}``
+-- with the footnotes since the previous ``{footnotes}`` node. Remaining footnotes
+-- are appended to the content of the root node (with --chunked, of the chunk).
+-- The pass is skipped if hooks are registered for ``footnote`` or ``footnotes``.
+-- @param root  the root node
+-- @return  the root node with footnotes placed
+Litua.place_footnotes = function (root)
+    for _, call in ipairs({ Litua.footnote_call, Litua.footnotes_call }) do
+        if Litua.has_node_hooks(call) then
+            Litua.log("transform", Litua.format("footnotes are not placed, since hooks for call %1 are registered", call))
+            return root
+        end
+    end
+    local pending = {}
+
+    local function source_args(node, args)
+        for _, key in ipairs({ "=span", "=file" }) do
+            if node.args[key] ~= nil then
+                args[key] = node.args[key]
+            end
+        end
+        return args
+    end
+
+    local function footnote_list()
+        local list = Litua.Node.init("footnote-list", {}, pending)
+        pending = {}
+        return list
+    end
+
+    local place
+    local function marker(node)
+        Litua.footnote_count = Litua.footnote_count + 1
+        local number = tostring(Litua.footnote_count)
+        local body = Litua.Node.init("footnote-body", source_args(node, {
+            ["number"] = { number },
+            ["id"] = { "footnote-" .. number },
+            ["target"] = { "footnote-ref-" .. number },
+        }), node.content)
+        table.insert(pending, body)
+        place(body)
+        return Litua.Node.init("footnote-ref", source_args(node, {
+            ["number"] = { number },
+            ["id"] = { "footnote-ref-" .. number },
+            ["target"] = { "footnote-" .. number },
+        }), { number })
+    end
+
+    place = function (node)
+        local content = {}
+        for _, value in ipairs(node.content) do
+            if value.is_node and value.call == Litua.footnote_call then
+                table.insert(content, marker(value))
+            elseif value.is_node and value.call == Litua.footnotes_call then
+                if #pending > 0 then
+                    table.insert(content, footnote_list())
+                end
+            else
+                if value.is_node then
+                    place(value)
+                end
+                table.insert(content, value)
+            end
+        end
+        node.content = content
+    end
+
+    place(root)
+    if #pending > 0 then
+        table.insert(root.content, footnote_list())
+    end
+    return root
+end
+
+--- Run the passes registered with Litua.register_pass over the whole tree
+--- in order of registration. Every pass receives the root node returned by
+--- the previous pass.
+-- @param root  the root node
+-- @return  the root node returned by the last pass
+Litua.run_passes = function (root)
+    local root_tostring = root.tostring
+    for _, name in ipairs(Litua.pass_order) do
+        local hook = Litua.hooks.pass[name][1]
+        Litua.log("transform", "ran pass '" .. name .. "' from " .. hook.src)
+        local result = Litua.invoke_hook("pass", hook, "", "", root, name)
+        if type(result) ~= "table" or not result.is_node then
+            Litua.error(Litua.format("pass %1 returned no node", name), {
+                ["context"] = "passes return the root node of the tree (modified or replaced)",
+                ["expected"] = "return value node",
+                ["actual"] = Litua.format("return value is %1", result),
+                ["fix"] = "make the pass return the root node",
+                ["source"] = hook.src,
+            })
+        end
+        Litua.Node.check_args(result, hook.src)
+        -- NOTE: the root node has a special string representation
+        result.tostring = root_tostring
+        root = result
+    end
+    return root
 end
 
 --- Transformation function taking a root element `tree`,
@@ -262,6 +869,22 @@ Litua.transform = function (tree)
     local function run_intermediate_hooks(top_node)
         local err, repr, hook_name
 
+        -- (1b) substitute variables
+        Litua.log("transform", "substitute variables")
+        top_node = Litua.substitute_vars(top_node)
+
+        -- (1c) collect index terms and expand printindex nodes
+        Litua.log("transform", "expand index")
+        top_node = Litua.expand_index(top_node)
+
+        -- (1d) number footnotes and place their bodies
+        Litua.log("transform", "place footnotes")
+        top_node = Litua.place_footnotes(top_node)
+
+        -- (1e) add default values of arguments
+        Litua.log("transform", "apply argument defaults")
+        top_node = Litua.apply_defaults(top_node)
+
         -- (2) run read_new_node hooks
         hook_name = "read_new_node"
         Litua.log("transform", "run " .. hook_name .. " hooks")
@@ -286,6 +909,17 @@ Litua.transform = function (tree)
             return err
         end
 
+        -- (4b) run passes over the whole tree
+        Litua.log("transform", "run passes")
+        if top_node.is_node then
+            top_node = Litua.run_passes(top_node)
+        end
+
+        -- keep the transformed tree, since converting it to a string modifies it
+        if Litua.config.dump_transformed and top_node.is_node then
+            Litua.transformed_tree = top_node:copy()
+        end
+
         -- (5) run convert_node_to_string hooks
         hook_name = "convert_node_to_string"
         Litua.log("transform", "run " .. hook_name .. " hooks")
@@ -298,6 +932,9 @@ Litua.transform = function (tree)
     end
 
     local no_error_occured, err_or_text = pcall(run_intermediate_hooks, root)
+    if not no_error_occured then
+        error(err_or_text, 0)
+    end
     return err_or_text
 end
 
@@ -306,13 +943,100 @@ end
 -- @param text  the text document content
 -- @return  text document content
 Litua.postprocess = function (text)
-    local result, hook_name
+    text = Litua.postprocess_chunk(text)
+    Litua.teardown()
+    return text
+end
+
+--- Returns a deep copy of a value. Tables are copied with their metatables.
+-- @param value  any Lua value
+-- @param copies  table mapping the tables copied so far to their copies
+-- @return  the copy of the value
+local function deep_copy(value, copies)
+    if type(value) ~= "table" then
+        return value
+    end
+    if copies[value] ~= nil then
+        return copies[value]
+    end
+    local copy = {}
+    copies[value] = copy
+    for key, item in next, value do
+        rawset(copy, deep_copy(key, copies), deep_copy(item, copies))
+    end
+    return setmetatable(copy, getmetatable(value))
+end
+
+-- Litua.global as set up by the on_setup hooks before the first chunk
+local setup_global = {}
+
+--- Reset the state of the previous chunk before a chunk
+--- of a document is transformed (see --chunked).
+--- Litua.global is restored to its state after the on_setup hooks,
+--- only Litua.global_state persists across chunks.
+-- @param index  the 1-based index of the chunk
+Litua.begin_chunk = function (index)
+    if index == 1 then
+        setup_global = deep_copy(Litua.global, {})
+    end
+    Litua.global = setmetatable(deep_copy(setup_global, {}), AccessLoggingTable)
+    Litua.fragments = {}
+    Litua.spans = {}
+    Litua.transformed_tree = nil
+    Litua.config.chunk = index
+end
+
+--- Returns the state persisting across the chunks of a document
+--- (see --chunked and --chunk-cache)
+-- @return  table with keys global_state, index_entries, and footnote_count
+Litua.chunk_state = function ()
+    return {
+        ["global_state"] = Litua.global_state,
+        ["index_entries"] = Litua.index_entries,
+        ["footnote_count"] = Litua.footnote_count,
+    }
+end
+
+--- Restore the state persisting across chunks returned by Litua.chunk_state
+-- @param state  table with keys global_state, index_entries, and footnote_count
+Litua.restore_chunk_state = function (state)
+    Litua.global_state = state.global_state or {}
+    Litua.index_entries = state.index_entries or {}
+    Litua.footnote_count = state.footnote_count or 0
+end
+
+--- Returns the context the chunks of a document are transformed in:
+--- the configuration, the variables, the argument defaults, and the hook files of the registered hooks
+-- @return  table with keys config, vars, defaults, and hooks
+Litua.chunk_context = function ()
+    local hooks = {}
+    for _, hook_name in ipairs(Litua.sorted_keys(Litua.hooks)) do
+        for _, filter in ipairs(Litua.sorted_keys(Litua.hooks[hook_name])) do
+            for _, hook in ipairs(Litua.hooks[hook_name][filter]) do
+                table.insert(hooks, hook_name .. " " .. filter .. " " .. tostring(hook.file or hook.src))
+            end
+        end
+    end
+    return {
+        ["config"] = Litua.config,
+        ["vars"] = Litua.vars,
+        ["defaults"] = Litua.defaults,
+        ["hooks"] = hooks,
+    }
+end
+
+--- Run the modify_final_string hooks on the string
+--- representation of the document (or one chunk of it)
+-- @param text  the text document content
+-- @return  text document content
+Litua.postprocess_chunk = function (text)
+    local hook_name
 
     -- (6) run modify_final_string hooks
     hook_name = "modify_final_string"
     Litua.log("postprocess", "run " .. hook_name .. " hooks")
     for i=1,#Litua.hooks[hook_name][""] do
-        text = Litua.hooks[hook_name][""][i].impl(text)
+        text = Litua.invoke_hook(hook_name, Litua.hooks[hook_name][""][i], "", "", text)
         if type(text) ~= "string" then
             Litua.error(Litua.format("%1 hook returned non-string value as first return value", hook_name), {
                 ["context"] = Litua.format("%1 hooks must return two values (string representation and error)", hook_name),
@@ -324,11 +1048,18 @@ Litua.postprocess = function (text)
         end
     end
 
+    return text
+end
+
+--- Run the on_teardown hooks after the last chunk has been written
+Litua.teardown = function ()
+    local result, hook_name
+
     -- (7) run on_teardown hooks
     hook_name = "on_teardown"
     Litua.log("postprocess", "run " .. hook_name .. " hooks")
     for i=1,#Litua.hooks[hook_name][""] do
-        result = Litua.hooks[hook_name][""][i].impl()
+        result = Litua.invoke_hook(hook_name, Litua.hooks[hook_name][""][i], "", "")
         if result ~= nil then
             Litua.error(Litua.format("%1 hook returned non-nil value", hook_name), {
                 ["expected"] = Litua.format("%1 hooks must return nil", hook_name),
@@ -337,6 +1068,4 @@ Litua.postprocess = function (text)
             })
         end
     end
-
-    return text
 end
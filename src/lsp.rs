@@ -0,0 +1,329 @@
+//! Language server for litua text documents
+//!
+//! `litua lsp` speaks the Language Server Protocol over stdin and stdout.
+//! It offers the call names of the catalog of the loaded hooks (see `catalog`)
+//! as completions after `{`, the argument keys the hooks of a call read as
+//! completions after `[`, and the documentation of a call as hover text.
+//! Documents are synchronized in full, so every change sends the entire text.
+
+use std::collections::HashMap;
+use std::io;
+
+use crate::catalog;
+use crate::lexer;
+
+/// LSP error code of requests with an unknown method
+const METHOD_NOT_FOUND: i64 = -32601;
+/// LSP kind of completion items for call names
+const KIND_FUNCTION: u8 = 3;
+/// LSP kind of completion items for argument keys
+const KIND_FIELD: u8 = 5;
+
+/// Read one message (a JSON value framed by a `Content-Length` header) from `reader`.
+/// Returns `None` at the end of the input.
+pub fn read_message<R: io::BufRead>(reader: &mut R) -> io::Result<Option<serde_json::Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "message without Content-Length header"))?;
+    let mut content = vec![0; length];
+    reader.read_exact(&mut content)?;
+    serde_json::from_slice(&content).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Write `message` framed by a `Content-Length` header to `writer`
+pub fn write_message<W: io::Write>(writer: &mut W, message: &serde_json::Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{content}", content.len())?;
+    writer.flush()
+}
+
+/// Can `chr` be part of a call name?
+fn is_name_char(chr: char) -> bool {
+    chr.is_alphanumeric() || lexer::CALL_NAME_PUNCTUATION.contains(chr)
+}
+
+/// Returns the byte offset of the LSP position `line` (0-based) and `character`
+/// (in UTF-16 code units) within `text`. Positions beyond a line refer to its end.
+pub fn byte_offset(text: &str, line: usize, character: usize) -> usize {
+    let start = match line {
+        0 => 0,
+        _ => match text.match_indices('\n').nth(line - 1) {
+            Some((index, _)) => index + 1,
+            None => return text.len(),
+        },
+    };
+    let mut units = 0;
+    for (index, chr) in text[start..].char_indices() {
+        if units >= character || chr == '\n' {
+            return start + index;
+        }
+        units += chr.len_utf16();
+    }
+    text.len()
+}
+
+/// `Completion` is what the cursor completes
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum Completion<'t> {
+    /// a call name after `{`
+    CallName,
+    /// an argument key of the given call after `[`
+    ArgKey(&'t str),
+}
+
+/// Returns what is completed at byte offset `offset` of `text` (if anything)
+pub fn completion_at(text: &str, offset: usize) -> Option<Completion<'_>> {
+    let prefix = text.get(..offset)?;
+    let before = prefix.trim_end_matches(is_name_char);
+    if let Some(before) = before.strip_suffix(lexer::OPEN_FUNCTION) {
+        // NOTE: `{{` is the start of an escaped literal brace, not of a call
+        return (!before.ends_with(lexer::OPEN_FUNCTION)).then_some(Completion::CallName);
+    }
+    // NOTE: skip the preceding arguments like `[id=intro]` up to the call name
+    let mut before = before.strip_suffix(lexer::OPEN_ARG)?;
+    while let Some(arg) = before.strip_suffix(lexer::CLOSE_ARG) {
+        before = &arg[..arg.rfind(lexer::OPEN_ARG)?];
+    }
+    let call = &before[before.trim_end_matches(is_name_char).len()..];
+    let opened = before[..before.len() - call.len()].ends_with(lexer::OPEN_FUNCTION);
+    (opened && !call.is_empty()).then_some(Completion::ArgKey(call))
+}
+
+/// Returns the call name `{name` with its byte range, which contains byte offset `offset` of `text`
+pub fn call_at(text: &str, offset: usize) -> Option<(&str, std::ops::Range<usize>)> {
+    let prefix = text.get(..offset)?;
+    let start = prefix.trim_end_matches(is_name_char).len();
+    let end = offset + text[offset..].find(|chr| !is_name_char(chr)).unwrap_or(text.len() - offset);
+    let opened = text[..start].strip_suffix(lexer::OPEN_FUNCTION).is_some_and(|before| !before.ends_with(lexer::OPEN_FUNCTION));
+    (opened && start < end).then(|| (&text[start..end], start..end))
+}
+
+/// Returns the LSP position (0-based line and character in UTF-16 code units) of byte offset `offset` of `text`
+fn position(text: &str, offset: usize) -> serde_json::Value {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    serde_json::json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+/// Describe the hooks registered for a call like `convert_node_to_string (hooks.lua:4)`
+fn hook_sites(info: &catalog::CallInfo) -> String {
+    info.hooks.iter().map(|site| format!("{} ({}:{})", site.hook, site.file, site.line)).collect::<Vec<_>>().join(", ")
+}
+
+/// `Server` answers the requests of an editor regarding the documents it opened
+#[derive(Clone,Debug,Default)]
+pub struct Server {
+    catalog: catalog::Catalog,
+    /// text of the open documents by URI
+    documents: HashMap<String, String>,
+    /// has the client requested a shutdown?
+    shutdown: bool,
+}
+
+impl Server {
+    /// Create a server offering the calls of `catalog`
+    pub fn new(catalog: catalog::Catalog) -> Self {
+        Self { catalog, documents: HashMap::new(), shutdown: false }
+    }
+
+    /// Handle `message` and return the response to a request
+    /// (notifications like `textDocument/didOpen` have none)
+    pub fn handle(&mut self, message: &serde_json::Value) -> Option<serde_json::Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let result = match method {
+            "initialize" => serde_json::json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "completionProvider": { "triggerCharacters": [lexer::OPEN_FUNCTION.to_string(), lexer::OPEN_ARG.to_string()] },
+                    "hoverProvider": true,
+                },
+                "serverInfo": { "name": "litua", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                if let (Some(uri), Some(text)) = (document["uri"].as_str(), document["text"].as_str()) {
+                    self.documents.insert(uri.to_owned(), text.to_owned());
+                }
+                return None;
+            },
+            "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                let text = params["contentChanges"].as_array().and_then(|changes| changes.last()?["text"].as_str());
+                if let (Some(document), Some(text)) = (self.documents.get_mut(uri), text) {
+                    *document = text.to_owned();
+                }
+                return None;
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(params["textDocument"]["uri"].as_str().unwrap_or_default());
+                return None;
+            },
+            "textDocument/completion" => self.completion(params),
+            "textDocument/hover" => self.hover(params),
+            "shutdown" => {
+                self.shutdown = true;
+                serde_json::Value::Null
+            },
+            _ if message.get("id").is_none() => return None,
+            _ => return Some(serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": message["id"],
+                "error": { "code": METHOD_NOT_FOUND, "message": format!("method '{method}' is not supported") },
+            })),
+        };
+        message.get("id").map(|id| serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+    }
+
+    /// Returns the text of the document and the byte offset of the position given by `params`
+    fn document_position(&self, params: &serde_json::Value) -> Option<(&str, usize)> {
+        let text = self.documents.get(params["textDocument"]["uri"].as_str()?)?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let character = params["position"]["character"].as_u64()? as usize;
+        Some((text, byte_offset(text, line, character)))
+    }
+
+    /// Returns the completion items at the position given by `params`
+    fn completion(&self, params: &serde_json::Value) -> serde_json::Value {
+        let completion = self.document_position(params).and_then(|(text, offset)| completion_at(text, offset));
+        let items: Vec<serde_json::Value> = match completion {
+            Some(Completion::CallName) => self.catalog.calls.iter().map(|(call, info)| serde_json::json!({
+                "label": call,
+                "kind": KIND_FUNCTION,
+                "detail": hook_sites(info),
+                "documentation": { "kind": "markdown", "value": info.doc },
+            })).collect(),
+            Some(Completion::ArgKey(call)) => self.catalog.calls.get(call).into_iter()
+                .flat_map(|info| info.args.iter())
+                .map(|key| serde_json::json!({ "label": key, "kind": KIND_FIELD, "detail": format!("argument of call '{call}'") }))
+                .collect(),
+            None => vec![],
+        };
+        serde_json::Value::from(items)
+    }
+
+    /// Returns the hover text of the call at the position given by `params` (or null)
+    fn hover(&self, params: &serde_json::Value) -> serde_json::Value {
+        let hover = self.document_position(params).and_then(|(text, offset)| {
+            let (call, range) = call_at(text, offset)?;
+            let info = self.catalog.calls.get(call)?;
+            let mut value = format!("**{call}**");
+            if !info.doc.is_empty() {
+                value.push_str(&format!("\n\n{}", info.doc));
+            }
+            if !info.args.is_empty() {
+                value.push_str(&format!("\n\narguments: {}", info.args.iter().map(|key| format!("`{key}`")).collect::<Vec<_>>().join(", ")));
+            }
+            value.push_str(&format!("\n\nhooks: {}", hook_sites(info)));
+            Some(serde_json::json!({
+                "contents": { "kind": "markdown", "value": value },
+                "range": { "start": position(text, range.start), "end": position(text, range.end) },
+            }))
+        });
+        hover.unwrap_or(serde_json::Value::Null)
+    }
+}
+
+/// Answer the messages read from `reader` with `server` and write the responses to `writer`
+/// until the client sends `exit`. Returns whether the client requested a shutdown before.
+pub fn serve<R: io::BufRead, W: io::Write>(server: &mut Server, reader: &mut R, writer: &mut W) -> io::Result<bool> {
+    while let Some(message) = read_message(reader)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        if let Some(response) = server.handle(&message) {
+            write_message(writer, &response)?;
+        }
+    }
+    Ok(server.shutdown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> catalog::Catalog {
+        let src = "--- A section with a heading\nLitua.convert_node_to_string(\"section\", function (node)\n  return node.args.title[1]\nend)\n";
+        let mut catalog = catalog::Catalog::new();
+        let site = catalog::HookSite { hook: "convert_node_to_string".to_owned(), file: "hooks.lua".to_owned(), line: 2 };
+        catalog.add("section", site, Some(src), 2..=4);
+        catalog
+    }
+
+    #[test]
+    fn completion_contexts() {
+        assert_eq!(completion_at("a {", 3), Some(Completion::CallName));
+        assert_eq!(completion_at("a {sec", 6), Some(Completion::CallName));
+        assert_eq!(completion_at("a {{", 4), None);
+        assert_eq!(completion_at("{section[", 9), Some(Completion::ArgKey("section")));
+        assert_eq!(completion_at("{section[id=x][ti", 17), Some(Completion::ArgKey("section")));
+        assert_eq!(completion_at("text [", 6), None);
+        assert_eq!(completion_at("text", 2), None);
+    }
+
+    #[test]
+    fn offsets_of_positions() {
+        let text = "ä{x}\n𝔸{y}\n";
+        assert_eq!(byte_offset(text, 0, 1), 2);
+        assert_eq!(byte_offset(text, 1, 2), 10);
+        assert_eq!(byte_offset(text, 1, 99), 13);
+        assert_eq!(byte_offset(text, 5, 0), text.len());
+        assert_eq!(position(text, 10), serde_json::json!({ "line": 1, "character": 2 }));
+    }
+
+    #[test]
+    fn session() {
+        let uri = "file:///doc.lit";
+        let messages = [
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            serde_json::json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": { "uri": uri, "text": "" } } }),
+            serde_json::json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": { "textDocument": { "uri": uri }, "contentChanges": [{ "text": "{section[" }] } }),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/completion", "params": { "textDocument": { "uri": uri }, "position": { "line": 0, "character": 1 } } }),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 3, "method": "textDocument/completion", "params": { "textDocument": { "uri": uri }, "position": { "line": 0, "character": 9 } } }),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 4, "method": "textDocument/hover", "params": { "textDocument": { "uri": uri }, "position": { "line": 0, "character": 3 } } }),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 5, "method": "textDocument/definition", "params": {} }),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 6, "method": "shutdown" }),
+            serde_json::json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ];
+        let mut input = vec![];
+        for message in messages.iter() {
+            write_message(&mut input, message).expect("write message");
+        }
+        let mut output = vec![];
+        let mut server = Server::new(catalog());
+        assert!(serve(&mut server, &mut io::Cursor::new(input), &mut output).expect("serve"));
+
+        let mut reader = io::Cursor::new(output);
+        let mut responses = vec![];
+        while let Some(response) = read_message(&mut reader).expect("read response") {
+            responses.push(response);
+        }
+        assert_eq!(responses.len(), 6);
+        assert_eq!(responses[0]["result"]["capabilities"]["completionProvider"]["triggerCharacters"], serde_json::json!(["{", "["]));
+        assert_eq!(responses[1]["result"][0]["label"], "section");
+        assert_eq!(responses[1]["result"][0]["documentation"]["value"], "A section with a heading");
+        assert_eq!(responses[2]["result"], serde_json::json!([{ "label": "title", "kind": KIND_FIELD, "detail": "argument of call 'section'" }]));
+        assert_eq!(responses[3]["result"]["contents"]["value"],
+            "**section**\n\nA section with a heading\n\narguments: `title`\n\nhooks: convert_node_to_string (hooks.lua:2)");
+        assert_eq!(responses[3]["result"]["range"], serde_json::json!({ "start": { "line": 0, "character": 1 }, "end": { "line": 0, "character": 8 } }));
+        assert_eq!(responses[4]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(responses[5], serde_json::json!({ "jsonrpc": "2.0", "id": 6, "result": null }));
+    }
+}
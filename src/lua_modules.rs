@@ -0,0 +1,51 @@
+//! Pure-Lua utility modules bundled with litua
+//!
+//! Hooks can load these modules with ``require`` without any external files,
+//! e.g. ``local json = require("litua.json")``.
+
+/// Module names and Lua source code of the bundled modules
+pub const MODULES: [(&str, &str); 3] = [
+    ("litua.inspect", include_str!("litua_inspect.lua")),
+    ("litua.json", include_str!("litua_json.lua")),
+    ("litua.path", include_str!("litua_path.lua")),
+];
+
+/// Register the bundled modules in ``package.preload`` of `lua`,
+/// such that ``require`` finds them before searching the filesystem
+pub fn register(lua: &mlua::Lua) -> mlua::Result<()> {
+    let preload: mlua::Table = lua.globals().get::<_, mlua::Table>("package")?.get("preload")?;
+    for (name, src) in MODULES.iter() {
+        let chunk_name = format!("{}.lua", name.replace('.', "_"));
+        preload.set(*name, lua.load(*src).set_name(&chunk_name)?.into_function()?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn require_bundled_modules() -> mlua::Result<()> {
+        let lua = mlua::Lua::new();
+        register(&lua)?;
+        let eval = |code: &str| lua.load(code).eval::<String>();
+
+        assert_eq!(eval(r#"return require("litua.json").encode({ b = { 1, 0.1, "x\n" }, a = true })"#)?, r#"{"a":true,"b":[1,0.1,"x\n"]}"#);
+        assert_eq!(eval(r#"local json = require("litua.json")
+            return json.encode(json.decode(' {"k": [null, "ä😀", -1e2, {}, []]} '), 1)"#)?,
+            "{\n \"k\": [\n  null,\n  \"ä😀\",\n  -100,\n  {},\n  []\n ]\n}");
+        assert!(eval(r#"return require("litua.json").decode("[1,]")"#).is_err());
+        assert!(eval(r#"return require("litua.json").encode(0/0)"#).is_err());
+
+        assert_eq!(eval(r#"local t = { 1, "a", x = { y = false } }; t.self = t
+            return require("litua.inspect")(t)"#)?, r#"{ 1, "a", self = <cycle>, x = { y = false } }"#);
+        assert_eq!(eval(r#"return require("litua.inspect").inspect({ { {} } }, { depth = 1 })"#)?, "{ {...} }");
+
+        assert_eq!(eval(r#"local path = require("litua.path")
+            return table.concat({ path.join("a/", "b", "c.tar.gz"), path.normalize("/x/../y/./z/"), path.normalize("../a/.."),
+                path.dirname("a/b/c.lit"), path.dirname("/c"), path.basename("a/b/"), path.stem("a/c.tar.gz"), path.extension("a.d/c") }, "|")"#)?,
+            "a/b/c.tar.gz|/y/z|..|a/b|/|b|c.tar|");
+        Ok(())
+    }
+}
@@ -1,20 +1,142 @@
-use litua;
-
 use mlua::prelude::*;
-use clap::Parser;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use unicode_normalization::UnicodeNormalization;
+
+use litua::hook_package::{self, HookPackage};
+use litua::net;
+use litua::messages::{Lang, Message};
 
+use std::collections;
+use std::ffi;
+use std::cell;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
 use std::path;
+use std::process;
+use std::rc;
 use std::str;
+use std::sync;
+use std::time;
 
 use std::error;
 use std::fmt;
 
 macro_rules! log {
-    ($fmt:literal) => { eprintln!(concat!("LOG[rust]:\t", $fmt)); };
-    ($fmt:literal, $($args:expr),+) => { eprintln!(concat!("LOG[rust]:\t", $fmt), $($args),+); };
+    ($fmt:literal) => { log_line("rust", $fmt); };
+    ($fmt:literal, $($args:expr),+) => { log_line("rust", &format!($fmt, $($args),+)); };
+}
+
+/// File receiving a copy of all log lines and diagnostics (see `--log-file`)
+static LOG_FILE: sync::Mutex<Option<fs::File>> = sync::Mutex::new(None);
+
+/// Are log lines and diagnostics emitted as JSON objects (see `--log-format json`)?
+static JSON_EVENTS: sync::atomic::AtomicBool = sync::atomic::AtomicBool::new(false);
+
+/// Template of the prefix of log lines (see `--log-prefix`)
+static LOG_PREFIX: sync::OnceLock<String> = sync::OnceLock::new();
+
+/// Are the begin and end of every pipeline stage logged (see `--stage-markers`)?
+static STAGE_MARKERS: sync::atomic::AtomicBool = sync::atomic::AtomicBool::new(false);
+
+/// Template of the prefix of log lines unless `--log-prefix` is given.
+/// `{component}` is replaced by the component logging the line.
+const DEFAULT_LOG_PREFIX: &str = "LOG[{component}]:\t";
+
+/// Print the line `msg` to stderr and append it to the log file
+fn report(msg: &str) {
+    if JSON_EVENTS.load(sync::atomic::Ordering::Relaxed) {
+        emit_event(serde_json::json!({ "event": "log", "component": null, "message": msg }));
+        return;
+    }
+    eprintln!("{msg}");
+    write_log_file(msg);
+}
+
+/// Print the log line `message` of `component` (like `rust` for log lines of
+/// the runtime) with the prefix of `--log-prefix` (or as JSON object)
+fn log_line(component: &str, message: &str) {
+    if JSON_EVENTS.load(sync::atomic::Ordering::Relaxed) {
+        emit_event(serde_json::json!({ "event": "log", "component": component, "message": message }));
+        return;
+    }
+    let prefix = LOG_PREFIX.get().map_or(DEFAULT_LOG_PREFIX, String::as_str).replace("{component}", component);
+    report(&format!("{prefix}{message}"));
+}
+
+/// Print `event` with its timestamp as JSON object in one line to stderr and append it to the log file
+fn emit_event(mut event: serde_json::Value) {
+    event["time"] = serde_json::Value::from(format_timestamp(time::SystemTime::now()));
+    let line = event.to_string();
+    eprintln!("{line}");
+    write_log_file(&line);
+}
+
+/// `StageTimer` emits an event with the duration of each pipeline stage (see `--log-format json`)
+/// and markers at the begin and end of each stage (see `--stage-markers`)
+struct StageTimer {
+    file: path::PathBuf,
+    last: time::Instant,
+}
+
+impl StageTimer {
+    fn new(file: &path::Path) -> StageTimer {
+        StageTimer { file: file.to_owned(), last: time::Instant::now() }
+    }
+
+    /// Emit the marker that `stage` begins
+    fn begin(&mut self, stage: &str) {
+        self.last = time::Instant::now();
+        if !STAGE_MARKERS.load(sync::atomic::Ordering::Relaxed) {
+            return;
+        }
+        if JSON_EVENTS.load(sync::atomic::Ordering::Relaxed) {
+            emit_event(serde_json::json!({
+                "event": "stage_begin",
+                "stage": stage,
+                "file": self.file.display().to_string(),
+            }));
+        } else {
+            log_line("stage", &format!("begin stage={stage} file={}", self.file.display()));
+        }
+    }
+
+    /// Emit the event that `stage` finished (which started when it began or the previous stage finished)
+    fn finish(&mut self, stage: &str) {
+        let now = time::Instant::now();
+        let duration_ms = (now - self.last).as_secs_f64() * 1000.0;
+        if JSON_EVENTS.load(sync::atomic::Ordering::Relaxed) {
+            emit_event(serde_json::json!({
+                "event": "stage",
+                "stage": stage,
+                "file": self.file.display().to_string(),
+                "duration_ms": duration_ms,
+            }));
+        } else if STAGE_MARKERS.load(sync::atomic::Ordering::Relaxed) {
+            log_line("stage", &format!("end stage={stage} file={} duration_ms={duration_ms:.3}", self.file.display()));
+        }
+        self.last = now;
+    }
+}
+
+/// Append every line of `msg` prefixed with a timestamp to the log file (if any)
+fn write_log_file(msg: &str) {
+    if let Ok(mut log_file) = LOG_FILE.lock() {
+        if let Some(file) = log_file.as_mut() {
+            let timestamp = format_timestamp(time::SystemTime::now());
+            for line in msg.lines() {
+                // NOTE: a failing log file must not abort the run
+                let _ = writeln!(file, "{timestamp}\t{line}");
+            }
+        }
+    }
+}
+
+/// Represent `t` as UTC timestamp like `2023-04-01T12:34:56.789Z`
+fn format_timestamp(t: time::SystemTime) -> String {
+    let since_epoch = t.duration_since(time::UNIX_EPOCH).unwrap_or_default();
+    let seconds = litua::datetime::iso8601(since_epoch.as_secs() as i64);
+    format!("{}.{:03}Z", seconds.trim_end_matches('Z'), since_epoch.subsec_millis())
 }
 
 // Error type (covers all error cases)
@@ -24,7 +146,16 @@ enum Error {
     Io(io::Error),
     Encoding(str::Utf8Error),
     Litua(litua::errors::Error),
+    /// error regarding the text document with an excerpt of its source
+    Document(Box<litua::errors::Error>, Option<String>),
     Mlua(mlua::Error),
+    TestsFailed(usize),
+    OutputDiffers(path::PathBuf),
+    LintWarnings(usize),
+    BrokenLinks(usize),
+    DocumentsFailed(usize),
+    Net(String),
+    IncompatibleHookApi(String),
 }
 
 impl error::Error for Error {}
@@ -37,8 +168,53 @@ impl fmt::Display for Error {
             CLIArg(msg) => write!(f, "{msg}"),
             Io(err) => write!(f, "{err:?}"),
             Encoding(err) => write!(f, "{err:?}"),
-            Litua(err) => write!(f, "{err:?}"),
+            Litua(err) => write!(f, "{err}"),
+            Document(err, None) => write!(f, "{err}"),
+            Document(err, Some(excerpt)) => write!(f, "{err}\n{excerpt}"),
             Mlua(err) => write!(f, "{err}"),
+            IncompatibleHookApi(msg) => write!(f, "{msg}"),
+            Net(msg) => write!(f, "{msg}"),
+            TestsFailed(count) => write!(f, "{count} test{} failed", if *count == 1 { "" } else { "s" }),
+            LintWarnings(count) => write!(f, "{count} lint warning{} found", if *count == 1 { "" } else { "s" }),
+            BrokenLinks(count) => write!(f, "{count} broken or duplicate link target{} found", if *count == 1 { "" } else { "s" }),
+            DocumentsFailed(count) => write!(f, "{count} text document{} failed", if *count == 1 { "" } else { "s" }),
+            OutputDiffers(filepath) => write!(f, "output differs from the content of '{}'", filepath.display()),
+        }
+    }
+}
+
+impl Error {
+    /// Returns the message code of errors regarding the text document
+    fn code(&self) -> Option<&'static str> {
+        match self {
+            Error::Litua(err) => Some(err.message().code),
+            Error::Document(err, _) => Some(err.message().code),
+            _ => None,
+        }
+    }
+
+    /// Returns the exit code of litua failing with this error. Like diff(1), it is 1 if
+    /// an output differs (see `--diff` and `litua fmt --check`) and 2 for any other error.
+    fn exit_code(&self) -> u8 {
+        match self {
+            Error::OutputDiffers(_) => 1,
+            _ => 2,
+        }
+    }
+
+    /// Represent this error as human-readable message (including the
+    /// message code of errors regarding the text document) in language `lang`
+    fn localized(&self, lang: Lang) -> String {
+        let (err, excerpt) = match self {
+            Error::Litua(err) => (err, None),
+            Error::Document(err, excerpt) => (err.as_ref(), excerpt.as_deref()),
+            other => return Message::new("E0005", vec![other.to_string()]).render(lang),
+        };
+
+        let msg = Message::new("E0004", vec![err.message().code.to_owned(), err.localized(lang)]).render(lang);
+        match excerpt {
+            Some(excerpt) => format!("{msg}\n{}", excerpt.trim_end()),
+            None => msg,
         }
     }
 }
@@ -67,6 +243,62 @@ impl From<mlua::Error> for Error {
     }
 }
 
+/// Number of lines shown before and after the position of an error in the text document
+const DEFAULT_ERROR_CONTEXT: usize = 2;
+
+/// Options of all subcommands (which can also be given before the subcommand)
+const GLOBAL_OPTIONS: [&str; 6] = ["lang", "log_file", "log_format", "log_prefix", "stage_markers", "error_context"];
+
+/// Environment variable listing additional directories with hook files
+const HOOKS_PATH_ENV_VAR: &str = "LITUA_HOOKS_PATH";
+
+/// Resolve the error `e` regarding the text document `src` at `filepath`
+/// and attach an excerpt with `context_lines` lines around the error
+/// (or the included document, if the error refers to one)
+fn document_error(e: litua::errors::Error, filepath: &path::Path, src: &str, context_lines: usize) -> Error {
+    if let litua::errors::Error::InIncludedFile(included, _) = &e {
+        if let Ok(included_src) = fs::read_to_string(included) {
+            let excerpt = e.byte_range(&included_src).map(|range| litua::errors::excerpt(&included_src, range, context_lines));
+            return Error::Document(Box::new(e.format_with_source(filepath, &included_src)), excerpt);
+        }
+    }
+    let excerpt = e.byte_range(src).map(|range| litua::errors::excerpt(src, range, context_lines));
+    Error::Document(Box::new(e.format_with_source(filepath, src)), excerpt)
+}
+
+/// Name template of output files if only `--destination-dir` is given
+const DEFAULT_NAME_TEMPLATE: &str = "{stem}.out";
+
+/// `OutputNames` determines the filepaths of output files (see `--destination-dir`
+/// and `--name-template`) by rendering the template with the placeholders of the
+/// source file: `{stem}` (file name without extension), `{ext}`, and `{name}`
+#[derive(Clone, Debug)]
+struct OutputNames {
+    /// directory of the output files
+    dir: path::PathBuf,
+    template: String,
+    placeholders: collections::BTreeMap<String, String>,
+    /// is `dir` created if it does not exist?
+    create_dir: bool,
+}
+
+impl OutputNames {
+    fn new(source: &path::Path, dir: path::PathBuf, template: &str, create_dir: bool) -> Self {
+        let mut placeholders = collections::BTreeMap::new();
+        placeholders.insert("stem".to_owned(), source.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_else(|| "doc".to_owned()));
+        placeholders.insert("ext".to_owned(), source.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_default());
+        placeholders.insert("name".to_owned(), source.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(|| "doc".to_owned()));
+        OutputNames { dir, template: template.to_owned(), placeholders, create_dir }
+    }
+
+    /// Returns the filepath of an output file. `overrides` provides values for
+    /// placeholders which replace (or extend) the placeholders of the source file.
+    fn filepath<F: Fn(&str) -> Option<String>>(&self, overrides: F) -> Result<path::PathBuf, String> {
+        let name = litua::text::render_template(&self.template, |name| overrides(name).or_else(|| self.placeholders.get(name).cloned()))?;
+        Ok(self.dir.join(name))
+    }
+}
+
 fn derive_destination_filepath(p: &path::Path) -> path::PathBuf {
     if let Some(ext) = p.extension() {
         if ext == "lit" {
@@ -89,244 +321,3294 @@ fn path_is_empty(p: &path::Path) -> bool {
     }
 }
 
-/// Determine the set of hook files in the directory at the given filepath
-fn find_hook_files(hooks_dir: &path::Path) -> Result<Vec<path::PathBuf>, io::Error> {
-    let mut hook_files = vec![];
-    for dir_entry in fs::read_dir(hooks_dir)? {
-        let entry = dir_entry?;
-        let basename = entry.file_name();
-        if let Some(name) = basename.to_str() {
-            if name.starts_with("hook") && name.ends_with(".lua") {
-                hook_files.push(entry.path());
-            }
-        }
+/// Determine the directories (or hook packages) searched for hook files in order of precedence:
+/// `hooks_dir`, the `hook_packages`, the entries of `LITUA_HOOKS_PATH`, and the per-user config directory.
+/// The latter two are only searched if `shared_hooks` is set.
+/// Every location is returned together with a description of its origin.
+fn hook_search_dirs(hooks_dir: &path::Path, hook_packages: &[path::PathBuf], shared_hooks: bool) -> Vec<(path::PathBuf, &'static str)> {
+    let mut dirs = vec![(hooks_dir.to_owned(), "--hooks-dir or directory of the source file")];
+    dirs.extend(hook_packages.iter().map(|package| (package.to_owned(), "--hook-package")));
+    if !shared_hooks {
+        return dirs;
     }
-    Ok(hook_files)
+    if let Some(hooks_path) = std::env::var_os(HOOKS_PATH_ENV_VAR) {
+        dirs.extend(std::env::split_paths(&hooks_path).filter(|p| !path_is_empty(p)).map(|dir| (dir, HOOKS_PATH_ENV_VAR)));
+    }
+    if let Some(user_dir) = user_hooks_dir() {
+        dirs.push((user_dir, "per-user hooks directory"));
+    }
+    dirs
 }
 
-/// Run the entire pipeline according to the operation specified in `conf`.
-/// Might include lexing and parsing unless you specified a debugging operation
-/// like dump_lexed or dump_parsed. It reads some source code, prepares the
-/// Lua runtime, lexes and parses the source code, applies some hook, and
-/// writes the result back to a file.
-/// In conclusion, this is Litua's main routine.
-fn run(conf: &Settings) -> Result<(), Error> {
-    // (0) initialize Lua runtime
-    // NOTE: 'debug' library is only available with Lua::unsafe_new()
-    //       https://github.com/khvzak/mlua/issues/39
-    let lua = unsafe { Lua::unsafe_new() };
-    log!("Lua runtime initialized");
+/// Returns the per-user hooks directory `$XDG_CONFIG_HOME/litua/hooks`
+/// (default: `~/.config/litua/hooks`)
+fn user_hooks_dir() -> Option<path::PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => path::PathBuf::from(dir),
+        _ => path::PathBuf::from(std::env::var_os("HOME").filter(|home| !home.is_empty())?).join(".config"),
+    };
+    Some(config_dir.join("litua").join("hooks"))
+}
 
-    // (1) add paths to Lua path variable
-    for lua_path in conf.lua_path_additions.iter() {
-        let addition_str = path::PathBuf::from(&lua_path);
-        match addition_str.to_str() {
-            Some(s) if !s.is_empty() => lua.load(&format!("package.path = package.path .. ';{s}'")).exec()?,
-            Some(_) => {},
-            None => return Err(Error::CLIArg("cannot convert the luapath extension path (supplied as --add-require-path) to a UTF-8 string. But this is sadly required by the mlua interface (the library to run Lua)".to_owned())),
-        };
+/// Write `content` to a temporary file in the directory of `filepath` and
+/// rename it to `filepath`, so `filepath` is never left truncated. If `backup`
+/// is set, the previous content of `filepath` is kept as `filepath` + ".bak".
+/// The permissions of an existing `filepath` are kept and if `filepath` is a
+/// symbolic link, the file it points to is replaced instead of the link.
+fn write_atomically(filepath: &path::Path, content: &[u8], backup: bool) -> Result<(), io::Error> {
+    write_atomically_with(filepath, backup, |tmp| tmp.write_all(content))
+}
+
+/// Create the directory of the destination file if it is a (missing) `--destination-dir`
+fn create_destination_dir(conf: &Settings) -> Result<(), io::Error> {
+    match conf.destination.parent() {
+        Some(dir) if conf.output_names.create_dir && !path_is_empty(dir) => fs::create_dir_all(dir),
+        _ => Ok(()),
     }
-    log!("Lua paths added");
+}
 
-    // (2) find hook files
-    let hook_files = find_hook_files(&conf.hooks_dir).map_err(Error::Io)?;
-    log!("{} hook file{} found", hook_files.len(), if hook_files.len() == 1 { "" } else { "" });
+/// Returns the filepath a symbolic link `filepath` points to (following
+/// chains of links) or `filepath` itself if it is not a symbolic link
+fn resolve_symlink(filepath: &path::Path) -> Result<path::PathBuf, io::Error> {
+    let mut resolved = filepath.to_owned();
+    // NOTE: the number of links followed is limited like by the OS to reject cycles
+    for _ in 0..40 {
+        match fs::symlink_metadata(&resolved) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let link = fs::read_link(&resolved)?;
+                resolved = match resolved.parent() {
+                    Some(dir) => dir.join(link),
+                    None => link,
+                };
+            },
+            _ => return Ok(resolved),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidInput, format!("too many levels of symbolic links at '{}'", filepath.display())))
+}
 
-    // (3) load litua libraries
-    let litua_table = include_str!("litua.lua");
-    lua.load(litua_table).set_name("litua.lua")?.exec()?;
-    let litua_lib = include_str!("litua_stdlib.lua");
-    lua.load(litua_lib).set_name("litua_stdlib.lua")?.exec()?;
-    log!("litua standard library loaded");
+/// Like `write_atomically`, but the content is written piecewise by `fill`
+fn write_atomically_with<E, F>(filepath: &path::Path, backup: bool, fill: F) -> Result<(), E>
+    where E: From<io::Error>, F: FnOnce(&mut fs::File) -> Result<(), E>
+{
+    let target = resolve_symlink(filepath)?;
+    let basename = match target.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not a filepath", filepath.display())).into()),
+    };
+    let tmp_filepath = target.with_file_name(format!(".{basename}.{}.tmp", process::id()));
 
-    // (4) read hook files
-    for hook_file in hook_files.iter() {
-        log!("Loading hook file '{}'", hook_file.display());
+    let written = fs::File::create(&tmp_filepath).map_err(E::from).and_then(|mut tmp| {
+        fill(&mut tmp)?;
+        if let Ok(metadata) = fs::metadata(&target) {
+            tmp.set_permissions(metadata.permissions())?;
+        }
+        Ok(tmp.sync_all()?)
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp_filepath);
+        return Err(e);
+    }
 
-        let lua_file_src = fs::read_to_string(hook_file)?;
-        let mut chunk = lua.load(&lua_file_src);
-        {
-            let filepath = hook_file.display();
-            chunk = chunk.set_name(&filepath.to_string())?;
+    if backup && target.is_file() {
+        let mut backup_filepath = filepath.as_os_str().to_owned();
+        backup_filepath.push(".bak");
+        if let Err(e) = fs::copy(&target, &backup_filepath) {
+            let _ = fs::remove_file(&tmp_filepath);
+            return Err(e.into());
         }
-        chunk.exec()?;
     }
-    log!("All hook files loaded");
 
-    // (5) run preprocessing hooks
-    let mut doc_src = {
-        let mut fd = fs::File::open(&conf.source)?;
-        let mut buf = Vec::new();
-        fd.read_to_end(&mut buf)?;
-        str::from_utf8(&buf)?.to_owned()
+    Ok(fs::rename(&tmp_filepath, &target).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_filepath);
+    })?)
+}
+
+/// Read the file `reference` (a filepath or URL) relative to the source file
+/// of `conf` (or the source file itself if `is_source` is set). URLs can
+/// only be read if network access was allowed.
+fn read_include(conf: &Settings, reference: &str, is_source: bool) -> Result<Vec<u8>, Error> {
+    let source = conf.source.to_string_lossy();
+    let location = match (is_source, net::is_url(&source)) {
+        (true, _) => reference.to_owned(),
+        (false, true) => net::resolve(&source, reference),
+        (false, false) if net::is_url(reference) => reference.to_owned(),
+        (false, false) => match conf.source.parent() {
+            Some(dir) => dir.join(reference).to_string_lossy().into_owned(),
+            None => reference.to_owned(),
+        },
     };
-    log!("source file '{}' read", conf.source.display());
 
-    {
-        let globals = lua.globals();
-        let global_litua: mlua::Table = globals.get("Litua")?;
-        let preprocess: mlua::Function = global_litua.get("preprocess")?;
-        let lua_result = preprocess.call::<mlua::Value, mlua::String>(doc_src.to_lua(&lua)?)?;
-        // TODO verify which errors are triggered for non-UTF-8 return values
-        doc_src = lua_result.to_str()?.to_owned();
+    if !net::is_url(&location) {
+        let content = fs::read(&location)?;
+        record_input(conf, path::Path::new(&location), &content);
+        return Ok(content);
     }
-    log!("source file '{}' pre-processed", conf.source.display());
-
-    // (6) lex and parse source code to turn it into a tree
-    let doc_tree = {
-        let l = litua::lexer::Lexer::new(&doc_src);
+    if !conf.allow_net {
+        return Err(Error::CLIArg(format!("reading '{location}' requires network access, which must be allowed with --allow-net")));
+    }
+    let response = net::fetch(&location, net::DEFAULT_TIMEOUT, net::DEFAULT_MAX_SIZE).map_err(|e| Error::Net(e.to_string()))?;
+    if !(200..300).contains(&response.status) {
+        return Err(Error::Net(format!("fetching '{location}' failed with HTTP status {}", response.status)));
+    }
+    log!("'{}' fetched ({} bytes)", location, response.body.len());
+    record_input(conf, path::Path::new(&location), &response.body);
+    Ok(response.body)
+}
 
-        if conf.op == "dump_lexed" {
-            // Read the source file mentioned in `conf` and lex its source code.
-            // Print the resulting sequence of tokens. Useful for debugging.
-            let l = litua::lexer::Lexer::new(&doc_src);
+/// Returns the error raised by `Litua.hook_error` if it caused the Lua error `e`
+fn find_hook_error(e: &mlua::Error) -> Option<litua::errors::Error> {
+    match e {
+        mlua::Error::CallbackError { cause, .. } => find_hook_error(cause),
+        mlua::Error::ExternalError(err) => err.downcast_ref::<litua::errors::Error>().cloned(),
+        _ => None,
+    }
+}
 
-            for tok_or_err in l.iter() {
-                let token = match tok_or_err {
-                    Ok(tok) => tok,
-                    Err(e) => return Err(Error::Litua(e.format_with_source(&conf.source, &doc_src))),
-                };
-                println!("{token:?}");
-            }
+/// Filepath and content of a hook file
+type HookFile = (path::PathBuf, Vec<u8>);
 
-            return Ok(());
+/// Determine the hook files in the given directories
+/// or hook packages in order of precedence and the hook packages read.
+/// The first directory and hook packages given explicitly must exist, others are skipped
+/// if they do not exist. A hook file shadows hook files with the same name in subsequent
+/// directories. Within a directory, hook files are sorted by name. The decisions about
+/// every location and file are appended to `resolution` (see `--dump-hooks`).
+fn find_hook_files(hooks_dirs: &[(path::PathBuf, &str)], required: usize, resolution: &mut Vec<String>) -> Result<(Vec<HookFile>, Vec<HookPackage>), io::Error> {
+    let mut hook_files = vec![];
+    let mut packages = vec![];
+    let mut seen_names: Vec<(ffi::OsString, path::PathBuf)> = vec![];
+    let mut seen_dirs: Vec<(path::PathBuf, path::PathBuf)> = vec![];
+    for (i, (hooks_dir, origin)) in hooks_dirs.iter().enumerate() {
+        let location = format!("{}. {} ({origin})", i + 1, hooks_dir.display());
+        if i >= required && !hooks_dir.exists() {
+            resolution.push(format!("{location}: skipped, does not exist"));
+            continue;
         }
+        let canonical_dir = hooks_dir.canonicalize()
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot access hooks directory '{}': {e}", hooks_dir.display())))?;
+        if let Some((_, first)) = seen_dirs.iter().find(|(dir, _)| *dir == canonical_dir) {
+            resolution.push(format!("{location}: skipped, same location as {}", first.display()));
+            continue;
+        }
+        seen_dirs.push((canonical_dir, hooks_dir.to_owned()));
+        resolution.push(location);
 
-        let mut p = litua::parser::Parser::new(&conf.source, &doc_src);
-        p.consume_iter(l.iter())?;
-        p.finalize()?;
-
-        p.tree()
-    };
-    log!("source file '{}' lexed and parsed", conf.source.display());
+        let mut dir_files = vec![];
+        if hooks_dir.is_file() && hook_package::is_package_path(hooks_dir) {
+            let package = HookPackage::open(hooks_dir)
+                .map_err(|e| io::Error::new(e.kind(), format!("cannot read hook package '{}': {e}", hooks_dir.display())))?;
+            for (name, content) in package.hook_files() {
+                dir_files.push((ffi::OsString::from(name), hooks_dir.join(name), content.to_vec()));
+            }
+            packages.push(package);
+        } else {
+            let mut entries = fs::read_dir(hooks_dir)?.collect::<Result<Vec<_>, _>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+            for entry in entries {
+                let basename = entry.file_name();
+                match basename.to_str() {
+                    Some(name) if name.starts_with("hook") && name.ends_with(".lua") => {
+                        dir_files.push((basename.clone(), entry.path(), fs::read(entry.path())?));
+                    },
+                    Some(name) if name.ends_with(".lua") => {
+                        resolution.push(format!("   skipped {name}: name does not match 'hook*.lua'"));
+                    },
+                    _ => {},
+                }
+            }
+        }
+        for (name, hook_file, content) in dir_files {
+            if let Some((_, shadowing)) = seen_names.iter().find(|(seen, _)| *seen == name) {
+                resolution.push(format!("   skipped {}: shadowed by {}", name.to_string_lossy(), shadowing.display()));
+                continue;
+            }
+            resolution.push(format!("   loaded  {}", name.to_string_lossy()));
+            seen_names.push((name, hook_file.to_owned()));
+            hook_files.push((hook_file, content));
+        }
+    }
+    Ok((hook_files, packages))
+}
 
-    if conf.op == "dump_parsed" {
-        // Read the source file mentioned in `conf` and lex and parse
-        // its source code. Print the resulting tree. Useful for debugging.
-        println!("{doc_tree:?}");
-        return Ok(());
+/// Parse a hook API version like "2" or "2.1" into (major, minor)
+fn parse_api_version(version: &str) -> Option<(u32, u32)> {
+    match version.trim().split_once('.') {
+        Some((major, minor)) => Some((major.parse().ok()?, minor.parse().ok()?)),
+        None => Some((version.trim().parse().ok()?, 0)),
     }
+}
 
-    // (7) turn tree into a Lua object
-    let tree = doc_tree.to_lua(&lua)?;
-    log!("parsed tree converted into a Lua table");
+/// Maximum number of byte offsets of invalid UTF-8 sequences listed in the warning
+const MAX_REPORTED_OFFSETS: usize = 20;
 
-    // (8) load transform function and node object (libraries, which users must not modify)
-    let litua_trans = include_str!("litua_transform.lua");
-    lua.load(litua_trans).set_name("litua_transform.lua")?.exec()?;
-    let litua_node = include_str!("litua_node.lua");
-    lua.load(litua_node).set_name("litua_node.lua")?.exec()?;
-    log!("litua transformation routines loaded");
+/// Returns the value of SOURCE_DATE_EPOCH, which defaults to "0" for `--reproducible`
+fn source_date_epoch(conf: &Settings) -> Option<String> {
+    std::env::var(litua::datetime::SOURCE_DATE_EPOCH_ENV_VAR).ok()
+        .or_else(|| conf.reproducible.then(|| "0".to_owned()))
+}
 
-    // (9) call transformation
-    let globals = lua.globals();
-    let global_litua: mlua::Table = globals.get("Litua")?;
+/// Parse a definition of a root argument like "KEY=VALUE" or "KEY" (with value "true")
+fn parse_define(define: &str) -> Result<(String, String), String> {
+    match define.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        None if !define.is_empty() => Ok((define.to_owned(), "true".to_owned())),
+        _ => Err(format!("expects an argument like KEY=VALUE or KEY, got '{define}'")),
+    }
+}
+
+/// Return the settings `conf` updated by the directives in the header of the
+/// text document. Directives not known to litua are only passed on to the hooks.
+fn apply_directives(conf: &Settings, directives: &[litua::header::Directive]) -> Result<Settings, litua::errors::Error> {
+    let mut conf = conf.clone();
+    for directive in directives.iter() {
+        let value = directive.value.as_str();
+        if let Some(result) = litua::header::apply_lexer_directive(&mut conf.lexer_options, &directive.key, value) {
+            if let Err(msg) = result.and_then(|_| conf.lexer_options.validate()) {
+                return Err(litua::header::invalid_directive(directive, msg));
+            }
+            continue;
+        }
+        let result = match directive.key.as_str() {
+            "normalize-names" => litua::header::parse_bool(value).map(|b| conf.normalize_names = b),
+            "lossless" => litua::header::parse_bool(value).map(|b| conf.lossless = b),
+            "merge-texts" => litua::header::parse_bool(value).map(|b| conf.merge_texts = b),
+            "whitespace-texts" => match value {
+                "keep" => Ok(litua::parser::WhitespaceTexts::Keep),
+                "drop" => Ok(litua::parser::WhitespaceTexts::Drop),
+                "collapse" => Ok(litua::parser::WhitespaceTexts::Collapse),
+                _ => Err(format!("expects keep, drop, or collapse, got '{value}'")),
+            }.map(|policy| conf.whitespace_texts = policy),
+            "preserve-whitespace" => {
+                conf.preserve_whitespace.push(value.to_owned());
+                Ok(())
+            },
+            "verbatim" => {
+                conf.verbatim.push(value.to_owned());
+                Ok(())
+            },
+            "root-call" => {
+                conf.root_call = value.to_owned();
+                Ok(())
+            },
+            "define" => parse_define(value).map(|define| conf.root_args.push(define)),
+            _ => Ok(()),
+        };
+        if let Err(msg) = result {
+            return Err(litua::header::invalid_directive(directive, msg));
+        }
+    }
+    Ok(conf)
+}
 
-    let intermediate = {
-        let transform: mlua::Function = global_litua.get("transform")?;
-        transform.call::<mlua::Value, mlua::String>(tree)?
+/// Verify that the hook API version `provided` by the runtime satisfies
+/// all versions requested via `Litua.require_api` so far. Versions are
+/// compatible if the major versions are equal and the provided minor
+/// version is at least the required one.
+fn check_hook_api(global_litua: &mlua::Table) -> Result<(), Error> {
+    let provided: String = global_litua.get("api_version")?;
+    let (provided_major, provided_minor) = match parse_api_version(&provided) {
+        Some(v) => v,
+        None => return Err(Error::IncompatibleHookApi(format!("invalid hook API version '{provided}' of the runtime"))),
     };
-    log!("litua hooks for tree manipulation finished");
 
-    // (10) run postprocessing hooks
-    let postprocess: mlua::Function = global_litua.get("postprocess")?;
-    let lua_result = postprocess.call::<mlua::Value, mlua::String>(intermediate.to_lua(&lua)?)?;
-    let output = lua_result.to_str()?;
-    log!("source file '{}' post-processed", conf.source.display());
+    let required: mlua::Table = global_litua.get("required_api_versions")?;
+    for entry in required.sequence_values::<mlua::Table>() {
+        let entry = entry?;
+        let version: String = entry.get("version")?;
+        let src: String = entry.get("src")?;
 
-    // (11) print the result
-    fs::write(&conf.destination, output)?;
-    log!("File '{}' written.", conf.destination.display());
+        let compatible = match parse_api_version(&version) {
+            Some((major, minor)) => major == provided_major && minor <= provided_minor,
+            None => return Err(Error::IncompatibleHookApi(format!("{src} requires hook API version '{version}', but this is not a valid version (expected a version like \"{provided_major}\" or \"{provided_major}.{provided_minor}\")"))),
+        };
+        if !compatible {
+            return Err(Error::IncompatibleHookApi(format!(
+                "{src} requires hook API version {version}, but litua {} provides hook API version {provided}. Please use a litua version providing hook API {version} or update the hooks.",
+                env!("CARGO_PKG_VERSION")
+            )));
+        }
+    }
 
     Ok(())
 }
 
-#[derive(Parser, Debug)]
-#[command(name = "litua")]
-#[command(author = "tajpulo <admin@lukas-prokop.at>")]
-#[command(version = "2.0.0")]
-#[command(about = "Read document as tree and apply Lua functions to nodes")]
-#[command(author, version, about, long_about = None)]
-struct CLISettings {
-    // helpful for debugging 
-    #[arg(long, help = "if set, only prints the runtime configuration and exits")]
-    dump_config: bool,
-    #[arg(long, help = "if set, only lexes the source file, prints its tokens and exits")]
-    dump_lexed: bool,
-    #[arg(long, help = "if set, only parses the source file, prints the resulting tree and exits")]
-    dump_parsed: bool,
+/// Where to report diagnostics like hook traces or profiles to
+#[derive(Clone, Debug)]
+enum ReportTarget {
+    /// human-readable output on stderr
+    Stderr,
+    /// JSON output in the file at the given filepath
+    File(path::PathBuf),
+}
 
-    // configuration
-    #[arg(long, value_name = "DIR", help = "filepath to directory with hook files (default: same as source file)")]
-    hooks_dir: Option<path::PathBuf>,
-    #[arg(long, value_name = "DIR", help = "directories to add as search location for require(…) calls")]
-    add_require_path: Vec<path::PathBuf>,
+/// Create the Lua function `Litua.trace_hook` which receives one table per hook invocation
+/// (with fields hook, src, call, path, duration, output_length). It reports the invocation
+/// to `trace` (one line on stderr or one JSON object per line in a file) and records it in `profile`.
+fn create_trace_hook<'lua>(lua: &'lua Lua, trace: Option<&ReportTarget>, profile: Option<rc::Rc<cell::RefCell<litua::profile::Profile>>>) -> Result<mlua::Function<'lua>, Error> {
+    let ndjson_file = match trace {
+        Some(ReportTarget::File(filepath)) => Some(cell::RefCell::new(io::LineWriter::new(fs::File::create(filepath)?))),
+        _ => None,
+    };
+    let trace_to_stderr = matches!(trace, Some(ReportTarget::Stderr));
 
-    // optional argument
-    #[arg(short = 'o', long, value_name = "PATH")]
-    destination: Option<path::PathBuf>,
+    let trace_hook = lua.create_function(move |_, event: mlua::Table| {
+        let hook: String = event.get("hook")?;
+        let src: String = event.get("src")?;
+        let call: String = event.get("call")?;
+        let node_path: String = event.get("path")?;
+        let duration: f64 = event.get("duration")?;
+        let output_length: Option<usize> = event.get("output_length")?;
 
-    // positional argument
-    source: path::PathBuf,
-}
+        if let Some(profile) = &profile {
+            profile.borrow_mut().record(&src, &call, duration);
+        }
 
-#[derive(Debug)]
-struct Settings {
-    hooks_dir: path::PathBuf,
-    lua_path_additions: Vec<path::PathBuf>,
-    source: path::PathBuf,
-    destination: path::PathBuf,
-    op: &'static str,
+        if trace_to_stderr {
+            let output = output_length.map_or_else(String::new, |len| format!(", output {len} bytes"));
+            let node = if node_path.is_empty() { String::new() } else { format!(" at {node_path}") };
+            report(&format!("TRACE:\t{src}{node} took {:.3} ms{output}", duration * 1000.0));
+        }
+
+        if let Some(file) = &ndjson_file {
+            let line = serde_json::json!({
+                "hook": hook,
+                "src": src,
+                "call": call,
+                "path": node_path,
+                "duration_ms": duration * 1000.0,
+                "output_length": output_length,
+            });
+            writeln!(file.borrow_mut(), "{line}").map_err(mlua::Error::external)?;
+        }
+        Ok(())
+    })?;
+
+    Ok(trace_hook)
 }
 
-fn main() -> Result<(), Error> {
-    // CLI argument parsing
-    let settings = CLISettings::parse();
+/// Select the call `selector` of `doc_tree` for `--explain` and create the Lua functions
+/// `Litua.explain_step` and `Litua.explain_output` collecting its transformation
+fn install_explainer(lua: &Lua, selector: &str, doc_tree: &litua::tree::DocumentTree, doc_src: &str) -> Result<rc::Rc<cell::RefCell<litua::explain::Explanation>>, Error> {
+    let func = litua::explain::resolve(doc_tree, selector)
+        .map_err(|msg| Error::CLIArg(format!("cannot explain '{selector}': {msg}")))?;
+    let span = func.span().ok_or_else(|| Error::CLIArg(format!("cannot explain '{selector}': the call has no source span")))?;
+    let mut explanation = litua::explain::Explanation::new(selector, func, doc_src);
+    let mut matching = vec![];
+    // NOTE: hooks for a namespace like `Litua.modify_namespace("html", …)` are registered for filter `html:*`
+    let namespace_filter = func.namespace().map(|namespace| format!("{namespace}{}*", litua::tree::NAMESPACE_SEPARATOR));
+    for registration in registered_hooks(lua)? {
+        let stage = litua::explain::NODE_HOOKS.iter().position(|hook| *hook == registration.hook);
+        if let Some(stage) = stage.filter(|_| registration.filter == func.call || registration.filter.is_empty() || namespace_filter.as_ref() == Some(&registration.filter)) {
+            let filter = if registration.filter.is_empty() {
+                "every call".to_owned()
+            } else if namespace_filter.as_ref() == Some(&registration.filter) {
+                format!("namespace '{}'", func.namespace().unwrap_or_default())
+            } else {
+                format!("call '{}'", registration.filter)
+            };
+            matching.push((stage, format!("{} (for {filter})", registration.src)));
+        }
+    }
+    // NOTE: stable sort keeps the order of hooks within one stage
+    matching.sort_by_key(|(stage, _)| *stage);
+    explanation.matching_hooks = matching.into_iter().map(|(_, hook)| hook).collect();
+    let explanation = rc::Rc::new(cell::RefCell::new(explanation));
 
-    let derived_dst = derive_destination_filepath(&settings.source);
-    let dst = match &settings.destination {
-        Some(p) => p.as_path(),
-        None => derived_dst.as_path(),
-    };
+    let global_litua: mlua::Table = lua.globals().get("Litua")?;
+    let target = lua.create_table()?;
+    target.set("start", span.start)?;
+    target.set("end", span.end)?;
+    if let Some([litua::tree::DocumentElement::Text(file)]) = func.args.get(litua::tree::FILE_ARG).map(Vec::as_slice) {
+        target.set("file", file.as_str())?;
+    }
+    global_litua.set("explain_target", target)?;
 
-    // if you specified some hook directory, use it.
-    // if not, use the folder the source file lies within
-    let default_hooks_dir = path::PathBuf::from(".");
-    let hooks_dir = match &settings.hooks_dir {
-        Some(d) if path_is_empty(&d) => default_hooks_dir.as_path(),
-        Some(d) => d.as_path(),
-        None => match settings.source.parent() {
-            Some(p) if path_is_empty(p) => &default_hooks_dir.as_path(),
-            Some(p) => p,
-            None => &default_hooks_dir.as_path(),
-        },
-    };
+    let steps = explanation.clone();
+    global_litua.set("explain_step", lua.create_function(move |lua, (hook, src, path, result): (String, String, String, LuaValue)| {
+        let tostring: mlua::Function = lua.globals().get("tostring")?;
+        let value = match result {
+            LuaValue::Nil => None,
+            LuaValue::Table(items) if items.get::<_, Option<bool>>("is_node")?.is_none() => {
+                let mut repr = String::new();
+                for item in items.sequence_values::<LuaValue>() {
+                    repr.push_str(&tostring.call::<_, String>(item?)?);
+                }
+                Some(repr)
+            },
+            result => Some(tostring.call::<_, String>(result)?),
+        };
+        steps.borrow_mut().steps.push(litua::explain::Step { hook, src, path, value });
+        Ok(())
+    })?)?;
+    let contributions = explanation.clone();
+    global_litua.set("explain_output", lua.create_function(move |_, repr: String| {
+        // NOTE: the output marked by Litua.span is usually also the returned string representation
+        let mut explanation = contributions.borrow_mut();
+        if explanation.contributions.last() != Some(&repr) {
+            explanation.contributions.push(repr);
+        }
+        Ok(())
+    })?)?;
+    log!("transformation of '{selector}' is explained");
+    Ok(explanation)
+}
 
-    let mut lua_path_additions = vec![];
-    for dir in settings.add_require_path.iter() {
-        lua_path_additions.push(dir.to_owned());
+/// `RecordingWriter` writes the recorded hook invocations to a file when the run
+/// ends, even if it failed (see `--record`)
+struct RecordingWriter {
+    recording: rc::Rc<cell::RefCell<litua::recording::Recording>>,
+    filepath: path::PathBuf,
+}
+
+impl Drop for RecordingWriter {
+    fn drop(&mut self) {
+        let recording = self.recording.borrow();
+        match fs::write(&self.filepath, format!("{:#}\n", recording.to_json())) {
+            Ok(()) => { log!("Recording '{}' with {} hook invocations written.", self.filepath.display(), recording.invocations.len()); },
+            Err(e) => report(&format!("ERROR: cannot write recording '{}': {e}", self.filepath.display())),
+        }
     }
+}
 
-    // define execution configuration
-    let conf = Settings {
-        hooks_dir: hooks_dir.to_owned(),
-        lua_path_additions,
-        source: settings.source,
-        destination: dst.to_owned(),
-        op: if settings.dump_lexed {
-            "dump_lexed"
-        } else if settings.dump_parsed {
-            "dump_parsed"
-        } else {
-            "run"
-        },
+/// Returns the hooks registered in `Litua.hooks` ordered by hook name and call name
+fn registered_hooks(lua: &Lua) -> Result<Vec<litua::recording::Registration>, Error> {
+    let global_litua: mlua::Table = lua.globals().get("Litua")?;
+    let hooks: mlua::Table = global_litua.get("hooks")?;
+    let pass_order: Vec<String> = global_litua.get("pass_order")?;
+    let mut registrations = vec![];
+    for pair in hooks.pairs::<String, mlua::Table>() {
+        let (hook, filters) = pair?;
+        for pair in filters.pairs::<String, mlua::Table>() {
+            let (filter, entries) = pair?;
+            for entry in entries.sequence_values::<mlua::Table>() {
+                registrations.push(litua::recording::Registration { hook: hook.clone(), filter: filter.clone(), src: entry?.get("src")? });
+            }
+        }
+    }
+    // NOTE: the sort is stable, so hooks for the same call keep their order.
+    //       Passes keep their order of registration, since they run in this order.
+    let pass_index = |registration: &litua::recording::Registration| match registration.hook.as_str() {
+        "pass" => pass_order.iter().position(|name| *name == registration.filter),
+        _ => None,
     };
+    registrations.sort_by(|a, b| (&a.hook, pass_index(a), &a.filter).cmp(&(&b.hook, pass_index(b), &b.filter)));
+    Ok(registrations)
+}
 
-    // run main routine
-    if settings.dump_config {
-        println!("{:?}", &conf);
-        return Ok(());
+/// Create the Lua functions `Litua.record_invocation`, `Litua.record_results`, and
+/// `Litua.record_vars` appending the hook invocations to `recording` (see `--record`)
+fn install_recorder(lua: &Lua, recording: &rc::Rc<cell::RefCell<litua::recording::Recording>>) -> Result<(), Error> {
+    let global_litua: mlua::Table = lua.globals().get("Litua")?;
+    let invocations = recording.clone();
+    global_litua.set("record_invocation", lua.create_function(move |lua, (hook, src, call, path, inputs): (String, String, String, String, mlua::MultiValue)| {
+        let inputs = inputs.into_iter().map(|input| litua::recording::lua_to_json(lua, input)).collect::<mlua::Result<Vec<_>>>()?;
+        let mut recording = invocations.borrow_mut();
+        recording.invocations.push(litua::recording::Invocation { hook, src, call, path, inputs, outputs: vec![], error: None });
+        Ok(recording.invocations.len())
+    })?)?;
+    let results = recording.clone();
+    global_litua.set("record_results", lua.create_function(move |lua, (index, error, outputs): (usize, Option<String>, mlua::MultiValue)| {
+        let outputs = outputs.into_iter().map(|output| litua::recording::lua_to_json(lua, output)).collect::<mlua::Result<Vec<_>>>()?;
+        if let Some(invocation) = results.borrow_mut().invocations.get_mut(index - 1) {
+            invocation.outputs = outputs;
+            invocation.error = error;
+        }
+        Ok(())
+    })?)?;
+    let vars = recording.clone();
+    global_litua.set("record_vars", lua.create_function(move |lua, value: mlua::Value| {
+        vars.borrow_mut().vars = litua::recording::lua_to_json(lua, value)?;
+        Ok(())
+    })?)?;
+    Ok(())
+}
+
+/// Register the hooks of the recording of `replay` and create the Lua functions
+/// `Litua.replay_invocation` and `Litua.replayed_vars` (see `--replay`)
+fn install_replay(lua: &Lua, replay: &rc::Rc<cell::RefCell<litua::recording::Replay>>) -> Result<(), Error> {
+    let global_litua: mlua::Table = lua.globals().get("Litua")?;
+    let hooks: mlua::Table = global_litua.get("hooks")?;
+    let pass_order: mlua::Table = global_litua.get("pass_order")?;
+    let not_replayed = lua.create_function(|_, ()| -> mlua::Result<()> { Err(mlua::Error::external("hook implementations are not available in a replay")) })?;
+    for registration in replay.borrow().recording.registrations.iter() {
+        let filters: mlua::Table = hooks.get(registration.hook.as_str())
+            .map_err(|_| Error::CLIArg(format!("--replay: unknown hook '{}' in recording", registration.hook)))?;
+        let entries = match filters.get::<_, Option<mlua::Table>>(registration.filter.as_str())? {
+            Some(entries) => entries,
+            None => {
+                let entries = lua.create_table()?;
+                filters.set(registration.filter.as_str(), entries.clone())?;
+                if registration.hook == "pass" {
+                    pass_order.raw_insert(pass_order.raw_len() + 1, registration.filter.as_str())?;
+                }
+                entries
+            },
+        };
+        let entry = lua.create_table()?;
+        entry.set("src", registration.src.as_str())?;
+        entry.set("impl", not_replayed.clone())?;
+        entry.set("file", "")?;
+        entry.set("line", 0)?;
+        entry.set("first_line", 0)?;
+        entry.set("last_line", 0)?;
+        entries.raw_insert(entries.raw_len() + 1, entry)?;
     }
 
-    run(&conf)
+    let invocations = replay.clone();
+    global_litua.set("replay_invocation", lua.create_function(move |lua, (hook, src, call, path, inputs): (String, String, String, String, mlua::MultiValue)| {
+        let inputs = inputs.into_iter().map(|input| litua::recording::lua_to_json(lua, input)).collect::<mlua::Result<Vec<_>>>()?;
+        let invocation = invocations.borrow_mut().next(&hook, &src, &call, &path, &inputs).map_err(mlua::Error::external)?.clone();
+        // NOTE: hooks return Litua.Node objects, which are created by the transformation routines
+        let tree_to_nodes: Option<mlua::Function> = lua.globals().get::<_, mlua::Table>("Litua")?.get("tree_to_nodes")?;
+        let results = lua.create_table()?;
+        for (index, output) in invocation.outputs.iter().enumerate() {
+            results.raw_set(index + 1, litua::recording::json_to_lua(lua, output, &|node| match &tree_to_nodes {
+                Some(tree_to_nodes) => tree_to_nodes.call(node),
+                None => Ok(node),
+            })?)?;
+        }
+        results.set("n", invocation.outputs.len())?;
+        results.set("error", invocation.error)?;
+        Ok(results)
+    })?)?;
+    let vars = replay.clone();
+    global_litua.set("replayed_vars", lua.create_function(move |lua, ()| {
+        let vars = vars.borrow().recording.vars.clone();
+        litua::recording::json_to_lua(lua, &vars, &Ok)
+    })?)?;
+    Ok(())
+}
+
+/// `Runtime` is the Lua runtime with the litua libraries and the hook files loaded
+struct Runtime {
+    lua: Lua,
+    hook_files: Vec<HookFile>,
+    profile: Option<rc::Rc<cell::RefCell<litua::profile::Profile>>>,
+    replay: Option<rc::Rc<cell::RefCell<litua::recording::Replay>>>,
+    /// writes the recorded hook invocations when the runtime is dropped
+    _recording: Option<RecordingWriter>,
+}
+
+/// `SourceDocument` is the pre-processed text document with the settings its header directives apply
+struct SourceDocument {
+    conf: Settings,
+    src: String,
+    directives: Vec<litua::header::Directive>,
+    metadata: Option<litua::file_metadata::FileMetadata>,
+}
+
+/// `Output` is the post-processed output of a text document
+struct Output {
+    bytes: Vec<u8>,
+    /// spans marked by `Litua.span` (see `--sourcemap`)
+    marked_spans: Vec<litua::sourcemap::MarkedSpan>,
+}
+
+/// Run the entire pipeline according to the operation specified in `conf`.
+/// Might include lexing and parsing unless you specified a debugging operation
+/// like dump_lexed or dump_parsed. It reads some source code, prepares the
+/// Lua runtime, lexes and parses the source code, applies some hook, and
+/// writes the result back to a file.
+/// In conclusion, this is Litua's main routine.
+fn run(conf: &Settings) -> Result<(), Error> {
+    let mut stages = StageTimer::new(&conf.source);
+    stages.begin("load_hooks");
+    let runtime = load_runtime(conf)?;
+    stages.finish("load_hooks");
+    run_with(&runtime, conf, &mut stages)
+}
+
+/// Run the operation of `conf` with the hooks loaded into `runtime`
+fn run_with(runtime: &Runtime, conf: &Settings, stages: &mut StageTimer) -> Result<(), Error> {
+    match conf.op {
+        Operation::Repl => return run_repl(&runtime.lua),
+        Operation::Lsp => return run_lsp(runtime),
+        Operation::ListCalls => return list_calls(runtime),
+        Operation::ListHooks => return list_hooks(&runtime.lua),
+        _ => {},
+    }
+
+    let mut stats = litua::stats::Stats::default();
+    let document = read_document(runtime, conf, &mut stats, stages)?;
+    let conf = &document.conf;
+    if conf.op == Operation::DumpLexed {
+        return dump_lexed(conf, &document.src);
+    }
+
+    let pipeline = document_pipeline(conf, document.metadata.as_ref());
+    if conf.chunked && conf.op == Operation::Run {
+        run_chunked(runtime, &document, &pipeline, &mut stats, stages)?;
+        return report_measurements(conf, runtime.profile.as_ref(), &stats);
+    }
+
+    let doc_tree = parse_document(conf, &pipeline, &document.src, &mut stats, stages)?;
+    match conf.op {
+        Operation::Check => {
+            // Stop before running the transformation hooks. Useful to validate documents in CI.
+            log!("source file '{}' has no errors", conf.source.display());
+            return Ok(());
+        },
+        Operation::DumpParsed => {
+            // Print the tree of the source code. Useful for debugging.
+            println!("{doc_tree:?}");
+            return Ok(());
+        },
+        Operation::Explore => {
+            load_transform(&runtime.lua, conf, &document.directives)?;
+            return run_explore(&runtime.lua, conf, doc_tree, &document.src);
+        },
+        _ => {},
+    }
+
+    let explanation = match &conf.explain {
+        Some(selector) => Some(install_explainer(&runtime.lua, selector, &doc_tree, &document.src)?),
+        None => None,
+    };
+    if let Some(unhandled_calls) = &conf.unhandled_calls {
+        let unhandled_calls = unhandled_calls.clone();
+        let source = conf.source.display().to_string();
+        let global_litua: mlua::Table = runtime.lua.globals().get("Litua")?;
+        global_litua.set("unhandled_call", runtime.lua.create_function(move |_, call: String| {
+            unhandled_calls.borrow_mut().record(&call, &source);
+            Ok(())
+        })?)?;
+    }
+
+    let intermediate = transform_document(runtime, &document, doc_tree, &mut stats, stages)?;
+    if conf.op == Operation::DumpTransformed {
+        return dump_transformed(&runtime.lua, &intermediate);
+    }
+
+    let output = postprocess_document(runtime, conf, &intermediate, stages)?;
+    stats.output_bytes = Some(output.bytes.len());
+    report_measurements(conf, runtime.profile.as_ref(), &stats)?;
+
+    if let Some(explanation) = explanation {
+        print!("{}", explanation.borrow().to_text());
+        return Ok(());
+    }
+    if conf.unhandled_calls.is_some() {
+        log!("output of '{}' not written, because unhandled calls are reported", conf.source.display());
+        return Ok(());
+    }
+    if conf.op == Operation::Diff {
+        return diff_output(conf, &output.bytes, stages);
+    }
+
+    write_output(conf, &output.bytes, stages)?;
+    if let Some(sourcemap_path) = &conf.sourcemap {
+        write_sourcemap(&runtime.lua, conf, sourcemap_path, output, &document.src, stages)?;
+    }
+    Ok(())
+}
+
+/// Initialize a Lua runtime with the litua libraries and load the hook files found for `conf`
+fn load_runtime(conf: &Settings) -> Result<Runtime, Error> {
+    // (0) initialize Lua runtime
+    // NOTE: 'debug' library is only available with Lua::unsafe_new()
+    //       https://github.com/khvzak/mlua/issues/39
+    let lua = unsafe { Lua::unsafe_new() };
+    log!("Lua runtime initialized");
+
+    // (1) add paths to Lua path variable
+    for lua_path in conf.lua_path_additions.iter() {
+        let addition_str = path::PathBuf::from(&lua_path);
+        match addition_str.to_str() {
+            Some(s) if !s.is_empty() => lua.load(&format!("package.path = package.path .. ';{s}'")).exec()?,
+            Some(_) => {},
+            None => return Err(Error::CLIArg("cannot convert the luapath extension path (supplied as --add-require-path) to a UTF-8 string. But this is sadly required by the mlua interface (the library to run Lua)".to_owned())),
+        };
+    }
+    log!("Lua paths added");
+
+    // (2) find hook files
+    let hooks_dirs = hook_search_dirs(&conf.hooks_dir, &conf.hook_packages, conf.shared_hooks);
+    let mut resolution = vec![];
+    let (hook_files, hook_packages) = match conf.replay {
+        // NOTE: a replay does not run any hook
+        Some(_) => (vec![], vec![]),
+        None => find_hook_files(&hooks_dirs, 1 + conf.hook_packages.len(), &mut resolution).map_err(Error::Io)?,
+    };
+    if conf.dump_hooks {
+        report(&format!("hook files in the order they are loaded:\n{}", resolution.join("\n")));
+    }
+    log!("{} hook file{} found", hook_files.len(), if hook_files.len() == 1 { "" } else { "s" });
+    record_in_manifest(conf, |document| {
+        document.hooks = hook_files.iter().map(|(filepath, content)| (filepath.display().to_string(), litua::tree::stable_hash(content))).collect();
+    });
+    for (hook_file, _) in hook_files.iter() {
+        record_dependency(conf, hook_file);
+    }
+
+    // (3) load litua libraries
+    load_libraries(&lua, conf)?;
+
+    let profile = conf.profile.as_ref().map(|_| rc::Rc::new(cell::RefCell::new(litua::profile::Profile::default())));
+    if conf.trace_hooks.is_some() || profile.is_some() {
+        let global_litua: mlua::Table = lua.globals().get("Litua")?;
+        let start = time::Instant::now();
+        global_litua.set("clock", lua.create_function(move |_, ()| Ok(start.elapsed().as_secs_f64()))?)?;
+        global_litua.set("trace_hook", create_trace_hook(&lua, conf.trace_hooks.as_ref(), profile.clone())?)?;
+        log!("hook tracing enabled");
+    }
+
+    let recording = match &conf.record {
+        Some(filepath) => {
+            let recording = rc::Rc::new(cell::RefCell::new(litua::recording::Recording::new(&conf.source.display().to_string())));
+            install_recorder(&lua, &recording)?;
+            log!("hook invocations are recorded");
+            Some(RecordingWriter { recording, filepath: filepath.to_owned() })
+        },
+        None => None,
+    };
+    let replay = match &conf.replay {
+        Some(filepath) => {
+            let json = serde_json::from_slice(&fs::read(filepath)?)
+                .map_err(|e| Error::CLIArg(format!("--replay: cannot read recording '{}': {e}", filepath.display())))?;
+            let recording = litua::recording::Recording::from_json(&json)
+                .map_err(|e| Error::CLIArg(format!("--replay: invalid recording '{}': {e}", filepath.display())))?;
+            let replay = rc::Rc::new(cell::RefCell::new(litua::recording::Replay::new(recording)));
+            install_replay(&lua, &replay)?;
+            log!("hook invocations are replayed from '{}'", filepath.display());
+            Some(replay)
+        },
+        None => None,
+    };
+
+    install_library(&lua, conf)?;
+
+    // (4) read hook files
+    load_hook_files(&lua, &hook_files, hook_packages)?;
+    if let Some(recording) = &recording {
+        recording.recording.borrow_mut().registrations = registered_hooks(&lua)?;
+    }
+    log!("All hook files loaded");
+
+    Ok(Runtime { lua, hook_files, profile, replay, _recording: recording })
+}
+
+/// Load the litua libraries written in Lua into `lua` and route `Litua.log` as configured
+fn load_libraries(lua: &Lua, conf: &Settings) -> Result<(), Error> {
+    let litua_table = include_str!("litua.lua");
+    lua.load(litua_table).set_name("litua.lua")?.exec()?;
+    let litua_lib = include_str!("litua_stdlib.lua");
+    lua.load(litua_lib).set_name("litua_stdlib.lua")?.exec()?;
+    if let Some(prefix) = LOG_PREFIX.get() {
+        lua.globals().get::<_, mlua::Table>("Litua")?.set("log_prefix", prefix.as_str())?;
+    }
+    litua::lua_modules::register(lua)?;
+    if conf.op == Operation::Lsp {
+        // NOTE: stdout carries the messages of the language server, so print (and Litua.log) writes to stderr
+        lua.globals().set("print", lua.create_function(|lua, values: mlua::MultiValue| {
+            let tostring: mlua::Function = lua.globals().get("tostring")?;
+            let values = values.into_iter().map(|value| tostring.call::<_, String>(value)).collect::<mlua::Result<Vec<_>>>()?;
+            report(&values.join("\t"));
+            Ok(())
+        })?)?;
+    }
+    if JSON_EVENTS.load(sync::atomic::Ordering::Relaxed) {
+        let global_litua: mlua::Table = lua.globals().get("Litua")?;
+        global_litua.set("log", lua.create_function(|lua, (component, msg): (String, LuaValue)| {
+            let tostring: mlua::Function = lua.globals().get("tostring")?;
+            let message: String = tostring.call(msg)?;
+            emit_event(serde_json::json!({ "event": "log", "component": component, "message": message }));
+            Ok(())
+        })?)?;
+    } else if LOG_FILE.lock().is_ok_and(|log_file| log_file.is_some()) {
+        // NOTE: Litua.log prints to stdout, so we additionally write its lines to the log file
+        let global_litua: mlua::Table = lua.globals().get("Litua")?;
+        global_litua.set("write_log_file", lua.create_function(|_, msg: String| {
+            write_log_file(&msg);
+            Ok(())
+        })?)?;
+        lua.load(r#"
+            local log = Litua.log
+            Litua.log = function (component, msg)
+                log(component, msg)
+                Litua.write_log_file(Litua.log_line(component, msg))
+            end
+        "#).set_name("log_file")?.exec()?;
+    }
+    log!("litua standard library loaded");
+
+    if conf.lua_debug {
+        let litua_debug = include_str!("litua_debug.lua");
+        lua.load(litua_debug).set_name("litua_debug.lua")?.exec()?;
+        log!("litua debugger loaded");
+    }
+    Ok(())
+}
+
+/// Provide the functions implemented in Rust to hooks as members of table `Litua`
+fn install_library(lua: &Lua, conf: &Settings) -> Result<(), Error> {
+    {
+        let global_litua: mlua::Table = lua.globals().get("Litua")?;
+        global_litua.set("trim", lua.create_function(|_, text: String| Ok(litua::text::trim(&text).to_owned()))?)?;
+        global_litua.set("split", lua.create_function(|_, (text, separator): (String, Option<String>)| {
+            Ok(litua::text::split(&text, separator.as_deref()).into_iter().map(str::to_owned).collect::<Vec<String>>())
+        })?)?;
+        global_litua.set("dedent", lua.create_function(|_, text: String| Ok(litua::text::dedent(&text)))?)?;
+        global_litua.set("slugify", lua.create_function(|_, text: String| Ok(litua::text::slugify(&text)))?)?;
+        global_litua.set("wrap", lua.create_function(|_, (text, width): (String, usize)| Ok(litua::text::wrap(&text, width)))?)?;
+        let output_names = conf.output_names.clone();
+        global_litua.set("output_name", lua.create_function(move |_, overrides: Option<collections::HashMap<String, String>>| {
+            let overrides = overrides.unwrap_or_default();
+            let filepath = output_names.filepath(|name| overrides.get(name).cloned()).map_err(mlua::Error::external)?;
+            Ok(filepath.display().to_string())
+        })?)?;
+        global_litua.set("check_structure", lua.create_function(|_, (node, schema): (LuaValue, mlua::Table)| {
+            let node = match node {
+                LuaValue::Table(node) if node.contains_key("call")? => node,
+                other => return Err(mlua::Error::external(format!("expected a node to check, got {}", other.type_name()))),
+            };
+            let schema = litua::structure::Schema::new(
+                schema.get::<_, Option<Vec<String>>>("args")?.as_deref(),
+                schema.get::<_, Option<Vec<String>>>("children")?.as_deref(),
+            ).map_err(mlua::Error::external)?;
+            let call: String = node.get("call")?;
+            let mut arg_keys = vec![];
+            for pair in node.get::<_, mlua::Table>("args")?.pairs::<String, LuaValue>() {
+                arg_keys.push(pair?.0);
+            }
+            arg_keys.sort();
+            let mut children = vec![];
+            for element in node.get::<_, mlua::Table>("content")?.sequence_values::<LuaValue>() {
+                if let LuaValue::Table(child) = element? {
+                    children.push(child.get::<_, String>("call")?);
+                }
+            }
+            let arg_keys: Vec<&str> = arg_keys.iter().map(String::as_str).collect();
+            let children: Vec<&str> = children.iter().map(String::as_str).collect();
+            Ok(schema.check(&call, &arg_keys, &children))
+        })?)?;
+        global_litua.set("highlight", lua.create_function(|_, (code, language, style, theme): (String, String, Option<String>, Option<String>)| {
+            let style_name = style.unwrap_or_else(|| "html".to_owned());
+            let style = litua::syntax_highlight::Style::from_name(&style_name)
+                .ok_or_else(|| mlua::Error::external(format!("style must be 'html' or 'ansi', got '{style_name}'")))?;
+            litua::syntax_highlight::highlight(&code, &language, style, theme.as_deref()).map_err(mlua::Error::external)
+        })?)?;
+    }
+
+    {
+        let global_litua: mlua::Table = lua.globals().get("Litua")?;
+        let hook_error = lua.create_function(|_, (src, call, msg, start, end, file): (String, String, String, usize, usize, Option<String>)| -> mlua::Result<()> {
+            let message = Message::new("T0001", vec![src, call, msg]);
+            let err = litua::errors::Error::HookError(message, start..end);
+            Err(mlua::Error::external(match file {
+                Some(file) => litua::errors::Error::InIncludedFile(path::PathBuf::from(file), Box::new(err)),
+                None => err,
+            }))
+        })?;
+        global_litua.set("hook_error", hook_error)?;
+    }
+
+    if conf.normalize_names {
+        let global_litua: mlua::Table = lua.globals().get("Litua")?;
+        let normalize = lua.create_function(|_, name: String| Ok(name.nfc().collect::<String>()))?;
+        global_litua.set("normalize_name", normalize)?;
+    }
+
+    {
+        let global_litua: mlua::Table = lua.globals().get("Litua")?;
+        let include_conf = conf.clone();
+        let read = lua.create_function(move |lua, reference: String| {
+            let content = read_include(&include_conf, &reference, false).map_err(mlua::Error::external)?;
+            lua.create_string(&content)
+        })?;
+        global_litua.set("read_include", read)?;
+
+        let read_conf = conf.clone();
+        let read_file = lua.create_function(move |lua, filepath: String| {
+            let content = fs::read(&filepath).map_err(|e| mlua::Error::external(format!("cannot read file '{filepath}': {e}")))?;
+            record_input(&read_conf, path::Path::new(&filepath), &content);
+            lua.create_string(&content)
+        })?;
+        global_litua.set("read_file", read_file)?;
+    }
+
+    if conf.depfile.is_some() {
+        // NOTE: require(…) returns the filepath of a module as second value when it loads the module
+        let require = lua.create_registry_value(lua.globals().get::<_, mlua::Function>("require")?)?;
+        let require_conf = conf.clone();
+        let tracked_require = lua.create_function(move |lua, module: mlua::Value| {
+            let results: mlua::MultiValue = lua.registry_value::<mlua::Function>(&require)?.call(module)?;
+            if let Some(LuaValue::String(filepath)) = results.iter().nth(1) {
+                record_dependency(&require_conf, path::Path::new(filepath.to_str()?));
+            }
+            Ok(results)
+        })?;
+        lua.globals().set("require", tracked_require)?;
+    }
+
+    {
+        let datetime: mlua::Table = lua.globals().get::<_, mlua::Table>("Litua")?.get("datetime")?;
+        let source_date_epoch = source_date_epoch(conf);
+        let build_time = litua::datetime::build_timestamp(source_date_epoch.as_deref(), time::SystemTime::now());
+        let build_time_fn = build_time.clone();
+        datetime.set("build_time", lua.create_function(move |_, ()| build_time_fn.clone().map_err(mlua::Error::external))?)?;
+
+        // NOTE: with SOURCE_DATE_EPOCH, later modification times are clamped to the build timestamp
+        let mtime = match net::is_url(&conf.source.to_string_lossy()) {
+            true => None,
+            false => fs::metadata(&conf.source).and_then(|m| m.modified()).ok().map(litua::datetime::unix_timestamp),
+        };
+        let mtime = match (mtime, source_date_epoch, build_time) {
+            (Some(mtime), Some(_), Ok(build_time)) => Some(mtime.min(build_time)),
+            (mtime, _, _) => mtime,
+        };
+        datetime.set("document_mtime", lua.create_function(move |_, ()| Ok(mtime))?)?;
+        let locale = litua::datetime::locale(|name| std::env::var(name).ok());
+        datetime.set("locale", lua.create_function(move |_, ()| Ok(locale.clone()))?)?;
+    }
+
+    if conf.reproducible {
+        // NOTE: Lua seeds math.random randomly and os.time() returns the current time
+        lua.load(r#"
+            math.randomseed(0)
+            local time, date = os.time, os.date
+            os.time = function (t) if t == nil then return Litua.datetime.build_time() end return time(t) end
+            os.date = function (format, t) return date(format, t or Litua.datetime.build_time()) end
+        "#).set_name("=reproducible")?.exec()?;
+        log!("reproducible mode enabled");
+    }
+
+    {
+        let collation: mlua::Table = lua.globals().get::<_, mlua::Table>("Litua")?.get("collation")?;
+        let default_locale = litua::datetime::locale(|name| std::env::var(name).ok());
+        let locale = default_locale.clone();
+        collation.set("compare", lua.create_function(move |_, (a, b, lc): (String, String, Option<String>)| {
+            Ok(litua::collation::compare(&a, &b, lc.as_deref().unwrap_or(&locale)) as i8)
+        })?)?;
+        let locale = default_locale.clone();
+        collation.set("sort", lua.create_function(move |_, (mut texts, lc): (Vec<String>, Option<String>)| {
+            litua::collation::sort(&mut texts, lc.as_deref().unwrap_or(&locale));
+            Ok(texts)
+        })?)?;
+        let locale = default_locale;
+        collation.set("group", lua.create_function(move |_, (text, lc): (String, Option<String>)| {
+            Ok(litua::collation::group(&text, lc.as_deref().unwrap_or(&locale)))
+        })?)?;
+    }
+
+    {
+        let global_litua: mlua::Table = lua.globals().get("Litua")?;
+        let allow_exec = conf.allow_exec;
+        let exec = lua.create_function(move |lua, (cmd, args, stdin, timeout): (String, Option<Vec<String>>, Option<mlua::String>, Option<f64>)| {
+            if !allow_exec {
+                return Err(mlua::Error::external(format!("running command '{cmd}' requires permission, which must be granted with --allow-exec")));
+            }
+            let timeout = match timeout {
+                Some(secs) if secs.is_finite() && secs > 0.0 => time::Duration::from_secs_f64(secs),
+                Some(secs) => return Err(mlua::Error::external(format!("timeout must be a positive number of seconds, got {secs}"))),
+                None => litua::exec::DEFAULT_TIMEOUT,
+            };
+            let stdin = stdin.as_ref().map(|s| s.as_bytes()).unwrap_or_default();
+            let output = litua::exec::run(&cmd, &args.unwrap_or_default(), stdin, timeout).map_err(mlua::Error::external)?;
+            log!("command '{}' finished with status {:?}", cmd, output.status);
+
+            let result = lua.create_table()?;
+            result.set("status", output.status)?;
+            result.set("stdout", lua.create_string(&output.stdout)?)?;
+            result.set("stderr", lua.create_string(&output.stderr)?)?;
+            Ok(result)
+        })?;
+        global_litua.set("exec", exec)?;
+    }
+
+    {
+        let global_litua: mlua::Table = lua.globals().get("Litua")?;
+        let allow_net = conf.allow_net;
+        let fetch = lua.create_function(move |lua, (url, options): (String, Option<mlua::Table>)| {
+            if !allow_net {
+                return Err(mlua::Error::external(format!("fetching '{url}' requires network access, which must be allowed with --allow-net")));
+            }
+            if !net::is_url(&url) {
+                return Err(mlua::Error::external(format!("'{url}' is not an HTTP(S) URL")));
+            }
+            let (mut timeout, mut max_size) = (net::DEFAULT_TIMEOUT, net::DEFAULT_MAX_SIZE);
+            if let Some(options) = options {
+                if let Some(secs) = options.get::<_, Option<f64>>("timeout")? {
+                    if !secs.is_finite() || secs <= 0.0 {
+                        return Err(mlua::Error::external(format!("timeout must be a positive number of seconds, got {secs}")));
+                    }
+                    timeout = time::Duration::from_secs_f64(secs);
+                }
+                max_size = options.get::<_, Option<u64>>("max_size")?.unwrap_or(max_size);
+            }
+
+            let response = net::fetch(&url, timeout, max_size).map_err(mlua::Error::external)?;
+            log!("'{}' fetched with HTTP status {} ({} bytes)", url, response.status, response.body.len());
+            Ok((lua.create_string(&response.body)?, response.status))
+        })?;
+        global_litua.set("fetch", fetch)?;
+    }
+
+    {
+        let global_litua: mlua::Table = lua.globals().get("Litua")?;
+        let parse_fragment = lua.create_function(|lua, src: String| {
+            let fragment = litua::parser::Parser::parse_fragment(&src)
+                .map_err(|e| mlua::Error::external(e.format_with_source(path::Path::new("fragment"), &src)))?;
+
+            // NOTE: Litua.tree_to_nodes is defined in litua_transform.lua, which is loaded
+            //       after the hook files, but before any hook can be invoked
+            let tree_to_nodes: mlua::Function = lua.globals().get::<_, mlua::Table>("Litua")?.get("tree_to_nodes")?;
+            let elements = lua.create_table()?;
+            for mut element in fragment {
+                match element {
+                    litua::tree::DocumentElement::Function(ref mut func) => {
+                        func.remove_spans();
+                        elements.push(tree_to_nodes.call::<_, mlua::Value>(&element)?)?;
+                    },
+                    litua::tree::DocumentElement::Text(text) => elements.push(text)?,
+                }
+            }
+            Ok(elements)
+        })?;
+        global_litua.set("parse_fragment", parse_fragment)?;
+    }
+    Ok(())
+}
+
+/// Run the hook files `hook_files` in `lua`. Modules of `hook_packages` can be required by them.
+fn load_hook_files(lua: &Lua, hook_files: &[HookFile], hook_packages: Vec<HookPackage>) -> Result<(), Error> {
+    if !hook_packages.is_empty() {
+        // NOTE: modules of hook packages are found by require(…) before modules in package.path
+        let searcher = lua.create_function(move |lua, module: String| {
+            for package in hook_packages.iter() {
+                if let Some((name, content)) = package.find_module(&module) {
+                    let filepath = package.path.join(name).display().to_string();
+                    let loader = lua.load(content).set_name(&filepath)?.into_function()?;
+                    return Ok((LuaValue::Function(loader), Some(filepath)));
+                }
+            }
+            let tried = hook_packages.iter().map(|p| format!("\n\tno module in hook package '{}'", p.path.display())).collect::<String>();
+            Ok((LuaValue::String(lua.create_string(&tried)?), None))
+        })?;
+        let searchers: mlua::Table = lua.globals().get::<_, mlua::Table>("package")?.get("searchers")?;
+        searchers.raw_insert(2, searcher)?;
+    }
+
+    for (hook_file, lua_file_src) in hook_files.iter() {
+        log!("Loading hook file '{}'", hook_file.display());
+
+        let mut chunk = lua.load(lua_file_src);
+        {
+            let filepath = hook_file.display();
+            chunk = chunk.set_name(filepath.to_string())?;
+        }
+        chunk.exec()?;
+    }
+    check_hook_api(&lua.globals().get("Litua")?)
+}
+
+/// Print the calls handled by hooks as JSON catalog for editor integrations
+fn list_calls(runtime: &Runtime) -> Result<(), Error> {
+    println!("{:#}", call_catalog(runtime)?.to_json());
+    Ok(())
+}
+
+/// Describe the calls handled by the hooks of `runtime`
+fn call_catalog(runtime: &Runtime) -> Result<litua::catalog::Catalog, Error> {
+    let global_litua: mlua::Table = runtime.lua.globals().get("Litua")?;
+    let describe_calls: mlua::Function = global_litua.get("describe_calls")?;
+    let mut catalog = litua::catalog::Catalog::new();
+    for site in describe_calls.call::<_, mlua::Table>(())?.sequence_values::<mlua::Table>() {
+        let site = site?;
+        let file: String = site.get("file")?;
+        let line: usize = site.get("line")?;
+        let src = runtime.hook_files.iter().find(|(hook_file, _)| hook_file.display().to_string() == file).and_then(|(_, src)| str::from_utf8(src).ok());
+        let lines = site.get::<_, usize>("first_line")?..=site.get::<_, usize>("last_line")?;
+        let hook_site = litua::catalog::HookSite { hook: site.get("hook")?, file, line };
+        catalog.add(&site.get::<_, String>("call")?, hook_site, src, lines);
+    }
+    Ok(catalog)
+}
+
+/// Print the registered hooks in the order they run. Useful for debugging.
+fn list_hooks(lua: &Lua) -> Result<(), Error> {
+    let global_litua: mlua::Table = lua.globals().get("Litua")?;
+    let describe_hooks: mlua::Function = global_litua.get("describe_hooks")?;
+    print!("{}", describe_hooks.call::<_, String>(())?);
+    Ok(())
+}
+
+/// Read the text document of `conf`, run the preprocessing hooks of `runtime`,
+/// and apply the directives of its header to `conf`
+fn read_document(runtime: &Runtime, conf: &Settings, stats: &mut litua::stats::Stats, stages: &mut StageTimer) -> Result<SourceDocument, Error> {
+    stages.begin("read");
+    let buf = read_include(conf, &conf.source.to_string_lossy(), true)?;
+    let file_metadata = match net::is_url(&conf.source.to_string_lossy()) {
+        true => None,
+        false => Some(litua::file_metadata::FileMetadata::of_file(&conf.source, &buf)),
+    };
+    // NOTE: with SOURCE_DATE_EPOCH, later modification times are clamped to the build timestamp
+    let file_metadata = file_metadata.map(|mut metadata| {
+        let epoch = source_date_epoch(conf).and_then(|epoch| litua::datetime::build_timestamp(Some(&epoch), time::SystemTime::now()).ok());
+        metadata.modified = match (metadata.modified, epoch) {
+            (Some(modified), Some(epoch)) => Some(modified.min(epoch)),
+            (modified, _) => modified,
+        };
+        metadata
+    });
+    let (doc_src, invalid) = litua::text::decode_utf8(&buf, conf.invalid_utf8)?;
+    if !invalid.is_empty() {
+        let offsets: Vec<String> = invalid.iter().take(MAX_REPORTED_OFFSETS).map(usize::to_string).collect();
+        let more = if invalid.len() > MAX_REPORTED_OFFSETS { ", …" } else { "" };
+        report(&format!("WARNING: source file '{}' contains {} invalid UTF-8 sequence{} ({}) at byte offsets {}{more}",
+            conf.source.display(), invalid.len(), if invalid.len() == 1 { "" } else { "s" },
+            if conf.invalid_utf8 == litua::text::InvalidUtf8::Replace { "replaced by U+FFFD" } else { "removed" }, offsets.join(", ")));
+    }
+    stats.source_bytes = doc_src.len();
+    log!("source file '{}' read", conf.source.display());
+    stages.finish("read");
+
+    // (5) run preprocessing hooks
+    stages.begin("preprocess");
+    let doc_src = {
+        let global_litua: mlua::Table = runtime.lua.globals().get("Litua")?;
+        let preprocess: mlua::Function = global_litua.get("preprocess")?;
+        let lua_result = preprocess.call::<mlua::Value, mlua::String>(doc_src.to_lua(&runtime.lua)?)?;
+        // TODO verify which errors are triggered for non-UTF-8 return values
+        lua_result.to_str()?.to_owned()
+    };
+    log!("source file '{}' pre-processed", conf.source.display());
+    stages.finish("preprocess");
+
+    // NOTE: directives in the header of the text document override the settings
+    let directives = litua::header::directives(&doc_src)
+        .map_err(|e| document_error(e, &conf.source, &doc_src, conf.error_context))?;
+    let directed_conf = apply_directives(conf, &directives)
+        .map_err(|e| document_error(e, &conf.source, &doc_src, conf.error_context))?;
+    if !directives.is_empty() {
+        log!("{} directives applied", directives.len());
+    }
+
+    Ok(SourceDocument { conf: directed_conf, src: doc_src, directives, metadata: file_metadata })
+}
+
+/// Read the source file mentioned in `conf` and lex its source code `doc_src`.
+/// Print the resulting sequence of tokens. Useful for debugging.
+fn dump_lexed(conf: &Settings, doc_src: &str) -> Result<(), Error> {
+    let l = litua::lexer::Lexer::with_options(doc_src, conf.lexer_options.clone());
+    let mut iter = l.iter();
+    if conf.trace_lexer {
+        iter.enable_trace();
+    }
+    while let Some(tok_or_err) = iter.next() {
+        for transition in iter.take_trace() {
+            println!("TRACE:\t{transition}");
+        }
+        let token = match tok_or_err {
+            Ok(tok) => tok,
+            Err(e) => return Err(document_error(e, &conf.source, doc_src, conf.error_context)),
+        };
+        println!("{token:?}");
+    }
+    Ok(())
+}
+
+/// Returns the pipeline lexing and parsing text documents as configured by `conf`.
+/// The `metadata` of the source file (if any) is provided as arguments of the root call.
+fn document_pipeline(conf: &Settings, metadata: Option<&litua::file_metadata::FileMetadata>) -> litua::pipeline::Pipeline {
+    let mut builder = litua::parser::ParserBuilder::new()
+        .root_call(&conf.root_call)
+        .normalize_names(conf.normalize_names)
+        .lossless(conf.lossless)
+        .merge_texts(conf.merge_texts)
+        .whitespace_texts(conf.whitespace_texts);
+    for call in conf.preserve_whitespace.iter() {
+        builder = builder.preserve_whitespace(call);
+    }
+    for call in conf.verbatim.iter() {
+        builder = builder.verbatim(call);
+    }
+    for (key, value) in metadata.iter().flat_map(|metadata| metadata.root_args()) {
+        builder = builder.root_arg(&key, &value);
+    }
+    for (key, value) in conf.root_args.iter() {
+        builder = builder.root_arg(key, value);
+    }
+    conf.token_filters.iter().fold(litua::pipeline::Pipeline::new(), |pipeline, filter| pipeline.token_filter(filter.clone()))
+        .lexer_options(conf.lexer_options.clone())
+        .parser(builder)
+}
+
+/// (6) lex and parse source code `doc_src` with `pipeline` to turn it into a tree.
+/// Included documents and conditionals are resolved if `conf` asks for it.
+fn parse_document(conf: &Settings, pipeline: &litua::pipeline::Pipeline, doc_src: &str, stats: &mut litua::stats::Stats, stages: &mut StageTimer) -> Result<litua::tree::DocumentTree, Error> {
+    stages.begin("parse");
+    if conf.stats {
+        stats.tokens = pipeline.tokens(doc_src).count();
+    }
+    let mut doc_tree = pipeline.parse(&conf.source, doc_src)
+        .map_err(|e| document_error(e, &conf.source, doc_src, conf.error_context))?;
+    if conf.project {
+        let mut read = |filepath: &path::Path| -> io::Result<String> {
+            let content = fs::read_to_string(filepath)?;
+            record_input(conf, filepath, content.as_bytes());
+            Ok(content)
+        };
+        let included = litua::include::resolve_includes(&mut doc_tree, &conf.source, pipeline, &mut read)
+            .map_err(|e| document_error(e, &conf.source, doc_src, conf.error_context))?;
+        log!("{} included document{} resolved", included.len(), if included.len() == 1 { "" } else { "s" });
+    }
+    if conf.conditionals {
+        litua::conditional::evaluate(&mut doc_tree)
+            .map_err(|e| document_error(e, &conf.source, doc_src, conf.error_context))?;
+    }
+    log!("source file '{}' lexed and parsed", conf.source.display());
+    stages.finish("parse");
+    if conf.stats {
+        stats.record_tree(&doc_tree);
+    }
+    Ok(doc_tree)
+}
+
+/// Run the transformation hooks of `runtime` on `doc_tree` of `document`.
+/// Returns the string representation before post-processing.
+fn transform_document(runtime: &Runtime, document: &SourceDocument, doc_tree: litua::tree::DocumentTree, stats: &mut litua::stats::Stats, stages: &mut StageTimer) -> Result<Vec<u8>, Error> {
+    let lua = &runtime.lua;
+    stages.begin("transform");
+    // (7) turn tree into a Lua object
+    let tree = doc_tree.to_lua(lua)?;
+    log!("parsed tree converted into a Lua table");
+
+    // (8) load transform function and node object (libraries, which users must not modify)
+    load_transform(lua, &document.conf, &document.directives)?;
+
+    // (9) call transformation
+    let global_litua: mlua::Table = lua.globals().get("Litua")?;
+    let transform: mlua::Function = global_litua.get("transform")?;
+    let intermediate = transform.call::<mlua::Value, mlua::String>(tree)
+        .map_err(|e| transform_error(e, &document.conf, &document.src))?;
+    log!("litua hooks for tree manipulation finished");
+    stages.finish("transform");
+    stats.lua_bytes = Some(lua.used_memory());
+    Ok(intermediate.as_bytes().to_vec())
+}
+
+/// Print the string representation `intermediate` after running the transformation
+/// hooks, but before post-processing. If the hooks kept a tree, print
+/// the tree before its conversion to a string as well. Useful for debugging.
+fn dump_transformed(lua: &Lua, intermediate: &[u8]) -> Result<(), Error> {
+    println!("=== transformed text ===");
+    println!("{}", String::from_utf8_lossy(intermediate));
+    let global_litua: mlua::Table = lua.globals().get("Litua")?;
+    if let Some(tree) = global_litua.get::<_, Option<litua::tree::DocumentTree>>("transformed_tree")? {
+        println!("=== transformed tree ===");
+        println!("{tree:?}");
+    }
+    Ok(())
+}
+
+/// (10) run the postprocessing hooks of `runtime` on `intermediate` and apply the output settings of `conf`
+fn postprocess_document(runtime: &Runtime, conf: &Settings, intermediate: &[u8], stages: &mut StageTimer) -> Result<Output, Error> {
+    stages.begin("postprocess");
+    let global_litua: mlua::Table = runtime.lua.globals().get("Litua")?;
+    let postprocess: mlua::Function = global_litua.get("postprocess")?;
+    let lua_result = postprocess.call::<mlua::String, mlua::String>(runtime.lua.create_string(intermediate)?)?;
+    if let Some(remaining) = runtime.replay.as_ref().map(|replay| replay.borrow().remaining()).filter(|remaining| *remaining > 0) {
+        report(&format!("WARNING: {remaining} recorded hook invocation{} not replayed", if remaining == 1 { " was" } else { "s were" }));
+    }
+    // NOTE: Lua strings are byte strings, so hooks might emit binary data
+    let bytes = conf.newlines.apply(lua_result.as_bytes());
+    let (bytes, marked_spans) = match conf.sourcemap {
+        Some(_) => litua::sourcemap::strip_markers(&bytes),
+        None => (bytes, vec![]),
+    };
+    if conf.validate_utf8 {
+        str::from_utf8(&bytes)?;
+    }
+    log!("source file '{}' post-processed", conf.source.display());
+    stages.finish("postprocess");
+    Ok(Output { bytes, marked_spans })
+}
+
+/// (11) compare `output` with the destination file of `conf` and print their differences
+fn diff_output(conf: &Settings, output: &[u8], stages: &mut StageTimer) -> Result<(), Error> {
+    stages.begin("diff");
+    let current = match fs::read(&conf.destination) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e.into()),
+    };
+    if current == output {
+        log!("File '{}' is up to date.", conf.destination.display());
+        stages.finish("diff");
+        return Ok(());
+    }
+    let current_text = String::from_utf8_lossy(&current);
+    let output_text = String::from_utf8_lossy(output);
+    let diff = similar::TextDiff::from_lines(current_text.as_ref(), output_text.as_ref());
+    print!("{}", diff.unified_diff().header(&conf.destination.display().to_string(), "output"));
+    stages.finish("diff");
+    Err(Error::OutputDiffers(conf.destination.clone()))
+}
+
+/// (11) write `output` to the destination file of `conf`
+fn write_output(conf: &Settings, output: &[u8], stages: &mut StageTimer) -> Result<(), Error> {
+    stages.begin("write");
+    create_destination_dir(conf)?;
+    write_atomically(&conf.destination, output, conf.backup)?;
+    record_in_manifest(conf, |document| document.add_output(&conf.destination.display().to_string(), output));
+    log!("File '{}' written.", conf.destination.display());
+    write_depfile(conf)?;
+    stages.finish("write");
+    Ok(())
+}
+
+/// (12) write the source map relating `output` to the text document `doc_src` to `filepath`
+fn write_sourcemap(lua: &Lua, conf: &Settings, filepath: &path::Path, output: Output, doc_src: &str, stages: &mut StageTimer) -> Result<(), Error> {
+    stages.begin("sourcemap");
+    let global_litua: mlua::Table = lua.globals().get("Litua")?;
+    let mut fragments = vec![];
+    let recorded: mlua::Table = global_litua.get("fragments")?;
+    for entry in recorded.sequence_values::<mlua::Table>() {
+        let entry = entry?;
+        fragments.push(litua::sourcemap::Fragment {
+            call: entry.get("call")?,
+            source: entry.get::<_, usize>("start")?..entry.get::<_, usize>("end")?,
+            depth: entry.get("depth")?,
+            output: litua::sourcemap::strip_markers(entry.get::<_, mlua::String>("output")?.as_bytes()).0,
+        });
+    }
+
+    let mut marked = vec![];
+    let spans: mlua::Table = global_litua.get("spans")?;
+    for span in output.marked_spans {
+        // NOTE: markers with unknown ids were not inserted by Litua.span
+        let entry: mlua::Table = match spans.get(span.id)? {
+            Some(entry) => entry,
+            None => continue,
+        };
+        marked.push(litua::sourcemap::Mapping {
+            call: entry.get("call")?,
+            source: entry.get::<_, usize>("start")?..entry.get::<_, usize>("end")?,
+            output: span.output,
+        });
+    }
+
+    let mut map = litua::sourcemap::SourceMap::locate(&output.bytes, &fragments);
+    map.add_marked(marked);
+    let sourcemap = format!("{:#}\n", map.to_json(&conf.source, doc_src));
+    fs::write(filepath, &sourcemap)?;
+    record_in_manifest(conf, |document| document.add_output(&filepath.display().to_string(), sourcemap.as_bytes()));
+    log!("Source map '{}' with {} mappings written.", filepath.display(), map.mappings.len());
+    stages.finish("sourcemap");
+    Ok(())
+}
+
+/// Load the transformation routines into `lua` and provide the configuration to hooks
+fn load_transform(lua: &Lua, conf: &Settings, directives: &[litua::header::Directive]) -> Result<(), Error> {
+    let litua_trans = include_str!("litua_transform.lua");
+    lua.load(litua_trans).set_name("litua_transform.lua")?.exec()?;
+    let litua_node = include_str!("litua_node.lua");
+    lua.load(litua_node).set_name("litua_node.lua")?.exec()?;
+    log!("litua transformation routines loaded");
+
+    let global_litua: mlua::Table = lua.globals().get("Litua")?;
+    let config: mlua::Table = global_litua.get("config")?;
+    let lua_directives = lua.create_table()?;
+    for directive in directives.iter() {
+        lua_directives.set(directive.key.as_str(), directive.value.as_str())?;
+    }
+    config.set("directives", lua_directives)?;
+    global_litua.set("grammar", grammar_table(lua, &conf.lexer_options)?)?;
+    if conf.sourcemap.is_some() {
+        config.set("sourcemap", true)?;
+        let markers = lua.create_table()?;
+        markers.set("start", litua::sourcemap::MARK_START.to_string())?;
+        markers.set("separator", litua::sourcemap::MARK_SEPARATOR.to_string())?;
+        markers.set("end", litua::sourcemap::MARK_END.to_string())?;
+        global_litua.set("span_markers", markers)?;
+    }
+    if conf.op == Operation::DumpTransformed {
+        config.set("dump_transformed", true)?;
+    }
+    // NOTE: on_setup hooks might have defined variables, which replayed hooks do not
+    lua.load(r#"
+        if Litua.record_vars ~= nil then Litua.record_vars(Litua.vars) end
+        if Litua.replayed_vars ~= nil then Litua.vars = Litua.replayed_vars() or {} end
+    "#).set_name("recording")?.exec()?;
+    Ok(())
+}
+
+/// Describe the syntax configuration `options` as table `Litua.grammar` with
+/// functions escaping text for generated litua source code
+fn grammar_table<'lua>(lua: &'lua Lua, options: &litua::lexer::LexerOptions) -> mlua::Result<mlua::Table<'lua>> {
+    use litua::grammar;
+    use litua::lexer;
+
+    let table = lua.create_table()?;
+    for (key, chr) in [
+        ("open_call", lexer::OPEN_FUNCTION), ("close_call", lexer::CLOSE_FUNCTION),
+        ("open_arg", lexer::OPEN_ARG), ("close_arg", lexer::CLOSE_ARG), ("assign", lexer::ASSIGN),
+        ("open_raw", options.open_raw), ("close_raw", options.close_raw), ("line_call", lexer::LINE_CALL),
+    ] {
+        table.set(key, chr.to_string())?;
+    }
+    table.set("max_raw_delimiter_length", lexer::MAX_RAW_DELIMITER_LENGTH)?;
+    table.set("trim_raw_whitespace", options.trim_raw_whitespace)?;
+    table.set("line_calls", options.line_calls)?;
+    table.set("call_names", match options.call_names {
+        lexer::CallNamePolicy::Identifier => "identifier",
+        lexer::CallNamePolicy::Permissive => "permissive",
+    })?;
+    table.set("call_name_punctuation", lexer::CALL_NAME_PUNCTUATION)?;
+    let escapes = lua.create_table()?;
+    escapes.set(lexer::OPEN_FUNCTION.to_string(), grammar::ESCAPED_OPEN_FUNCTION)?;
+    escapes.set(lexer::CLOSE_FUNCTION.to_string(), grammar::ESCAPED_CLOSE_FUNCTION)?;
+    table.set("escapes", escapes)?;
+
+    let opts = options.clone();
+    table.set("escape", lua.create_function(move |_, text: String| Ok(grammar::escape_text(&text, &opts)))?)?;
+    let opts = options.clone();
+    table.set("escape_arg", lua.create_function(move |_, text: String| Ok(grammar::escape_arg(&text, &opts)))?)?;
+    let opts = options.clone();
+    table.set("raw", lua.create_function(move |_, text: String| grammar::raw_string(&text, &opts).map_err(mlua::Error::external))?)?;
+    Ok(table)
+}
+
+/// Turn the error `e` of `Litua.transform` into an error referring to the text document `doc_src` if a hook failed
+fn transform_error(e: mlua::Error, conf: &Settings, doc_src: &str) -> Error {
+    match find_hook_error(&e) {
+        Some(hook_error) => document_error(hook_error, &conf.source, doc_src, conf.error_context),
+        None => Error::Mlua(e),
+    }
+}
+
+/// Print the profile and statistics if requested by `conf`
+fn report_measurements(conf: &Settings, profile: Option<&rc::Rc<cell::RefCell<litua::profile::Profile>>>, stats: &litua::stats::Stats) -> Result<(), Error> {
+    if let (Some(target), Some(profile)) = (&conf.profile, profile) {
+        match target {
+            ReportTarget::Stderr => report(profile.borrow().to_table().trim_end()),
+            ReportTarget::File(filepath) => {
+                fs::write(filepath, format!("{:#}\n", profile.borrow().to_json()))?;
+                log!("Profile '{}' written.", filepath.display());
+            },
+        }
+    }
+
+    if conf.stats {
+        if JSON_EVENTS.load(sync::atomic::Ordering::Relaxed) {
+            let mut event = stats.to_json();
+            event["event"] = serde_json::Value::from("stats");
+            event["file"] = serde_json::Value::from(conf.source.display().to_string());
+            emit_event(event);
+        } else {
+            report(stats.to_table().trim_end());
+        }
+    }
+    Ok(())
+}
+
+/// Lex, parse, transform, and post-process the text `document` one top-level
+/// call at a time and append the output of each chunk to the destination file.
+/// Thus the memory is bounded by the largest top-level call (see `--chunked`).
+/// With `--chunk-cache`, the output of unchanged chunks is reused from the cache
+/// instead (the contents of the hook files determine whether hooks changed).
+fn run_chunked(runtime: &Runtime, document: &SourceDocument, pipeline: &litua::pipeline::Pipeline, stats: &mut litua::stats::Stats, stages: &mut StageTimer) -> Result<(), Error> {
+    let (lua, conf, doc_src) = (&runtime.lua, &document.conf, document.src.as_str());
+    stages.begin("chunks");
+    load_transform(lua, conf, &document.directives)?;
+    let global_litua: mlua::Table = lua.globals().get("Litua")?;
+    let begin_chunk: mlua::Function = global_litua.get("begin_chunk")?;
+    let transform: mlua::Function = global_litua.get("transform")?;
+    let postprocess_chunk: mlua::Function = global_litua.get("postprocess_chunk")?;
+    let mut cache = match &conf.chunk_cache {
+        Some(filepath) => Some(BuildCache::open(lua, filepath, &runtime.hook_files)?),
+        None => None,
+    };
+
+    if conf.stats {
+        stats.tokens = pipeline.tokens(doc_src).count();
+    }
+
+    let mut chunks = 0;
+    let mut output_bytes = 0;
+    let mut root_stats = litua::stats::Stats::default();
+    create_destination_dir(conf)?;
+    write_atomically_with(&conf.destination, conf.backup, |file| {
+        for (index, chunk) in pipeline.chunks(&conf.source, doc_src).enumerate() {
+            let mut chunk = chunk.map_err(|e| document_error(e, &conf.source, doc_src, conf.error_context))?;
+            if conf.conditionals {
+                litua::conditional::evaluate(&mut chunk)
+                    .map_err(|e| document_error(e, &conf.source, doc_src, conf.error_context))?;
+            }
+            if conf.stats {
+                let mut chunk_stats = litua::stats::Stats::default();
+                chunk_stats.record_tree(&chunk);
+                // NOTE: the root call (and the texts of its arguments) is counted once
+                if let (0, litua::tree::DocumentElement::Function(root)) = (index, &chunk.0) {
+                    let root = litua::tree::DocumentFunction { call: root.call.clone(), args: root.args.clone(), content: vec![] };
+                    root_stats.record_tree(&litua::tree::DocumentTree(litua::tree::DocumentElement::Function(root)));
+                    stats.nodes = root_stats.nodes;
+                    stats.texts = root_stats.texts;
+                }
+                stats.nodes += chunk_stats.nodes - root_stats.nodes;
+                stats.texts += chunk_stats.texts - root_stats.texts;
+                stats.tree_bytes = stats.tree_bytes.max(chunk_stats.tree_bytes);
+            }
+
+            begin_chunk.call::<_, ()>(index + 1)?;
+            let transform_chunk = || -> Result<Vec<u8>, Error> {
+                let intermediate = transform.call::<_, mlua::String>(chunk.to_lua(lua)?)
+                    .map_err(|e| transform_error(e, conf, doc_src))?;
+                Ok(postprocess_chunk.call::<_, mlua::String>(intermediate)?.as_bytes().to_vec())
+            };
+            let output = match &mut cache {
+                Some(cache) => cache.transform(lua, &chunk, index, transform_chunk)?,
+                None => transform_chunk()?,
+            };
+            if conf.validate_utf8 {
+                str::from_utf8(&output)?;
+            }
+            let output = conf.newlines.apply(&output);
+            file.write_all(&output)?;
+
+            chunks += 1;
+            output_bytes += output.len();
+            stats.lua_bytes = stats.lua_bytes.max(Some(lua.used_memory()));
+            // NOTE: the trees of previous chunks are unreachable now
+            lua.gc_collect()?;
+        }
+        Ok::<(), Error>(())
+    })?;
+    stages.finish("chunks");
+
+    if let Some(cache) = cache {
+        cache.write()?;
+    }
+
+    let teardown: mlua::Function = global_litua.get("teardown")?;
+    teardown.call::<_, ()>(())?;
+    log!("File '{}' with {} chunks written.", conf.destination.display(), chunks);
+    if conf.manifest.is_some() {
+        // NOTE: the output was written piecewise, so read it back for its hash
+        let output = fs::read(&conf.destination)?;
+        record_in_manifest(conf, |document| document.add_output(&conf.destination.display().to_string(), &output));
+    }
+    write_depfile(conf)?;
+    stats.output_bytes = Some(output_bytes);
+    Ok(())
+}
+
+/// `BuildCache` reuses the output of chunks unchanged since the previous run (see `--chunk-cache`)
+struct BuildCache {
+    filepath: path::PathBuf,
+    cache: litua::incremental::Cache,
+    /// hash of the configuration, the variables, and the registered hooks
+    context: u64,
+    /// content hashes of hook files and Lua modules by chunk name
+    hook_hashes: collections::HashMap<String, u64>,
+    reused: usize,
+    transformed: usize,
+    uncached: usize,
+}
+
+impl BuildCache {
+    /// Read the cache file at `filepath`. A missing file is an empty cache,
+    /// an invalid file is reported and replaced by an empty cache.
+    fn open(lua: &Lua, filepath: &path::Path, hook_files: &[HookFile]) -> Result<Self, Error> {
+        let cache = match fs::read(filepath) {
+            Ok(content) => match serde_json::from_slice(&content).map_err(|e| e.to_string()).and_then(|value| litua::incremental::Cache::from_json(&value)) {
+                Ok(cache) => cache,
+                Err(e) => {
+                    report(&format!("WARNING: cache '{}' ignored: {e}", filepath.display()));
+                    litua::incremental::Cache::new()
+                },
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => litua::incremental::Cache::new(),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        let chunk_context: mlua::Function = lua.globals().get::<_, mlua::Table>("Litua")?.get("chunk_context")?;
+        let context = litua::recording::lua_to_json(lua, chunk_context.call(())?)?;
+        let context = format!("{} {}", env!("CARGO_PKG_VERSION"), context);
+        let hook_hashes = hook_files.iter()
+            .map(|(hook_file, content)| (hook_file.display().to_string(), litua::tree::stable_hash(content)))
+            .collect();
+        Ok(Self { filepath: filepath.to_owned(), cache, context: litua::tree::stable_hash(context.as_bytes()), hook_hashes, reused: 0, transformed: 0, uncached: 0 })
+    }
+
+    /// Returns the content hash of the hook file or Lua module `file`
+    /// (modules like `@lib/util.lua` are read from disk)
+    fn hook_hash(hook_hashes: &mut collections::HashMap<String, u64>, file: &str) -> u64 {
+        *hook_hashes.entry(file.to_owned()).or_insert_with(|| {
+            let content = fs::read(file.trim_start_matches('@')).unwrap_or_else(|_| file.as_bytes().to_vec());
+            litua::tree::stable_hash(&content)
+        })
+    }
+
+    /// Returns the output of `chunk` with 0-based index `index`. If the cache holds it,
+    /// the state persisting across chunks is restored to the state after the chunk.
+    /// Otherwise the output of `transform_chunk` is returned and added to the cache.
+    fn transform<F: FnOnce() -> Result<Vec<u8>, Error>>(&mut self, lua: &Lua, chunk: &litua::tree::DocumentTree, index: usize, transform_chunk: F) -> Result<Vec<u8>, Error> {
+        let global_litua: mlua::Table = lua.globals().get("Litua")?;
+        let chunk_state: mlua::Function = global_litua.get("chunk_state")?;
+        let state = litua::recording::lua_to_json(lua, chunk_state.call(())?)?;
+        let key = litua::incremental::chunk_key(chunk, index, self.context, &state);
+
+        let hook_hashes = &mut self.hook_hashes;
+        if let Some(entry) = self.cache.lookup(key, |file| Self::hook_hash(hook_hashes, file)) {
+            let tree_to_nodes: mlua::Function = global_litua.get("tree_to_nodes")?;
+            let state = litua::recording::json_to_lua(lua, &entry.state, &|node| tree_to_nodes.call(node))?;
+            global_litua.get::<_, mlua::Function>("restore_chunk_state")?.call::<_, ()>(state)?;
+            self.reused += 1;
+            return Ok(entry.output.as_bytes().to_vec());
+        }
+
+        global_litua.set("invoked_hook_files", lua.create_table()?)?;
+        let output = transform_chunk();
+        let invoked: mlua::Table = global_litua.get("invoked_hook_files")?;
+        global_litua.set("invoked_hook_files", mlua::Value::Nil)?;
+        let output = output?;
+        self.transformed += 1;
+
+        // NOTE: the cache file is JSON, so outputs which are not valid UTF-8 are not cached
+        let text = match str::from_utf8(&output) {
+            Ok(text) => text,
+            Err(e) => {
+                log!("chunk {} not cached: its output is not valid UTF-8 ({e})", index + 1);
+                self.uncached += 1;
+                return Ok(output);
+            },
+        };
+        let mut hooks = collections::BTreeMap::new();
+        for file in invoked.pairs::<String, bool>() {
+            let (file, _) = file?;
+            let hash = Self::hook_hash(&mut self.hook_hashes, &file);
+            hooks.insert(file, hash);
+        }
+        let state = litua::recording::lua_to_json(lua, chunk_state.call(())?)?;
+        self.cache.insert(key, litua::incremental::Entry { output: text.to_owned(), state, hooks });
+        Ok(output)
+    }
+
+    /// Write the entries used by this run to the cache file
+    fn write(&self) -> Result<(), Error> {
+        fs::write(&self.filepath, self.cache.to_json().to_string())?;
+        log!("{} chunk{} reused and {} transformed, cache '{}' written.", self.reused, if self.reused == 1 { "" } else { "s" }, self.transformed, self.filepath.display());
+        if self.uncached > 0 {
+            report(&format!("WARNING: {} chunk{} not cached, because {} output is not valid UTF-8", self.uncached, if self.uncached == 1 { " was" } else { "s were" }, if self.uncached == 1 { "its" } else { "their" }));
+        }
+        Ok(())
+    }
+}
+
+/// Browse `doc_tree` in the terminal. Previews transform a subtree with the hooks loaded
+/// into `lua` like a chunk of the document (see `litua::explore`).
+fn run_explore(lua: &Lua, conf: &Settings, doc_tree: litua::tree::DocumentTree, doc_src: &str) -> Result<(), Error> {
+    let global_litua: mlua::Table = lua.globals().get("Litua")?;
+    let begin_chunk: mlua::Function = global_litua.get("begin_chunk")?;
+    let transform: mlua::Function = global_litua.get("transform")?;
+    let postprocess_chunk: mlua::Function = global_litua.get("postprocess_chunk")?;
+    // NOTE: log lines of hooks would disturb the terminal interface
+    global_litua.set("log", lua.create_function(|_, _: mlua::MultiValue| Ok(()))?)?;
+
+    let preview = |subtree: &litua::tree::DocumentTree| {
+        let output = (|| {
+            begin_chunk.call::<_, ()>(1)?;
+            let intermediate = transform.call::<_, mlua::String>(subtree.to_lua(lua)?)?;
+            postprocess_chunk.call::<_, mlua::String>(intermediate)
+        })();
+        match output {
+            Ok(output) => Ok(String::from_utf8_lossy(output.as_bytes()).into_owned()),
+            Err(e) => Err(transform_error(e, conf, doc_src).to_string()),
+        }
+    };
+    litua::explore::run(litua::explore::Explorer::new(doc_tree), doc_src, preview)?;
+    Ok(())
+}
+
+/// Serve completions and hover texts for the calls handled by the hooks of `runtime`
+/// to an editor over the Language Server Protocol on stdin and stdout
+fn run_lsp(runtime: &Runtime) -> Result<(), Error> {
+    let mut server = litua::lsp::Server::new(call_catalog(runtime)?);
+    log!("language server started");
+    if !litua::lsp::serve(&mut server, &mut io::stdin().lock(), &mut io::stdout().lock())? {
+        report("WARNING: the client exited without shutdown request");
+    }
+    Ok(())
+}
+
+/// Read Lua statements or expressions from stdin line by line, evaluate
+/// them in `lua` and print the values of expressions
+fn run_repl(lua: &Lua) -> Result<(), Error> {
+    let tostring: mlua::Function = lua.globals().get("tostring")?;
+    let stdin = io::stdin();
+    loop {
+        eprint!("litua> ");
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            eprintln!();
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // NOTE: like the interactive Lua interpreter, try to evaluate the line as expression first
+        let result = match lua.load(&format!("return {line}")).set_name("repl")?.into_function() {
+            Ok(function) => function.call::<_, mlua::MultiValue>(()),
+            Err(_) => lua.load(&line).set_name("repl")?.call::<_, mlua::MultiValue>(()),
+        };
+        match result {
+            Ok(values) if !values.is_empty() => {
+                let mut reprs = vec![];
+                for value in values {
+                    reprs.push(tostring.call::<_, String>(value)?);
+                }
+                println!("{}", reprs.join("\t"));
+            },
+            Ok(_) => {},
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+/// Generate the grammar of the litua input syntax configured by `options` in
+/// the format specified and write its files to the directory `destination`.
+fn run_grammar(format: &GrammarFormat, destination: &path::Path, options: &litua::lexer::LexerOptions) -> Result<(), Error> {
+    let files = match format {
+        GrammarFormat::TreeSitter => litua::grammar::tree_sitter(options),
+    };
+
+    for (relative_filepath, content) in files.files() {
+        let filepath = destination.join(relative_filepath);
+        if let Some(parent) = filepath.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&filepath, content)?;
+        log!("File '{}' written.", filepath.display());
+    }
+
+    Ok(())
+}
+
+/// Format the text documents at `sources` in place. If `check` is set,
+/// print a unified diff for every file which is not formatted instead.
+/// If `stdin` is set, format the document read from stdin and write it to stdout.
+/// The formatter options are read from the closest `litua.toml` file.
+fn run_fmt(sources: &[path::PathBuf], stdin: bool, check: bool, error_context: usize) -> Result<(), Error> {
+    if stdin {
+        let source = path::Path::new("<stdin>");
+        let mut doc_src = String::new();
+        io::stdin().read_to_string(&mut doc_src)?;
+        let options = fmt_options(&std::env::current_dir()?)?;
+        let formatted = match litua::format::format_document_with(&doc_src, &options) {
+            Ok(formatted) => formatted,
+            Err(e) => return Err(document_error(e, source, &doc_src, error_context)),
+        };
+        if !check {
+            print!("{formatted}");
+        } else if formatted != doc_src {
+            let diff = similar::TextDiff::from_lines(&doc_src, &formatted);
+            print!("{}", diff.unified_diff().header(&source.display().to_string(), "formatted"));
+            return Err(Error::OutputDiffers(source.to_owned()));
+        }
+        return Ok(());
+    }
+
+    let mut unformatted = vec![];
+    for source in sources.iter() {
+        let doc_src = fs::read_to_string(source)?;
+        let options = fmt_options(path::absolute(source)?.parent().unwrap_or(path::Path::new("/")))?;
+        let formatted = match litua::format::format_document_with(&doc_src, &options) {
+            Ok(formatted) => formatted,
+            Err(e) => return Err(document_error(e, source, &doc_src, error_context)),
+        };
+        if formatted == doc_src {
+            continue;
+        }
+
+        if check {
+            let diff = similar::TextDiff::from_lines(&doc_src, &formatted);
+            print!("{}", diff.unified_diff().header(&source.display().to_string(), "formatted"));
+            unformatted.push(source.to_owned());
+        } else {
+            write_atomically(source, formatted.as_bytes(), false)?;
+            log!("File '{}' formatted.", source.display());
+        }
+    }
+
+    match unformatted.into_iter().next() {
+        Some(source) => Err(Error::OutputDiffers(source)),
+        None => Ok(()),
+    }
+}
+
+/// Read the formatter options of the `litua.toml` file closest to directory `dir`
+/// (or the default options if there is none)
+fn fmt_options(dir: &path::Path) -> Result<litua::format::FormatOptions, Error> {
+    match litua::format::find_config(dir) {
+        Some(config) => litua::format::FormatOptions::from_toml(&fs::read_to_string(&config)?)
+            .map_err(|msg| Error::CLIArg(format!("invalid configuration '{}': {msg}", config.display()))),
+        None => Ok(litua::format::FormatOptions::default()),
+    }
+}
+
+/// Report `diagnostic` of the text document `doc_src` (with line index `lines`) read from `source` with an excerpt
+fn report_diagnostic(diagnostic: &litua::lint::Diagnostic, source: &path::Path, doc_src: &str, lines: &litua::text::LineIndex, lang: Lang, error_context: usize) {
+    let located = litua::errors::Error::InvalidSyntax(diagnostic.message.clone(), diagnostic.range.start)
+        .format_with_line_index(source, doc_src, lines);
+    let msg = Message::new("E0006", vec![diagnostic.message.code.to_owned(), located.localized(lang)]).render(lang);
+    let excerpt = litua::errors::excerpt(doc_src, diagnostic.range.clone(), error_context);
+    report(&format!("{msg}\n{}", excerpt.trim_end()));
+}
+
+/// Report the lint diagnostics of the text documents at `sources` including the
+/// diagnostics of the Lua rules in `rules_dir` (default: the directory of the document)
+fn run_lint(sources: &[path::PathBuf], rules_dir: Option<&path::Path>, lang: Lang, error_context: usize) -> Result<(), Error> {
+    let mut count = 0;
+    for source in sources.iter() {
+        let doc_src = fs::read_to_string(source)?;
+        let dir = match (rules_dir, source.parent()) {
+            (Some(dir), _) => dir,
+            (None, Some(dir)) if !path_is_empty(dir) => dir,
+            (None, _) => path::Path::new("."),
+        };
+        let rules = litua::lint::find_rules(dir)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot read lint rules in '{}': {e}", dir.display())))?;
+        let diagnostics = match litua::lint::lint_with_rules(&doc_src, &rules) {
+            Ok(diagnostics) => diagnostics,
+            Err(e) => return Err(match find_hook_error(&e) {
+                Some(e) => document_error(e, source, &doc_src, error_context),
+                None => Error::Mlua(e),
+            }),
+        };
+
+        let lines = litua::text::LineIndex::new(&doc_src);
+        for diagnostic in diagnostics.iter() {
+            report_diagnostic(diagnostic, source, &doc_src, &lines, lang, error_context);
+        }
+        count += diagnostics.len();
+    }
+
+    if count > 0 {
+        return Err(Error::LintWarnings(count));
+    }
+    Ok(())
+}
+
+/// Interval between two looks for modified files by `litua check --watch`
+const WATCH_INTERVAL: time::Duration = time::Duration::from_millis(500);
+
+/// Returns the modification times of the source file and the Lua files of the hooks directory
+fn watched_files(conf: &Settings) -> Vec<(path::PathBuf, Option<time::SystemTime>)> {
+    let mut files = vec![conf.source.to_owned()];
+    if let Ok(entries) = fs::read_dir(&conf.hooks_dir) {
+        files.extend(entries.flatten().map(|entry| entry.path()).filter(|file| file.extension().is_some_and(|ext| ext == "lua")));
+    }
+    files.sort();
+    files.into_iter().map(|file| {
+        let modified = fs::metadata(&file).and_then(|metadata| metadata.modified()).ok();
+        (file, modified)
+    }).collect()
+}
+
+/// Represent the error `e` of a check as JSON object for `litua check --serve`
+fn diagnostic_json(e: &Error, lang: Lang) -> serde_json::Value {
+    let (message, position) = match e {
+        Error::Litua(err) => (err.message().render(lang), err.line_column()),
+        Error::Document(err, _) => (err.message().render(lang), err.line_column()),
+        other => (other.to_string(), None),
+    };
+    serde_json::json!({
+        "severity": "error",
+        "code": e.code(),
+        "message": message,
+        "line": position.map(|(line, _)| line),
+        "column": position.map(|(_, column)| column),
+    })
+}
+
+/// Represent the lint `diagnostic` of text document `doc_src` as JSON object for `litua check --serve`
+fn lint_diagnostic_json(diagnostic: &litua::lint::Diagnostic, doc_src: &str, lines: &litua::text::LineIndex, lang: Lang) -> serde_json::Value {
+    let (line, column) = lines.line_column(doc_src, diagnostic.range.start);
+    serde_json::json!({
+        "severity": "warning",
+        "code": diagnostic.message.code,
+        "message": diagnostic.message.render(lang),
+        "line": line,
+        "column": column,
+    })
+}
+
+/// `WatchedCheck` is the state `litua check --watch` keeps across checks
+#[derive(Default)]
+struct WatchedCheck {
+    /// Lua runtime with the hooks, reloaded if a Lua file of the hooks directory changes
+    runtime: Option<Runtime>,
+    /// preprocessed source of the latest check with its diagnostics
+    checked: Option<(String, Vec<serde_json::Value>)>,
+}
+
+impl WatchedCheck {
+    /// Check the document of `conf` and return its diagnostics. The hooks are reloaded
+    /// if `hooks_changed`, the document is only parsed again if its source changed.
+    fn check(&mut self, conf: &Settings, hooks_changed: bool, lang: Lang) -> Vec<serde_json::Value> {
+        let error = |e: Error| {
+            report(&e.localized(lang));
+            vec![diagnostic_json(&e, lang)]
+        };
+        let mut stages = StageTimer::new(&conf.source);
+        if hooks_changed || self.runtime.is_none() {
+            // NOTE: lint rules are files of the hooks directory as well
+            self.checked = None;
+            self.runtime = None;
+            stages.begin("load_hooks");
+            match load_runtime(conf) {
+                Ok(runtime) => self.runtime = Some(runtime),
+                Err(e) => return error(e),
+            }
+            stages.finish("load_hooks");
+        }
+        let runtime = self.runtime.as_ref().expect("runtime loaded above");
+
+        let mut stats = litua::stats::Stats::default();
+        let document = match read_document(runtime, conf, &mut stats, &mut stages) {
+            Ok(document) => document,
+            Err(e) => return error(e),
+        };
+        if let Some((_, diagnostics)) = self.checked.as_ref().filter(|(src, _)| *src == document.src) {
+            log!("source file '{}' unchanged", conf.source.display());
+            return diagnostics.clone();
+        }
+
+        let pipeline = document_pipeline(&document.conf, document.metadata.as_ref());
+        let diagnostics = match parse_document(&document.conf, &pipeline, &document.src, &mut stats, &mut stages) {
+            Ok(_) => {
+                let rules = litua::lint::find_rules(&conf.hooks_dir).unwrap_or_default();
+                match litua::lint::lint_with_rules(&document.src, &rules) {
+                    Ok(found) => {
+                        let lines = litua::text::LineIndex::new(&document.src);
+                        found.iter().map(|diagnostic| {
+                            report_diagnostic(diagnostic, &conf.source, &document.src, &lines, lang, conf.error_context);
+                            lint_diagnostic_json(diagnostic, &document.src, &lines, lang)
+                        }).collect()
+                    },
+                    Err(e) => error(transform_error(e, conf, &document.src)),
+                }
+            },
+            Err(e) => error(e),
+        };
+        self.checked = Some((document.src, diagnostics.clone()));
+        diagnostics
+    }
+}
+
+/// Check the document of `conf` whenever it or a file of its hooks directory changes
+/// and serve the diagnostics of the latest check at `serve` (if given). Runs until interrupted.
+/// The hooks stay loaded and an unchanged document is not parsed again.
+fn run_check_watch(conf: &Settings, serve: Option<&str>, lang: Lang) -> Result<(), Error> {
+    let source = conf.source.display().to_string();
+    let published: litua::serve::Published = sync::Arc::new(sync::Mutex::new(serde_json::json!({
+        "source": source, "ok": null, "checked_at": null, "duration_ms": null, "diagnostics": [],
+    })));
+    if let Some(addr) = serve {
+        let local_addr = litua::serve::spawn(addr, published.clone())
+            .map_err(|e| Error::CLIArg(format!("cannot serve diagnostics at '{addr}': {e}")))?;
+        log!("Diagnostics served at http://{}/diagnostics", local_addr);
+    }
+
+    let mut watched = WatchedCheck::default();
+    let mut last_files: Option<Vec<_>> = None;
+    loop {
+        let files = watched_files(conf);
+        if last_files.as_ref() != Some(&files) {
+            let hook_files = |files: &[(path::PathBuf, Option<time::SystemTime>)]| files.iter().filter(|(file, _)| *file != conf.source).cloned().collect::<Vec<_>>();
+            let hooks_changed = last_files.as_ref().is_none_or(|last| hook_files(last) != hook_files(&files));
+            last_files = Some(files);
+            let started = time::Instant::now();
+            let diagnostics = watched.check(conf, hooks_changed, lang);
+            let ok = diagnostics.iter().all(|diagnostic| diagnostic["severity"] != "error");
+            if let Ok(mut document) = published.lock() {
+                *document = serde_json::json!({
+                    "source": source,
+                    "ok": ok,
+                    "checked_at": format_timestamp(time::SystemTime::now()),
+                    "duration_ms": started.elapsed().as_secs_f64() * 1000.0,
+                    "diagnostics": diagnostics,
+                });
+            }
+        }
+        std::thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Convert the document at `source` given in format `from` into format `format` without running any hooks
+fn run_convert(source: &path::Path, from: ConvertSource, format: ConvertFormat, destination: Option<&path::Path>, docbook_map: Option<&path::Path>, error_context: usize) -> Result<(), Error> {
+    if docbook_map.is_some() && format != ConvertFormat::Docbook {
+        return Err(Error::CLIArg("--docbook-map is only supported by 'litua convert --to docbook'".to_owned()));
+    }
+    let mapping = match docbook_map {
+        Some(filepath) => {
+            let value = serde_json::from_str(&fs::read_to_string(filepath)?)
+                .map_err(|e| Error::CLIArg(format!("--docbook-map '{}' is not valid JSON: {e}", filepath.display())))?;
+            litua::docbook::Mapping::from_json(&value)
+                .map_err(|msg| Error::CLIArg(format!("--docbook-map '{}': {msg}", filepath.display())))?
+        },
+        None => litua::docbook::Mapping::new(),
+    };
+
+    let doc_src = fs::read_to_string(source)?;
+    let imported = match from {
+        ConvertSource::Litua => None,
+        ConvertSource::Json => {
+            let value = serde_json::from_str(&doc_src)
+                .map_err(|e| Error::CLIArg(format!("'{}' is not valid JSON: {e}", source.display())))?;
+            Some(litua::tree::DocumentTree::from_json(&value))
+        },
+        ConvertSource::Xml => Some(litua::xml::from_xml(&doc_src)),
+        ConvertSource::Markdown => Some(litua::markup::from_markdown(&doc_src)),
+    };
+    let imported = imported.transpose()
+        .map_err(|msg| Error::CLIArg(format!("cannot read '{}': {msg}", source.display())))?;
+    // NOTE: directives in the header of a litua text document configure its syntax
+    let pipeline = match from {
+        ConvertSource::Litua => litua::pipeline::Pipeline::for_document(&doc_src)
+            .map_err(|e| document_error(e, source, &doc_src, error_context))?,
+        _ => litua::pipeline::Pipeline::new(),
+    };
+
+    if format == ConvertFormat::Epub {
+        let destination = destination.map(path::Path::to_owned).unwrap_or_else(|| source.with_extension("epub"));
+        return match imported {
+            Some(tree) => {
+                let elements = match tree.0 {
+                    litua::tree::DocumentElement::Function(root) => root.content,
+                    text => vec![text],
+                };
+                run_convert_epub(source, &destination, elements.into_iter().map(|element| Ok(vec![element])))
+            },
+            None => {
+                let chunks = pipeline.chunks(source, &doc_src).map(|chunk| match chunk {
+                    Ok(litua::tree::DocumentTree(litua::tree::DocumentElement::Function(root))) => Ok(root.content),
+                    Ok(litua::tree::DocumentTree(text)) => Ok(vec![text]),
+                    Err(e) => Err(document_error(e, source, &doc_src, error_context)),
+                });
+                run_convert_epub(source, &destination, chunks)
+            },
+        };
+    }
+    let tree = match imported.map(Ok).unwrap_or_else(|| pipeline.parse(path::Path::new(""), &doc_src)) {
+        Ok(tree) => tree,
+        Err(e) => return Err(document_error(e, source, &doc_src, error_context)),
+    };
+    let output = match format {
+        ConvertFormat::Litua if from == ConvertSource::Litua => format!("{}{}", &doc_src[..litua::header::header_length(&doc_src)], tree.to_litua_syntax_with(pipeline.syntax())),
+        ConvertFormat::Litua => tree.to_litua_syntax(),
+        ConvertFormat::Json => format!("{:#}\n", tree.to_json()),
+        ConvertFormat::Markdown => litua::markup::to_markdown(&tree),
+        ConvertFormat::Rst => litua::markup::to_rst(&tree),
+        ConvertFormat::Docbook => litua::docbook::to_docbook(&tree, &mapping),
+        ConvertFormat::Man => litua::man::to_man(&tree, &source.file_stem().unwrap_or_default().to_string_lossy()),
+        ConvertFormat::Ansi => litua::ansi::to_ansi(&tree),
+        ConvertFormat::Xml => litua::xml::to_xml(&tree),
+        ConvertFormat::Html => litua::html::to_html(&tree),
+        ConvertFormat::Epub => unreachable!("EPUB archives are written by run_convert_epub"),
+    };
+
+    match destination {
+        Some(filepath) => {
+            write_atomically(filepath, output.as_bytes(), false)?;
+            log!("File '{}' written.", filepath.display());
+        },
+        None => io::stdout().write_all(output.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+/// Write the top-level elements `chunks` of the document read from `source` as EPUB
+/// archive to `destination`. Every top-level `h1` call starts a new chapter. Only one
+/// chapter is kept in memory, if `chunks` parses the document one call at a time.
+fn run_convert_epub<I: Iterator<Item=Result<Vec<litua::tree::DocumentElement>, Error>>>(source: &path::Path, destination: &path::Path, chunks: I) -> Result<(), Error> {
+    let now = time::SystemTime::now();
+    let source_date_epoch = std::env::var(litua::datetime::SOURCE_DATE_EPOCH_ENV_VAR).ok();
+    let timestamp = litua::datetime::build_timestamp(source_date_epoch.as_deref(), now).map_err(Error::CLIArg)?;
+    let language = match litua::datetime::locale(|name| std::env::var(name).ok()).as_str() {
+        "C" | "POSIX" => "en".to_owned(),
+        locale => locale.replace('_', "-"),
+    };
+    let metadata = litua::epub::Metadata {
+        title: None,
+        language,
+        identifier: format!("urn:litua:{}", litua::text::slugify(&source.file_stem().unwrap_or_default().to_string_lossy())),
+        modified: litua::datetime::iso8601(timestamp),
+    };
+
+    let mut chapters = 0;
+    write_atomically_with(destination, false, |file| {
+        let mut epub = litua::epub::EpubWriter::new(file, metadata)?;
+        let mut title = String::new();
+        let mut content = vec![];
+        for elements in chunks {
+            let elements = elements?;
+            if let Some(next_title) = elements.iter().find_map(litua::epub::chapter_title) {
+                let is_blank = |e: &litua::tree::DocumentElement| matches!(e, litua::tree::DocumentElement::Text(text) if text.trim().is_empty());
+                if !title.is_empty() || !content.iter().all(is_blank) {
+                    epub.add_chapter(&title, &content)?;
+                    chapters += 1;
+                }
+                title = next_title;
+                content.clear();
+            }
+            content.extend(elements);
+        }
+        epub.add_chapter(&title, &content)?;
+        chapters += 1;
+        epub.finish()?;
+        Ok::<(), Error>(())
+    })?;
+    log!("EPUB archive '{}' with {} chapter{} written.", destination.display(), chapters, if chapters == 1 { "" } else { "s" });
+    Ok(())
+}
+
+/// Determine the set of litua text documents (`*.lit` files) in the directory
+/// at the given filepath and its subdirectories
+fn find_documents(dir: &path::Path) -> Result<Vec<path::PathBuf>, io::Error> {
+    let mut test_files = vec![];
+    for dir_entry in fs::read_dir(dir)? {
+        let entry = dir_entry?;
+        let filepath = entry.path();
+        if entry.file_type()?.is_dir() {
+            test_files.append(&mut find_documents(&filepath)?);
+        } else if filepath.extension().is_some_and(|ext| ext == "lit") {
+            test_files.push(filepath);
+        }
+    }
+    test_files.sort();
+    Ok(test_files)
+}
+
+/// Lex and parse every `*.lit` file of the corpus in directory `dir` and compare
+/// the results with its snapshot files (see `litua::corpus`). If `bless` is set,
+/// the snapshot files are updated with the actual results instead.
+fn run_selftest(dir: &path::Path, bless: bool) -> Result<(), Error> {
+    let inputs = litua::corpus::find_inputs(dir)?;
+    let mut failed = 0;
+
+    for input in inputs.iter() {
+        if bless {
+            for snapshot in litua::corpus::bless(input)? {
+                println!("BLESSED {}", snapshot.display());
+            }
+            continue;
+        }
+
+        let checks = match litua::corpus::check(input) {
+            Ok(checks) => checks,
+            Err(e) => {
+                println!("FAIL {}: {e}", input.display());
+                failed += 1;
+                continue;
+            },
+        };
+        if checks.is_empty() {
+            println!("FAIL {}: no snapshot file exists (use --bless to create one)", input.display());
+            failed += 1;
+            continue;
+        }
+
+        let mut passed = true;
+        for check in checks.iter().filter(|check| !check.passed()) {
+            println!("FAIL {}: result differs from '{}'", input.display(), check.snapshot.display());
+            let diff = similar::TextDiff::from_lines(&check.expected, &check.actual);
+            print!("{}", diff.unified_diff().header(&check.snapshot.display().to_string(), "actual"));
+            passed = false;
+        }
+        if passed {
+            println!("PASS {}", input.display());
+        } else {
+            failed += 1;
+        }
+    }
+
+    println!("{} input{} checked, {} failed", inputs.len(), if inputs.len() == 1 { "" } else { "s" }, failed);
+    if failed > 0 {
+        return Err(Error::TestsFailed(failed));
+    }
+    Ok(())
+}
+
+/// Run the pipeline for every `*.lit` file in directory `dir` and compare the
+/// output with the sibling `*.expected` file. If `bless` is set, the expected
+/// files are updated with the actual output instead.
+fn run_tests(dir: &path::Path, bless: bool) -> Result<(), Error> {
+    let test_files = find_documents(dir)?;
+    let mut failed = 0;
+
+    for (index, source) in test_files.iter().enumerate() {
+        let expected_path = source.with_extension("expected");
+        let actual_path = std::env::temp_dir().join(format!("litua-test-{}-{index}.out", std::process::id()));
+
+        let mut conf = build_settings(BuildArgs::default(), source, None, DEFAULT_ERROR_CONTEXT)?;
+        conf.destination = actual_path.clone();
+        // NOTE: test cases must not depend on hooks installed for the user
+        conf.shared_hooks = false;
+
+        let result = run(&conf);
+        let actual = match result {
+            Ok(()) => fs::read(&actual_path),
+            Err(e) => {
+                println!("FAIL {}: {e}", source.display());
+                failed += 1;
+                continue;
+            },
+        };
+        let _ = fs::remove_file(&actual_path);
+        let actual = actual?;
+
+        if bless {
+            fs::write(&expected_path, &actual)?;
+            println!("BLESSED {}", expected_path.display());
+            continue;
+        }
+
+        let expected = match fs::read(&expected_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                println!("FAIL {}: expected output '{}' does not exist (use --bless to create it)", source.display(), expected_path.display());
+                failed += 1;
+                continue;
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        if expected == actual {
+            println!("PASS {}", source.display());
+        } else {
+            println!("FAIL {}: output differs from '{}'", source.display(), expected_path.display());
+            let expected_text = String::from_utf8_lossy(&expected);
+            let actual_text = String::from_utf8_lossy(&actual);
+            let diff = similar::TextDiff::from_lines(expected_text.as_ref(), actual_text.as_ref());
+            print!("{}", diff.unified_diff().header(&expected_path.display().to_string(), "actual"));
+            failed += 1;
+        }
+    }
+
+    println!("{} test{} run, {} failed", test_files.len(), if test_files.len() == 1 { "" } else { "s" }, failed);
+    if failed > 0 {
+        return Err(Error::TestsFailed(failed));
+    }
+    Ok(())
+}
+
+/// Highlight the litua text document `source` in the format specified
+/// and write it to `destination` (or stdout if no destination is given).
+/// The document is lexed with `options` updated by its header directives.
+fn run_highlight(source: &path::Path, format: &HighlightFormat, destination: Option<&path::Path>, options: &litua::lexer::LexerOptions) -> Result<(), Error> {
+    let doc_src = fs::read_to_string(source)?;
+    let highlighted = litua::header::directives(&doc_src)
+        .and_then(|directives| litua::header::apply_lexer_directives(options, &directives))
+        .and_then(|options| match format {
+            HighlightFormat::Html => litua::highlight::to_html_with(&doc_src, &options),
+            HighlightFormat::Ansi => litua::highlight::to_ansi_with(&doc_src, &options),
+        });
+    let output = match highlighted {
+        Ok(out) => out,
+        Err(e) => return Err(Error::Litua(e.format_with_source(source, &doc_src))),
+    };
+
+    match destination {
+        Some(filepath) => {
+            fs::write(filepath, output)?;
+            log!("File '{}' written.", filepath.display());
+        },
+        None => io::stdout().write_all(output.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+/// Write a litua text document titled `title` describing the calls documented in the hook files of `hooks_dir`
+fn run_doc(hooks_dir: &path::Path, title: &str, destination: Option<&path::Path>) -> Result<(), Error> {
+    let (mut hook_files, _) = find_hook_files(&[(hooks_dir.to_owned(), "--hooks-dir")], 1, &mut vec![])?;
+    hook_files.sort();
+    let mut reference = litua::hook_doc::Reference::new();
+    for (hook_file, content) in hook_files.iter() {
+        let name = hook_file.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        reference.add_hook_file(&name, str::from_utf8(content)?);
+    }
+    let output = reference.to_litua(title);
+
+    match destination {
+        Some(filepath) => {
+            fs::write(filepath, output)?;
+            log!("File '{}' written.", filepath.display());
+        },
+        None => io::stdout().write_all(output.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// Write one JSON object per line to stdout for every token of the text document
+/// `source` and every top-level element parsed, as soon as it is produced. Every
+/// object has the key `elapsed_us` (microseconds since parsing started). A final
+/// object with key `event` set to `end` (or `error`) summarizes the run.
+fn run_events(source: &path::Path, error_context: usize) -> Result<(), Error> {
+    let doc_src = fs::read_to_string(source)?;
+    let mut stdout = io::stdout().lock();
+    let mut write_error = None;
+    let (mut tokens, mut elements) = (0usize, 0usize);
+    let start = time::Instant::now();
+    let elapsed_us = || u64::try_from(start.elapsed().as_micros()).unwrap_or(u64::MAX);
+
+    let pipeline = litua::pipeline::Pipeline::for_document(&doc_src)
+        .map_err(|e| document_error(e, source, &doc_src, error_context))?;
+    let result = pipeline.parse_with_events(source, &doc_src, |event| {
+        match event {
+            litua::pipeline::Event::Token(_) => tokens += 1,
+            litua::pipeline::Event::Element(_) => elements += 1,
+        }
+        if write_error.is_none() {
+            let mut json = event.to_json(&doc_src);
+            json["elapsed_us"] = elapsed_us().into();
+            write_error = writeln!(stdout, "{json}").err();
+        }
+    });
+    if let Some(e) = write_error {
+        return Err(e.into());
+    }
+
+    let summary = match &result {
+        Ok(_) => serde_json::json!({ "event": "end", "tokens": tokens, "elements": elements, "elapsed_us": elapsed_us() }),
+        Err(e) => serde_json::json!({ "event": "error", "code": e.message().code, "tokens": tokens, "elements": elements, "elapsed_us": elapsed_us() }),
+    };
+    writeln!(stdout, "{summary}")?;
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(document_error(e, source, &doc_src, error_context)),
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DumpStage {
+    /// runtime configuration
+    Config,
+    /// registered hooks in the order they run
+    Hooks,
+    /// JSON catalog of the calls handled by hooks (argument keys read and documentation comments)
+    Calls,
+    /// tokens of the lexer
+    Lexed,
+    /// tree of the parser
+    Parsed,
+    /// result of the transformation hooks before post-processing
+    Transformed,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ConvertFormat {
+    /// litua input syntax with arguments sorted by key
+    Litua,
+    /// tree as JSON object with keys 'call', 'args', and 'content'
+    Json,
+    /// CommonMark document mapping calls like 'h1', 'p', 'em', and 'a' to Markdown
+    Markdown,
+    /// reStructuredText document mapping calls like 'h1', 'p', 'em', and 'a' to reStructuredText
+    Rst,
+    /// DocBook 5 article mapping calls to DocBook elements (see --docbook-map)
+    Docbook,
+    /// EPUB 3 e-book with one chapter per top-level 'h1' call (written to -o or '<source>.epub')
+    Epub,
+    /// man page (roff) mapping calls like 'section', 'option', and 'example' to man macros
+    Man,
+    /// preview for terminals with bold headings, indented lists, and highlighted 'pre' blocks
+    Ansi,
+    /// generic XML with one element per call and arguments as attributes (read by --from xml)
+    Xml,
+    /// HTML document mapping calls like 'h1', 'p', 'em', and 'a' to HTML elements
+    Html,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ConvertSource {
+    /// litua text document
+    Litua,
+    /// tree as JSON object like written by --to json
+    Json,
+    /// XML document like written by --to xml (other XML documents map elements to calls)
+    Xml,
+    /// CommonMark document mapped to calls like 'h1', 'p', 'em', and 'a'
+    Markdown,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum HighlightFormat {
+    /// HTML pre element with span elements carrying CSS classes
+    Html,
+    /// ANSI escape sequences for terminals
+    Ansi,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LogFormat {
+    /// human-readable lines
+    Text,
+    /// one JSON object per line for every log line, pipeline stage, and diagnostic
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum InvalidUtf8Policy {
+    /// fail at the first invalid sequence
+    Error,
+    /// replace invalid sequences by U+FFFD REPLACEMENT CHARACTER
+    Replace,
+    /// remove invalid sequences
+    Strip,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum NewlinesPolicy {
+    /// keep the line terminators written by the hooks
+    Keep,
+    /// terminate lines with '\n'
+    Lf,
+    /// terminate lines with '\r\n'
+    Crlf,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum WhitespaceTextsPolicy {
+    /// keep whitespace-only text elements
+    Keep,
+    /// remove whitespace-only text elements
+    Drop,
+    /// replace whitespace-only text elements by a single space
+    Collapse,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum GrammarFormat {
+    /// grammar.js, external scanner, and highlight queries for tree-sitter
+    TreeSitter,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the hooks on a text document and write the output (default if no subcommand is given)
+    Build {
+        #[command(flatten)]
+        args: BuildArgs,
+        source: path::PathBuf,
+    },
+    /// Load the hooks, lex and parse a text document, and report errors without writing any output
+    Check {
+        #[arg(long, help = "if set, checks the document again whenever it or a file of the hooks directory changes")]
+        watch: bool,
+        #[arg(long, value_name = "ADDR", requires = "watch", help = "serve the diagnostics of the latest check as JSON over HTTP at ADDR like ':8090' (requires --watch)")]
+        serve: Option<String>,
+        #[command(flatten)]
+        args: BuildArgs,
+        source: path::PathBuf,
+    },
+    /// Print an intermediate result of the pipeline for debugging
+    Dump {
+        #[arg(value_enum)]
+        stage: DumpStage,
+        #[arg(long, help = "if set, 'dump lexed' also prints every state transition of the lexer (byte offset, character, old and new state, emitted tokens)")]
+        trace: bool,
+        #[command(flatten)]
+        args: BuildArgs,
+        source: path::PathBuf,
+    },
+    /// Format litua text documents in place
+    Fmt {
+        #[arg(long, help = "if set, does not modify the files, but prints a unified diff and fails if some file is not formatted")]
+        check: bool,
+        #[arg(long, conflicts_with = "sources", help = "if set, formats the document read from stdin and writes it to stdout")]
+        stdin: bool,
+        #[arg(required_unless_present = "stdin")]
+        sources: Vec<path::PathBuf>,
+    },
+    /// Report questionable constructs in litua text documents
+    Lint {
+        #[arg(long, value_name = "DIR", help = "directory with lint rules 'lint_*.lua' (default: same as source file)")]
+        rules_dir: Option<path::PathBuf>,
+        #[arg(required = true)]
+        sources: Vec<path::PathBuf>,
+    },
+    /// Convert a document between litua and other formats without running any hooks
+    Convert {
+        #[arg(long, value_enum, default_value = "litua", help = "format of the input")]
+        from: ConvertSource,
+        #[arg(long, value_enum, help = "format of the output")]
+        to: ConvertFormat,
+        #[arg(short = 'o', long, value_name = "PATH", help = "file to write the output to (default: stdout)")]
+        destination: Option<path::PathBuf>,
+        #[arg(long, value_name = "PATH", help = "JSON file with an object mapping call names to DocBook elements like 'emphasis role=\"strong\"' (extends the default mapping, --to docbook only)")]
+        docbook_map: Option<path::PathBuf>,
+        source: path::PathBuf,
+    },
+    /// Browse the tree of a text document in the terminal and preview the transform result of subtrees
+    Explore {
+        #[command(flatten)]
+        args: BuildArgs,
+        source: path::PathBuf,
+    },
+    /// Evaluate Lua statements interactively with the litua libraries and hooks loaded
+    Repl {
+        #[command(flatten)]
+        args: BuildArgs,
+    },
+    /// Run a language server on stdin and stdout offering the calls handled by the hooks as completions and hover texts
+    Lsp {
+        #[command(flatten)]
+        args: BuildArgs,
+    },
+    /// Generate a grammar of the litua input syntax for other tools
+    Grammar {
+        #[arg(long, value_enum, help = "format of the grammar to generate")]
+        format: GrammarFormat,
+        #[arg(short = 'o', long, value_name = "DIR", default_value = ".", help = "directory to write the grammar files to")]
+        destination: path::PathBuf,
+        #[command(flatten)]
+        lexer: LexerArgs,
+    },
+    /// Run every *.lit file in a directory and compare its output with the sibling *.expected file
+    Test {
+        #[arg(long, help = "if set, updates the *.expected files with the actual output")]
+        bless: bool,
+        #[arg(value_name = "DIR")]
+        directory: path::PathBuf,
+    },
+    /// Lex and parse every *.lit file in a corpus directory and compare the results with its snapshot files (*.tokens, *.tokens.json, *.tree, *.tree.json)
+    Selftest {
+        #[arg(long, help = "if set, updates the snapshot files with the actual results (creating *.tokens and *.tree files for inputs without snapshot)")]
+        bless: bool,
+        #[arg(value_name = "DIR")]
+        directory: path::PathBuf,
+    },
+    /// Highlight a litua text document using the litua lexer
+    Highlight {
+        #[arg(long, value_enum, default_value = "ansi", help = "format of the highlighted output")]
+        format: HighlightFormat,
+        #[arg(short = 'o', long, value_name = "PATH", help = "file to write the highlighted output to (default: stdout)")]
+        destination: Option<path::PathBuf>,
+        #[command(flatten)]
+        lexer: LexerArgs,
+        source: path::PathBuf,
+    },
+    /// Generate a litua text document describing the calls documented in hook files
+    Doc {
+        #[arg(long, default_value = "Call reference", help = "title of the generated document")]
+        title: String,
+        #[arg(short = 'o', long, value_name = "PATH", help = "file to write the document to (default: stdout)")]
+        destination: Option<path::PathBuf>,
+        #[arg(help = "directory (or zip or tar archive) with hook files")]
+        hooks_dir: path::PathBuf,
+    },
+    /// Stream one JSON object per lexer token and per parsed top-level element of a litua text document
+    Events {
+        source: path::PathBuf,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "litua")]
+#[command(author = "tajpulo <admin@lukas-prokop.at>")]
+#[command(version = "2.0.0")]
+#[command(about = "Read document as tree and apply Lua functions to nodes")]
+#[command(author, version, about, long_about = None)]
+#[command(subcommand_negates_reqs = true, override_usage = "litua [OPTIONS] <SOURCE>\n       litua <COMMAND> [OPTIONS] …")]
+struct CLISettings {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // helpful for debugging (deprecated, use the subcommand dump)
+    #[arg(long, help = "if set, only prints the runtime configuration and exits")]
+    dump_config: bool,
+    #[arg(long, help = "if set, only loads the hook files, prints the registered hooks in the order they run and exits")]
+    list_hooks: bool,
+    #[arg(long, help = "if set, only lexes the source file, prints its tokens and exits")]
+    dump_lexed: bool,
+    #[arg(long, requires = "dump_lexed", help = "if set, --dump-lexed also prints every state transition of the lexer (byte offset, character, old and new state, emitted tokens)")]
+    trace: bool,
+    #[arg(long, help = "if set, only parses the source file, prints the resulting tree and exits")]
+    dump_parsed: bool,
+    #[arg(long, help = "if set, runs the transformation hooks, prints the result before post-processing and exits")]
+    dump_transformed: bool,
+
+    #[command(flatten)]
+    build: BuildArgs,
+
+    // options of all subcommands
+    #[arg(long, global = true, value_name = "LANG", help = "language of diagnostic messages, 'en' or 'de' (default: environment variable LITUA_LANG or English)")]
+    lang: Option<String>,
+    #[arg(long, global = true, value_name = "PATH", help = "if set, additionally writes all log lines and diagnostics with timestamps to this file")]
+    log_file: Option<path::PathBuf>,
+    #[arg(long, global = true, value_enum, default_value = "text", help = "format of log lines and diagnostics on stderr")]
+    log_format: LogFormat,
+    #[arg(long, global = true, value_name = "TEMPLATE", help = "prefix of log lines where '{component}' is replaced by the logging component (default: 'LOG[{component}]:' followed by a tab)")]
+    log_prefix: Option<String>,
+    #[arg(long, global = true, help = "log the begin and end (with duration) of every pipeline stage")]
+    stage_markers: bool,
+    #[arg(long, global = true, value_name = "N", default_value_t = DEFAULT_ERROR_CONTEXT, help = "number of lines shown before and after the position of an error in the text document")]
+    error_context: usize,
+
+    // positional argument
+    #[arg(required = true)]
+    source: Option<path::PathBuf>,
+}
+
+/// Options configuring the pipeline (shared by `litua build`, `check`, `dump`, and `repl`)
+#[derive(Args, Clone, Debug)]
+struct BuildArgs {
+    // configuration
+    #[arg(long, value_name = "DIR", help = "filepath to directory (or zip or tar archive) with hook files (default: same as source file), searched before LITUA_HOOKS_PATH and ~/.config/litua/hooks")]
+    hooks_dir: Option<path::PathBuf>,
+    #[arg(long, value_name = "ARCHIVE", help = "zip or tar archive with hook files and modules for require(…) calls, searched after --hooks-dir (can be repeated)")]
+    hook_package: Vec<path::PathBuf>,
+    #[arg(long, value_name = "DIR", help = "directories to add as search location for require(…) calls")]
+    add_require_path: Vec<path::PathBuf>,
+    #[arg(long, help = "if set, prints the searched hook locations and the hook files in the order they are loaded (or why they were skipped) to stderr")]
+    dump_hooks: bool,
+
+    #[arg(long, conflicts_with = "chunked", help = "if set, calls {include chapter.lit} are replaced recursively by the content of the included documents")]
+    project: bool,
+    #[arg(long, help = "if set, the source file may be an HTTP(S) URL and Litua.read_include and Litua.fetch may read files over the network")]
+    allow_net: bool,
+    #[arg(long, help = "if set, hooks may run external commands with Litua.exec")]
+    allow_exec: bool,
+
+    // optional argument
+    #[arg(long, help = "if set, runs the pipeline without writing the destination file, prints a unified diff of its content and the new output and fails if they differ")]
+    diff: bool,
+    #[arg(short = 'o', long, value_name = "PATH")]
+    destination: Option<path::PathBuf>,
+    #[arg(long, value_name = "DIR", conflicts_with = "destination", help = "directory to write the output to (created if missing, subdirectories of a source directory are mirrored)")]
+    destination_dir: Option<path::PathBuf>,
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "destination", help = "file name of the output with placeholders {stem}, {ext}, and {name} of the source file like '{stem}.html' (default: '{stem}.out' with --destination-dir)")]
+    name_template: Option<String>,
+    #[arg(long, help = "if set, keeps the previous content of the destination file as '<destination>.bak'")]
+    backup: bool,
+    #[arg(long, value_name = "PATH", help = "write a JSON source map relating byte ranges of the output to positions in the source file")]
+    sourcemap: Option<path::PathBuf>,
+    #[arg(long, help = "if set, fails unless the output is valid UTF-8 (by default, the output is written as raw bytes)")]
+    validate_utf8: bool,
+    #[arg(long, value_enum, default_value = "error", help = "how invalid UTF-8 sequences of the source file are handled (with a warning listing their byte offsets unless 'error')")]
+    invalid_utf8: InvalidUtf8Policy,
+    #[arg(long, value_enum, default_value = "keep", help = "line terminators of the written output")]
+    newlines: NewlinesPolicy,
+    #[arg(long, help = "if set, the output is byte-identical across runs and platforms: line terminators of the source and the output become '\\n' (unless --newlines crlf), the build timestamp defaults to 0 if SOURCE_DATE_EPOCH is unset, os.time() and os.date() use the build timestamp, and math.random is seeded with 0")]
+    reproducible: bool,
+    #[arg(long, help = "if set, call names and argument keys are not normalized to Unicode NFC")]
+    no_normalize_names: bool,
+    #[arg(long, help = "if set, consecutive text elements are not merged into one text element")]
+    no_merge_texts: bool,
+    #[arg(long, value_enum, default_value = "keep", help = "how text elements consisting only of whitespace in the content of calls are handled")]
+    whitespace_texts: WhitespaceTextsPolicy,
+    #[arg(long, value_name = "CALL", help = "keep whitespace-only text elements within calls of this name (can be repeated)")]
+    preserve_whitespace: Vec<String>,
+    #[arg(long, value_name = "CALL", help = "keep all whitespace within calls of this name exactly as in the source code, even with --whitespace-texts, --lossless, or --trim-raw-whitespace (can be repeated)")]
+    verbatim: Vec<String>,
+    #[arg(long, value_name = "NAME", help = "run the built-in token filter NAME between lexer and parser (can be repeated): strip-comments removes calls named 'comment'")]
+    token_filter: Vec<String>,
+    #[arg(long, help = "if set, the whitespace before the end of every call is stored as argument '=whitespace-after' instead of as part of the content")]
+    lossless: bool,
+    #[arg(long, value_name = "NAME", default_value = "document", help = "call name of the root node wrapping the document")]
+    root_call: String,
+    #[arg(short = 'D', long, value_name = "KEY=VALUE", help = "add argument KEY with text VALUE (or 'true' if only KEY is given) to the root node (can be repeated)")]
+    define: Vec<String>,
+    #[arg(long, help = "if set, calls {if[flag=KEY] …} and {ifnot[flag=KEY] …} are passed to the hooks instead of being selected by the root arguments")]
+    no_conditionals: bool,
+    #[command(flatten)]
+    lexer: LexerArgs,
+    #[arg(long, help = "if set, starts the 'mobdebug' remote debugger if available or pauses in an interactive prompt on hook errors")]
+    lua_debug: bool,
+    #[arg(long, help = "if set, logs every hook invocation to stderr")]
+    trace_hooks: bool,
+    #[arg(long, value_name = "PATH", help = "if set, writes every hook invocation as JSON object per line to this file")]
+    trace_hooks_ndjson: Option<path::PathBuf>,
+    #[arg(long, value_name = "PATH", conflicts_with = "replay", help = "if set, writes the registered hooks and every hook invocation with its inputs and outputs as JSON to this file")]
+    record: Option<path::PathBuf>,
+    #[arg(long, value_name = "PATH", help = "if set, does not load any hook file, but replays the hook invocations recorded by --record in this file")]
+    replay: Option<path::PathBuf>,
+    #[arg(long, help = "if set, prints cumulative time and number of invocations per hook and per call name to stderr")]
+    profile: bool,
+    #[arg(long, value_name = "PATH", help = "if set, writes cumulative time and number of invocations per hook and per call name as JSON to this file")]
+    profile_json: Option<path::PathBuf>,
+    #[arg(long, help = "if set, prints source size, token and node count, estimated tree memory, Lua memory after transform, and output size to stderr")]
+    stats: bool,
+    #[arg(long, conflicts_with_all = ["diff", "sourcemap"], help = "if set, lexes, parses, transforms, and writes one top-level call at a time to bound the memory (hooks only see the current chunk, Litua.global_state persists across chunks)")]
+    chunked: bool,
+    #[arg(long, value_name = "PATH", requires = "chunked", conflicts_with_all = ["record", "replay"], help = "if set, caches the output of every chunk in the file PATH and reuses the output of chunks unchanged since the previous run (requires --chunked)")]
+    chunk_cache: Option<path::PathBuf>,
+    #[arg(long, value_name = "NODE", conflicts_with_all = ["chunked", "diff"], help = "if set, prints how the call at NODE (a path like '/document/p[2]', '#id', or a call name) is transformed by the hooks instead of writing the output")]
+    explain: Option<String>,
+    #[arg(long, conflicts_with_all = ["chunked", "diff", "explain"], help = "if set, runs the hooks without writing outputs, continues with the next document after errors, and finally prints the calls without convert_node_to_string hook by frequency")]
+    report_unhandled_calls: bool,
+    #[arg(long, value_name = "PATH", help = "write a JSON manifest listing the inputs, outputs, hook files with their content hashes, and diagnostics of every text document to PATH (errors do not stop a batch then)")]
+    manifest: Option<path::PathBuf>,
+    #[arg(long, value_name = "PATH", help = "write the files the output depends on (source, included documents, hook files, required Lua modules, and files read by hooks) as Makefile rule to PATH")]
+    depfile: Option<path::PathBuf>,
+}
+
+impl Default for BuildArgs {
+    /// The options as if none was given on the command line
+    fn default() -> Self {
+        let command = BuildArgs::augment_args(clap::Command::new("litua"));
+        BuildArgs::from_arg_matches(&command.get_matches_from(["litua"])).expect("default options are valid")
+    }
+}
+
+/// Options configuring the syntax accepted by the lexer (shared by the
+/// pipeline options, `litua grammar`, and `litua highlight`)
+#[derive(Args, Clone, Debug)]
+struct LexerArgs {
+    #[arg(long, help = "if set, call names may contain any character except whitespace and delimiters (for legacy documents)")]
+    permissive_call_names: bool,
+    #[arg(long, help = "if set, all whitespace before the terminating '>' sequence of a raw string is excluded from its text")]
+    trim_raw_whitespace: bool,
+    #[arg(long, help = "if set, '@name[key=value] text' at the start of a line is a call whose content ends at the end of the line")]
+    line_calls: bool,
+    #[arg(long, value_name = "CHARS", help = "characters opening and closing raw strings instead of '<' and '>' (one character for both or two characters like '(' and ')')")]
+    raw_delimiters: Option<String>,
+}
+
+impl LexerArgs {
+    /// Returns the lexer options selected by these arguments
+    fn options(&self) -> Result<litua::lexer::LexerOptions, Error> {
+        let (open_raw, close_raw) = match &self.raw_delimiters {
+            None => (litua::lexer::OPEN_RAW, litua::lexer::CLOSE_RAW),
+            Some(chars) => litua::header::parse_raw_delimiters(chars).map_err(|msg| Error::CLIArg(format!("--raw-delimiters {msg}")))?,
+        };
+        let options = litua::lexer::LexerOptions {
+            call_names: if self.permissive_call_names {
+                litua::lexer::CallNamePolicy::Permissive
+            } else {
+                litua::lexer::CallNamePolicy::Identifier
+            },
+            trim_raw_whitespace: self.trim_raw_whitespace,
+            open_raw,
+            close_raw,
+            skip_header: true,
+            line_calls: self.line_calls,
+        };
+        options.validate().map_err(Error::CLIArg)?;
+        Ok(options)
+    }
+}
+
+/// `Operation` is what `run` does with a text document
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operation {
+    /// transform the text document and write the output
+    Run,
+    /// transform the text document and compare the output with the destination file
+    Diff,
+    /// stop after parsing the text document
+    Check,
+    DumpConfig,
+    DumpLexed,
+    DumpParsed,
+    DumpTransformed,
+    ListHooks,
+    ListCalls,
+    Explore,
+    Repl,
+    Lsp,
+}
+
+#[derive(Clone, Debug)]
+struct Settings {
+    hooks_dir: path::PathBuf,
+    hook_packages: Vec<path::PathBuf>,
+    /// whether hooks of `LITUA_HOOKS_PATH` and the per-user hooks directory are loaded
+    shared_hooks: bool,
+    dump_hooks: bool,
+    lua_path_additions: Vec<path::PathBuf>,
+    source: path::PathBuf,
+    destination: path::PathBuf,
+    output_names: OutputNames,
+    backup: bool,
+    sourcemap: Option<path::PathBuf>,
+    invalid_utf8: litua::text::InvalidUtf8,
+    validate_utf8: bool,
+    newlines: litua::text::Newlines,
+    reproducible: bool,
+    normalize_names: bool,
+    lossless: bool,
+    merge_texts: bool,
+    whitespace_texts: litua::parser::WhitespaceTexts,
+    preserve_whitespace: Vec<String>,
+    verbatim: Vec<String>,
+    token_filters: Vec<sync::Arc<dyn litua::token_filter::TokenFilter>>,
+    root_call: String,
+    root_args: Vec<(String, String)>,
+    lexer_options: litua::lexer::LexerOptions,
+    trace_lexer: bool,
+    trace_hooks: Option<ReportTarget>,
+    record: Option<path::PathBuf>,
+    replay: Option<path::PathBuf>,
+    profile: Option<ReportTarget>,
+    stats: bool,
+    chunked: bool,
+    chunk_cache: Option<path::PathBuf>,
+    explain: Option<String>,
+    unhandled_calls: Option<rc::Rc<cell::RefCell<litua::coverage::UnhandledCalls>>>,
+    manifest: Option<rc::Rc<cell::RefCell<litua::manifest::Manifest>>>,
+    depfile: Option<(path::PathBuf, rc::Rc<cell::RefCell<litua::depfile::Depfile>>)>,
+    project: bool,
+    conditionals: bool,
+    lua_debug: bool,
+    allow_net: bool,
+    allow_exec: bool,
+    error_context: usize,
+    op: Operation,
+}
+
+/// Determine the language of diagnostic messages from `--lang` or
+/// the environment variable `LITUA_LANG` (ignored if unavailable)
+fn diagnostics_language(lang: Option<&str>) -> Result<Lang, Error> {
+    match lang {
+        Some(tag) => Lang::from_tag(tag).ok_or_else(|| Error::CLIArg(format!("--lang expects one of {}, got '{tag}'", Lang::TAGS.join(", ")))),
+        None => Ok(std::env::var(litua::messages::LANG_ENV_VAR).ok().and_then(|tag| Lang::from_tag(&tag)).unwrap_or_default()),
+    }
+}
+
+/// Create the log file at `filepath` (if any) receiving a copy of all log lines and diagnostics
+fn open_log_file(filepath: Option<&path::Path>) -> Result<(), Error> {
+    if let Some(filepath) = filepath {
+        let file = fs::File::create(filepath)?;
+        if let Ok(mut log_file) = LOG_FILE.lock() {
+            *log_file = Some(file);
+        }
+    }
+    Ok(())
+}
+
+fn main() -> process::ExitCode {
+    // CLI argument parsing
+    let matches = CLISettings::command().get_matches();
+    // NOTE: options of 'litua build' given before a subcommand would be ignored
+    if let Some((name, _)) = matches.subcommand() {
+        let given = matches.ids().find(|id| {
+            !GLOBAL_OPTIONS.contains(&id.as_str()) && matches.value_source(id.as_str()) == Some(clap::parser::ValueSource::CommandLine)
+        });
+        if let Some(id) = given {
+            let mut cmd = CLISettings::command();
+            let arg = match cmd.get_arguments().find(|arg| arg.get_id() == id).and_then(|arg| arg.get_long()) {
+                Some(long) => format!("--{long}"),
+                None => id.to_string().to_uppercase(),
+            };
+            let msg = format!("the argument '{arg}' must be given after the subcommand '{name}'");
+            cmd.error(clap::error::ErrorKind::ArgumentConflict, msg).exit();
+        }
+    }
+    let settings = match CLISettings::from_arg_matches(&matches) {
+        Ok(settings) => settings,
+        Err(e) => e.exit(),
+    };
+    let json_events = settings.log_format == LogFormat::Json;
+    JSON_EVENTS.store(json_events, sync::atomic::Ordering::Relaxed);
+    STAGE_MARKERS.store(settings.stage_markers, sync::atomic::Ordering::Relaxed);
+    if let Some(prefix) = &settings.log_prefix {
+        let _ = LOG_PREFIX.set(prefix.to_owned());
+    }
+    let started = time::Instant::now();
+    let source = settings.source.as_ref().map(|p| p.display().to_string());
+
+    let (lang, result) = match (diagnostics_language(settings.lang.as_deref()), open_log_file(settings.log_file.as_deref())) {
+        (Ok(lang), Ok(())) => (lang, run_cli(settings, lang)),
+        (Ok(lang), Err(e)) => (lang, Err(e)),
+        (Err(e), _) => (Lang::default(), Err(e)),
+    };
+    if let Err(e) = &result {
+        if json_events {
+            emit_event(serde_json::json!({
+                "event": "diagnostic",
+                "severity": "error",
+                "code": e.code(),
+                "file": source,
+                "message": e.localized(lang),
+            }));
+        } else {
+            report(&e.localized(lang));
+        }
+    }
+    if json_events {
+        emit_event(serde_json::json!({
+            "event": "finished",
+            "success": result.is_ok(),
+            "file": source,
+            "duration_ms": started.elapsed().as_secs_f64() * 1000.0,
+        }));
+    }
+
+    match result {
+        Ok(()) => process::ExitCode::SUCCESS,
+        Err(e) => process::ExitCode::from(e.exit_code()),
+    }
+}
+
+fn run_cli(settings: CLISettings, lang: Lang) -> Result<(), Error> {
+    let error_context = settings.error_context;
+    let (args, source, op, trace_lexer) = match settings.command {
+        Some(Command::Grammar { format, destination, lexer }) => return run_grammar(&format, &destination, &lexer.options()?),
+        Some(Command::Test { bless, directory }) => return run_tests(&directory, bless),
+        Some(Command::Selftest { bless, directory }) => return run_selftest(&directory, bless),
+        Some(Command::Highlight { format, destination, lexer, source }) => return run_highlight(&source, &format, destination.as_deref(), &lexer.options()?),
+        Some(Command::Doc { title, destination, hooks_dir }) => return run_doc(&hooks_dir, &title, destination.as_deref()),
+        Some(Command::Fmt { check, stdin, sources }) => return run_fmt(&sources, stdin, check, error_context),
+        Some(Command::Lint { rules_dir, sources }) => return run_lint(&sources, rules_dir.as_deref(), lang, error_context),
+        Some(Command::Events { source }) => return run_events(&source, error_context),
+        Some(Command::Convert { from, to, destination, docbook_map, source }) => return run_convert(&source, from, to, destination.as_deref(), docbook_map.as_deref(), error_context),
+        Some(Command::Build { args, source }) => (args, source, None, false),
+        Some(Command::Check { watch: true, serve, args, source }) => {
+            let mut conf = build_settings(args, &source, None, error_context)?;
+            conf.op = Operation::Check;
+            return run_check_watch(&conf, serve.as_deref(), lang);
+        },
+        Some(Command::Check { args, source, .. }) => (args, source, Some(Operation::Check), false),
+        Some(Command::Dump { stage, trace, args, source }) => {
+            if trace && stage != DumpStage::Lexed {
+                return Err(Error::CLIArg("--trace is only supported by 'litua dump lexed'".to_owned()));
+            }
+            let op = match stage {
+                DumpStage::Config => Operation::DumpConfig,
+                DumpStage::Hooks => Operation::ListHooks,
+                DumpStage::Calls => Operation::ListCalls,
+                DumpStage::Lexed => Operation::DumpLexed,
+                DumpStage::Parsed => Operation::DumpParsed,
+                DumpStage::Transformed => Operation::DumpTransformed,
+            };
+            (args, source, Some(op), trace)
+        },
+        Some(Command::Explore { args, source }) => (args, source, Some(Operation::Explore), false),
+        Some(Command::Repl { args }) => (args, path::PathBuf::new(), Some(Operation::Repl), false),
+        Some(Command::Lsp { args }) => (args, path::PathBuf::new(), Some(Operation::Lsp), false),
+        None => {
+            // NOTE: invoking litua without subcommand is an alias of 'litua build'
+            let source = match settings.source {
+                Some(src) => src,
+                None => return Err(Error::CLIArg("a source file is required".to_owned())),
+            };
+            let op = if settings.dump_config {
+                Some(Operation::DumpConfig)
+            } else if settings.list_hooks {
+                Some(Operation::ListHooks)
+            } else if settings.dump_lexed {
+                Some(Operation::DumpLexed)
+            } else if settings.dump_parsed {
+                Some(Operation::DumpParsed)
+            } else if settings.dump_transformed {
+                Some(Operation::DumpTransformed)
+            } else {
+                None
+            };
+            (settings.build, source, op, settings.trace)
+        },
+    };
+
+    if source.is_dir() && matches!(op, None | Some(Operation::Check)) {
+        return run_directory(args, &source, op, lang, error_context);
+    }
+
+    let args_manifest = args.manifest.clone();
+    let mut conf = build_settings(args, &source, None, error_context)?;
+    if let Some(op) = op {
+        conf.op = op;
+    }
+    conf.trace_lexer = trace_lexer;
+
+    // run main routine
+    if conf.op == Operation::DumpConfig {
+        println!("{:?}", &conf);
+        return Ok(());
+    }
+
+    let result = match &conf.unhandled_calls {
+        Some(unhandled_calls) => {
+            if let Err(e) = run(&conf) {
+                report(&format!("WARNING: {e}"));
+            }
+            print!("{}", unhandled_calls.borrow().to_table());
+            Ok(())
+        },
+        None => run(&conf),
+    };
+    if let (Some(manifest), Some(filepath)) = (&conf.manifest, &args_manifest) {
+        if let Err(e) = &result {
+            record_error(&conf, e, lang);
+        }
+        write_manifest(&manifest.borrow(), filepath)?;
+    }
+    result
+}
+
+/// Record `f` applied to the manifest entry of the text document of `conf` (if `--manifest` is given)
+fn record_in_manifest<F: FnOnce(&mut litua::manifest::Document)>(conf: &Settings, f: F) {
+    if let Some(manifest) = &conf.manifest {
+        f(manifest.borrow_mut().document(&conf.source.display().to_string()));
+    }
+}
+
+/// Record the error `e` in the manifest entry of the text document of `conf`
+fn record_error(conf: &Settings, e: &Error, lang: Lang) {
+    record_in_manifest(conf, |document| document.diagnostics.push(litua::manifest::Diagnostic {
+        severity: "error".to_owned(),
+        code: e.code().map(str::to_owned),
+        message: e.localized(lang),
+    }));
+}
+
+/// Record the file `filepath` with content `content` read for the text document of `conf`
+/// as input in the manifest and as dependency in the depfile
+fn record_input(conf: &Settings, filepath: &path::Path, content: &[u8]) {
+    record_in_manifest(conf, |document| document.add_input(&filepath.display().to_string(), content));
+    record_dependency(conf, filepath);
+}
+
+/// Record the file `filepath` as dependency of the output of `conf` (if `--depfile` is given).
+/// Files within hook packages are represented by the archive, anything else which is
+/// not a file (like URLs) is skipped.
+fn record_dependency(conf: &Settings, filepath: &path::Path) {
+    if let Some((_, depfile)) = &conf.depfile {
+        if let Some(dependency) = filepath.ancestors().find(|p| p.is_file()) {
+            depfile.borrow_mut().add(&dependency.display().to_string());
+        }
+    }
+}
+
+/// Write the depfile of the output of `conf` (if `--depfile` is given)
+fn write_depfile(conf: &Settings) -> Result<(), Error> {
+    if let Some((filepath, depfile)) = &conf.depfile {
+        let depfile = depfile.borrow();
+        write_atomically(filepath, depfile.to_makefile(&conf.destination.display().to_string()).as_bytes(), false)?;
+        log!("Depfile '{}' with {} dependencies written.", filepath.display(), depfile.dependencies().len());
+    }
+    Ok(())
+}
+
+/// Write `manifest` as JSON to `filepath`
+fn write_manifest(manifest: &litua::manifest::Manifest, filepath: &path::Path) -> Result<(), Error> {
+    write_atomically(filepath, format!("{:#}\n", manifest.to_json()).as_bytes(), false)?;
+    log!("Manifest '{}' with {} documents written.", filepath.display(), manifest.documents().len());
+    Ok(())
+}
+
+/// Build (or check if `op` is `Operation::Check`) every text document in directory `dir` and its
+/// subdirectories and report links between them to undeclared targets and targets
+/// declared more than once (see `litua::links`)
+fn run_directory(args: BuildArgs, dir: &path::Path, op: Option<Operation>, lang: Lang, error_context: usize) -> Result<(), Error> {
+    if args.destination.is_some() {
+        return Err(Error::CLIArg("--destination cannot be used if the source is a directory, use --destination-dir instead".to_owned()));
+    }
+    if args.depfile.is_some() {
+        return Err(Error::CLIArg("--depfile cannot be used if the source is a directory, use --manifest instead".to_owned()));
+    }
+
+    let unhandled_calls = args.report_unhandled_calls.then(|| rc::Rc::new(cell::RefCell::new(litua::coverage::UnhandledCalls::new())));
+    let manifest = args.manifest.as_ref().map(|_| rc::Rc::new(cell::RefCell::new(litua::manifest::Manifest::new())));
+    let mut failed = 0;
+    let mut links = litua::links::LinkChecker::new();
+    let mut documents = collections::HashMap::new();
+    for source in find_documents(dir)? {
+        let mut conf = build_settings(args.clone(), &source, Some(dir), error_context)?;
+        if let Some(op) = op {
+            conf.op = op;
+        }
+        conf.manifest = manifest.clone();
+        if let Some(unhandled_calls) = &unhandled_calls {
+            // NOTE: all documents share one report and errors do not stop the batch
+            conf.unhandled_calls = Some(unhandled_calls.clone());
+            if let Err(e) = run(&conf) {
+                record_error(&conf, &e, lang);
+                report(&format!("WARNING: {e}"));
+            }
+            continue;
+        }
+        if let Err(e) = run(&conf) {
+            if manifest.is_none() {
+                return Err(e);
+            }
+            // NOTE: with a manifest, errors are recorded and do not stop the batch
+            record_error(&conf, &e, lang);
+            report(&e.localized(lang));
+            failed += 1;
+            continue;
+        }
+
+        // NOTE: links are checked in the tree built like the tree given to the hooks
+        let buf = fs::read(&source)?;
+        let metadata = litua::file_metadata::FileMetadata::of_file(&source, &buf);
+        let (doc_src, _) = litua::text::decode_utf8(&buf, conf.invalid_utf8)?;
+        let directives = litua::header::directives(&doc_src)
+            .map_err(|e| document_error(e, &source, &doc_src, error_context))?;
+        let conf = apply_directives(&conf, &directives)
+            .map_err(|e| document_error(e, &source, &doc_src, error_context))?;
+        let mut tree = document_pipeline(&conf, Some(&metadata))
+            .parse(&source, &doc_src)
+            .map_err(|e| document_error(e, &source, &doc_src, error_context))?;
+        if conf.conditionals {
+            litua::conditional::evaluate(&mut tree)
+                .map_err(|e| document_error(e, &source, &doc_src, error_context))?;
+        }
+        links.add_document(&source, &tree);
+        documents.insert(litua::include::normalize(&source), (source, doc_src));
+    }
+
+    if let Some(unhandled_calls) = unhandled_calls {
+        if let (Some(manifest), Some(filepath)) = (&manifest, &args.manifest) {
+            write_manifest(&manifest.borrow(), filepath)?;
+        }
+        print!("{}", unhandled_calls.borrow().to_table());
+        return Ok(());
+    }
+
+    let diagnostics = links.diagnostics(|filepath| filepath.exists());
+    for (file, diagnostic) in diagnostics.iter() {
+        if let Some((source, doc_src)) = documents.get(file) {
+            report_diagnostic(diagnostic, source, doc_src, &litua::text::LineIndex::new(doc_src), lang, error_context);
+            if let Some(manifest) = &manifest {
+                manifest.borrow_mut().document(&source.display().to_string()).diagnostics.push(litua::manifest::Diagnostic {
+                    severity: "warning".to_owned(),
+                    code: Some(diagnostic.message.code.to_owned()),
+                    message: diagnostic.message.render(lang),
+                });
+            }
+        }
+    }
+    log!("{} documents processed and their links checked", documents.len());
+    if let (Some(manifest), Some(filepath)) = (&manifest, &args.manifest) {
+        write_manifest(&manifest.borrow(), filepath)?;
+    }
+    if failed > 0 {
+        return Err(Error::DocumentsFailed(failed));
+    }
+    if !diagnostics.is_empty() {
+        return Err(Error::BrokenLinks(diagnostics.len()));
+    }
+    Ok(())
+}
+
+/// Determine the execution configuration of the pipeline for the text document `source`
+/// If `source` belongs to directory `base_dir` (see `run_directory`), its subdirectory
+/// is mirrored within `--destination-dir`.
+fn build_settings(args: BuildArgs, source: &path::Path, base_dir: Option<&path::Path>, error_context: usize) -> Result<Settings, Error> {
+    // NOTE: for a URL, the destination is derived from its last path segment within the working directory
+    let source_url = source.to_str().filter(|s| net::is_url(s));
+    let source_name = match source_url {
+        Some(url) => path::Path::new(url.rsplit('/').next().unwrap_or_default()),
+        None => source,
+    };
+    let output_dir = match (&args.destination_dir, source_url) {
+        (Some(dir), _) => {
+            let subdir = base_dir.and_then(|base| source.parent()?.strip_prefix(base).ok()).unwrap_or(path::Path::new(""));
+            dir.join(subdir)
+        },
+        (None, Some(_)) => path::PathBuf::new(),
+        (None, None) => source.parent().map(path::Path::to_owned).unwrap_or_default(),
+    };
+    let template = args.name_template.as_deref().unwrap_or(DEFAULT_NAME_TEMPLATE);
+    let output_names = OutputNames::new(source_name, output_dir, template, args.destination_dir.is_some());
+    let dst = match (&args.destination, args.destination_dir.is_some() || args.name_template.is_some()) {
+        (Some(p), _) => p.to_owned(),
+        (None, true) => output_names.filepath(|_| None).map_err(|msg| Error::CLIArg(format!("--name-template {msg}")))?,
+        (None, false) => derive_destination_filepath(source_name),
+    };
+
+    // if you specified some hook directory, use it.
+    // if not, use the folder the source file lies within
+    let default_hooks_dir = path::PathBuf::from(".");
+    let hooks_dir = match &args.hooks_dir {
+        Some(d) if path_is_empty(d) => default_hooks_dir.as_path(),
+        Some(d) => d.as_path(),
+        None if source_url.is_some() => default_hooks_dir.as_path(),
+        None => match source.parent() {
+            Some(p) if path_is_empty(p) => default_hooks_dir.as_path(),
+            Some(p) => p,
+            None => default_hooks_dir.as_path(),
+        },
+    };
+
+    let mut lua_path_additions = vec![];
+    for dir in args.add_require_path.iter() {
+        lua_path_additions.push(dir.to_owned());
+    }
+
+    let mut root_args = vec![];
+    for define in args.define.iter() {
+        root_args.push(parse_define(define).map_err(|msg| Error::CLIArg(format!("--define {msg}")))?);
+    }
+
+    let mut token_filters = vec![];
+    for name in args.token_filter.iter() {
+        let known = litua::token_filter::BUILTIN.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(", ");
+        token_filters.push(litua::token_filter::builtin(name)
+            .ok_or_else(|| Error::CLIArg(format!("--token-filter: unknown filter '{name}' (known filters: {known})")))?);
+    }
+
+    // define execution configuration
+    let conf = Settings {
+        hooks_dir: hooks_dir.to_owned(),
+        hook_packages: args.hook_package,
+        shared_hooks: true,
+        dump_hooks: args.dump_hooks,
+        lua_path_additions,
+        source: source.to_owned(),
+        destination: dst,
+        output_names,
+        backup: args.backup,
+        sourcemap: args.sourcemap,
+        invalid_utf8: match args.invalid_utf8 {
+            InvalidUtf8Policy::Error => litua::text::InvalidUtf8::Error,
+            InvalidUtf8Policy::Replace => litua::text::InvalidUtf8::Replace,
+            InvalidUtf8Policy::Strip => litua::text::InvalidUtf8::Strip,
+        },
+        validate_utf8: args.validate_utf8,
+        newlines: match (args.newlines, args.reproducible) {
+            (NewlinesPolicy::Keep, false) => litua::text::Newlines::Keep,
+            (NewlinesPolicy::Keep, true) | (NewlinesPolicy::Lf, _) => litua::text::Newlines::Lf,
+            (NewlinesPolicy::Crlf, _) => litua::text::Newlines::CrLf,
+        },
+        reproducible: args.reproducible,
+        normalize_names: !args.no_normalize_names,
+        lossless: args.lossless,
+        merge_texts: !args.no_merge_texts,
+        whitespace_texts: match args.whitespace_texts {
+            WhitespaceTextsPolicy::Keep => litua::parser::WhitespaceTexts::Keep,
+            WhitespaceTextsPolicy::Drop => litua::parser::WhitespaceTexts::Drop,
+            WhitespaceTextsPolicy::Collapse => litua::parser::WhitespaceTexts::Collapse,
+        },
+        preserve_whitespace: args.preserve_whitespace,
+        verbatim: args.verbatim,
+        token_filters,
+        root_call: args.root_call,
+        root_args,
+        lexer_options: args.lexer.options()?,
+        trace_lexer: false,
+        trace_hooks: match args.trace_hooks_ndjson {
+            Some(filepath) => Some(ReportTarget::File(filepath)),
+            None if args.trace_hooks => Some(ReportTarget::Stderr),
+            None => None,
+        },
+        record: args.record,
+        replay: args.replay,
+        lua_debug: args.lua_debug,
+        stats: args.stats,
+        chunked: args.chunked,
+        chunk_cache: args.chunk_cache,
+        explain: args.explain,
+        unhandled_calls: args.report_unhandled_calls.then(|| rc::Rc::new(cell::RefCell::new(litua::coverage::UnhandledCalls::new()))),
+        manifest: args.manifest.as_ref().map(|_| rc::Rc::new(cell::RefCell::new(litua::manifest::Manifest::new()))),
+        depfile: args.depfile.map(|filepath| (filepath, rc::Rc::new(cell::RefCell::new(litua::depfile::Depfile::new())))),
+        project: args.project,
+        conditionals: !args.no_conditionals,
+        allow_net: args.allow_net,
+        allow_exec: args.allow_exec,
+        error_context,
+        profile: match args.profile_json {
+            Some(filepath) => Some(ReportTarget::File(filepath)),
+            None if args.profile => Some(ReportTarget::Stderr),
+            None => None,
+        },
+        op: if args.diff { Operation::Diff } else { Operation::Run },
+    };
+    Ok(conf)
 }
@@ -0,0 +1,212 @@
+//! Writer representing litua text documents as man pages (man(7) roff)
+//!
+//! The writer maps the following call vocabulary to man macros:
+//!
+//! * `manpage[name=…][section=…][date=…][source=…][manual=…]` provides the
+//!   arguments of the title line `.TH` (the name defaults to the name given to `to_man`)
+//! * `section[title=…]` becomes `.SH` (or `.SS` if nested in another section)
+//! * `option[flag=…][arg=…]` becomes a tagged paragraph `.TP` with the flag
+//!   in bold, its argument in italics, and the content as description
+//! * `example` and `pre` become verbatim examples `.EX`/`.EE`
+//! * `p` becomes `.PP`, `ul` and `ol` with `li` items become indented paragraphs `.IP`
+//! * inline: `em` or `i` (italics), `strong`, `b`, or `code` (bold), `a[href=…]`, and `br`
+//!
+//! Other calls are transparent, i.e. only their content is written.
+
+use crate::markup::{arg, code_block, collapse_whitespace};
+use crate::tree;
+
+/// Returns the tree as man page. `name` is the name of the page unless
+/// given by a `manpage` call.
+pub fn to_man(tree: &tree::DocumentTree, name: &str) -> String {
+    let node = match &tree.0 {
+        tree::DocumentElement::Function(root) => root.content.as_slice(),
+        text @ tree::DocumentElement::Text(_) => std::slice::from_ref(text),
+    };
+    let page = node.iter().find_map(|element| match element {
+        tree::DocumentElement::Function(func) if func.call == "manpage" => Some(func),
+        _ => None,
+    });
+
+    let title_arg = |key: &str, default: &str| {
+        let mut value = collapse_whitespace(&page.and_then(|page| arg(page, key)).unwrap_or_else(|| default.to_owned()));
+        if key == "name" {
+            value = value.to_uppercase();
+        }
+        format!("\"{}\"", escape(value.trim()).replace('"', "\\(dq"))
+    };
+    let mut out = format!(
+        ".TH {} {} {} {} {}\n",
+        title_arg("name", name), title_arg("section", "1"),
+        title_arg("date", ""), title_arg("source", ""), title_arg("manual", ""),
+    );
+    blocks(node, 0, false, &mut out);
+    out
+}
+
+/// Escape `text` for use in roff text lines and macro arguments
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
+
+/// Write `line` as roff text line, protecting a leading control character
+fn push_text_line(out: &mut String, line: &str) {
+    if line.starts_with(['.', '\'']) {
+        out.push_str("\\&");
+    }
+    out.push_str(line);
+    out.push('\n');
+}
+
+fn is_block(call: &str) -> bool {
+    matches!(call, "manpage" | "section" | "option" | "example" | "pre" | "p" | "ul" | "ol" | "li")
+}
+
+/// Does the transparent call `func` contain blocks?
+fn contains_blocks(func: &tree::DocumentFunction) -> bool {
+    func.content.iter().any(|element| match element {
+        tree::DocumentElement::Function(child) => is_block(&child.call) || contains_blocks(child),
+        tree::DocumentElement::Text(_) => false,
+    })
+}
+
+/// Write the elements of `node` as blocks within sections nested `depth` levels.
+/// If `indented` is set, paragraphs keep the indentation of a preceding `.TP` or `.IP`.
+fn blocks(node: &[tree::DocumentElement], depth: usize, indented: bool, out: &mut String) {
+    let mut inline = vec![];
+    for element in node {
+        match element {
+            tree::DocumentElement::Function(func) if is_block(&func.call) || (!is_inline(&func.call) && contains_blocks(func)) => {
+                paragraph(&inline, indented, out);
+                inline.clear();
+                block(func, depth, indented, out);
+            },
+            _ => inline.push(element.clone()),
+        }
+    }
+    paragraph(&inline, indented, out);
+}
+
+fn is_inline(call: &str) -> bool {
+    matches!(call, "em" | "i" | "strong" | "b" | "code" | "a" | "br")
+}
+
+/// Write the inline elements `node` as paragraph unless they are whitespace only
+fn paragraph(node: &[tree::DocumentElement], indented: bool, out: &mut String) {
+    let content = inline(node);
+    if content.trim().is_empty() {
+        return;
+    }
+    out.push_str(if indented { ".IP\n" } else { ".PP\n" });
+    text_lines(&content, out);
+}
+
+/// Write the inline representation `content` as text lines (one per `.br`)
+fn text_lines(content: &str, out: &mut String) {
+    for (i, line) in content.split('\n').enumerate() {
+        if i > 0 {
+            out.push_str(".br\n");
+        }
+        let line = line.trim();
+        if !line.is_empty() {
+            push_text_line(out, line);
+        }
+    }
+}
+
+/// Write the block call `func`
+fn block(func: &tree::DocumentFunction, depth: usize, indented: bool, out: &mut String) {
+    match func.call.as_str() {
+        "manpage" => blocks(&func.content, depth, indented, out),
+        "section" => {
+            let title = escape(&collapse_whitespace(&arg(func, "title").unwrap_or_default()).to_uppercase());
+            out.push_str(&format!("{} {}\n", if depth == 0 { ".SH" } else { ".SS" }, title.trim()));
+            blocks(&func.content, depth + 1, false, out);
+        },
+        "option" => {
+            out.push_str(".TP\n");
+            let mut tag = format!("\\fB{}\\fR", escape(arg(func, "flag").unwrap_or_default().trim()));
+            if let Some(argument) = arg(func, "arg") {
+                tag.push_str(&format!(" \\fI{}\\fR", escape(argument.trim())));
+            }
+            push_text_line(out, &tag);
+            let description = inline(&func.content);
+            if func.content.iter().any(|e| matches!(e, tree::DocumentElement::Function(child) if is_block(&child.call))) {
+                // NOTE: the first paragraph is the body of the tagged paragraph
+                let mut description = String::new();
+                blocks(&func.content, depth, true, &mut description);
+                out.push_str(description.strip_prefix(".IP\n").unwrap_or(&description));
+            } else if !description.trim().is_empty() {
+                text_lines(&description, out);
+            }
+        },
+        "example" | "pre" => {
+            let code = code_block(&func.content);
+            out.push_str(if indented { ".IP\n.EX\n" } else { ".PP\n.EX\n" });
+            for line in code.lines() {
+                push_text_line(out, &escape(line));
+            }
+            out.push_str(".EE\n");
+        },
+        "p" | "li" => paragraph(&func.content, indented, out),
+        "ul" | "ol" => {
+            let start = arg(func, "start").and_then(|start| start.trim().parse::<usize>().ok()).unwrap_or(1);
+            let items = func.content.iter().filter_map(|element| match element {
+                tree::DocumentElement::Function(item) if item.call == "li" => Some(item),
+                _ => None,
+            });
+            for (i, item) in items.enumerate() {
+                let marker = if func.call == "ul" { "\\(bu".to_owned() } else { format!("{}.", start + i) };
+                out.push_str(&format!(".IP {marker} 4\n"));
+                text_lines(&inline(&item.content), out);
+            }
+        },
+        _ => blocks(&func.content, depth, indented, out),
+    }
+}
+
+/// Represent the inline elements `node` with whitespace collapsed
+/// and line breaks (`br`) as `\n`
+fn inline(node: &[tree::DocumentElement]) -> String {
+    let mut out = String::new();
+    for element in node {
+        let func = match element {
+            tree::DocumentElement::Text(text) => {
+                out.push_str(&escape(&collapse_whitespace(text)));
+                continue;
+            },
+            tree::DocumentElement::Function(func) => func,
+        };
+        let content = inline(&func.content);
+        match func.call.as_str() {
+            "em" | "i" => out.push_str(&format!("\\fI{content}\\fP")),
+            "strong" | "b" | "code" => out.push_str(&format!("\\fB{content}\\fP")),
+            "a" => match arg(func, "href") {
+                Some(href) if content.trim().is_empty() => out.push_str(&format!("\\fI{}\\fP", escape(&href))),
+                Some(href) => out.push_str(&format!("{content} <\\fI{}\\fP>", escape(&href))),
+                None => out.push_str(&content),
+            },
+            "br" => out.push('\n'),
+            _ => out.push_str(&content),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_man_page() {
+        let src = "{manpage[name=litua][date=2023-04-01]\n\
+            {section[title=Name] litua - {em read} a document}\n\
+            {section[title=Options]\n  {option[flag=--stats] print {b sizes}}\n  {option[flag=-o][arg=PATH] destination}}\n\
+            {section[title=Examples] {example\n  litua doc.lit\n  .hidden \\n\n}}}";
+        let tree = crate::parse_str(src).expect("valid document");
+        assert_eq!(to_man(&tree, "ignored"), ".TH \"LITUA\" \"1\" \"2023\\-04\\-01\" \"\" \"\"\n\
+            .SH NAME\n.PP\nlitua \\- \\fIread\\fP a document\n\
+            .SH OPTIONS\n.TP\n\\fB\\-\\-stats\\fR\nprint \\fBsizes\\fP\n.TP\n\\fB\\-o\\fR \\fIPATH\\fR\ndestination\n\
+            .SH EXAMPLES\n.PP\n.EX\nlitua doc.lit\n\\&.hidden \\en\n.EE\n");
+    }
+}
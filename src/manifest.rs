@@ -0,0 +1,138 @@
+//! Machine-readable manifest of the text documents processed by a build
+//!
+//! With `--manifest build.json`, litua lists for every text document its
+//! inputs (the document, the documents it includes, and files read by hooks),
+//! its outputs, the hook files loaded, and the diagnostics reported. Every file
+//! comes with its content hash (see `tree::stable_hash`) as 16 hex digits, so
+//! build systems like Ninja or Bazel can track the dependencies of outputs and
+//! decide which documents to rebuild.
+
+use crate::tree;
+
+/// Version of the manifest format
+pub const FORMAT_VERSION: u64 = 1;
+
+/// `Diagnostic` is an error or warning reported for a text document
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Diagnostic {
+    /// `error` or `warning`
+    pub severity: String,
+    /// message code like `P0107` (if any)
+    pub code: Option<String>,
+    /// human-readable message
+    pub message: String,
+}
+
+/// `Document` collects the files and diagnostics of one text document
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Document {
+    /// filepath of the text document
+    pub source: String,
+    /// filepaths and content hashes of the files read (the document first)
+    pub inputs: Vec<(String, u64)>,
+    /// filepaths and content hashes of the files written
+    pub outputs: Vec<(String, u64)>,
+    /// filepaths and content hashes of the hook files loaded
+    pub hooks: Vec<(String, u64)>,
+    /// diagnostics in the order they were reported
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+    /// Record the input `filepath` with content `content`
+    pub fn add_input(&mut self, filepath: &str, content: &[u8]) {
+        self.inputs.push((filepath.to_owned(), tree::stable_hash(content)));
+    }
+
+    /// Record the output `filepath` with content `content`
+    pub fn add_output(&mut self, filepath: &str, content: &[u8]) {
+        self.outputs.retain(|(output, _)| output != filepath);
+        self.outputs.push((filepath.to_owned(), tree::stable_hash(content)));
+    }
+
+    /// Was the text document processed without errors?
+    pub fn succeeded(&self) -> bool {
+        !self.diagnostics.iter().any(|diagnostic| diagnostic.severity == "error")
+    }
+}
+
+/// `Manifest` lists the text documents processed in order of processing
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Manifest {
+    documents: Vec<Document>,
+}
+
+/// Represent (filepath, hash) pairs as JSON array of objects with keys `path` and `hash`
+fn files_to_json(files: &[(String, u64)]) -> serde_json::Value {
+    files.iter().map(|(filepath, hash)| serde_json::json!({ "path": filepath, "hash": format!("{hash:016x}") })).collect()
+}
+
+impl Manifest {
+    /// Create an empty manifest
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the entry of the text document `source` (appended if it is not listed yet)
+    pub fn document(&mut self, source: &str) -> &mut Document {
+        let index = match self.documents.iter().position(|document| document.source == source) {
+            Some(index) => index,
+            None => {
+                self.documents.push(Document { source: source.to_owned(), ..Document::default() });
+                self.documents.len() - 1
+            },
+        };
+        &mut self.documents[index]
+    }
+
+    /// Returns the text documents in order of processing
+    pub fn documents(&self) -> &[Document] {
+        &self.documents
+    }
+
+    /// Represent the manifest as JSON object with keys `version` and `documents`
+    /// (an array of objects with keys `source`, `success`, `inputs`, `outputs`,
+    /// `hooks`, and `diagnostics`)
+    pub fn to_json(&self) -> serde_json::Value {
+        let documents: Vec<serde_json::Value> = self.documents.iter().map(|document| serde_json::json!({
+            "source": document.source,
+            "success": document.succeeded(),
+            "inputs": files_to_json(&document.inputs),
+            "outputs": files_to_json(&document.outputs),
+            "hooks": files_to_json(&document.hooks),
+            "diagnostics": document.diagnostics.iter().map(|diagnostic| serde_json::json!({
+                "severity": diagnostic.severity,
+                "code": diagnostic.code,
+                "message": diagnostic.message,
+            })).collect::<Vec<_>>(),
+        })).collect();
+        serde_json::json!({ "version": FORMAT_VERSION, "documents": documents })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_documents_with_hashes() {
+        let mut manifest = Manifest::new();
+        manifest.document("a.lit").add_input("a.lit", b"{p a}");
+        manifest.document("b.lit").diagnostics.push(Diagnostic { severity: "error".to_owned(), code: Some("P0107".to_owned()), message: "unexpected end".to_owned() });
+        let a = manifest.document("a.lit");
+        a.add_output("a.out", b"<p>a</p>");
+        a.add_output("a.out", b"<p>b</p>");
+        a.hooks.push(("hooks.lua".to_owned(), 7));
+
+        let json = manifest.to_json();
+        assert_eq!(json["version"], FORMAT_VERSION);
+        assert_eq!(manifest.documents().len(), 2);
+        assert_eq!(json["documents"][0]["inputs"][0]["hash"], format!("{:016x}", tree::stable_hash(b"{p a}")));
+        assert_eq!(json["documents"][0]["outputs"].as_array().map(Vec::len), Some(1));
+        assert_eq!(json["documents"][0]["outputs"][0]["hash"], format!("{:016x}", tree::stable_hash(b"<p>b</p>")));
+        assert_eq!(json["documents"][0]["hooks"][0], serde_json::json!({ "path": "hooks.lua", "hash": "0000000000000007" }));
+        assert_eq!(json["documents"][0]["success"], true);
+        assert_eq!(json["documents"][1]["success"], false);
+        assert_eq!(json["documents"][1]["diagnostics"][0]["code"], "P0107");
+    }
+}
@@ -0,0 +1,581 @@
+//! Writers representing litua text documents as Markdown or reStructuredText
+//! and a reader of Markdown documents
+//!
+//! The writers map the following standard call vocabulary (borrowed from HTML)
+//! to the corresponding constructs and escape all texts:
+//!
+//! * blocks: `h1` to `h6`, `p`, `ul` and `ol` (with optional argument `start`)
+//!   with `li` items, `blockquote`, `pre` (with optional argument `lang`), and `hr`
+//! * inline: `em` or `i`, `strong` or `b`, `code`, `a[href=…]`, `img[src=…][alt=…]`, and `br`
+//!
+//! Other calls are transparent, i.e. only their content is written. Texts
+//! outside of blocks form paragraphs and whitespace within paragraphs is
+//! collapsed. reStructuredText does not support nested inline markup,
+//! thus the content of inline markup is written as plain text there.
+//!
+//! `from_markdown` reads CommonMark documents into trees of the same call
+//! vocabulary (requires feature `import`).
+
+use crate::text;
+use crate::tree;
+
+/// Lightweight markup languages supported by the writers
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+enum Flavor {
+    Markdown,
+    Rst,
+}
+
+/// Part of a paragraph
+#[derive(Clone,Debug,PartialEq,Eq)]
+enum Piece {
+    /// escaped text
+    Text(String),
+    /// inline markup like emphasis
+    Markup(String),
+    /// forced line break
+    Break,
+}
+
+/// Returns the tree as CommonMark document
+pub fn to_markdown(tree: &tree::DocumentTree) -> String {
+    write(tree, Flavor::Markdown)
+}
+
+/// Returns the tree as reStructuredText document
+pub fn to_rst(tree: &tree::DocumentTree) -> String {
+    write(tree, Flavor::Rst)
+}
+
+fn write(tree: &tree::DocumentTree, flavor: Flavor) -> String {
+    let blocks = match &tree.0 {
+        tree::DocumentElement::Function(root) => blocks(&root.content, flavor),
+        text @ tree::DocumentElement::Text(_) => blocks(std::slice::from_ref(text), flavor),
+    };
+    let mut out = blocks.join("\n\n");
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+fn is_block(call: &str) -> bool {
+    matches!(call, "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "p" | "ul" | "ol" | "li" | "blockquote" | "pre" | "hr")
+}
+
+fn is_inline(call: &str) -> bool {
+    matches!(call, "em" | "i" | "strong" | "b" | "code" | "a" | "img" | "br")
+}
+
+/// Does `func` (a transparent call) contain blocks?
+fn contains_blocks(func: &tree::DocumentFunction) -> bool {
+    func.content.iter().any(|element| match element {
+        tree::DocumentElement::Function(child) => is_block(&child.call) || (!is_inline(&child.call) && contains_blocks(child)),
+        tree::DocumentElement::Text(_) => false,
+    })
+}
+
+/// Returns the concatenated texts of `node` and its descendants
+pub(crate) fn plain_text(node: &[tree::DocumentElement]) -> String {
+    node.iter().map(|element| match element {
+        tree::DocumentElement::Text(text) => text.clone(),
+        tree::DocumentElement::Function(func) => plain_text(&func.content),
+    }).collect()
+}
+
+/// Returns the text of the code block `node` without trailing whitespace and
+/// without the indentation common to its lines. The whitespace separating the
+/// call name from the content is not part of the content, thus a non-empty
+/// first line does not determine the indentation.
+pub(crate) fn code_block(node: &[tree::DocumentElement]) -> String {
+    let code = plain_text(node);
+    let code = code.trim_end();
+    match code.split_once('\n') {
+        Some((first, rest)) if !first.trim().is_empty() => format!("{first}\n{}", text::dedent(rest)),
+        _ => text::dedent(code.trim_start_matches(['\r', '\n'])),
+    }
+}
+
+/// Returns the text of argument `key` of `func` (if non-empty)
+pub(crate) fn arg(func: &tree::DocumentFunction, key: &str) -> Option<String> {
+    func.args.get(key).map(|value| plain_text(value)).filter(|value| !value.is_empty())
+}
+
+/// Represent the elements of `node` as sequence of blocks
+fn blocks(node: &[tree::DocumentElement], flavor: Flavor) -> Vec<String> {
+    let mut out: Vec<String> = vec![];
+    let mut after_list = false;
+    let mut inline = vec![];
+
+    let mut push = |out: &mut Vec<String>, block: String, is_list: bool| {
+        let continues = after_list || out.last().and_then(|b| b.lines().last()).is_some_and(|line| line.starts_with(' '));
+        if flavor == Flavor::Rst && continues && block.starts_with(' ') {
+            // NOTE: an empty comment prevents an indented block from continuing the previous block
+            out.push("..".to_owned());
+        }
+        after_list = is_list;
+        out.push(block);
+    };
+
+    for element in node {
+        let func = match element {
+            tree::DocumentElement::Function(func) if is_block(&func.call) || (!is_inline(&func.call) && contains_blocks(func)) => func,
+            _ => {
+                inline.push(element.clone());
+                continue;
+            },
+        };
+        if let Some(paragraph) = paragraph(&inline, flavor) {
+            push(&mut out, paragraph, false);
+        }
+        inline.clear();
+
+        if is_block(&func.call) {
+            if let Some(block) = block(func, flavor) {
+                push(&mut out, block, func.call == "ul" || func.call == "ol");
+            }
+        } else {
+            for block in blocks(&func.content, flavor) {
+                push(&mut out, block, false);
+            }
+        }
+    }
+    if let Some(paragraph) = paragraph(&inline, flavor) {
+        push(&mut out, paragraph, false);
+    }
+    out
+}
+
+/// Represent the block call `func`
+fn block(func: &tree::DocumentFunction, flavor: Flavor) -> Option<String> {
+    match func.call.as_str() {
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level = usize::from(func.call.as_bytes()[1] - b'0');
+            let title = join_line(&pieces(&func.content, flavor, false), flavor);
+            if title.is_empty() {
+                return None;
+            }
+            Some(match flavor {
+                Flavor::Markdown => format!("{} {title}", "#".repeat(level)),
+                Flavor::Rst => {
+                    let underline = ['=', '-', '~', '^', '"', '\''][level - 1];
+                    format!("{title}\n{}", underline.to_string().repeat(title.chars().count()))
+                },
+            })
+        },
+        "p" | "li" => paragraph(&func.content, flavor),
+        "ul" | "ol" => {
+            let start = arg(func, "start").and_then(|start| start.trim().parse::<usize>().ok()).unwrap_or(1);
+            let items: Vec<Vec<String>> = func.content.iter()
+                .filter(|element| !matches!(element, tree::DocumentElement::Text(text) if text.trim().is_empty()))
+                .map(|element| match element {
+                    tree::DocumentElement::Function(item) if item.call == "li" => blocks(&item.content, flavor),
+                    _ => blocks(std::slice::from_ref(element), flavor),
+                })
+                .collect();
+            if items.is_empty() {
+                return None;
+            }
+
+            let tight = items.iter().all(|item| item.len() <= 1);
+            let rendered: Vec<String> = items.iter().enumerate().map(|(i, item)| {
+                let marker = if func.call == "ul" { "-".to_owned() } else { format!("{}.", start + i) };
+                let body = item.join("\n\n");
+                let indent = " ".repeat(marker.chars().count() + 1);
+                let indented = indent_lines(&body, &indent);
+                format!("{marker} {}", indented.trim_start())
+            }).collect();
+            Some(rendered.join(if tight { "\n" } else { "\n\n" }))
+        },
+        "blockquote" => {
+            let body = blocks(&func.content, flavor).join("\n\n");
+            if body.is_empty() {
+                return None;
+            }
+            Some(match flavor {
+                Flavor::Markdown => body.lines().map(|line| if line.is_empty() { ">".to_owned() } else { format!("> {line}") }).collect::<Vec<_>>().join("\n"),
+                Flavor::Rst => indent_lines(&body, "    "),
+            })
+        },
+        "pre" => {
+            let code = code_block(&func.content);
+            let lang = arg(func, "lang").unwrap_or_default();
+            Some(match flavor {
+                Flavor::Markdown => {
+                    let longest = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+                    let fence = "`".repeat(longest.max(2) + 1);
+                    format!("{fence}{lang}\n{code}\n{fence}")
+                },
+                Flavor::Rst if lang.is_empty() => format!("::\n\n{}", indent_lines(&code, "    ")),
+                Flavor::Rst => format!(".. code:: {lang}\n\n{}", indent_lines(&code, "    ")),
+            })
+        },
+        "hr" => Some(match flavor {
+            Flavor::Markdown => "---".to_owned(),
+            Flavor::Rst => "----".to_owned(),
+        }),
+        _ => None,
+    }
+}
+
+/// Prefix every non-empty line of `text` with `indent`
+fn indent_lines(text: &str, indent: &str) -> String {
+    text.lines()
+        .map(|line| if line.is_empty() { String::new() } else { format!("{indent}{line}") })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Represent the inline elements `node` as paragraph (if non-empty)
+fn paragraph(node: &[tree::DocumentElement], flavor: Flavor) -> Option<String> {
+    // NOTE: reStructuredText does not support inline images, but images as block
+    if let (Flavor::Rst, [tree::DocumentElement::Function(img)]) = (flavor, trim_whitespace_texts(node)) {
+        if img.call == "img" {
+            let src = arg(img, "src").unwrap_or_default();
+            return Some(match arg(img, "alt") {
+                Some(alt) => format!(".. image:: {src}\n   :alt: {}", alt.split_whitespace().collect::<Vec<_>>().join(" ")),
+                None => format!(".. image:: {src}"),
+            });
+        }
+    }
+
+    let lines = split_lines(&pieces(node, flavor, false), flavor);
+    if lines.is_empty() {
+        return None;
+    }
+    Some(match flavor {
+        Flavor::Markdown => lines.join("\\\n"),
+        Flavor::Rst if lines.len() > 1 => lines.iter().map(|line| format!("| {line}")).collect::<Vec<_>>().join("\n"),
+        Flavor::Rst => lines.join("\n"),
+    })
+}
+
+/// Returns `node` without leading and trailing whitespace-only texts
+fn trim_whitespace_texts(mut node: &[tree::DocumentElement]) -> &[tree::DocumentElement] {
+    let is_whitespace = |element: &tree::DocumentElement| matches!(element, tree::DocumentElement::Text(text) if text.trim().is_empty());
+    while node.first().is_some_and(is_whitespace) {
+        node = &node[1..];
+    }
+    while node.last().is_some_and(is_whitespace) {
+        node = &node[..node.len() - 1];
+    }
+    node
+}
+
+/// Represent the inline elements `node` as pieces. If `plain` is set,
+/// inline markup is omitted and only texts are kept.
+fn pieces(node: &[tree::DocumentElement], flavor: Flavor, plain: bool) -> Vec<Piece> {
+    let mut out = vec![];
+    for element in node {
+        let func = match element {
+            tree::DocumentElement::Text(text) => {
+                out.push(Piece::Text(escape(text, flavor)));
+                continue;
+            },
+            tree::DocumentElement::Function(func) => func,
+        };
+        if func.call == "br" {
+            out.push(if plain { Piece::Text(" ".to_owned()) } else { Piece::Break });
+            continue;
+        }
+        if plain || !is_inline(&func.call) {
+            out.extend(pieces(&func.content, flavor, plain));
+            continue;
+        }
+
+        // NOTE: reStructuredText does not support nested inline markup
+        let nested = |content: &[tree::DocumentElement]| join_line(&pieces(content, flavor, flavor == Flavor::Rst), flavor);
+        let markup = match (func.call.as_str(), flavor) {
+            ("em" | "i", _) => wrap_markup(&nested(&func.content), "*"),
+            ("strong" | "b", _) => wrap_markup(&nested(&func.content), "**"),
+            ("code", Flavor::Markdown) => code_span(&collapse_whitespace(&plain_text(&func.content))),
+            ("code", Flavor::Rst) => wrap_markup(&collapse_whitespace(&plain_text(&func.content)), "``"),
+            ("a", _) => link(&nested(&func.content), &arg(func, "href").unwrap_or_default(), flavor, false),
+            ("img", _) => {
+                let alt = escape(&collapse_whitespace(&arg(func, "alt").unwrap_or_default()), flavor);
+                link(&alt, &arg(func, "src").unwrap_or_default(), flavor, true)
+            },
+            _ => vec![],
+        };
+        out.extend(markup);
+    }
+    out
+}
+
+/// Wrap `content` in `delimiter` as markup. Surrounding whitespace of
+/// `content` is moved outside, because delimiters must be adjacent to text.
+fn wrap_markup(content: &str, delimiter: &str) -> Vec<Piece> {
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        return vec![Piece::Text(content.to_owned())];
+    }
+    let mut out = vec![];
+    if trimmed.len() < content.len() && content.starts_with(char::is_whitespace) {
+        out.push(Piece::Text(" ".to_owned()));
+    }
+    out.push(Piece::Markup(format!("{delimiter}{trimmed}{delimiter}")));
+    if content.ends_with(char::is_whitespace) {
+        out.push(Piece::Text(" ".to_owned()));
+    }
+    out
+}
+
+/// Represent `code` as Markdown code span with a sufficiently long backtick sequence
+fn code_span(code: &str) -> Vec<Piece> {
+    if code.trim().is_empty() {
+        return vec![];
+    }
+    let longest = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let ticks = "`".repeat(longest + 1);
+    let pad = if code.starts_with('`') || code.ends_with('`') { " " } else { "" };
+    vec![Piece::Markup(format!("{ticks}{pad}{code}{pad}{ticks}"))]
+}
+
+/// Represent a hyperlink (or image if `image` is set) with escaped text `label` referring to `url`
+fn link(label: &str, url: &str, flavor: Flavor, image: bool) -> Vec<Piece> {
+    let label = label.trim();
+    match flavor {
+        Flavor::Markdown => {
+            let destination = if url.contains(char::is_whitespace) {
+                format!("<{}>", url.replace('\\', "\\\\").replace('<', "\\<").replace('>', "\\>"))
+            } else {
+                url.replace('\\', "\\\\").replace('(', "\\(").replace(')', "\\)")
+            };
+            vec![Piece::Markup(format!("{}[{label}]({destination})", if image { "!" } else { "" }))]
+        },
+        Flavor::Rst if label.is_empty() => vec![Piece::Markup(format!("`<{url}>`__"))],
+        Flavor::Rst => vec![Piece::Markup(format!("`{} <{url}>`__", label.replace('<', "\\<")))],
+    }
+}
+
+/// Replace every sequence of whitespace in `text` by a single space
+pub(crate) fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut whitespace = false;
+    for chr in text.chars() {
+        if chr.is_whitespace() {
+            whitespace = true;
+            continue;
+        }
+        if whitespace {
+            out.push(' ');
+            whitespace = false;
+        }
+        out.push(chr);
+    }
+    if whitespace {
+        out.push(' ');
+    }
+    out
+}
+
+/// Join `pieces` to one line ignoring line breaks
+fn join_line(pieces: &[Piece], flavor: Flavor) -> String {
+    let without_breaks: Vec<Piece> = pieces.iter()
+        .map(|piece| if *piece == Piece::Break { Piece::Text(" ".to_owned()) } else { piece.clone() })
+        .collect();
+    split_lines(&without_breaks, flavor).join(" ")
+}
+
+/// Join `pieces` to non-empty lines separated by line breaks
+/// with whitespace collapsed and line starts of text escaped
+fn split_lines(pieces: &[Piece], flavor: Flavor) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    let mut previous_markup = false;
+    // NOTE: markup at the start of a line is never mistaken for a block, so only text is escaped
+    let mut starts_with_text = None;
+    for piece in pieces.iter().chain(std::iter::once(&Piece::Break)) {
+        match piece {
+            Piece::Text(text) => {
+                // NOTE: reStructuredText markup must be separated from adjacent words
+                if flavor == Flavor::Rst && previous_markup && text.starts_with(char::is_alphanumeric) {
+                    line.push_str("\\ ");
+                }
+                line.push_str(text);
+                if starts_with_text.is_none() && !text.trim().is_empty() {
+                    starts_with_text = Some(true);
+                }
+                previous_markup = false;
+            },
+            Piece::Markup(markup) => {
+                if flavor == Flavor::Rst && (previous_markup || line.ends_with(char::is_alphanumeric)) {
+                    line.push_str("\\ ");
+                }
+                line.push_str(markup);
+                starts_with_text.get_or_insert(false);
+                previous_markup = true;
+            },
+            Piece::Break => {
+                let collapsed = collapse_whitespace(&line);
+                let trimmed = collapsed.trim();
+                if !trimmed.is_empty() {
+                    lines.push(match starts_with_text {
+                        Some(true) => escape_line_start(trimmed, flavor),
+                        _ => trimmed.to_owned(),
+                    });
+                }
+                line.clear();
+                starts_with_text = None;
+                previous_markup = false;
+            },
+        }
+    }
+    lines
+}
+
+/// Escape characters of `text` with special meaning within lines
+fn escape(text: &str, flavor: Flavor) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(chr) = chars.next() {
+        let special = match flavor {
+            Flavor::Markdown => matches!(chr, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>')
+                || (chr == '&' && chars.peek().is_some_and(|next| next.is_alphanumeric() || *next == '#')),
+            Flavor::Rst => matches!(chr, '\\' | '`' | '*' | '_' | '|'),
+        };
+        if special {
+            out.push('\\');
+        }
+        out.push(chr);
+    }
+    out
+}
+
+/// Escape the start of `line` if it would start a block (like a list item)
+fn escape_line_start(line: &str, flavor: Flavor) -> String {
+    let first_word = line.split(' ').next().unwrap_or_default();
+    let enumerator = first_word.len() > 1
+        && first_word.ends_with(['.', ')'])
+        && first_word[..first_word.len() - 1].chars().all(|c| c.is_ascii_digit() || (flavor == Flavor::Rst && c.is_alphabetic()));
+    let special = match (flavor, line.chars().next()) {
+        (Flavor::Markdown, Some(chr)) => matches!(chr, '#' | '+' | '-' | '=' | '~' | '|'),
+        (Flavor::Rst, Some(chr)) => chr.is_ascii_punctuation() && chr != '\\',
+        (_, None) => false,
+    };
+
+    if enumerator {
+        // NOTE: escaping the terminating punctuation keeps the number intact
+        let (number, rest) = line.split_at(first_word.len() - 1);
+        format!("{number}\\{rest}")
+    } else if special {
+        format!("\\{line}")
+    } else {
+        line.to_owned()
+    }
+}
+
+/// Create a tree of the standard call vocabulary from the CommonMark document `src`.
+/// Raw HTML is kept as text and blocks are separated by newlines.
+#[cfg(feature = "import")]
+pub fn from_markdown(src: &str) -> Result<tree::DocumentTree, String> {
+    use pulldown_cmark::{CodeBlockKind, Event, Tag};
+
+    fn push_text(node: &mut tree::DocumentNode, text: &str) {
+        match node.last_mut() {
+            Some(tree::DocumentElement::Text(previous)) => previous.push_str(text),
+            _ => node.push(tree::DocumentElement::Text(text.to_owned())),
+        }
+    }
+    fn call(name: &str, args: &[(&str, &str)], content: tree::DocumentNode) -> tree::DocumentFunction {
+        let mut func = tree::DocumentFunction { call: name.to_owned(), args: Default::default(), content };
+        for (key, value) in args.iter().filter(|(_, value)| !value.is_empty()) {
+            func.args.insert(key.to_string(), vec![tree::DocumentElement::Text(value.to_string())]);
+        }
+        func
+    }
+
+    let mut stack = vec![tree::DocumentFunction { call: "document".to_owned(), args: Default::default(), content: vec![] }];
+    for event in pulldown_cmark::Parser::new(src) {
+        let parent = stack.last_mut().expect("the root call is never closed");
+        match event {
+            Event::Start(tag) => {
+                let func = match tag {
+                    Tag::Paragraph => call("p", &[], vec![]),
+                    Tag::Heading(level, _, _) => call(&level.to_string(), &[], vec![]),
+                    Tag::BlockQuote => call("blockquote", &[], vec![]),
+                    Tag::CodeBlock(CodeBlockKind::Fenced(info)) => call("pre", &[("lang", info.split_whitespace().next().unwrap_or_default())], vec![]),
+                    Tag::CodeBlock(CodeBlockKind::Indented) => call("pre", &[], vec![]),
+                    Tag::List(Some(start)) if start != 1 => call("ol", &[("start", &start.to_string())], vec![]),
+                    Tag::List(Some(_)) => call("ol", &[], vec![]),
+                    Tag::List(None) => call("ul", &[], vec![]),
+                    Tag::Item => call("li", &[], vec![]),
+                    Tag::Emphasis => call("em", &[], vec![]),
+                    Tag::Strong => call("strong", &[], vec![]),
+                    Tag::Link(_, url, title) => call("a", &[("href", &url), ("title", &title)], vec![]),
+                    Tag::Image(_, url, title) => call("img", &[("src", &url), ("title", &title)], vec![]),
+                    // NOTE: extensions like tables are not enabled
+                    _ => call("span", &[], vec![]),
+                };
+                stack.push(func);
+            },
+            Event::End(tag) => {
+                let mut func = stack.pop().expect("every end event has a start event");
+                if func.call == "img" {
+                    let alt = plain_text(&func.content);
+                    func.content.clear();
+                    func.args.insert("alt".to_owned(), vec![tree::DocumentElement::Text(alt)]);
+                }
+                let parent = stack.last_mut().expect("the root call is never closed");
+                parent.content.push(tree::DocumentElement::Function(func));
+                if !matches!(tag, Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)) {
+                    push_text(&mut parent.content, "\n");
+                }
+            },
+            Event::Text(text) | Event::Html(text) => push_text(&mut parent.content, &text),
+            Event::Code(code) => parent.content.push(tree::DocumentElement::Function(call("code", &[], vec![tree::DocumentElement::Text(code.to_string())]))),
+            Event::SoftBreak => push_text(&mut parent.content, "\n"),
+            Event::HardBreak => parent.content.push(tree::DocumentElement::Function(call("br", &[], vec![]))),
+            Event::Rule => {
+                parent.content.push(tree::DocumentElement::Function(call("hr", &[], vec![])));
+                push_text(&mut parent.content, "\n");
+            },
+            Event::FootnoteReference(_) | Event::TaskListMarker(_) => {},
+        }
+    }
+    let root = stack.pop().expect("the root call is never closed");
+    Ok(tree::DocumentTree(tree::DocumentElement::Function(root)))
+}
+
+/// Create a tree from a CommonMark document, which requires feature `import`
+#[cfg(not(feature = "import"))]
+pub fn from_markdown(_src: &str) -> Result<tree::DocumentTree, String> {
+    Err("cannot read Markdown documents, because litua was built without feature 'import'".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_markdown_and_rst() {
+        let src = "{h1 Title *1*}\n\nSome {em emphasized} and {strong bold} text with {code a`b} and {a[href=https://example.org/x_(y)] a link}.\n\
+            {ul {li one} {li two {br} lines}}\n{ol[start=3] {li 3. item}}\n{pre[lang=lua]\n  print(\"```\")\n}\n{blockquote - not a list}";
+        let tree = crate::parse_str(src).expect("valid document");
+
+        assert_eq!(to_markdown(&tree), "# Title \\*1\\*\n\n\
+            Some *emphasized* and **bold** text with ``a`b`` and [a link](https://example.org/x_\\(y\\)).\n\n\
+            - one\n- two\\\n  lines\n\n3. 3\\. item\n\n````lua\nprint(\"```\")\n````\n\n> \\- not a list\n");
+        assert_eq!(to_rst(&tree), "Title \\*1\\*\n===========\n\n\
+            Some *emphasized* and **bold** text with ``a`b`` and `a link <https://example.org/x_(y)>`__.\n\n\
+            - one\n- | two\n  | lines\n\n3. 3\\. item\n\n.. code:: lua\n\n    print(\"```\")\n\n..\n\n    \\- not a list\n");
+    }
+
+    #[test]
+    fn write_rst_with_markup_at_line_start() {
+        let src = "{p {strong Bold} text}\n{p {a[href=x.html] Link} first}\n{p {em Emphasis}{br}{strong Bold} again}\n{ul {li {em item}}}\n{p *text* first}";
+        let tree = crate::parse_str(src).expect("valid document");
+        assert_eq!(to_rst(&tree), "**Bold** text\n\n`Link <x.html>`__ first\n\n\
+            | *Emphasis*\n| **Bold** again\n\n- *item*\n\n\\*text\\* first\n");
+    }
+
+    #[cfg(feature = "import")]
+    #[test]
+    fn read_markdown() -> Result<(), String> {
+        let tree = from_markdown("# Title\n\nSome *emphasized*\ntext with `code`  \nand [a link](x.html \"X\").\n\n\
+            3. item\n4. ![alt *text*](a.png)\n\n```lua extra\nprint(1)\n```\n\n---\n")?;
+        assert_eq!(tree.to_litua_syntax(), "{h1 Title}\n{p Some {em emphasized}\ntext with {code code}{br}and {a[href=x.html][title=X] a link}.}\n\
+            {ol[start=3] {li item}\n{li {img[alt=alt text][src=a.png]}}\n}\n{pre[lang=lua] print(1)\n}\n{hr}\n");
+        Ok(())
+    }
+}
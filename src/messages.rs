@@ -0,0 +1,180 @@
+//! Catalog of user-facing diagnostic messages
+//!
+//! Every diagnostic is identified by a code like ``L0004``. The catalog
+//! associates each code with a message template per language. Templates
+//! contain placeholders ``{0}``, ``{1}``, … replaced by the arguments
+//! of the `Message`.
+
+use std::fmt;
+
+/// Environment variable selecting the language of diagnostic messages
+pub const LANG_ENV_VAR: &str = "LITUA_LANG";
+
+/// `Lang` is a language diagnostic messages are available in
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+pub enum Lang {
+    #[default]
+    English,
+    German,
+}
+
+impl Lang {
+    /// Language tags accepted by `Lang::from_tag`
+    pub const TAGS: [&'static str; 2] = ["en", "de"];
+
+    /// Returns the language of a language tag like `de` or `de-AT`
+    /// or a POSIX locale like `de_DE.UTF-8` if it is available
+    pub fn from_tag(tag: &str) -> Option<Lang> {
+        let primary = tag.split(['-', '_', '.']).next()?.to_ascii_lowercase();
+        match primary.as_str() {
+            "en" => Some(Lang::English),
+            "de" => Some(Lang::German),
+            _ => None,
+        }
+    }
+}
+
+/// Message templates as (code, English, German)
+const CATALOG: &[(&str, &str, &str)] = &[
+    // representation of errors
+    ("E0001", "{0} in file {1}, line {2} at column {3} (byte offset {4} within line)", "{0} in Datei {1}, Zeile {2}, Spalte {3} (Byte-Offset {4} innerhalb der Zeile)"),
+    ("E0002", "{0} in file {1} from line {2} at column {3} until line {4} at column {5}", "{0} in Datei {1} von Zeile {2}, Spalte {3} bis Zeile {4}, Spalte {5}"),
+    ("E0003", "{0} at byte {1}", "{0} bei Byte {1}"),
+    ("E0004", "Error[{0}]: {1}", "Fehler[{0}]: {1}"),
+    ("E0005", "Error: {0}", "Fehler: {0}"),
+    ("E0006", "Warning[{0}]: {1}", "Warnung[{0}]: {1}"),
+    ("E0007", "{0} in included file {1}", "{0} in eingebundener Datei {1}"),
+    ("E0008", "{0} in file {1}, line {2} at byte offset {3} within line", "{0} in Datei {1}, Zeile {2} bei Byte-Offset {3} innerhalb der Zeile"),
+    ("E0009", "{0} in file {1} from line {2} at byte offset {3} until line {4} at byte offset {5} within line", "{0} in Datei {1} von Zeile {2} bei Byte-Offset {3} bis Zeile {4} bei Byte-Offset {5} innerhalb der Zeile"),
+    // lexer
+    ("L0001", "character '{0}' (U+{1}) is not allowed in call names, which may only contain letters, digits, and any of '{2}' (use the permissive call name policy for legacy documents)", "Zeichen '{0}' (U+{1}) ist in Aufrufnamen nicht erlaubt, diese dürfen nur Buchstaben, Ziffern und die Zeichen '{2}' enthalten (für ältere Dokumente die tolerante Richtlinie für Aufrufnamen verwenden)"),
+    ("L0002", "scope ended at byte {0} but it never started", "Bereich endet bei Byte {0}, wurde aber nie begonnen"),
+    ("L0003", "scope {0} ended at byte {1} but it never started", "Bereich {0} endet bei Byte {1}, wurde aber nie begonnen"),
+    ("L0004", "call '{0}' was immediately closed by '{1}', but empty calls are not allowed", "Aufruf '{0}' wurde sofort durch '{1}' geschlossen, aber leere Aufrufe sind nicht erlaubt"),
+    ("L0005", "raw string delimiter must not exceed length {0}", "Begrenzer von Rohtexten dürfen höchstens {0} Zeichen lang sein"),
+    ("L0006", "unexpected character '{0}' while reading raw string start", "unerwartetes Zeichen '{0}' am Anfang eines Rohtexts"),
+    ("L0007", "unexpected character '{0}' - only '}' after a '{1}' sequence terminates a raw string", "unerwartetes Zeichen '{0}' - nur '}' nach einer Folge von '{1}' beendet einen Rohtext"),
+    ("L0008", "argument key must not be an empty string (keys starting with '{0}' are reserved for litua)", "Argumentschlüssel dürfen nicht leer sein (Schlüssel beginnend mit '{0}' sind für litua reserviert)"),
+    ("L0009", "after ending arguments with '{0}', I require a whitespace character to continue with content", "nach dem Ende der Argumente mit '{0}' wird ein Leerraumzeichen vor dem Inhalt benötigt"),
+    // header
+    ("H0001", "directive '{0}' must have the form key=value", "Direktive '{0}' muss die Form Schlüssel=Wert haben"),
+    ("H0002", "directive '{0}' {1}", "Direktive '{0}': {1}"),
+    // lint rules
+    ("W0001", "trailing whitespace", "Leerraum am Zeilenende"),
+    ("W0002", "argument '{0}' of call '{1}' has an empty value", "Argument '{0}' des Aufrufs '{1}' hat einen leeren Wert"),
+    ("W0003", "{0} (rule '{1}')", "{0} (Regel '{1}')"),
+    // includes
+    ("I0001", "call '{0}' must contain the filepath of the included document as text", "Aufruf '{0}' muss den Dateipfad des eingebundenen Dokuments als Text enthalten"),
+    ("I0002", "include cycle {0}", "zyklische Einbindung {0}"),
+    ("I0003", "cannot read included document '{0}': {1}", "eingebundenes Dokument '{0}' kann nicht gelesen werden: {1}"),
+    // links
+    ("R0001", "broken link target '{0}'", "defektes Linkziel '{0}'"),
+    ("R0002", "target '{0}' is already declared in {1}", "Ziel '{0}' ist bereits in {1} deklariert"),
+    // conditionals
+    ("C0001", "call '{0}' requires argument 'flag' with the name of a define as text", "Aufruf '{0}' benötigt Argument 'flag' mit dem Namen einer Definition als Text"),
+    // hooks
+    ("T0001", "{0} failed for call '{1}': {2}", "{0} schlug für Aufruf '{1}' fehl: {2}"),
+    // parser
+    ("P0001", "unexpected end of lexer tokens iterator", "unerwartetes Ende der Token des Lexers"),
+    ("P0101", "expected start of raw string, but got token '{0}'", "Anfang eines Rohtexts erwartet, aber Token '{0}' erhalten"),
+    ("P0102", "expected whitespace before, but got token '{0}'", "Leerraum davor erwartet, aber Token '{0}' erhalten"),
+    ("P0103", "expected text string, but got token '{0}'", "Text erwartet, aber Token '{0}' erhalten"),
+    ("P0104", "expected whitespace after raw string, but got token '{0}'", "Leerraum nach Rohtext erwartet, aber Token '{0}' erhalten"),
+    ("P0105", "expected end of raw string, but got token '{0}'", "Ende eines Rohtexts erwartet, aber Token '{0}' erhalten"),
+    ("P0106", "expected start of content, but got token '{0}'", "Anfang des Inhalts erwartet, aber Token '{0}' erhalten"),
+    ("P0107", "expected start of function/raw string or some text or end of content, but got token '{0}'", "Anfang eines Aufrufs/Rohtexts, Text oder Ende des Inhalts erwartet, aber Token '{0}' erhalten"),
+    ("P0108", "expected end of content, but got token '{0}'", "Ende des Inhalts erwartet, aber Token '{0}' erhalten"),
+    ("P0109", "expected start of argument value, but got token '{0}'", "Anfang eines Argumentwerts erwartet, aber Token '{0}' erhalten"),
+    ("P0110", "expected start of function/raw string or some text or end of argument value, but got token '{0}'", "Anfang eines Aufrufs/Rohtexts, Text oder Ende des Argumentwerts erwartet, aber Token '{0}' erhalten"),
+    ("P0111", "expected end of argument value, but got token '{0}'", "Ende des Argumentwerts erwartet, aber Token '{0}' erhalten"),
+    ("P0112", "expected start of function, but got token '{0}'", "Anfang eines Aufrufs erwartet, aber Token '{0}' erhalten"),
+    ("P0113", "expected call name, but got token '{0}'", "Aufrufname erwartet, aber Token '{0}' erhalten"),
+    ("P0114", "expected start of arguments, but got token '{0}'", "Anfang der Argumente erwartet, aber Token '{0}' erhalten"),
+    ("P0115", "expected end of arguments or the next argument key, but got token '{0}'", "Ende der Argumente oder nächster Argumentschlüssel erwartet, aber Token '{0}' erhalten"),
+    ("P0116", "expected end of arguments, but got token '{0}'", "Ende der Argumente erwartet, aber Token '{0}' erhalten"),
+    ("P0117", "expected some whitespace, but got token '{0}'", "Leerraum erwartet, aber Token '{0}' erhalten"),
+    ("P0118", "expected whitespace, but got token '{0}'", "Leerraumzeichen erwartet, aber Token '{0}' erhalten"),
+    ("P0119", "expected end of function, but got token '{0}'", "Ende des Aufrufs erwartet, aber Token '{0}' erhalten"),
+    ("P0120", "unexpected token '{0}' while parsing document", "unerwartetes Token '{0}' beim Verarbeiten des Dokuments"),
+    ("P0121", "argument '{0}' is declared as {1}, but its value '{2}' is not", "Argument '{0}' ist als {1} deklariert, aber sein Wert '{2}' nicht"),
+];
+
+/// `Message` is a diagnostic message identified by its code in the catalog
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Message {
+    /// code identifying the message template in the catalog
+    pub code: &'static str,
+    /// values substituted for the placeholders `{0}`, `{1}`, … of the template
+    pub args: Vec<String>,
+}
+
+impl Message {
+    pub fn new(code: &'static str, args: Vec<String>) -> Message {
+        Message { code, args }
+    }
+
+    /// Represent this message in language `lang`
+    pub fn render(&self, lang: Lang) -> String {
+        let template = match CATALOG.iter().find(|(code, _, _)| *code == self.code) {
+            Some((_, en, de)) => match lang {
+                Lang::English => en,
+                Lang::German => de,
+            },
+            None => return format!("{} {:?}", self.code, self.args),
+        };
+
+        let mut out = String::new();
+        let mut rest = *template;
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let placeholder = rest[1..].find('}').and_then(|end| {
+                let index: usize = rest[1..=end].parse().ok()?;
+                Some((index, end + 2))
+            });
+            match placeholder {
+                Some((index, length)) if index < self.args.len() => {
+                    out.push_str(&self.args[index]);
+                    rest = &rest[length..];
+                },
+                _ => {
+                    out.push('{');
+                    rest = &rest[1..];
+                },
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.render(Lang::English))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_catalog_messages() {
+        let msg = Message::new("L0007", vec!["x".to_owned(), ">".to_owned()]);
+        assert_eq!(msg.render(Lang::English), "unexpected character 'x' - only '}' after a '>' sequence terminates a raw string");
+        assert_eq!(msg.render(Lang::German), "unerwartetes Zeichen 'x' - nur '}' nach einer Folge von '>' beendet einen Rohtext");
+
+        assert_eq!(Lang::from_tag("de_DE.UTF-8"), Some(Lang::German));
+        assert_eq!(Lang::from_tag("EN-gb"), Some(Lang::English));
+        assert_eq!(Lang::from_tag("fr"), None);
+
+        // every translation uses the same placeholders
+        for (code, en, de) in CATALOG.iter() {
+            for index in 0..8 {
+                let placeholder = format!("{{{index}}}");
+                assert_eq!(en.contains(&placeholder), de.contains(&placeholder), "placeholder {placeholder} of {code}");
+            }
+            assert_eq!(CATALOG.iter().filter(|(c, _, _)| c == code).count(), 1, "duplicate code {code}");
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Access to text documents and resources over HTTP(S)
+//!
+//! Network access is opt-in (``--allow-net``), thus these functions are only
+//! called if the user allowed it. Reading responses requires feature `net`.
+
+use std::io;
+use std::time;
+
+/// Default time limit for establishing a connection and reading a response
+pub const DEFAULT_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+/// Default maximum size of a response body in bytes
+pub const DEFAULT_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// `Response` is the status code and body of an HTTP response
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// Is `location` an HTTP or HTTPS URL?
+pub fn is_url(location: &str) -> bool {
+    let lower = location.get(..8).unwrap_or(location).to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Resolve the (possibly relative) reference `reference` against the URL `base`
+/// like a browser resolves links (e.g. `img/a.png` against `https://example.org/doc/index.lit`
+/// yields `https://example.org/doc/img/a.png`)
+pub fn resolve(base: &str, reference: &str) -> String {
+    if is_url(reference) {
+        return reference.to_owned();
+    }
+    let scheme_end = base.find("://").map(|i| i + 3).unwrap_or(0);
+    let authority_end = base[scheme_end..].find('/').map(|i| scheme_end + i).unwrap_or(base.len());
+    if let Some(rest) = reference.strip_prefix("//") {
+        return format!("{}{rest}", &base[..scheme_end]);
+    }
+
+    // NOTE: query and fragment of the base do not belong to its path
+    let base_path = &base[authority_end..];
+    let base_path = &base_path[..base_path.find(['?', '#']).unwrap_or(base_path.len())];
+    let path = if reference.starts_with('/') {
+        reference.to_owned()
+    } else {
+        let directory = &base_path[..base_path.rfind('/').map(|i| i + 1).unwrap_or(0)];
+        format!("{}{reference}", if directory.is_empty() { "/" } else { directory })
+    };
+
+    let mut segments: Vec<&str> = vec![];
+    let parts: Vec<&str> = path.split('/').skip(1).collect();
+    for (i, segment) in parts.iter().enumerate() {
+        let last = i + 1 == parts.len();
+        match *segment {
+            "." => if last { segments.push("") },
+            ".." => {
+                segments.pop();
+                if last {
+                    segments.push("");
+                }
+            },
+            _ => segments.push(segment),
+        }
+    }
+    format!("{}/{}", &base[..authority_end], segments.join("/"))
+}
+
+/// Fetch `url` with HTTP GET. Fails if no response was read within `timeout`
+/// or its body exceeds `max_size` bytes. Responses with status codes indicating
+/// an error are returned, not treated as error.
+#[cfg(feature = "net")]
+pub fn fetch(url: &str, timeout: time::Duration, max_size: u64) -> io::Result<Response> {
+    let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+    let response = match agent.get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(_, response)) => response,
+        Err(ureq::Error::Transport(e)) => return Err(io::Error::other(format!("cannot fetch '{url}': {e}"))),
+    };
+
+    let status = response.status();
+    let mut body = vec![];
+    io::Read::read_to_end(&mut io::Read::take(response.into_reader(), max_size + 1), &mut body)?;
+    if body.len() as u64 > max_size {
+        return Err(io::Error::other(format!("response of '{url}' exceeds the size limit of {max_size} bytes")));
+    }
+    Ok(Response { status, body })
+}
+
+/// Fetch `url` with HTTP GET, which requires feature `net`
+#[cfg(not(feature = "net"))]
+pub fn fetch(url: &str, _timeout: time::Duration, _max_size: u64) -> io::Result<Response> {
+    let msg = format!("cannot fetch '{url}', because litua was built without feature 'net'");
+    Err(io::Error::new(io::ErrorKind::Unsupported, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_relative_references() {
+        let base = "https://example.org/docs/book/index.lit?rev=2";
+        assert_eq!(resolve(base, "chapter1.lit"), "https://example.org/docs/book/chapter1.lit");
+        assert_eq!(resolve(base, "../img/./a.png"), "https://example.org/docs/img/a.png");
+        assert_eq!(resolve(base, "/root.lit"), "https://example.org/root.lit");
+        assert_eq!(resolve(base, "//cdn.example.org/x.lit"), "https://cdn.example.org/x.lit");
+        assert_eq!(resolve(base, "HTTP://other.org/y.lit"), "HTTP://other.org/y.lit");
+        assert_eq!(resolve("https://example.org", "a.lit"), "https://example.org/a.lit");
+        assert!(is_url("https://example.org/doc.lit"));
+        assert!(!is_url("docs/http.lit"));
+    }
+}
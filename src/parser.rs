@@ -1,12 +1,31 @@
 //! Parser for litua text documents
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::iter;
+use std::mem;
+use std::ops;
 use std::path;
 
+use unicode_normalization::UnicodeNormalization;
+
 use crate::tree;
 use crate::lexer;
 use crate::errors;
+use crate::messages::Message;
+
+/// `WhitespaceTexts` defines how text elements consisting only of whitespace
+/// (e.g. the indentation between calls in pretty-printed source code) are handled
+#[derive(Clone,Copy,Debug,Default,Hash,PartialEq,Eq)]
+pub enum WhitespaceTexts {
+    /// keep them as they are
+    #[default]
+    Keep,
+    /// remove them from the content
+    Drop,
+    /// replace them by a single U+0020 SPACE
+    Collapse,
+}
 
 /// `Parser` holds a reference to the text document source code.
 /// To generate better error messages, we also store the filepath.
@@ -17,21 +36,141 @@ use crate::errors;
 /// generated tokens. Then `finalize` declares the termination of
 /// the token consumption. Finally one can fetch the resulting
 /// abstract syntax tree by calling the method `tree()`.
+///
+/// If `normalize_names` is set (default), call names and argument keys
+/// are normalized to Unicode Normalization Form C. Thus `{café}` dispatches
+/// to the same hooks independent of the composition of the `é`.
+///
+/// If `lossless` is set, every call additionally stores the whitespace
+/// before its terminating `}` as argument `=whitespace-after` (instead of
+/// as part of the content). Thus the source code can be reconstructed
+/// from the tree.
+///
+/// If `merge_texts` is set (default), consecutive text elements are
+/// coalesced into a single text element.
+///
+/// `whitespace_texts` determines how whitespace-only text elements in the
+/// content of calls are handled by `finalize`. The content of raw strings
+/// and of calls named in `preserve_whitespace` (and their descendants) is kept.
+///
+/// Within calls named in `verbatim` (and their descendants), all whitespace
+/// of the source code is kept in the content: only the first whitespace
+/// character after the call name (or arguments) separates it from the content,
+/// `lossless` does not move trailing whitespace into an argument,
+/// whitespace trimmed from raw strings by `LexerOptions::trim_raw_whitespace`
+/// is restored, and `whitespace_texts` does not apply.
 pub struct Parser<'s> {
-    pub filepath: path::PathBuf,
-    pub source_code: &'s str,
-    pub root: tree::DocumentFunction,
+    filepath: path::PathBuf,
+    source_code: &'s str,
+    root: tree::DocumentFunction,
+    normalize_names: bool,
+    lossless: bool,
+    merge_texts: bool,
+    whitespace_texts: WhitespaceTexts,
+    preserve_whitespace: HashSet<String>,
+    verbatim: HashSet<String>,
+    /// number of verbatim calls enclosing the element currently parsed
+    verbatim_depth: usize,
 }
 
-impl<'s> Parser<'s> {
-    pub fn new(filepath: &path::Path, source_code: &'s str) -> Parser<'s> {
+/// `ParserBuilder` configures a `Parser` before it is created.
+///
+/// The parser wraps the document in a synthetic root call. By default,
+/// it is called `document` and has one argument `filepath`. Its call name
+/// can be changed with `root_call` and additional arguments (e.g. a document
+/// title or build timestamp) can be injected with `root_arg`.
+#[derive(Clone,Debug,PartialEq)]
+pub struct ParserBuilder {
+    root_call: String,
+    root_args: HashMap<String, tree::DocumentNode>,
+    normalize_names: bool,
+    lossless: bool,
+    merge_texts: bool,
+    whitespace_texts: WhitespaceTexts,
+    preserve_whitespace: HashSet<String>,
+    verbatim: HashSet<String>,
+}
+
+impl Default for ParserBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParserBuilder {
+    pub fn new() -> Self {
+        Self {
+            root_call: "document".to_owned(),
+            root_args: HashMap::new(),
+            normalize_names: true,
+            lossless: false,
+            merge_texts: true,
+            whitespace_texts: WhitespaceTexts::default(),
+            preserve_whitespace: HashSet::new(),
+            verbatim: HashSet::new(),
+        }
+    }
+
+    /// Set the call name of the synthetic root call
+    pub fn root_call(mut self, name: &str) -> Self {
+        self.root_call = name.to_owned();
+        self
+    }
+
+    /// Add argument `key` with text `value` to the synthetic root call.
+    /// It takes precedence over the `filepath` argument set by the parser.
+    /// Keys starting with `tree::RESERVED_ARG_PREFIX` are reserved for litua.
+    pub fn root_arg(mut self, key: &str, value: &str) -> Self {
+        self.root_args.insert(key.to_owned(), vec![tree::DocumentElement::Text(value.to_owned())]);
+        self
+    }
+
+    /// See `Parser::normalize_names`
+    pub fn normalize_names(mut self, normalize_names: bool) -> Self {
+        self.normalize_names = normalize_names;
+        self
+    }
+
+    /// See `Parser::lossless`
+    pub fn lossless(mut self, lossless: bool) -> Self {
+        self.lossless = lossless;
+        self
+    }
+
+    /// See `Parser::merge_texts`
+    pub fn merge_texts(mut self, merge_texts: bool) -> Self {
+        self.merge_texts = merge_texts;
+        self
+    }
+
+    /// See `Parser::whitespace_texts`
+    pub fn whitespace_texts(mut self, whitespace_texts: WhitespaceTexts) -> Self {
+        self.whitespace_texts = whitespace_texts;
+        self
+    }
+
+    /// Keep whitespace-only text elements within calls named `call` (see `Parser::preserve_whitespace`)
+    pub fn preserve_whitespace(mut self, call: &str) -> Self {
+        self.preserve_whitespace.insert(call.to_owned());
+        self
+    }
+
+    /// Keep all whitespace within calls named `call` (see `Parser::verbatim`)
+    pub fn verbatim(mut self, call: &str) -> Self {
+        self.verbatim.insert(call.to_owned());
+        self
+    }
+
+    /// Create a `Parser` for the source code `source_code` read from `filepath`
+    pub fn build<'s>(self, filepath: &path::Path, source_code: &'s str) -> Parser<'s> {
         let mut args = HashMap::new();
         if let Some(fp) = filepath.to_str() {
             args.insert("filepath".to_owned(), vec![tree::DocumentElement::Text(fp.to_owned())]);
         }
+        args.extend(self.root_args);
 
         let root = tree::DocumentFunction {
-            call: "document".to_owned(),
+            call: self.root_call,
             args,
             content: vec!(),
         };
@@ -40,24 +179,68 @@ impl<'s> Parser<'s> {
             filepath: filepath.to_owned(),
             source_code,
             root,
+            normalize_names: self.normalize_names,
+            lossless: self.lossless,
+            merge_texts: self.merge_texts,
+            whitespace_texts: self.whitespace_texts,
+            preserve_whitespace: self.preserve_whitespace,
+            verbatim: self.verbatim,
+            verbatim_depth: 0,
+        }
+    }
+}
+
+impl<'s> Parser<'s> {
+    pub fn new(filepath: &path::Path, source_code: &'s str) -> Parser<'s> {
+        ParserBuilder::new().build(filepath, source_code)
+    }
+
+    /// Returns the filepath of the text document stored in the root call
+    pub fn filepath(&self) -> &path::Path {
+        &self.filepath
+    }
+
+    /// Returns the call name or argument key `name` as used in the tree
+    fn name(&self, name: &str) -> String {
+        if self.normalize_names {
+            name.nfc().collect()
+        } else {
+            name.to_owned()
         }
     }
 
+    /// Appends `element` to `node`. If `merge_texts` is set and both `element`
+    /// and the last element of `node` are texts, the texts are concatenated.
+    fn push_element(merge_texts: bool, node: &mut tree::DocumentNode, element: tree::DocumentElement) {
+        if merge_texts {
+            if let (Some(tree::DocumentElement::Text(last)), tree::DocumentElement::Text(text)) = (node.last_mut(), &element) {
+                last.push_str(text);
+                return;
+            }
+        }
+        node.push(element);
+    }
+
+    /// Terminate with the message `code` of the catalog stating what we expected instead of `tok`
     #[inline]
-    fn unexpected_token<T>(tok: &lexer::Token, expected: &str) -> Result<T, errors::Error> {
-        Err(errors::Error::UnexpectedToken(tok.clone(), expected.to_owned()))
+    fn unexpected_token<T>(tok: &lexer::Token, code: &'static str) -> Result<T, errors::Error> {
+        Err(errors::Error::UnexpectedToken(tok.clone(), Message::new(code, vec![tok.name().to_owned()])))
     }
 
     #[inline]
     fn unexpected_eof<T>() -> Result<T, errors::Error> {
-        Err(errors::Error::UnexpectedEOF("unexpected end of lexer tokens iterator".to_owned()))
+        Err(errors::Error::UnexpectedEOF(Message::new("P0001", vec![])))
     }
 
-    fn parse_raw(&mut self, iter: &mut iter::Peekable<lexer::LexingIterator>) -> Result<tree::DocumentElement, errors::Error> {
+    fn parse_raw<I: Iterator<Item = Result<lexer::Token, errors::Error>>>(&mut self, iter: &mut iter::Peekable<I>) -> Result<tree::DocumentElement, errors::Error> {
         let whitespace_before;
         let whitespace_after;
         let name;
+        let language;
         let text;
+        let text_range;
+        let span_start;
+        let span_end;
 
         // (1) consume BeginRaw
         match iter.next() {
@@ -66,10 +249,18 @@ impl<'s> Parser<'s> {
                 match token {
                     lexer::Token::BeginRaw(range) => {
                         // NOTE: expected token, yay!
-                        name = &self.source_code[range];
+                        span_start = range.start - lexer::OPEN_FUNCTION.len_utf8();
+                        // NOTE: raw strings are represented by `OPEN_RAW` characters
+                        //       independent of `LexerOptions::open_raw` to keep hooks portable
+                        let delimiters = &self.source_code[range];
+                        let delimiter = delimiters.chars().next();
+                        let length = delimiters.chars().take_while(|chr| Some(*chr) == delimiter).count();
+                        name = lexer::OPEN_RAW.to_string().repeat(length);
+                        // NOTE: the delimiter character is not admissible in language tags
+                        language = delimiters.char_indices().nth(length).map(|(i, _)| &delimiters[i..]);
                     },
                     lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                    _ => return Self::unexpected_token(&token, "start of raw string"),
+                    _ => return Self::unexpected_token(&token, "P0101"),
                 }
             },
             None => return Self::unexpected_eof(),
@@ -80,12 +271,12 @@ impl<'s> Parser<'s> {
             Some(tok_or_err) => {
                 let token = tok_or_err?;
                 match token {
-                    lexer::Token::Whitespace(_, ws) => {
-                        whitespace_before = ws;
+                    lexer::Token::Whitespace(range) => {
+                        whitespace_before = &self.source_code[range];
                         // NOTE: expected token, yay!
                     },
                     lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                    _ => return Self::unexpected_token(&token, "whitespace before"),
+                    _ => return Self::unexpected_token(&token, "P0102"),
                 }
             },
             None => return Self::unexpected_eof(),
@@ -97,11 +288,11 @@ impl<'s> Parser<'s> {
                 let token = tok_or_err?;
                 match token {
                     lexer::Token::Text(range) => {
-                        text = &self.source_code[range];
+                        text_range = range;
                         // NOTE: expected token, yay!
                     },
                     lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                    _ => return Self::unexpected_token(&token, "text string"),
+                    _ => return Self::unexpected_token(&token, "P0103"),
                 }
             },
             None => return Self::unexpected_eof(),
@@ -113,12 +304,18 @@ impl<'s> Parser<'s> {
             Some(tok_or_err) => {
                 let token = tok_or_err?;
                 match token {
-                    lexer::Token::Whitespace(_, ws) => {
-                        whitespace_after = ws;
+                    lexer::Token::Whitespace(range) => {
+                        // NOTE: `trim_raw_whitespace` excludes the whitespace before `range`
+                        //       from the text, except within verbatim calls
+                        text = match self.verbatim_depth {
+                            0 => &self.source_code[text_range],
+                            _ => &self.source_code[text_range.start..range.start],
+                        };
+                        whitespace_after = &self.source_code[range];
                         // NOTE: expected token, yay!
                     },
                     lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                    _ => return Self::unexpected_token(&token, "whitespace after raw string"),
+                    _ => return Self::unexpected_token(&token, "P0104"),
                 }
             },
             None => return Self::unexpected_eof(),
@@ -129,11 +326,12 @@ impl<'s> Parser<'s> {
             Some(tok_or_err) => {
                 let token = tok_or_err?;
                 match token {
-                    lexer::Token::EndRaw(_) => {
+                    lexer::Token::EndRaw(range) => {
                         // NOTE: expected token, yay!
+                        span_end = range.end + lexer::CLOSE_FUNCTION.len_utf8();
                     },
                     lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                    _ => return Self::unexpected_token(&token, "end of raw string"),
+                    _ => return Self::unexpected_token(&token, "P0105"),
                 }
             },
             None => return Self::unexpected_eof(),
@@ -141,16 +339,21 @@ impl<'s> Parser<'s> {
 
         // Ok(tree::DocumentElement::Text(text.to_owned()))  // NOTE would not convey `whitespace`
         let mut h = HashMap::new();
-        h.insert("=whitespace".to_owned(), vec![ tree::DocumentElement::Text(whitespace_before.to_string()) ]);
-        h.insert("=whitespace-after".to_owned(), vec![ tree::DocumentElement::Text(whitespace_after.to_string()) ]);
-        Ok(tree::DocumentElement::Function(tree::DocumentFunction {
-            call: name.to_string(),
+        h.insert(tree::WHITESPACE_ARG.to_owned(), vec![ tree::DocumentElement::Text(whitespace_before.to_string()) ]);
+        h.insert(tree::WHITESPACE_AFTER_ARG.to_owned(), vec![ tree::DocumentElement::Text(whitespace_after.to_string()) ]);
+        if let Some(language) = language {
+            h.insert(tree::LANGUAGE_ARG.to_owned(), vec![ tree::DocumentElement::Text(language.to_string()) ]);
+        }
+        let mut func = tree::DocumentFunction {
+            call: name,
             args: h,
             content: vec![tree::DocumentElement::Text(text.to_owned())],
-        }))
+        };
+        func.set_span(span_start..span_end);
+        Ok(tree::DocumentElement::Function(func))
     }
 
-    fn parse_content(&mut self, iter: &mut iter::Peekable<lexer::LexingIterator>) -> Result<tree::DocumentNode, errors::Error> {
+    fn parse_content<I: Iterator<Item = Result<lexer::Token, errors::Error>>>(&mut self, iter: &mut iter::Peekable<I>) -> Result<tree::DocumentNode, errors::Error> {
         let mut content = tree::DocumentNode::new();
 
         // (1) consume BeginContent
@@ -162,7 +365,7 @@ impl<'s> Parser<'s> {
                         // NOTE: expected token, yay!
                     },
                     lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                    _ => return Self::unexpected_token(&token, "start of content"),
+                    _ => return Self::unexpected_token(&token, "P0106"),
                 }
             },
             None => return Self::unexpected_eof(),
@@ -207,14 +410,14 @@ impl<'s> Parser<'s> {
                     // (8)     add text
                     if let Some(Ok(lexer::Token::Text(range))) = iter.next() {
                         let text = &self.source_code[range];
-                        content.push(tree::DocumentElement::Text(text.to_owned()));
+                        Self::push_element(self.merge_texts, &mut content, tree::DocumentElement::Text(text.to_owned()));
                     }
                 },
                 NextToken::EndContent => break,
                 NextToken::Unexpected => {
                     // protocol violation
                     match iter.next() {
-                        Some(Ok(tok)) => return Self::unexpected_token(&tok, "start of function/raw string or some text or end of content"),
+                        Some(Ok(tok)) => return Self::unexpected_token(&tok, "P0107"),
                         Some(Err(err)) => Err(err)?,
                         None => return Self::unexpected_eof(),
                     }
@@ -230,7 +433,7 @@ impl<'s> Parser<'s> {
                         // NOTE: expected token, yay!
                     },
                     lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                    _ => return Self::unexpected_token(&token, "end of content"),
+                    _ => return Self::unexpected_token(&token, "P0108"),
                 }
             },
             None => return Self::unexpected_eof(),
@@ -239,7 +442,7 @@ impl<'s> Parser<'s> {
         Ok(content)
     }
 
-    fn parse_argument_value(&mut self, iter: &mut iter::Peekable<lexer::LexingIterator>) -> Result<tree::DocumentNode, errors::Error> {
+    fn parse_argument_value<I: Iterator<Item = Result<lexer::Token, errors::Error>>>(&mut self, iter: &mut iter::Peekable<I>) -> Result<tree::DocumentNode, errors::Error> {
         let mut arg_value = tree::DocumentNode::new();
 
         // (1) consume BeginArgValue
@@ -251,7 +454,7 @@ impl<'s> Parser<'s> {
                         // NOTE: expected token, yay!
                     },
                     lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                    _ => return Self::unexpected_token(&token, "start of argument value"),
+                    _ => return Self::unexpected_token(&token, "P0109"),
                 }
             },
             None => return Self::unexpected_eof(),
@@ -296,14 +499,14 @@ impl<'s> Parser<'s> {
                     // (8)     add text
                     if let Some(Ok(lexer::Token::Text(range))) = iter.next() {
                         let content = &self.source_code[range];
-                        arg_value.push(tree::DocumentElement::Text(content.to_owned()));
+                        Self::push_element(self.merge_texts, &mut arg_value, tree::DocumentElement::Text(content.to_owned()));
                     }
                 },
                 NextToken::EndArgValue => break,
                 NextToken::Unexpected => {
                     // protocol violation
                     match iter.next() {
-                        Some(Ok(tok)) => return Self::unexpected_token(&tok, "start of function/raw string or some text or end of argument value"),
+                        Some(Ok(tok)) => return Self::unexpected_token(&tok, "P0110"),
                         Some(Err(err)) => Err(err)?,
                         None => return Self::unexpected_eof(),
                     }
@@ -320,7 +523,7 @@ impl<'s> Parser<'s> {
                         // NOTE: expected token, yay!
                     },
                     lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                    _ => return Self::unexpected_token(&token, "end of argument value"),
+                    _ => return Self::unexpected_token(&token, "P0111"),
                 }
             },
             None => return Self::unexpected_eof(),
@@ -329,19 +532,22 @@ impl<'s> Parser<'s> {
         Ok(arg_value)
     }
 
-    fn parse_function(&mut self, iter: &mut iter::Peekable<lexer::LexingIterator>) -> Result<tree::DocumentElement, errors::Error> {
+    fn parse_function<I: Iterator<Item = Result<lexer::Token, errors::Error>>>(&mut self, iter: &mut iter::Peekable<I>) -> Result<tree::DocumentElement, errors::Error> {
         let mut func = tree::DocumentFunction::new();
+        let span_start;
+        let mut leading_whitespace = String::new();
 
         // (01) consume BeginFunction
         match iter.next() {
             Some(tok_or_err) => {
                 let token = tok_or_err?;
                 match token {
-                    lexer::Token::BeginFunction(_) => {
+                    lexer::Token::BeginFunction(byte_offset) => {
                         // NOTE: expected token, yay!
+                        span_start = byte_offset;
                     },
                     lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                    _ => return Self::unexpected_token(&token, "start of function"),
+                    _ => return Self::unexpected_token(&token, "P0112"),
                 }
             },
             None => return Self::unexpected_eof(),
@@ -353,27 +559,29 @@ impl<'s> Parser<'s> {
                 let token = tok_or_err?;
                 match token {
                     lexer::Token::Call(range) => {
-                        let name = &self.source_code[range];
-                        func.call = name.to_owned();
+                        func.call = self.name(&self.source_code[range]);
+                        if self.verbatim.contains(&func.call) {
+                            self.verbatim_depth += 1;
+                        }
                     },
                     lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                    _ => return Self::unexpected_token(&token, "call name"),
+                    _ => return Self::unexpected_token(&token, "P0113"),
                 }
             },
             None => return Self::unexpected_eof(),
         }
 
         // (03) optionally consume Whitespace
-        if let Some(Ok(lexer::Token::Whitespace(_, _))) = iter.peek() {
+        if let Some(Ok(lexer::Token::Whitespace(_))) = iter.peek() {
             match iter.next() {
                 Some(tok_or_err) => {
                     let token = tok_or_err?;
                     match token {
-                        lexer::Token::Whitespace(_, whitespace) => {
-                            func.args.insert("=whitespace".to_owned(), vec![tree::DocumentElement::Text(format!("{whitespace}"))]);
+                        lexer::Token::Whitespace(range) => {
+                            leading_whitespace = self.insert_separator(&mut func, range);
                         },
                         lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                        _ => return Self::unexpected_token(&token, "whitespace"),
+                        _ => return Self::unexpected_token(&token, "P0118"),
                     }
                 },
                 None => return Self::unexpected_eof(),
@@ -391,7 +599,7 @@ impl<'s> Parser<'s> {
                             // NOTE: expected token, yay!
                         },
                         lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                        _ => return Self::unexpected_token(&token, "start of arguments"),
+                        _ => return Self::unexpected_token(&token, "P0114"),
                     }
                 },
                 None => return Self::unexpected_eof(),
@@ -402,7 +610,7 @@ impl<'s> Parser<'s> {
                 // NOTE: ok, we consume an argument key-value pair
 
                 // (07)     consume ArgKey
-                let arg_name = match iter.next() {
+                let (key_offset, arg_name) = match iter.next() {
                     Some(token_or_err) => {
                         let token = token_or_err?;
                         match token {
@@ -411,18 +619,31 @@ impl<'s> Parser<'s> {
                                 break;
                             },
                             lexer::Token::ArgKey(range) => {
-                                &self.source_code[range]
+                                (range.start, self.name(&self.source_code[range]))
                             }
                             lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                            _ => return Self::unexpected_token(&token, "end of arguments or the next argument key"),
+                            _ => return Self::unexpected_token(&token, "P0115"),
                         }
                     },
                     None => return Self::unexpected_eof(),
-                }.to_owned();
+                };
 
                 // (08)     parse_argument_value
                 let arg_value = self.parse_argument_value(iter)?;
-                func.args.insert(arg_name, arg_value);
+                let (key, arg_type) = tree::ArgType::split_key(&arg_name);
+                if let Some(arg_type) = arg_type {
+                    let text = match arg_value.as_slice() {
+                        [tree::DocumentElement::Text(text)] if arg_type.admits(text) => None,
+                        [tree::DocumentElement::Text(text)] => Some(text.to_owned()),
+                        _ => Some(tree::DocumentTree(tree::DocumentElement::Function(tree::DocumentFunction { content: arg_value.clone(), ..tree::DocumentFunction::new() })).to_litua_syntax()),
+                    };
+                    if let Some(text) = text {
+                        let msg = Message::new("P0121", vec![key.to_owned(), arg_type.name().to_owned(), text]);
+                        return Err(errors::Error::InvalidSyntax(msg, key_offset));
+                    }
+                    func.set_arg_type(key, arg_type);
+                }
+                func.args.insert(key.to_owned(), arg_value);
             }
 
             // (09)   consume EndArgs
@@ -434,23 +655,23 @@ impl<'s> Parser<'s> {
                             // NOTE: expected token, yay!
                         },
                         lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                        _ => return Self::unexpected_token(&token, "end of arguments"),
+                        _ => return Self::unexpected_token(&token, "P0116"),
                     }
                 },
                 None => return Self::unexpected_eof(),
             }
 
             // (10)   optionally consume Whitespace
-            if let Some(Ok(lexer::Token::Whitespace(_, _))) = iter.peek() {
+            if let Some(Ok(lexer::Token::Whitespace(_))) = iter.peek() {
                 match iter.next() {
                     Some(tok_or_err) => {
                         let token = tok_or_err?;
                         match token {
-                            lexer::Token::Whitespace(_, whitespace) => {
-                                func.args.insert("=whitespace".to_owned(), vec![tree::DocumentElement::Text(format!("{whitespace}"))]);
+                            lexer::Token::Whitespace(range) => {
+                                leading_whitespace = self.insert_separator(&mut func, range);
                             },
                             lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                            _ => return Self::unexpected_token(&token, "some whitespace"),
+                            _ => return Self::unexpected_token(&token, "P0117"),
                         }
                     },
                     None => return Self::unexpected_eof(),
@@ -468,17 +689,37 @@ impl<'s> Parser<'s> {
             // (12)   parse_content
             func.content = self.parse_content(iter)?;
         }
+        if !leading_whitespace.is_empty() {
+            match func.content.first_mut() {
+                Some(tree::DocumentElement::Text(text)) if self.merge_texts => text.insert_str(0, &leading_whitespace),
+                _ => func.content.insert(0, tree::DocumentElement::Text(leading_whitespace)),
+            }
+        }
+
+        if self.lossless {
+            let whitespace = if self.verbatim_depth > 0 { String::new() } else { Self::split_trailing_whitespace(&mut func.content) };
+            func.args.insert(tree::WHITESPACE_AFTER_ARG.to_owned(), vec![tree::DocumentElement::Text(whitespace)]);
+        }
+        if self.verbatim.contains(&func.call) {
+            self.verbatim_depth -= 1;
+        }
 
         // (13) consume EndFunction
         match iter.next() {
             Some(tok_or_err) => {
                 let token = tok_or_err?;
                 match token {
-                    lexer::Token::EndFunction(_) => {
-                        // NOTE: expected token, yay!
+                    lexer::Token::EndFunction(byte_offset) => {
+                        // NOTE: expected token, yay! Line calls end before the line break instead.
+                        let span_end = if self.source_code[byte_offset..].starts_with(lexer::CLOSE_FUNCTION) {
+                            byte_offset + lexer::CLOSE_FUNCTION.len_utf8()
+                        } else {
+                            byte_offset
+                        };
+                        func.set_span(span_start..span_end);
                     },
                     lexer::Token::EndOfFile(_) => return Self::unexpected_eof(),
-                    _ => return Self::unexpected_token(&token, "end of function"),
+                    _ => return Self::unexpected_token(&token, "P0119"),
                 }
             },
             None => return Self::unexpected_eof(),
@@ -487,10 +728,53 @@ impl<'s> Parser<'s> {
         Ok(tree::DocumentElement::Function(func))
     }
 
+    /// Stores the whitespace in `range` separating the call name (or arguments)
+    /// from the content as argument `=whitespace` of `func`. Within verbatim calls,
+    /// only its first character is stored and the remainder is returned to be
+    /// prepended to the content.
+    fn insert_separator(&self, func: &mut tree::DocumentFunction, range: ops::Range<usize>) -> String {
+        let whitespace = &self.source_code[range];
+        let split = match whitespace.chars().next() {
+            Some(chr) if self.verbatim_depth > 0 => chr.len_utf8(),
+            _ => whitespace.len(),
+        };
+        func.args.insert(tree::WHITESPACE_ARG.to_owned(), vec![tree::DocumentElement::Text(whitespace[..split].to_owned())]);
+        whitespace[split..].to_owned()
+    }
+
+    /// Removes the whitespace at the end of the last text element of `content`
+    /// (and the element itself if it only consists of whitespace) and returns it
+    fn split_trailing_whitespace(content: &mut tree::DocumentNode) -> String {
+        let text = match content.last_mut() {
+            Some(tree::DocumentElement::Text(text)) => text,
+            _ => return String::new(),
+        };
+
+        let whitespace = text.split_off(text.trim_end().len());
+        if text.is_empty() {
+            content.pop();
+        }
+        whitespace
+    }
+
     /// Consumes the tokens provided by the `LexingIterator` argument
     pub fn consume_iter(&mut self, iter: lexer::LexingIterator) -> Result<(), errors::Error> {
-        let mut peekable_iter = iter.peekable();
+        self.consume_tokens(iter)
+    }
 
+    /// Consumes the tokens provided by `tokens`. Unlike `consume_iter`, the tokens
+    /// need not originate from a `LexingIterator` (e.g. synthetic token streams).
+    /// The byte offsets of the tokens must refer to `self.source_code`.
+    pub fn consume_tokens<T: IntoIterator<Item = Result<lexer::Token, errors::Error>>>(&mut self, tokens: T) -> Result<(), errors::Error> {
+        let mut peekable_iter = tokens.into_iter().peekable();
+        while self.consume_element(&mut peekable_iter)? {}
+        Ok(())
+    }
+
+    /// Consumes the tokens of the next top-level element (a call, raw string, or text)
+    /// provided by `iter` and appends it to the content of the root call.
+    /// Returns `false` if the end of the file has been reached.
+    pub fn consume_element<I: Iterator<Item = Result<lexer::Token, errors::Error>>>(&mut self, iter: &mut iter::Peekable<I>) -> Result<bool, errors::Error> {
         // admissible tokens
         enum NextToken {
             BeginFunction,
@@ -501,62 +785,120 @@ impl<'s> Parser<'s> {
             Unexpected,
         }
 
-        loop {
-            let mut next_token = NextToken::Unexpected;
+        let next_token = match iter.peek() {
+            Some(Ok(lexer::Token::BeginFunction(_))) => NextToken::BeginFunction,
+            Some(Ok(lexer::Token::BeginContent(_))) => NextToken::BeginContent,
+            Some(Ok(lexer::Token::BeginRaw(_))) => NextToken::BeginRaw,
+            Some(Ok(lexer::Token::Text(_))) => NextToken::Text,
+            Some(Ok(lexer::Token::EndOfFile(_))) => NextToken::EndOfFile,
+            _ => NextToken::Unexpected,
+        };
 
-            if let Some(token_or_err) = peekable_iter.peek() {
-                next_token = match token_or_err {
-                    Ok(lexer::Token::BeginFunction(_)) => NextToken::BeginFunction,
-                    Ok(lexer::Token::BeginContent(_)) => NextToken::BeginContent,
-                    Ok(lexer::Token::BeginRaw(_)) => NextToken::BeginRaw,
-                    Ok(lexer::Token::Text(_)) => NextToken::Text,
-                    Ok(lexer::Token::EndOfFile(_)) => NextToken::EndOfFile,
-                    _ => NextToken::Unexpected,
+        match next_token {
+            NextToken::BeginFunction => {
+                let func = self.parse_function(iter)?;
+                self.root.content.push(func);
+            },
+            NextToken::BeginContent => {
+                for element in self.parse_content(iter)? {
+                    Self::push_element(self.merge_texts, &mut self.root.content, element);
                 }
-            }
+            },
+            NextToken::BeginRaw => {
+                let text = self.parse_raw(iter)?;
+                self.root.content.push(text);
+            },
+            NextToken::Text => {
+                if let Some(Ok(lexer::Token::Text(range))) = iter.next() {
+                    let text = &self.source_code[range];
+                    Self::push_element(self.merge_texts, &mut self.root.content, tree::DocumentElement::Text(text.to_owned()));
+                }
+            },
+            NextToken::EndOfFile => {
+                // Already done? How sad.
+                return Ok(false);
+            },
+            NextToken::Unexpected => {
+                // protocol violation
+                match iter.next() {
+                    Some(Ok(tok)) => return Self::unexpected_token(&tok, "P0120"),
+                    Some(Err(err)) => Err(err)?,
+                    None => return Self::unexpected_token(&lexer::Token::EndOfFile(0), "P0001"),
+                }
+            },
+        }
 
-            match next_token {
-                NextToken::BeginFunction => {
-                    let func = self.parse_function(&mut peekable_iter)?;
-                    self.root.content.push(func);
-                },
-                NextToken::BeginContent => {
-                    let mut content = self.parse_content(&mut peekable_iter)?;
-                    self.root.content.append(&mut content);
-                },
-                NextToken::BeginRaw => {
-                    let text = self.parse_raw(&mut peekable_iter)?;
-                    self.root.content.push(text);
-                },
-                NextToken::Text => {
-                    if let Some(Ok(lexer::Token::Text(range))) = peekable_iter.next() {
-                        let text = &self.source_code[range];
-                        self.root.content.push(tree::DocumentElement::Text(text.to_owned()));
-                    }
-                },
-                NextToken::EndOfFile => {
-                    // Already done? How sad.
-                    break;
-                },
-                NextToken::Unexpected => {
-                    // protocol violation
-                    match peekable_iter.next() {
-                        Some(Ok(tok)) => return Self::unexpected_token(&tok, &format!("unexpected token {:?} while parsing document", peekable_iter.peek())),
-                        Some(Err(err)) => Err(err)?,
-                        None => return Self::unexpected_token(&lexer::Token::EndOfFile(0), "unexpected end of lexer tokens iterator"),
+        Ok(true)
+    }
+
+    /// Returns the top-level elements consumed so far (and not taken by `take_tree`)
+    pub fn pending(&self) -> &tree::DocumentNode {
+        &self.root.content
+    }
+
+    /// Lex and parse the snippet `src` with the default options and return
+    /// its sequence of elements without the synthetic root call. Thus the
+    /// result can be spliced into the content of an existing tree. Be aware
+    /// that the `=span` arguments refer to byte offsets within `src`.
+    pub fn parse_fragment(src: &str) -> Result<tree::DocumentNode, errors::Error> {
+        let lex = lexer::Lexer::new(src);
+        let mut par = Parser::new(path::Path::new(""), src);
+        par.consume_iter(lex.iter())?;
+        par.finalize()?;
+        Ok(par.root.content)
+    }
+
+    /// Applies `whitespace_texts` to the content of `func` and its descendants
+    fn handle_whitespace_texts(&self, func: &mut tree::DocumentFunction) {
+        if self.preserve_whitespace.contains(&func.call) || self.verbatim.contains(&func.call) || func.call.starts_with(lexer::OPEN_RAW) {
+            return;
+        }
+
+        let is_whitespace_only = |element: &tree::DocumentElement| {
+            matches!(element, tree::DocumentElement::Text(text) if !text.is_empty() && text.trim().is_empty())
+        };
+        match self.whitespace_texts {
+            WhitespaceTexts::Keep => {},
+            WhitespaceTexts::Drop => func.content.retain(|element| !is_whitespace_only(element)),
+            WhitespaceTexts::Collapse => {
+                for element in func.content.iter_mut() {
+                    if is_whitespace_only(element) {
+                        *element = tree::DocumentElement::Text(" ".to_owned());
                     }
-                },
-            }
+                }
+            },
         }
 
-        Ok(())
+        for element in func.args.values_mut().flatten().chain(func.content.iter_mut()) {
+            if let tree::DocumentElement::Function(child) = element {
+                self.handle_whitespace_texts(child);
+            }
+        }
     }
 
     /// Declares the end of the text document
     pub fn finalize(&mut self) -> Result<(), errors::Error> {
+        if self.whitespace_texts != WhitespaceTexts::Keep {
+            let mut root = mem::take(&mut self.root);
+            self.handle_whitespace_texts(&mut root);
+            self.root = root;
+        }
         Ok(())
     }
 
+    /// Finalizes the top-level elements consumed so far and returns them as tree
+    /// (with a copy of the root call). Unlike `tree`, parsing can continue afterwards
+    /// and subsequent trees only contain the elements consumed in the meantime.
+    pub fn take_tree(&mut self) -> Result<tree::DocumentTree, errors::Error> {
+        self.finalize()?;
+        let root = tree::DocumentFunction {
+            call: self.root.call.clone(),
+            args: self.root.args.clone(),
+            content: mem::take(&mut self.root.content),
+        };
+        Ok(tree::DocumentTree(tree::DocumentElement::Function(root)))
+    }
+
     /// Returns the Abstract Syntax Tree to be processed further
     pub fn tree(self) -> tree::DocumentTree {
         tree::DocumentTree(tree::DocumentElement::Function(self.root))
@@ -585,13 +927,331 @@ mod tests {
                         assert_eq!(elem.call, "e_lement");
                         assert_eq!(elem.args["a_ttr"], vec![tree::DocumentElement::Text("v_alue".to_string())]);
                         assert_eq!(elem.content, vec![tree::DocumentElement::Text("c_ontent".to_string())]);
+                        assert_eq!(elem.span(), Some(0..input.len()));
                     },
-                    _ => { assert!(false) },
+                    _ => panic!("expected a function as first element of the document"),
+                }
+            },
+            tree::DocumentElement::Text(_) => panic!("expected the document root to be a function"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_spans() -> Result<(), errors::Error> {
+        let input = "A {b[x={c}] {<< d >>}} E";
+        let lex = lexer::Lexer::new(input);
+        let mut par = Parser::new(path::Path::new("example"), input);
+        par.consume_iter(lex.iter())?;
+        let tree = par.tree();
+
+        let doc = match tree.0 {
+            tree::DocumentElement::Function(doc) => doc,
+            tree::DocumentElement::Text(_) => panic!("expected the document root to be a function"),
+        };
+        let b = match &doc.content[1] {
+            tree::DocumentElement::Function(b) => b,
+            _ => panic!("expected call 'b' as second element of the document"),
+        };
+        assert_eq!(b.span(), Some(2..22));
+        match &b.args["x"][0] {
+            tree::DocumentElement::Function(c) => assert_eq!(c.span(), Some(7..10)),
+            _ => panic!("expected call 'c' as argument value"),
+        }
+        match &b.content[0] {
+            tree::DocumentElement::Function(raw) => assert_eq!(raw.span(), Some(12..21)),
+            _ => panic!("expected a raw string as content"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn normalize_names() -> Result<(), errors::Error> {
+        // NOTE: call name and argument key in NFD (e followed by U+0301 COMBINING ACUTE ACCENT)
+        let input = "{cafe\u{301}[cle\u{301}=x] y}";
+        let call = |normalize_names| -> Result<tree::DocumentFunction, errors::Error> {
+            let lex = lexer::Lexer::new(input);
+            let mut par = Parser::new(path::Path::new("example"), input);
+            par.normalize_names = normalize_names;
+            par.consume_iter(lex.iter())?;
+            match par.tree().0 {
+                tree::DocumentElement::Function(mut doc) => match doc.content.remove(0) {
+                    tree::DocumentElement::Function(f) => Ok(f),
+                    _ => panic!("expected a function as first element of the document"),
+                },
+                tree::DocumentElement::Text(_) => panic!("expected the document root to be a function"),
+            }
+        };
+
+        let normalized = call(true)?;
+        assert_eq!(normalized.call, "caf\u{e9}");
+        assert!(normalized.args.contains_key("cl\u{e9}"));
+
+        let verbatim = call(false)?;
+        assert_eq!(verbatim.call, "cafe\u{301}");
+        assert!(verbatim.args.contains_key("cle\u{301}"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_lossless() -> Result<(), errors::Error> {
+        let input = "A {b[x=y]  c\n d \n} {e } {f[g=h]} {<< i >>}{j \n}";
+        let lex = lexer::Lexer::new(input);
+        let mut par = Parser::new(path::Path::new("example"), input);
+        par.lossless = true;
+        par.consume_iter(lex.iter())?;
+        let tree = par.tree();
+
+        if let tree::DocumentElement::Function(doc) = &tree.0 {
+            match &doc.content[1] {
+                tree::DocumentElement::Function(b) => {
+                    assert_eq!(b.content, vec![tree::DocumentElement::Text("c\n d".to_string())]);
+                    assert_eq!(b.args["=whitespace-after"], vec![tree::DocumentElement::Text(" \n".to_string())]);
+                },
+                _ => panic!("expected call 'b' as second element of the document"),
+            }
+        }
+        assert_eq!(tree.to_litua_syntax(), input);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_with_custom_root() -> Result<(), errors::Error> {
+        let input = "{a}";
+        let lex = lexer::Lexer::new(input);
+        let mut par = ParserBuilder::new()
+            .root_call("book")
+            .root_arg("title", "Example")
+            .build(path::Path::new("example"), input);
+        par.consume_iter(lex.iter())?;
+
+        match par.tree().0 {
+            tree::DocumentElement::Function(doc) => {
+                assert_eq!(doc.call, "book");
+                assert_eq!(doc.args["filepath"], vec![tree::DocumentElement::Text("example".to_string())]);
+                assert_eq!(doc.args["title"], vec![tree::DocumentElement::Text("Example".to_string())]);
+                assert_eq!(doc.content.len(), 1);
+            },
+            tree::DocumentElement::Text(_) => panic!("expected the document root to be a function"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_synthetic_tokens() -> Result<(), errors::Error> {
+        use lexer::Token;
+
+        let input = "{a b}";
+        let tokens = vec![
+            Token::BeginFunction(0), Token::Call(1..2), Token::Whitespace(2..3),
+            Token::BeginContent(3), Token::Text(3..4), Token::EndContent(4),
+            Token::EndFunction(4), Token::EndOfFile(5),
+        ];
+        let mut par = Parser::new(path::Path::new("example"), input);
+        par.consume_tokens(tokens.into_iter().map(Ok))?;
+        assert_eq!(par.tree().to_litua_syntax(), input);
+
+        let mut par = Parser::new(path::Path::new("example"), input);
+        let result = par.consume_tokens(vec![Ok(Token::BeginFunction(0)), Ok(Token::EndOfFile(5))]);
+        assert!(matches!(result, Err(errors::Error::UnexpectedEOF(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_fragment() -> Result<(), errors::Error> {
+        let mut fragment = Parser::parse_fragment("a {b[c={d}] e} f")?;
+        assert_eq!(fragment.len(), 3);
+        assert_eq!(fragment[0], tree::DocumentElement::Text("a ".to_string()));
+        assert_eq!(fragment[2], tree::DocumentElement::Text(" f".to_string()));
+        match &mut fragment[1] {
+            tree::DocumentElement::Function(b) => {
+                assert_eq!(b.call, "b");
+                assert_eq!(b.span(), Some(2..14));
+                b.remove_spans();
+                assert_eq!(b.span(), None);
+                match &b.args["c"][0] {
+                    tree::DocumentElement::Function(d) => assert_eq!(d.span(), None),
+                    _ => panic!("expected call 'd' as argument value"),
                 }
             },
-            tree::DocumentElement::Text(_) => assert!(false),
+            _ => panic!("expected call 'b' as second element of the fragment"),
+        }
+
+        assert!(Parser::parse_fragment("{a").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_texts() -> Result<(), errors::Error> {
+        use lexer::Token;
+
+        let input = "ab{c de}";
+        let tokens = || vec![
+            Token::Text(0..1), Token::Text(1..2),
+            Token::BeginFunction(2), Token::Call(3..4), Token::Whitespace(4..5),
+            Token::BeginContent(5), Token::Text(5..6), Token::Text(6..7), Token::EndContent(7),
+            Token::EndFunction(7), Token::EndOfFile(8),
+        ].into_iter().map(Ok);
+        let content = |merge_texts| -> Result<tree::DocumentNode, errors::Error> {
+            let mut par = ParserBuilder::new().merge_texts(merge_texts).build(path::Path::new("example"), input);
+            par.consume_tokens(tokens())?;
+            match par.tree().0 {
+                tree::DocumentElement::Function(doc) => Ok(doc.content),
+                tree::DocumentElement::Text(_) => panic!("expected the document root to be a function"),
+            }
+        };
+
+        let merged = content(true)?;
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0], tree::DocumentElement::Text("ab".to_string()));
+        match &merged[1] {
+            tree::DocumentElement::Function(c) => assert_eq!(c.content, vec![tree::DocumentElement::Text("de".to_string())]),
+            _ => panic!("expected call 'c' as second element of the document"),
         }
 
+        assert_eq!(content(false)?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn whitespace_texts() -> Result<(), errors::Error> {
+        let input = "{ul\n  {li a}\n  {li {code x}  {b y}}\n}\n{code\n  {b z}\n}";
+        let parse = |builder: ParserBuilder| -> Result<String, errors::Error> {
+            let lex = lexer::Lexer::new(input);
+            let mut par = builder.build(path::Path::new("example"), input);
+            par.consume_iter(lex.iter())?;
+            par.finalize()?;
+            Ok(par.tree().to_litua_syntax())
+        };
+
+        assert_eq!(parse(ParserBuilder::new())?, input);
+        assert_eq!(parse(ParserBuilder::new().whitespace_texts(WhitespaceTexts::Drop))?, "{ul\n  {li a}{li {code x}{b y}}}{code\n  {b z}}");
+        assert_eq!(parse(ParserBuilder::new().whitespace_texts(WhitespaceTexts::Collapse))?, "{ul\n  {li a} {li {code x} {b y}} } {code\n  {b z} }");
+        assert_eq!(parse(ParserBuilder::new().whitespace_texts(WhitespaceTexts::Drop).preserve_whitespace("code"))?, "{ul\n  {li a}{li {code x}{b y}}}{code\n  {b z}\n}");
+        Ok(())
+    }
+
+    #[test]
+    fn typed_arguments() -> Result<(), errors::Error> {
+        let tree = crate::parse_str("{img[width:int= 300 ][scale:float=0.5][draft:bool=true][xml:lang=de]}").map_err(|mut e| e.remove(0))?;
+        let img = match &tree.0 {
+            tree::DocumentElement::Function(root) => match root.content.first() {
+                Some(tree::DocumentElement::Function(img)) => img.clone(),
+                _ => panic!("expected call img"),
+            },
+            tree::DocumentElement::Text(_) => panic!("root is no call"),
+        };
+        assert_eq!(img.arg_type("width"), Some(tree::ArgType::Int));
+        assert_eq!(img.arg_type("scale"), Some(tree::ArgType::Float));
+        assert_eq!(img.arg_type("draft"), Some(tree::ArgType::Bool));
+        assert_eq!(img.arg_type("xml:lang"), None);
+        assert_eq!(img.text_arg("width"), Some(" 300 "));
+        assert_eq!(tree.to_litua_syntax(), "{img[draft:bool=true][scale:float=0.5][width:int= 300 ][xml:lang=de]}");
+
+        for invalid in ["{img[width:int=3.5]}", "{img[draft:bool=yes]}", "{img[scale:float=inf]}", "{img[width:int={b 3}]}"] {
+            assert!(matches!(crate::parse_str(invalid).map_err(|mut e| e.remove(0)), Err(errors::Error::InvalidSyntax(_, 5))), "{invalid}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn verbatim_calls() -> Result<(), errors::Error> {
+        let input = "{pre\n    {b  x}  \n  {<< y  >>}\n}{p  z  }";
+        let options = lexer::LexerOptions { trim_raw_whitespace: true, ..lexer::LexerOptions::default() };
+        let lex = lexer::Lexer::with_options(input, options);
+        let builder = ParserBuilder::new().lossless(true).whitespace_texts(WhitespaceTexts::Drop).verbatim("pre");
+        let mut par = builder.build(path::Path::new("example"), input);
+        par.consume_iter(lex.iter())?;
+        par.finalize()?;
+        let tree = par.tree();
+        assert_eq!(tree.to_litua_syntax(), input);
+
+        let text = |element: &tree::DocumentElement| match element {
+            tree::DocumentElement::Text(text) => text.to_owned(),
+            tree::DocumentElement::Function(func) => func.content.iter().filter_map(|e| match e {
+                tree::DocumentElement::Text(text) => Some(text.to_owned()),
+                _ => None,
+            }).collect(),
+        };
+        let root = match &tree.0 { tree::DocumentElement::Function(root) => root, _ => panic!("root is no call") };
+        let (pre, p) = match &root.content[..] {
+            [tree::DocumentElement::Function(pre), tree::DocumentElement::Function(p)] => (pre, p),
+            content => panic!("unexpected content {content:?}"),
+        };
+        assert_eq!(pre.content.iter().map(text).collect::<Vec<String>>(), vec!["    ", " x", "  \n  ", "y ", "\n"]);
+        assert_eq!(text(&tree::DocumentElement::Function(p.clone())), "z");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_alternative_raw_delimiters() -> Result<(), errors::Error> {
+        let input = "{|| <b> x | y ||}";
+        let options = lexer::LexerOptions { open_raw: '|', close_raw: '|', ..Default::default() };
+        let lex = lexer::Lexer::with_options(input, options);
+        let mut par = Parser::new(path::Path::new("example"), input);
+        par.consume_iter(lex.iter())?;
+
+        match par.tree().0 {
+            tree::DocumentElement::Function(doc) => match &doc.content[0] {
+                tree::DocumentElement::Function(raw) => {
+                    assert_eq!(raw.call, "<<");
+                    assert_eq!(raw.content, vec![tree::DocumentElement::Text("<b> x | y".to_string())]);
+                    assert_eq!(raw.span(), Some(0..input.len()));
+                },
+                _ => panic!("expected a raw string as first element of the document"),
+            },
+            tree::DocumentElement::Text(_) => panic!("expected the document root to be a function"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_raw_language_tags() -> Result<(), errors::Error> {
+        let parse = |input: &str, options: lexer::LexerOptions| -> Result<tree::DocumentTree, errors::Error> {
+            let lex = lexer::Lexer::with_options(input, options);
+            let mut par = Parser::new(path::Path::new("example"), input);
+            par.consume_iter(lex.iter())?;
+            Ok(par.tree())
+        };
+
+        let input = "{<<<lua print(\">>\") >>>}{<< plain >>}";
+        let tree = parse(input, lexer::LexerOptions::default())?;
+        let raws = tree.find_all(|func| func.call.starts_with(lexer::OPEN_RAW));
+        assert_eq!(raws[0].call, "<<<");
+        assert_eq!(raws[0].text_arg(tree::LANGUAGE_ARG), Some("lua"));
+        assert_eq!(raws[0].content, vec![tree::DocumentElement::Text("print(\">>\")".to_string())]);
+        assert_eq!(raws[1].text_arg(tree::LANGUAGE_ARG), None);
+        assert_eq!(tree.to_litua_syntax(), input);
+
+        let options = lexer::LexerOptions { open_raw: '|', close_raw: '|', ..Default::default() };
+        let tree = parse("{|||c++ a || b |||}", options)?;
+        assert_eq!(tree.find_all(|func| func.call == "<<<")[0].text_arg(tree::LANGUAGE_ARG), Some("c++"));
+
+        assert!(parse("{<<lua<< x >>}", lexer::LexerOptions::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_line_calls() -> Result<(), errors::Error> {
+        let parse = |input: &str| -> Result<String, errors::Error> {
+            let options = lexer::LexerOptions { line_calls: true, ..Default::default() };
+            let lex = lexer::Lexer::with_options(input, options);
+            let mut par = Parser::new(path::Path::new("example"), input);
+            par.consume_iter(lex.iter())?;
+            par.finalize()?;
+            Ok(par.tree().to_litua_syntax())
+        };
+        let shorthand = "@section[level=2] Heading {em text}\nProse a@b\n{list\n  @item one\n  @toc\n  @item[x=y] two}\n@end";
+        let braces = "{section[level=2] Heading {em text}}\nProse a@b\n{list\n  {item one}\n  {toc}\n  {item[x=y] two}}\n{end}";
+        assert_eq!(parse(shorthand)?, parse(braces)?);
+        assert!(parse("@<<< raw >>>}").is_err());
         Ok(())
     }
 }
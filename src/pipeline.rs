@@ -0,0 +1,256 @@
+//! Lexing and parsing a text document in one step
+//!
+//! `Pipeline` combines the configuration of the lexer and the parser
+//! as well as the token filters run in between.
+//! Running the Lua hooks is not part of the library.
+
+use std::cell;
+use std::iter;
+use std::path;
+use std::sync::Arc;
+
+use crate::errors;
+use crate::header;
+use crate::lexer;
+use crate::parser;
+use crate::token_filter;
+use crate::tree;
+
+/// `Pipeline` turns the source code of text documents into trees
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Pipeline {
+    lexer_options: lexer::LexerOptions,
+    parser: parser::ParserBuilder,
+    filters: Vec<Arc<dyn token_filter::TokenFilter>>,
+}
+
+impl Pipeline {
+    /// Create a pipeline with the default syntax and parser configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a pipeline with the syntax configured by the directives in the header
+    /// of the text document `src` (see `header::apply_lexer_directives`)
+    pub fn for_document(src: &str) -> Result<Self, errors::Error> {
+        let options = header::apply_lexer_directives(&lexer::LexerOptions::default(), &header::directives(src)?)?;
+        Ok(Self::new().lexer_options(options))
+    }
+
+    /// Returns the syntax configuration used for lexing
+    pub fn syntax(&self) -> &lexer::LexerOptions {
+        &self.lexer_options
+    }
+
+    /// Use the syntax configuration `options` for lexing
+    pub fn lexer_options(mut self, options: lexer::LexerOptions) -> Self {
+        self.lexer_options = options;
+        self
+    }
+
+    /// Use the parser configuration `builder` for parsing
+    pub fn parser(mut self, builder: parser::ParserBuilder) -> Self {
+        self.parser = builder;
+        self
+    }
+
+    /// Run `filter` on the tokens after the filters registered before
+    pub fn token_filter(mut self, filter: Arc<dyn token_filter::TokenFilter>) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Returns the tokens of the text document `src` as consumed by the parser
+    /// (i.e. after running the token filters)
+    pub fn tokens<'s>(&self, src: &'s str) -> token_filter::TokenStream<'s> {
+        let tokens: token_filter::TokenStream<'s> = Box::new(lexer::LexingIterator::with_options(src, self.lexer_options.clone()));
+        self.filters.iter().fold(tokens, |tokens, filter| filter.filter(src, tokens))
+    }
+
+    /// Lex and parse the text document `src` read from `filepath`. Byte offsets
+    /// of errors refer to `src` (see `errors::Error::format_with_source`).
+    pub fn parse(&self, filepath: &path::Path, src: &str) -> Result<tree::DocumentTree, errors::Error> {
+        let mut par = self.parser.clone().build(filepath, src);
+        par.consume_tokens(self.tokens(src))?;
+        par.finalize()?;
+        Ok(par.tree())
+    }
+
+    /// Lex and parse the text document `src` read from `filepath` incrementally.
+    /// The returned iterator yields one tree per top-level call (including
+    /// the texts preceding it) with the root call of the document, so only
+    /// one top-level call is kept in memory at a time. Byte offsets refer to `src`.
+    pub fn chunks<'s>(&self, filepath: &path::Path, src: &'s str) -> Chunks<'s> {
+        Chunks {
+            tokens: self.tokens(src).peekable(),
+            parser: self.parser.clone().build(filepath, src),
+            done: false,
+            failed: false,
+        }
+    }
+
+    /// Lex and parse the text document `src` read from `filepath` and report
+    /// every token and every completed top-level element to `on_event` as soon
+    /// as it is produced. Byte offsets refer to `src`.
+    pub fn parse_with_events<F: FnMut(Event)>(&self, filepath: &path::Path, src: &str, on_event: F) -> Result<tree::DocumentTree, errors::Error> {
+        let on_event = cell::RefCell::new(on_event);
+        let mut tokens = self.tokens(src)
+            .inspect(|token| if let Ok(token) = token {
+                (on_event.borrow_mut())(Event::Token(token));
+            })
+            .peekable();
+        let mut par = self.parser.clone().build(filepath, src);
+        while par.consume_element(&mut tokens)? {
+            if let Some(element) = par.pending().last() {
+                (on_event.borrow_mut())(Event::Element(element));
+            }
+        }
+        par.finalize()?;
+        Ok(par.tree())
+    }
+}
+
+/// `Event` is reported by `Pipeline::parse_with_events`
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Event<'e> {
+    /// the lexer emitted a token
+    Token(&'e lexer::Token),
+    /// the parser completed a top-level element (adjacent texts might be merged into one element)
+    Element(&'e tree::DocumentElement),
+}
+
+impl Event<'_> {
+    /// Represent this event as JSON object with key `event` (`token` or `element`).
+    /// Tokens have keys `token` (its name), `start`, `end` (if the token covers a
+    /// byte range), and `call` (for `Call` tokens). Elements have keys `call`,
+    /// `start`, and `end` for calls or `text_bytes` for texts.
+    pub fn to_json(&self, src: &str) -> serde_json::Value {
+        match self {
+            Event::Token(token) => {
+                let mut json = serde_json::json!({ "event": "token", "token": token.name() });
+                let (start, end) = token.byte_offsets();
+                json["start"] = start.into();
+                if let Some(end) = end {
+                    json["end"] = end.into();
+                }
+                if let lexer::Token::Call(range) = token {
+                    json["call"] = src.get(range.clone()).unwrap_or_default().into();
+                }
+                json
+            },
+            Event::Element(tree::DocumentElement::Function(func)) => {
+                let mut json = serde_json::json!({ "event": "element", "call": func.call });
+                if let Some(span) = func.span() {
+                    json["start"] = span.start.into();
+                    json["end"] = span.end.into();
+                }
+                json
+            },
+            Event::Element(tree::DocumentElement::Text(text)) => serde_json::json!({ "event": "element", "text_bytes": text.len() }),
+        }
+    }
+}
+
+/// `Chunks` is the iterator over the top-level calls of a text document (see `Pipeline::chunks`)
+pub struct Chunks<'s> {
+    tokens: iter::Peekable<token_filter::TokenStream<'s>>,
+    parser: parser::Parser<'s>,
+    done: bool,
+    failed: bool,
+}
+
+impl Iterator for Chunks<'_> {
+    type Item = Result<tree::DocumentTree, errors::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        while !self.done {
+            match self.parser.consume_element(&mut self.tokens) {
+                Ok(true) if matches!(self.parser.pending().last(), Some(tree::DocumentElement::Function(_))) => break,
+                Ok(true) => {},
+                Ok(false) => self.done = true,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                },
+            }
+        }
+
+        if self.parser.pending().is_empty() {
+            return None;
+        }
+        Some(self.parser.take_tree())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use std::path;
+
+    #[test]
+    fn parse_with_prelude() -> Result<(), Error> {
+        let pipeline = Pipeline::new()
+            .lexer_options(LexerOptions { open_raw: '|', close_raw: '|', ..LexerOptions::default() })
+            .parser(ParserBuilder::new().root_call("doc"));
+        let tree = pipeline.parse(path::Path::new("a.lit"), "{p {|| x ||}}")?;
+        match tree.0 {
+            DocumentElement::Function(root) => {
+                assert_eq!(root.call, "doc");
+                assert!(matches!(&root.content[0], DocumentElement::Function(p) if p.call == "p"));
+            },
+            DocumentElement::Text(_) => panic!("root must be a call"),
+        }
+        assert!(pipeline.parse(path::Path::new("a.lit"), "{p").is_err());
+        assert_eq!(parse_str("{p x}").expect("valid document").to_litua_syntax(), "{p x}");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_in_chunks() -> Result<(), Error> {
+        let src = "intro {p one}\n\n{p[a=b] two {i x}} outro";
+        let chunks = Pipeline::new().chunks(path::Path::new("a.lit"), src).collect::<Result<Vec<_>, _>>()?;
+        let syntax: Vec<String> = chunks.iter().map(|c| c.to_litua_syntax()).collect();
+        assert_eq!(syntax, ["intro {p one}", "\n\n{p[a=b] two {i x}}", " outro"]);
+        match &chunks[1].0 {
+            DocumentElement::Function(root) => assert_eq!(root.call, "document"),
+            DocumentElement::Text(_) => panic!("root must be a call"),
+        }
+
+        let mut failing = Pipeline::new().chunks(path::Path::new("a.lit"), "{p one} {p");
+        assert!(failing.next().is_some_and(|c| c.is_ok()));
+        assert!(failing.next().is_some_and(|c| c.is_err()));
+        assert!(failing.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn report_events_while_parsing() -> Result<(), Error> {
+        let src = "a {p[k=v] b}";
+        let mut events = vec![];
+        let tree = Pipeline::new().parse_with_events(path::Path::new("a.lit"), src, |event| events.push(event.to_json(src)))?;
+        assert_eq!(tree.to_litua_syntax(), src);
+        let names: Vec<&str> = events.iter().map(|e| e["token"].as_str().or(e["call"].as_str()).unwrap_or("text")).collect();
+        assert_eq!(names, ["Text", "text", "BeginFunction", "Call", "BeginArgs", "ArgKey", "BeginArgValue", "Text", "EndArgValue", "EndArgs",
+            "Whitespace", "BeginContent", "Text", "EndContent", "EndFunction", "p", "EndOfFile"]);
+        assert_eq!(events[15], serde_json::json!({ "event": "element", "call": "p", "start": 2, "end": 12 }));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_on_worker_threads() {
+        let pipeline = Pipeline::new();
+        let sources: Vec<String> = (0..8).map(|i| format!("{{p[n={i}] text {i}}}")).collect();
+        let trees: Vec<DocumentTree> = std::thread::scope(|scope| {
+            let workers: Vec<_> = sources.iter()
+                .map(|src| scope.spawn(|| pipeline.parse(path::Path::new("a.lit"), src)))
+                .collect();
+            workers.into_iter().map(|w| w.join().expect("worker panicked").expect("valid document")).collect()
+        });
+        for (src, tree) in sources.iter().zip(trees) {
+            assert_eq!(&tree.to_litua_syntax(), src);
+        }
+    }
+}
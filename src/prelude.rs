@@ -0,0 +1,14 @@
+//! Types supported for using litua as library
+//!
+//! ``use litua::prelude::*;`` imports everything needed to lex and parse
+//! text documents. Items re-exported here follow semantic versioning.
+//! Other public items of the modules are available, but may change
+//! in minor releases (e.g. enums like `lexer::LexingState` gain variants).
+
+pub use crate::errors::Error;
+pub use crate::lexer::{Lexer, LexerOptions, Token};
+pub use crate::parse_str;
+pub use crate::parser::{Parser, ParserBuilder};
+pub use crate::pipeline::Pipeline;
+pub use crate::token_filter::TokenFilter;
+pub use crate::tree::{DocumentElement, DocumentFunction, DocumentTree};
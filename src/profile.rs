@@ -0,0 +1,99 @@
+//! Profiling of hook invocations
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// `Stats` accumulates the invocations of one hook or for one call name
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Stats {
+    /// number of invocations
+    pub count: usize,
+    /// cumulative duration of all invocations in seconds
+    pub total_seconds: f64,
+}
+
+/// `Profile` accumulates the duration of hook invocations
+/// per registered hook and per call name
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Profile {
+    /// statistics per registered hook (identified by its source location)
+    pub hooks: HashMap<String, Stats>,
+    /// statistics per call name of the nodes the hooks were invoked for
+    pub calls: HashMap<String, Stats>,
+}
+
+impl Profile {
+    /// Record one invocation of hook `hook_src` for call `call`
+    /// (empty for global hooks like on_setup) which took `seconds`
+    pub fn record(&mut self, hook_src: &str, call: &str, seconds: f64) {
+        let stats = self.hooks.entry(hook_src.to_owned()).or_default();
+        stats.count += 1;
+        stats.total_seconds += seconds;
+
+        if !call.is_empty() {
+            let stats = self.calls.entry(call.to_owned()).or_default();
+            stats.count += 1;
+            stats.total_seconds += seconds;
+        }
+    }
+
+    /// Return the entries of `stats` sorted by cumulative duration (descending)
+    fn sorted(stats: &HashMap<String, Stats>) -> Vec<(&String, &Stats)> {
+        let mut entries: Vec<(&String, &Stats)> = stats.iter().collect();
+        entries.sort_by(|a, b| b.1.total_seconds.total_cmp(&a.1.total_seconds).then_with(|| a.0.cmp(b.0)));
+        entries
+    }
+
+    /// Represent the profile as human-readable table
+    pub fn to_table(&self) -> String {
+        let mut out = String::new();
+        for (title, stats) in [("hook", &self.hooks), ("call", &self.calls)] {
+            let _ = writeln!(out, "{:>12} {:>8} {:>10}  {title}", "total ms", "count", "mean ms");
+            for (name, entry) in Self::sorted(stats) {
+                let total_ms = entry.total_seconds * 1000.0;
+                let _ = writeln!(out, "{:>12.3} {:>8} {:>10.3}  {name}", total_ms, entry.count, total_ms / entry.count as f64);
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Represent the profile as JSON object
+    pub fn to_json(&self) -> serde_json::Value {
+        let entries = |stats: &HashMap<String, Stats>| -> Vec<serde_json::Value> {
+            Self::sorted(stats).into_iter().map(|(name, entry)| serde_json::json!({
+                "name": name,
+                "count": entry.count,
+                "total_ms": entry.total_seconds * 1000.0,
+            })).collect()
+        };
+
+        serde_json::json!({
+            "hooks": entries(&self.hooks),
+            "calls": entries(&self.calls),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulate_per_hook_and_call() {
+        let mut profile = Profile::default();
+        profile.record("hook A", "b", 0.5);
+        profile.record("hook A", "i", 0.25);
+        profile.record("hook B", "b", 1.0);
+        profile.record("hook C", "", 2.0);
+
+        assert_eq!(profile.hooks["hook A"], Stats { count: 2, total_seconds: 0.75 });
+        assert_eq!(profile.calls["b"], Stats { count: 2, total_seconds: 1.5 });
+        assert!(!profile.calls.contains_key(""));
+
+        let json = profile.to_json();
+        assert_eq!(json["hooks"][0]["name"], "hook C");
+        assert_eq!(json["calls"][0]["name"], "b");
+        assert_eq!(json["calls"][1]["count"], 1);
+    }
+}
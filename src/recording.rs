@@ -0,0 +1,297 @@
+//! Recording and replay of hook invocations
+//!
+//! A `Recording` captures the registered hooks and every hook invocation of a
+//! run with its inputs, outputs, or error (see `--record`). Replaying it (see
+//! `--replay`) runs the pipeline without loading any hook file: every hook
+//! invocation returns the recorded outputs instead, so behavior depending on
+//! the environment of the hooks (time, files, network, random numbers) is
+//! reproduced. Since the invocations are replayed in order, the inputs of each
+//! invocation must match the recorded ones, otherwise the replay diverges.
+//!
+//! Lua values are represented as JSON: nil, booleans, numbers, and strings as
+//! the corresponding JSON values, nodes as `{"node": …}` (see
+//! `tree::DocumentElement::to_json`), sequences as `{"array": […]}`, other
+//! tables as `{"table": {…}}` (with keys converted to strings), and values
+//! which cannot be represented (like functions) as `{"unsupported": "function"}`.
+
+use crate::tree;
+
+/// Version of the recording format
+pub const FORMAT_VERSION: u64 = 1;
+
+/// Maximum nesting depth of Lua tables represented in recordings
+const MAX_DEPTH: usize = 64;
+
+/// `Registration` is one hook registered by a hook file
+#[derive(Clone,Debug,PartialEq)]
+pub struct Registration {
+    /// name of the hook like `modify_node`
+    pub hook: String,
+    /// call name the hook is registered for (empty for every call)
+    pub filter: String,
+    /// location of the registration like `modify_node hook from @hooks.lua at line 3`
+    pub src: String,
+}
+
+/// `Invocation` is one invocation of a hook
+#[derive(Clone,Debug,PartialEq)]
+pub struct Invocation {
+    pub hook: String,
+    pub src: String,
+    /// call name of the node (empty for global hooks)
+    pub call: String,
+    /// path of the node within the tree (empty for global hooks)
+    pub path: String,
+    /// arguments passed to the hook
+    pub inputs: Vec<serde_json::Value>,
+    /// values returned by the hook
+    pub outputs: Vec<serde_json::Value>,
+    /// message of the error raised by the hook (if any)
+    pub error: Option<String>,
+}
+
+/// `Recording` captures the hooks of one run
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Recording {
+    /// filepath of the source document
+    pub source: String,
+    pub registrations: Vec<Registration>,
+    /// value of `Litua.vars` before the transformation
+    pub vars: serde_json::Value,
+    pub invocations: Vec<Invocation>,
+}
+
+fn string_field(object: &serde_json::Value, key: &str, what: &str) -> Result<String, String> {
+    object.get(key).and_then(serde_json::Value::as_str).map(str::to_owned)
+        .ok_or_else(|| format!("{what} must have a key '{key}' with a string value"))
+}
+
+fn array_field<'v>(object: &'v serde_json::Value, key: &str, what: &str) -> Result<&'v Vec<serde_json::Value>, String> {
+    object.get(key).and_then(serde_json::Value::as_array)
+        .ok_or_else(|| format!("{what} must have a key '{key}' with an array value"))
+}
+
+impl Recording {
+    /// Create an empty recording of the run of source document `source`
+    pub fn new(source: &str) -> Self {
+        Recording { source: source.to_owned(), ..Recording::default() }
+    }
+
+    /// Represent the recording as JSON object with keys `version`, `source`,
+    /// `hooks`, `vars`, and `invocations`
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "version": FORMAT_VERSION,
+            "source": self.source,
+            "hooks": self.registrations.iter().map(|registration| serde_json::json!({
+                "hook": registration.hook,
+                "filter": registration.filter,
+                "src": registration.src,
+            })).collect::<Vec<_>>(),
+            "vars": self.vars,
+            "invocations": self.invocations.iter().map(|invocation| serde_json::json!({
+                "hook": invocation.hook,
+                "src": invocation.src,
+                "call": invocation.call,
+                "path": invocation.path,
+                "inputs": invocation.inputs,
+                "outputs": invocation.outputs,
+                "error": invocation.error,
+            })).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Read a recording from its JSON representation (see `Recording::to_json`)
+    pub fn from_json(value: &serde_json::Value) -> Result<Recording, String> {
+        match value.get("version").and_then(serde_json::Value::as_u64) {
+            Some(FORMAT_VERSION) => {},
+            Some(version) => return Err(format!("unsupported recording format version {version}, expected {FORMAT_VERSION}")),
+            None => return Err("a recording must have a key 'version' with an integer value".to_owned()),
+        }
+        let mut recording = Recording::new(&string_field(value, "source", "a recording")?);
+        for registration in array_field(value, "hooks", "a recording")? {
+            recording.registrations.push(Registration {
+                hook: string_field(registration, "hook", "a hook")?,
+                filter: string_field(registration, "filter", "a hook")?,
+                src: string_field(registration, "src", "a hook")?,
+            });
+        }
+        recording.vars = value.get("vars").cloned().unwrap_or(serde_json::Value::Null);
+        for invocation in array_field(value, "invocations", "a recording")? {
+            recording.invocations.push(Invocation {
+                hook: string_field(invocation, "hook", "an invocation")?,
+                src: string_field(invocation, "src", "an invocation")?,
+                call: string_field(invocation, "call", "an invocation")?,
+                path: string_field(invocation, "path", "an invocation")?,
+                inputs: array_field(invocation, "inputs", "an invocation")?.clone(),
+                outputs: array_field(invocation, "outputs", "an invocation")?.clone(),
+                error: invocation.get("error").and_then(serde_json::Value::as_str).map(str::to_owned),
+            });
+        }
+        Ok(recording)
+    }
+}
+
+/// `Replay` hands out the recorded invocations in order
+#[derive(Clone,Debug)]
+pub struct Replay {
+    pub recording: Recording,
+    position: usize,
+}
+
+impl Replay {
+    /// Replay `recording` from its first invocation
+    pub fn new(recording: Recording) -> Self {
+        Replay { recording, position: 0 }
+    }
+
+    /// Returns the next recorded invocation if it is an invocation of hook `src`
+    /// for the node at `path` with arguments `inputs` and an error otherwise
+    pub fn next(&mut self, hook: &str, src: &str, call: &str, path: &str, inputs: &[serde_json::Value]) -> Result<&Invocation, String> {
+        let number = self.position + 1;
+        let describe = |hook: &str, src: &str, call: &str, path: &str| match call {
+            "" => format!("{hook} hook ({src})"),
+            _ => format!("{hook} hook ({src}) for call '{call}' at {path}"),
+        };
+        let invocation = match self.recording.invocations.get(self.position) {
+            Some(invocation) => invocation,
+            None => return Err(format!("replay diverged at hook invocation {number}: the recording ends, but the run invokes the {}", describe(hook, src, call, path))),
+        };
+        if (invocation.hook.as_str(), invocation.src.as_str(), invocation.call.as_str(), invocation.path.as_str()) != (hook, src, call, path) {
+            return Err(format!("replay diverged at hook invocation {number}: the recording invokes the {}, but the run invokes the {}",
+                describe(&invocation.hook, &invocation.src, &invocation.call, &invocation.path), describe(hook, src, call, path)));
+        }
+        if invocation.inputs != inputs {
+            return Err(format!("replay diverged at hook invocation {number}: the {} receives other arguments than recorded", describe(hook, src, call, path)));
+        }
+        self.position += 1;
+        Ok(invocation)
+    }
+
+    /// Returns the number of recorded invocations not replayed yet
+    pub fn remaining(&self) -> usize {
+        self.recording.invocations.len() - self.position
+    }
+}
+
+/// Represent the Lua value `value` as JSON (see module documentation)
+pub fn lua_to_json(lua: &mlua::Lua, value: mlua::Value) -> mlua::Result<serde_json::Value> {
+    lua_to_json_at(lua, value, 0)
+}
+
+fn lua_to_json_at(lua: &mlua::Lua, value: mlua::Value, depth: usize) -> mlua::Result<serde_json::Value> {
+    use mlua::FromLua;
+
+    Ok(match value {
+        mlua::Value::Nil => serde_json::Value::Null,
+        mlua::Value::Boolean(boolean) => serde_json::Value::from(boolean),
+        mlua::Value::Integer(integer) => serde_json::Value::from(integer),
+        mlua::Value::Number(number) => serde_json::Value::from(number),
+        mlua::Value::String(string) => serde_json::Value::from(String::from_utf8_lossy(string.as_bytes()).into_owned()),
+        mlua::Value::Table(_) if depth >= MAX_DEPTH => serde_json::json!({ "unsupported": "table nested too deeply" }),
+        mlua::Value::Table(table) => {
+            let is_node = matches!(table.get::<_, mlua::Value>("call")?, mlua::Value::String(_))
+                && matches!(table.get::<_, mlua::Value>("content")?, mlua::Value::Table(_));
+            if is_node {
+                let element = tree::DocumentElement::from_lua(mlua::Value::Table(table), lua)?;
+                return Ok(serde_json::json!({ "node": element.to_json() }));
+            }
+            let length = table.raw_len() as usize;
+            let mut count = 0;
+            let mut entries = serde_json::Map::new();
+            for pair in table.clone().pairs::<mlua::Value, mlua::Value>() {
+                let (key, value) = pair?;
+                count += 1;
+                let key = match key {
+                    mlua::Value::String(key) => String::from_utf8_lossy(key.as_bytes()).into_owned(),
+                    mlua::Value::Integer(key) => key.to_string(),
+                    mlua::Value::Number(key) => key.to_string(),
+                    mlua::Value::Boolean(key) => key.to_string(),
+                    other => format!("<{}>", other.type_name()),
+                };
+                entries.insert(key, lua_to_json_at(lua, value, depth + 1)?);
+            }
+            if length > 0 && count == length {
+                let values = (1..=length).map(|index| entries.remove(&index.to_string()).unwrap_or_default()).collect::<Vec<_>>();
+                serde_json::json!({ "array": values })
+            } else {
+                serde_json::json!({ "table": entries })
+            }
+        },
+        other => serde_json::json!({ "unsupported": other.type_name() }),
+    })
+}
+
+/// Create the Lua value represented by `value` (see module documentation).
+/// Nodes are converted by `to_node` (e.g. to `Litua.Node` objects).
+pub fn json_to_lua<'lua, F>(lua: &'lua mlua::Lua, value: &serde_json::Value, to_node: &F) -> mlua::Result<mlua::Value<'lua>>
+    where F: Fn(mlua::Value<'lua>) -> mlua::Result<mlua::Value<'lua>>
+{
+    use mlua::ToLua;
+
+    Ok(match value {
+        serde_json::Value::Null => mlua::Value::Nil,
+        serde_json::Value::Bool(boolean) => mlua::Value::Boolean(*boolean),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(integer) => mlua::Value::Integer(integer),
+            None => mlua::Value::Number(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(string) => mlua::Value::String(lua.create_string(string)?),
+        serde_json::Value::Array(_) => return Err(mlua::Error::external("unexpected JSON array in recording")),
+        serde_json::Value::Object(object) => {
+            if let Some(node) = object.get("node") {
+                let element = tree::DocumentElement::from_json(node).map_err(mlua::Error::external)?;
+                return to_node((&element).to_lua(lua)?);
+            }
+            let table = lua.create_table()?;
+            if let Some(serde_json::Value::Array(values)) = object.get("array") {
+                for (index, value) in values.iter().enumerate() {
+                    table.raw_set(index + 1, json_to_lua(lua, value, to_node)?)?;
+                }
+            } else if let Some(serde_json::Value::Object(entries)) = object.get("table") {
+                for (key, value) in entries.iter() {
+                    match key.parse::<i64>() {
+                        Ok(index) => table.raw_set(index, json_to_lua(lua, value, to_node)?)?,
+                        Err(_) => table.raw_set(key.as_str(), json_to_lua(lua, value, to_node)?)?,
+                    }
+                }
+            } else {
+                return Ok(mlua::Value::Nil);
+            }
+            mlua::Value::Table(table)
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_replay_invocations() -> mlua::Result<()> {
+        let lua = mlua::Lua::new();
+        let value: mlua::Value = lua.load(r#"{ { call = "em", args = { class = { "x" } }, content = { "hi" } }, 42, 0.5, true, { key = "value" } }"#).eval()?;
+        let json = lua_to_json(&lua, value)?;
+        assert_eq!(json, serde_json::json!({ "array": [
+            { "node": { "call": "em", "args": { "class": ["x"] }, "content": ["hi"] } },
+            42, 0.5, true, { "table": { "key": "value" } },
+        ]}));
+        let restored = json_to_lua(&lua, &json, &|node| Ok(node))?;
+        assert_eq!(lua_to_json(&lua, restored)?, json);
+
+        let invocation = Invocation {
+            hook: "modify_node".to_owned(), src: "hooks.lua:3".to_owned(), call: "em".to_owned(), path: "/document/em[1]".to_owned(),
+            inputs: vec![serde_json::json!(1)], outputs: vec![serde_json::json!("x")], error: None,
+        };
+        let mut recording = Recording::new("doc.lit");
+        recording.invocations.push(invocation.clone());
+        let recording = Recording::from_json(&recording.to_json()).map_err(mlua::Error::external)?;
+
+        let mut replay = Replay::new(recording.clone());
+        assert!(replay.next("modify_node", "hooks.lua:3", "em", "/document/em[1]", &[serde_json::json!(2)]).unwrap_err().contains("other arguments"));
+        assert_eq!(replay.next("modify_node", "hooks.lua:3", "em", "/document/em[1]", &[serde_json::json!(1)]), Ok(&invocation));
+        assert_eq!(replay.remaining(), 0);
+        assert!(replay.next("on_teardown", "hooks.lua:9", "", "", &[]).unwrap_err().contains("recording ends"));
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+//! Minimal HTTP server publishing a JSON document
+//!
+//! `litua check --watch --serve ADDR` uses it to publish the diagnostics of
+//! the latest check for dashboards and editors. The server answers `GET /`
+//! and `GET /diagnostics` with the current JSON document, handles one request
+//! per connection, and runs in a background thread.
+
+use std::io;
+use std::io::prelude::*;
+use std::net;
+use std::sync;
+use std::thread;
+
+/// `Published` is the JSON document shared between the server and the code updating it
+pub type Published = sync::Arc<sync::Mutex<serde_json::Value>>;
+
+/// Returns the socket address for `addr`, where an omitted host
+/// (like in `:8090`) refers to the loopback interface
+pub fn socket_address(addr: &str) -> String {
+    match addr.strip_prefix(':') {
+        Some(port) => format!("127.0.0.1:{port}"),
+        None => addr.to_owned(),
+    }
+}
+
+/// Serve `published` at address `addr` in a background thread
+/// and return the address the server is listening at
+pub fn spawn(addr: &str, published: Published) -> io::Result<net::SocketAddr> {
+    let listener = net::TcpListener::bind(socket_address(addr))?;
+    let local_addr = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // NOTE: a client closing the connection early must not stop the server
+            let _ = respond(stream, &published);
+        }
+    });
+    Ok(local_addr)
+}
+
+fn respond(mut stream: net::TcpStream, published: &Published) -> io::Result<()> {
+    let mut reader = io::BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // NOTE: consume the header lines, the request body is ignored
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && !line.trim().is_empty() {
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/" | "/diagnostics")) => {
+            let document = published.lock().map(|document| document.to_string()).unwrap_or_else(|_| "null".to_owned());
+            ("200 OK", document)
+        },
+        (Some("GET"), _) => ("404 Not Found", serde_json::json!({ "error": "not found" }).to_string()),
+        _ => ("405 Method Not Allowed", serde_json::json!({ "error": "method not allowed" }).to_string()),
+    };
+    write!(stream, "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+        Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{body}", body.len())?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serve_published_document() -> io::Result<()> {
+        let published: Published = sync::Arc::new(sync::Mutex::new(serde_json::json!({ "ok": true })));
+        let addr = spawn("127.0.0.1:0", published.clone())?;
+        let get = |path: &str| -> io::Result<String> {
+            let mut stream = net::TcpStream::connect(addr)?;
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        };
+
+        assert!(get("/diagnostics")?.starts_with("HTTP/1.1 200 OK\r\n") && get("/")?.ends_with("\r\n\r\n{\"ok\":true}"));
+        *published.lock().expect("lock is not poisoned") = serde_json::json!({ "ok": false });
+        assert!(get("/")?.ends_with("{\"ok\":false}"));
+        assert!(get("/other")?.starts_with("HTTP/1.1 404"));
+        assert_eq!(socket_address(":8090"), "127.0.0.1:8090");
+        Ok(())
+    }
+}
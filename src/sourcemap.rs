@@ -0,0 +1,273 @@
+//! Source maps relating the generated output back to the litua text document
+//!
+//! The output of calls is located in the final output by searching for the
+//! string representations recorded during the transformation (see `SourceMap::locate`).
+//! Hooks can relate output to calls precisely with `Litua.span(text, node)`,
+//! which surrounds `text` with markers of private-use characters like
+//! `MARK_START 42 MARK_SEPARATOR text MARK_END`. `strip_markers` removes
+//! them from the final output and returns the ranges of the marked texts.
+
+use std::ops;
+use std::path;
+
+use crate::text::LineIndex;
+
+/// Character starting a marker of `Litua.span` (followed by the decimal id of the span)
+pub const MARK_START: char = '\u{E000}';
+/// Character terminating the id of a marker of `Litua.span` (followed by the marked text)
+pub const MARK_SEPARATOR: char = '\u{E001}';
+/// Character terminating the text marked by `Litua.span`
+pub const MARK_END: char = '\u{E002}';
+
+/// `MarkedSpan` is the range of the output marked by `Litua.span` with the id of its marker
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct MarkedSpan {
+    pub id: usize,
+    pub output: ops::Range<usize>,
+}
+
+/// Removes the markers of `Litua.span` from `output` and returns the output
+/// without markers and the marked ranges within it (in the order the markers
+/// end). Markers may be nested. Unterminated markers extend to the end of the
+/// output and terminating markers without start are dropped.
+pub fn strip_markers(output: &[u8]) -> (Vec<u8>, Vec<MarkedSpan>) {
+    let encode = |chr: char| chr.to_string().into_bytes();
+    let (start, separator, end) = (encode(MARK_START), encode(MARK_SEPARATOR), encode(MARK_END));
+
+    let mut stripped = Vec::with_capacity(output.len());
+    let mut spans = vec![];
+    let mut open: Vec<(usize, usize)> = vec![];
+    let mut i = 0;
+    while i < output.len() {
+        let rest = &output[i..];
+        if rest.starts_with(&start) {
+            let digits = rest[start.len()..].iter().take_while(|byte| byte.is_ascii_digit()).count();
+            let id = std::str::from_utf8(&rest[start.len()..start.len() + digits]).ok().and_then(|id| id.parse().ok());
+            if let Some(id) = id.filter(|_| rest[start.len() + digits..].starts_with(&separator)) {
+                open.push((id, stripped.len()));
+                i += start.len() + digits + separator.len();
+                continue;
+            }
+        } else if rest.starts_with(&end) {
+            if let Some((id, begin)) = open.pop() {
+                spans.push(MarkedSpan { id, output: begin..stripped.len() });
+            }
+            i += end.len();
+            continue;
+        }
+        stripped.push(output[i]);
+        i += 1;
+    }
+    while let Some((id, begin)) = open.pop() {
+        spans.push(MarkedSpan { id, output: begin..stripped.len() });
+    }
+    (stripped, spans)
+}
+
+/// `Fragment` is the string representation a single call produced
+/// during the transformation. `source` is the byte range of the call
+/// within the source document (as recorded in the `=span` argument)
+/// and `depth` is the depth of the call within the tree.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Fragment {
+    pub call: String,
+    pub source: ops::Range<usize>,
+    pub depth: usize,
+    pub output: Vec<u8>,
+}
+
+/// `Mapping` associates a byte range of the generated output
+/// with the byte range of the call in the source document
+/// which produced it.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Mapping {
+    pub call: String,
+    pub source: ops::Range<usize>,
+    pub output: ops::Range<usize>,
+}
+
+/// `SourceMap` is the set of `Mapping`s sorted by source position.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct SourceMap {
+    pub mappings: Vec<Mapping>,
+}
+
+/// Returns the offset of the first occurrence of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// A call, which has already been located within the output,
+/// and the region of the output its children must be found in.
+struct Frame {
+    source: ops::Range<usize>,
+    end: usize,
+    cursor: usize,
+}
+
+impl SourceMap {
+    /// Locate the `fragments` within the final `output`. Hooks might emit
+    /// binary data, so the output is searched bytewise.
+    ///
+    /// Hooks are arbitrary functions, so we cannot know how a call
+    /// contributed to the output. We assume that the output of some call
+    /// is contained in the output of its parent call and that siblings
+    /// occur in source order. Fragments which cannot be found
+    /// (e.g. because a hook discarded or rewrote them) are skipped.
+    pub fn locate(output: &[u8], fragments: &[Fragment]) -> SourceMap {
+        let mut ordered: Vec<&Fragment> = fragments.iter().collect();
+        ordered.sort_by_key(|f| (f.source.start, f.depth));
+
+        let mut mappings = Vec::new();
+        let mut stack = vec![Frame { source: 0..usize::MAX, end: output.len(), cursor: 0 }];
+
+        for fragment in ordered {
+            while stack.len() > 1 {
+                let top = &stack[stack.len() - 1];
+                if top.source.start <= fragment.source.start && fragment.source.end <= top.source.end {
+                    break;
+                }
+                stack.pop();
+            }
+
+            let parent = stack.len() - 1;
+            let (cursor, end) = (stack[parent].cursor, stack[parent].end);
+
+            let found = if fragment.output.is_empty() {
+                None
+            } else {
+                output.get(cursor..end).and_then(|region| find(region, &fragment.output))
+            };
+
+            match found {
+                Some(relative_offset) => {
+                    let start = cursor + relative_offset;
+                    let stop = start + fragment.output.len();
+                    stack[parent].cursor = stop;
+                    mappings.push(Mapping {
+                        call: fragment.call.clone(),
+                        source: fragment.source.clone(),
+                        output: start..stop,
+                    });
+                    stack.push(Frame { source: fragment.source.clone(), end: stop, cursor: start });
+                },
+                None => {
+                    // NOTE: children of unlocated calls are searched in the region of the parent
+                    stack.push(Frame { source: fragment.source.clone(), end, cursor });
+                },
+            }
+        }
+
+        SourceMap { mappings }
+    }
+
+    /// Add the `mappings` of texts marked by `Litua.span`. They replace the
+    /// mappings of the same calls found by `locate`, which only guess the
+    /// output range by searching for the string representation.
+    pub fn add_marked(&mut self, mappings: Vec<Mapping>) {
+        self.mappings.retain(|mapping| !mappings.iter().any(|marked| marked.call == mapping.call && marked.source == mapping.source));
+        self.mappings.extend(mappings);
+        self.mappings.sort_by_key(|mapping| (mapping.source.start, std::cmp::Reverse(mapping.source.end), mapping.output.start));
+    }
+
+    /// Represent this source map as JSON object. Source positions are
+    /// provided as byte offsets as well as (one-based) line and column
+    /// within `source_code` of the file at `filepath`.
+    pub fn to_json(&self, filepath: &path::Path, source_code: &str) -> serde_json::Value {
+        let lines = LineIndex::new(source_code);
+        let mappings: Vec<serde_json::Value> = self.mappings.iter().map(|mapping| {
+            let (start_line, start_column) = lines.line_column(source_code, mapping.source.start);
+            let (end_line, end_column) = lines.line_column(source_code, mapping.source.end);
+            serde_json::json!({
+                "call": mapping.call,
+                "output": { "start": mapping.output.start, "end": mapping.output.end },
+                "source": {
+                    "start": mapping.source.start,
+                    "end": mapping.source.end,
+                    "start_line": start_line,
+                    "start_column": start_column,
+                    "end_line": end_line,
+                    "end_column": end_column,
+                },
+            })
+        }).collect();
+
+        serde_json::json!({
+            "version": 1,
+            "source": filepath.display().to_string(),
+            "mappings": mappings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragment(call: &str, source: ops::Range<usize>, depth: usize, output: &str) -> Fragment {
+        Fragment { call: call.to_owned(), source, depth, output: output.as_bytes().to_vec() }
+    }
+
+    #[test]
+    fn locate_nested_fragments() {
+        // source: "{a {b x}{b y}} {c}"
+        let output = b"<a>[x][y]</a> C";
+        let fragments = vec![
+            fragment("b", 3..8, 1, "[x]"),
+            fragment("b", 8..13, 1, "[y]"),
+            fragment("a", 0..14, 0, "<a>[x][y]</a>"),
+            fragment("c", 15..18, 0, "C"),
+        ];
+        let map = SourceMap::locate(output, &fragments);
+        assert_eq!(map.mappings.len(), 4);
+        assert_eq!(map.mappings[0].output, 0..13);
+        assert_eq!(map.mappings[1].output, 3..6);
+        assert_eq!(map.mappings[2].output, 6..9);
+        assert_eq!(map.mappings[3].output, 14..15);
+    }
+
+    #[test]
+    fn skip_discarded_fragments() {
+        let output = b"hello";
+        let fragments = vec![
+            fragment("a", 0..5, 0, "bye"),
+            fragment("b", 6..10, 0, ""),
+            fragment("c", 11..20, 0, "hello"),
+        ];
+        let map = SourceMap::locate(output, &fragments);
+        assert_eq!(map.mappings, vec![Mapping { call: "c".to_owned(), source: 11..20, output: 0..5 }]);
+    }
+
+    #[test]
+    fn strip_span_markers() {
+        let output = "<p>\u{E000}1\u{E001}a \u{E000}2\u{E001}b\u{E002}\u{E002}</p>\u{E002}\u{E000}x\u{E000}3\u{E001}c";
+        let (stripped, spans) = strip_markers(output.as_bytes());
+        assert_eq!(String::from_utf8(stripped).expect("valid UTF-8"), "<p>a b</p>\u{E000}xc");
+        assert_eq!(spans, vec![
+            MarkedSpan { id: 2, output: 5..6 },
+            MarkedSpan { id: 1, output: 3..6 },
+            MarkedSpan { id: 3, output: 14..15 },
+        ]);
+
+        let mut map = SourceMap::locate(b"<p>a b</p>", &[fragment("p", 0..9, 0, "<p>a b</p>"), fragment("b", 3..8, 1, "b")]);
+        map.add_marked(vec![Mapping { call: "b".to_owned(), source: 3..8, output: 5..6 }]);
+        assert_eq!(map.mappings.iter().map(|m| m.output.clone()).collect::<Vec<_>>(), vec![0..10, 5..6]);
+    }
+
+    #[test]
+    fn locate_fragments_in_binary_output() {
+        let output = b"\xff\xfe<b>x</b>\x00";
+        let fragments = vec![Fragment { call: "b".to_owned(), source: 0..7, depth: 0, output: b"<b>x</b>".to_vec() }];
+        let map = SourceMap::locate(output, &fragments);
+        assert_eq!(map.mappings, vec![Mapping { call: "b".to_owned(), source: 0..7, output: 2..10 }]);
+    }
+
+    #[test]
+    fn line_and_column_of_offsets() {
+        let src = "ab\ncλd";
+        let lines = LineIndex::new(src);
+        assert_eq!(lines.line_column(src, 0), (1, 1));
+        assert_eq!(lines.line_column(src, 3), (2, 1));
+        assert_eq!(lines.line_column(src, 6), (2, 3));
+    }
+}
@@ -0,0 +1,120 @@
+//! Size statistics of a text document and its processing
+//!
+//! The statistics help users of very large documents to decide whether
+//! to split them into several files.
+
+use std::fmt::Write;
+use std::mem;
+
+use crate::tree;
+
+/// `Stats` are the sizes determined while processing one text document
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Stats {
+    /// size of the source file in bytes
+    pub source_bytes: usize,
+    /// number of tokens emitted by the lexer
+    pub tokens: usize,
+    /// number of calls in the tree (including the root call)
+    pub nodes: usize,
+    /// number of text elements in the tree
+    pub texts: usize,
+    /// estimated memory occupied by the parsed tree in bytes
+    pub tree_bytes: usize,
+    /// memory used by the Lua runtime after running the transformation hooks in bytes
+    pub lua_bytes: Option<usize>,
+    /// size of the output in bytes
+    pub output_bytes: Option<usize>,
+}
+
+impl Stats {
+    /// Determine the number of calls and texts and the estimated memory of `tree`.
+    /// Texts of reserved arguments (like `=span`) are not counted, but their memory is.
+    pub fn record_tree(&mut self, tree: &tree::DocumentTree) {
+        self.nodes = 0;
+        self.texts = 0;
+        self.tree_bytes = mem::size_of::<tree::DocumentTree>() + self.visit(&tree.0, true);
+    }
+
+    /// Count the elements of `element` (if `count` is set) and return the heap memory
+    /// it occupies (the element itself is accounted for by the container)
+    fn visit(&mut self, element: &tree::DocumentElement, count: bool) -> usize {
+        match element {
+            tree::DocumentElement::Text(text) => {
+                self.texts += usize::from(count);
+                text.capacity()
+            },
+            tree::DocumentElement::Function(func) => {
+                self.nodes += usize::from(count);
+                let mut bytes = func.call.capacity() + Self::node_bytes(&func.content);
+                // NOTE: a hash table stores its entries and one control byte per bucket
+                bytes += func.args.capacity() * (mem::size_of::<(String, tree::DocumentNode)>() + 1);
+                for (key, value) in func.args.iter() {
+                    let count_value = count && !tree::is_reserved_arg(key);
+                    bytes += key.capacity() + Self::node_bytes(value);
+                    bytes += value.iter().map(|e| self.visit(e, count_value)).sum::<usize>();
+                }
+                bytes + func.content.iter().map(|e| self.visit(e, count)).sum::<usize>()
+            },
+        }
+    }
+
+    /// Returns the memory of the element vector `node` without the heap memory of its elements
+    fn node_bytes(node: &tree::DocumentNode) -> usize {
+        node.capacity() * mem::size_of::<tree::DocumentElement>()
+    }
+
+    /// Represent the statistics as human-readable table
+    pub fn to_table(&self) -> String {
+        let mut out = String::new();
+        let mut row = |name: &str, value: Option<usize>, unit: &str| {
+            if let Some(value) = value {
+                let _ = writeln!(out, "{value:>14} {unit:<5} {name}");
+            }
+        };
+        row("source size", Some(self.source_bytes), "bytes");
+        row("tokens", Some(self.tokens), "");
+        row("nodes", Some(self.nodes), "");
+        row("texts", Some(self.texts), "");
+        row("tree memory (estimated)", Some(self.tree_bytes), "bytes");
+        row("Lua memory after transform", self.lua_bytes, "bytes");
+        row("output size", self.output_bytes, "bytes");
+        out
+    }
+
+    /// Represent the statistics as JSON object
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "source_bytes": self.source_bytes,
+            "tokens": self.tokens,
+            "nodes": self.nodes,
+            "texts": self.texts,
+            "tree_bytes": self.tree_bytes,
+            "lua_bytes": self.lua_bytes,
+            "output_bytes": self.output_bytes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_nodes_and_estimate_memory() {
+        let small = crate::parse_str("{p text}").expect("valid document");
+        let large = crate::parse_str("{p[a={b x}] text {i more} text}").expect("valid document");
+
+        let mut stats = Stats::default();
+        stats.record_tree(&small);
+        let small_bytes = stats.tree_bytes;
+        stats.record_tree(&large);
+        assert_eq!((stats.nodes, stats.texts), (4, 5));
+        assert!(stats.tree_bytes > small_bytes);
+
+        stats.output_bytes = Some(12);
+        let table = stats.to_table();
+        assert!(table.contains("output size") && !table.contains("Lua memory"));
+        assert_eq!(stats.to_json()["nodes"], 4);
+    }
+}
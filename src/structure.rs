@@ -0,0 +1,153 @@
+//! Structural assertions of calls for hooks (see `Litua.assert_structure`)
+//!
+//! A schema lists the argument keys and the call names of child calls a call
+//! may have. An entry is a name followed by an optional quantifier: `?` for
+//! optional, `*` for any number, `+` for at least one occurrence, and none for
+//! exactly one occurrence. Arguments only support `?`. The entry `*` admits
+//! other arguments (or child calls) not listed. Without a list of arguments
+//! (or children), the arguments (or children) are not checked. Reserved
+//! arguments (like `=span`) and text content are never checked. Default values
+//! of arguments declared in `Litua.defaults` are added before the `read_new_node`
+//! hooks run (see `Litua.apply_defaults`), so schemas see them as present.
+
+/// `Quantifier` determines how often a schema entry may occur
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Quantifier {
+    /// exactly once (no suffix)
+    One,
+    /// at most once (suffix `?`)
+    Optional,
+    /// any number of times (suffix `*`)
+    Any,
+    /// at least once (suffix `+`)
+    AtLeastOne,
+}
+
+impl Quantifier {
+    /// Does `count` occurrences satisfy this quantifier?
+    fn admits(&self, count: usize) -> bool {
+        match self {
+            Quantifier::One => count == 1,
+            Quantifier::Optional => count <= 1,
+            Quantifier::Any => true,
+            Quantifier::AtLeastOne => count >= 1,
+        }
+    }
+
+    /// Describe the admissible number of occurrences
+    fn describe(&self) -> &'static str {
+        match self {
+            Quantifier::One => "exactly one",
+            Quantifier::Optional => "at most one",
+            Quantifier::Any => "any number of",
+            Quantifier::AtLeastOne => "at least one",
+        }
+    }
+}
+
+/// `Entries` are the names of a schema with their quantifiers and whether unlisted names are admitted
+type Entries = (Vec<(String, Quantifier)>, bool);
+
+/// `Schema` describes the admissible arguments and child calls of a call
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Schema {
+    args: Option<Entries>,
+    children: Option<Entries>,
+}
+
+/// Parse the schema entries `entries` (of arguments if `args` is set)
+fn parse_entries(entries: &[String], args: bool) -> Result<Entries, String> {
+    let mut parsed = vec![];
+    let mut others = false;
+    for entry in entries {
+        if entry == "*" {
+            others = true;
+            continue;
+        }
+        let (name, quantifier) = match entry.chars().last() {
+            Some('?') => (&entry[..entry.len() - 1], Quantifier::Optional),
+            Some('*') => (&entry[..entry.len() - 1], Quantifier::Any),
+            Some('+') => (&entry[..entry.len() - 1], Quantifier::AtLeastOne),
+            _ => (entry.as_str(), Quantifier::One),
+        };
+        if name.is_empty() {
+            return Err(format!("schema entry '{entry}' has no name"));
+        }
+        if args && !matches!(quantifier, Quantifier::One | Quantifier::Optional) {
+            return Err(format!("argument '{entry}' may only be marked optional with '?'"));
+        }
+        parsed.push((name.to_owned(), quantifier));
+    }
+    Ok((parsed, others))
+}
+
+impl Schema {
+    /// Create a schema from the entries `args` for arguments and `children` for child calls
+    pub fn new(args: Option<&[String]>, children: Option<&[String]>) -> Result<Schema, String> {
+        Ok(Schema {
+            args: args.map(|entries| parse_entries(entries, true)).transpose()?,
+            children: children.map(|entries| parse_entries(entries, false)).transpose()?,
+        })
+    }
+
+    /// Returns the violations of this schema by a call `call` with the argument
+    /// keys `arg_keys` and the call names `children` of the calls in its content
+    pub fn check(&self, call: &str, arg_keys: &[&str], children: &[&str]) -> Vec<String> {
+        let mut violations = vec![];
+        let arg_keys: Vec<&str> = arg_keys.iter().copied().filter(|key| !crate::tree::is_reserved_arg(key)).collect();
+        if let Some((entries, others)) = &self.args {
+            for (key, quantifier) in entries {
+                if *quantifier == Quantifier::One && !arg_keys.contains(&key.as_str()) {
+                    violations.push(format!("call '{call}' requires argument '{key}'"));
+                }
+            }
+            for key in arg_keys.iter() {
+                if !others && !entries.iter().any(|(name, _)| name == key) {
+                    violations.push(format!("call '{call}' must not have argument '{key}'"));
+                }
+            }
+        }
+        if let Some((entries, others)) = &self.children {
+            for (name, quantifier) in entries {
+                let count = children.iter().filter(|child| *child == name).count();
+                if !quantifier.admits(count) {
+                    violations.push(format!("call '{call}' must contain {} call '{name}', but contains {count}", quantifier.describe()));
+                }
+            }
+            let mut reported: Vec<&str> = vec![];
+            for child in children.iter() {
+                if !others && !entries.iter().any(|(name, _)| name == child) && !reported.contains(child) {
+                    violations.push(format!("call '{call}' must not contain call '{child}'"));
+                    reported.push(child);
+                }
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_arguments_and_children() -> Result<(), String> {
+        let entries = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<String>>();
+        let schema = Schema::new(Some(&entries(&["src", "alt?"])), Some(&entries(&["caption?", "source*"])))?;
+        assert!(schema.check("figure", &["src", "=span"], &["source", "source"]).is_empty());
+        assert_eq!(schema.check("figure", &["alt", "class"], &["caption", "caption", "p"]), vec![
+            "call 'figure' requires argument 'src'".to_owned(),
+            "call 'figure' must not have argument 'class'".to_owned(),
+            "call 'figure' must contain at most one call 'caption', but contains 2".to_owned(),
+            "call 'figure' must not contain call 'p'".to_owned(),
+        ]);
+
+        let open = Schema::new(Some(&entries(&["*"])), Some(&entries(&["item+", "*"])))?;
+        assert!(open.check("list", &["class"], &["item", "note"]).is_empty());
+        assert_eq!(open.check("list", &[], &[]).len(), 1);
+        assert!(Schema::new(None, None)?.check("any", &["x"], &["y"]).is_empty());
+        assert!(Schema::new(Some(&entries(&["src+"])), None).is_err());
+        assert!(Schema::new(None, Some(&entries(&["?"]))).is_err());
+        Ok(())
+    }
+}
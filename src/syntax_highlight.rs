@@ -0,0 +1,117 @@
+//! Syntax highlighting of code in other languages based on syntect
+//!
+//! The syntax definitions and themes bundled with syntect are loaded once on
+//! first use and shared afterwards, so highlighting many code blocks is cheap.
+//! Code in unknown languages is returned without highlighting (but escaped
+//! for HTML). Highlighting requires feature `syntect`.
+
+#[cfg(feature = "syntect")]
+use std::sync::OnceLock;
+
+/// Theme used for HTML output unless another theme is requested
+pub const DEFAULT_HTML_THEME: &str = "InspiredGitHub";
+
+/// Theme used for ANSI output unless another theme is requested
+pub const DEFAULT_ANSI_THEME: &str = "base16-ocean.dark";
+
+/// `Style` determines the representation of highlighted code
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Style {
+    /// HTML `span` elements with inline `style` attributes (without surrounding `pre` element)
+    Html,
+    /// 24-bit ANSI escape sequences for terminals
+    Ansi,
+}
+
+impl Style {
+    /// Returns the style called `name` (`html` or `ansi`)
+    pub fn from_name(name: &str) -> Option<Style> {
+        match name {
+            "html" => Some(Style::Html),
+            "ansi" => Some(Style::Ansi),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "syntect")]
+    fn default_theme(&self) -> &'static str {
+        match self {
+            Style::Html => DEFAULT_HTML_THEME,
+            Style::Ansi => DEFAULT_ANSI_THEME,
+        }
+    }
+}
+
+#[cfg(feature = "syntect")]
+fn syntaxes() -> &'static syntect::parsing::SyntaxSet {
+    static SYNTAXES: OnceLock<syntect::parsing::SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(syntect::parsing::SyntaxSet::load_defaults_newlines)
+}
+
+#[cfg(feature = "syntect")]
+fn themes() -> &'static syntect::highlighting::ThemeSet {
+    static THEMES: OnceLock<syntect::highlighting::ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(syntect::highlighting::ThemeSet::load_defaults)
+}
+
+/// Is `language` (a name like `Rust` or a file extension like `rs`) known to the highlighter?
+#[cfg(feature = "syntect")]
+pub fn is_supported(language: &str) -> bool {
+    syntaxes().find_syntax_by_token(language).is_some()
+}
+
+/// Is `language` known to the highlighter? Always false without feature `syntect`.
+#[cfg(not(feature = "syntect"))]
+pub fn is_supported(_language: &str) -> bool {
+    false
+}
+
+/// Highlight `code` written in `language` (a name like `Rust` or a file extension
+/// like `rs`) in `style` with theme `theme` (or the default theme of `style`)
+#[cfg(feature = "syntect")]
+pub fn highlight(code: &str, language: &str, style: Style, theme: Option<&str>) -> Result<String, String> {
+    let theme_name = theme.unwrap_or(style.default_theme());
+    let theme = themes().themes.get(theme_name).ok_or_else(|| {
+        let names: Vec<&str> = themes().themes.keys().map(String::as_str).collect();
+        format!("unknown theme '{theme_name}', expected one of {}", names.join(", "))
+    })?;
+    let syntax = syntaxes().find_syntax_by_token(language).unwrap_or_else(|| syntaxes().find_syntax_plain_text());
+
+    let mut highlighter = syntect::easy::HighlightLines::new(syntax, theme);
+    let mut out = String::with_capacity(code.len() * 2);
+    for line in syntect::util::LinesWithEndings::from(code) {
+        let regions = highlighter.highlight_line(line, syntaxes()).map_err(|e| e.to_string())?;
+        match style {
+            Style::Html => out.push_str(&syntect::html::styled_line_to_highlighted_html(&regions, syntect::html::IncludeBackground::No).map_err(|e| e.to_string())?),
+            Style::Ansi => out.push_str(&syntect::util::as_24_bit_terminal_escaped(&regions, false)),
+        }
+    }
+    if style == Style::Ansi && !out.is_empty() {
+        out.push_str("\x1b[0m");
+    }
+    Ok(out)
+}
+
+/// Highlight `code`, which requires feature `syntect`
+#[cfg(not(feature = "syntect"))]
+pub fn highlight(_code: &str, _language: &str, _style: Style, _theme: Option<&str>) -> Result<String, String> {
+    Err("cannot highlight code, because litua was built without feature 'syntect'".to_owned())
+}
+
+#[cfg(all(test, feature = "syntect"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_rust_as_html_and_ansi() -> Result<(), String> {
+        let html = highlight("fn main() { 1 < 2; }\n", "rust", Style::Html, None)?;
+        assert!(html.starts_with("<span style=\"") && html.contains("main") && html.contains("&lt;"));
+        let ansi = highlight("fn main() {}\n", "rs", Style::Ansi, None)?;
+        assert!(ansi.starts_with("\x1b[38;2;") && ansi.ends_with("\x1b[0m"));
+
+        assert_eq!(highlight("a < b", "no-such-language", Style::Html, None)?.matches("&lt;").count(), 1);
+        assert!(highlight("x", "rust", Style::Html, Some("no-such-theme")).is_err());
+        assert!(is_supported("Rust") && !is_supported("no-such-language"));
+        Ok(())
+    }
+}
@@ -0,0 +1,514 @@
+//! Text utilities treating text as sequence of Unicode scalar values
+//!
+//! `LinesWithByteIndices` maps between lines and byte offsets, which is
+//! needed to report positions in text documents (e.g. in diagnostics).
+//! The string utilities are provided to hooks. Whitespace is determined by
+//! the Unicode property ``White_Space``, thus e.g. U+00A0 NO-BREAK SPACE
+//! is whitespace, and widths are counted in characters.
+
+use std::ops;
+use std::str;
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Iterator over the lines of a Unicode string yielding (byte offset of the
+/// line start, line without terminator). Lines are terminated by the hard line
+/// breaks of Unicode TR#14 (e.g. U+000A, U+000D U+000A, or U+2028).
+///
+/// This object represents the state at the beginning of a line
+/// within a Unicode string. The stored index points to the byte
+/// index of the next start of a line or usize::MAX. The stored
+/// string contains the complete string we index in.
+/// via <https://gist.github.com/tajpulo/a717e5f0dc2b36ebd8eae63786d1dd72>
+#[derive(Clone,Debug)]
+pub struct LinesWithByteIndices<'s>(&'s str, usize);
+
+impl<'s> LinesWithByteIndices<'s> {
+    /// Iterate over the lines of `s`
+    pub fn new(s: &'s str) -> Self {
+        Self(s, 0)
+    }
+}
+
+/// Give me a substring and I will return the byte index of
+/// the (next line terminator, next line start). If they don't
+/// exist, we return usize::MAX in either case.
+fn find_next_line_terminator(substr: &str) -> (usize, usize) {
+    let mut iterator = substr.char_indices();
+
+    if substr.is_empty() {
+        return (usize::MAX, usize::MAX);
+    }
+
+    while let Some((byte_offset, chr)) = iterator.next() {
+        match chr {
+            '\u{000C}' | '\u{000B}' | '\u{2028}' | '\u{2029}' | // LB4
+            '\u{000A}' | '\u{0085}' // LB5
+            => {
+
+                // these single characters already break the current line
+                return (byte_offset, byte_offset + chr.len_utf8());
+            },
+            '\u{000D}' => {
+                // we might have (U+000D) or (U+000D, U+000A) which are both terminators
+                match iterator.next() {
+                    Some((next_byte_offset, '\u{000A}')) => return (byte_offset, next_byte_offset + 1),
+                    Some(_) => return (byte_offset, byte_offset + 1),
+                    None => return (byte_offset, byte_offset + 1),
+                }
+            },
+            _ => {},
+        };
+    }
+
+    // we did not find a terminator, so claim the terminator and
+    // the next line-start is past the end of the string
+    (usize::MAX, usize::MAX)
+}
+
+impl<'s> Iterator for LinesWithByteIndices<'s> {
+    type Item = (usize, &'s str); // (line start byte index, substring representing line)
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        // We follow the “hard line breaks” definition of Unicode TR#14 here.
+        // Specifically, we introduce a hard break after any of {000C, 000B, 2028, 2029}
+        // because of rule LB4. Furthermore a hard break follows after any of
+        // {000D, 000A, 0085} or the sequence 000A & 000D according to rule LB5.
+        // https://www.unicode.org/reports/tr14/
+        if self.1 == usize::MAX {
+            return None;
+        }
+
+        let this_line_start = self.1; // index of first byte of this line
+
+        // NOTE: self.0 must always be a valid byte offset to some Unicode scalar
+        let substr = self.0.get(this_line_start..).unwrap();
+        let (terminator_index, next_start_index) = find_next_line_terminator(substr);
+        if terminator_index == usize::MAX && next_start_index == usize::MAX {
+            self.1 = usize::MAX;
+            Some((this_line_start, substr))
+        } else {
+            self.1 = next_start_index.saturating_add(this_line_start);
+            Some((this_line_start, self.0.get(this_line_start..this_line_start.saturating_add(terminator_index)).unwrap()))
+        }
+    }
+}
+
+/// `StrLinesWithByteIndices` provides `lines_indices` for string slices
+/// (e.g. ``for (start, line) in src.lines_indices() {…}``)
+pub trait StrLinesWithByteIndices {
+    fn lines_indices<'s>(&'s self) -> LinesWithByteIndices<'s>;
+}
+
+impl<'s> StrLinesWithByteIndices for &'s str {
+    fn lines_indices(&self) -> LinesWithByteIndices<'s> {
+        LinesWithByteIndices::new(self)
+    }
+}
+
+/// Returns the byte range of the line with zero-based index `line_index`
+/// within `src` (excluding its terminator) or `None` if `src` has fewer lines
+pub fn line_range(src: &str, line_index: usize) -> Option<ops::Range<usize>> {
+    src.lines_indices().nth(line_index).map(|(start, line)| start..start + line.len())
+}
+
+/// `LineIndex` stores the byte ranges of the lines of a text document (excluding
+/// their terminators, see `LinesWithByteIndices`). Resolving a byte offset into
+/// a line is a binary search over the line starts, so diagnostics with many
+/// positions do not scan the document again for every position.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct LineIndex(Vec<ops::Range<usize>>);
+
+impl LineIndex {
+    /// Index the lines of `src`
+    pub fn new(src: &str) -> Self {
+        Self(src.lines_indices().map(|(start, line)| start..start + line.len()).collect())
+    }
+
+    /// Returns the number of lines (at least one, since an empty document has one empty line)
+    pub fn line_count(&self) -> usize {
+        self.0.len().max(1)
+    }
+
+    /// Returns the zero-based index of the line containing `byte_offset`. Offsets
+    /// within a line terminator belong to the line it terminates and offsets
+    /// beyond the end of the document to the last line.
+    pub fn line_of(&self, byte_offset: usize) -> usize {
+        self.0.partition_point(|line| line.start <= byte_offset).saturating_sub(1)
+    }
+
+    /// Returns the byte range of the line with zero-based index `line_index` (excluding its terminator)
+    pub fn line_range(&self, line_index: usize) -> Option<ops::Range<usize>> {
+        self.0.get(line_index).cloned()
+    }
+
+    /// Returns the one-based line and column (counted in Unicode scalars) of `byte_offset` within `src`
+    pub fn line_column(&self, src: &str, byte_offset: usize) -> (usize, usize) {
+        let line_index = self.line_of(byte_offset);
+        let line = self.line_range(line_index).unwrap_or(0..0);
+        let column = src.get(line.start..byte_offset.clamp(line.start, line.end)).map_or(0, |prefix| prefix.chars().count());
+        (line_index + 1, column + 1)
+    }
+}
+
+/// Remove leading and trailing whitespace
+pub fn trim(text: &str) -> &str {
+    text.trim()
+}
+
+/// Split `text` at every occurrence of `separator`, into characters if `separator`
+/// is empty, or at runs of whitespace if `separator` is `None` (in this case,
+/// empty items are skipped)
+pub fn split<'t>(text: &'t str, separator: Option<&str>) -> Vec<&'t str> {
+    match separator {
+        Some("") => text.char_indices().map(|(i, c)| &text[i..i + c.len_utf8()]).collect(),
+        Some(sep) => text.split(sep).collect(),
+        None => text.split_whitespace().collect(),
+    }
+}
+
+/// Remove the longest whitespace prefix common to all lines which are not blank.
+/// Blank lines become empty.
+pub fn dedent(text: &str) -> String {
+    let prefix_len = |line: &str| line.len() - line.trim_start().len();
+    let common = text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| &line[..prefix_len(line)])
+        .reduce(|common, prefix| {
+            let shared: usize = common.chars().zip(prefix.chars())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a.len_utf8())
+                .sum();
+            &common[..shared]
+        })
+        .unwrap_or_default();
+
+    text.split_inclusive('\n')
+        .map(|line| match line.trim().is_empty() {
+            true => &line[line.trim_end_matches(['\n', '\r']).len()..],
+            false => &line[common.len()..],
+        })
+        .collect()
+}
+
+/// Represent `text` as lowercase identifier for URLs or anchors, e.g. `Größe & Maß`
+/// becomes `grosse-mass`. Diacritics are removed and sequences of characters which
+/// are not alphanumeric are replaced by a single `-`.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    let mut separate = false;
+    for c in text.nfkd().filter(|c| !is_combining_mark(*c)).flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            if separate && !slug.is_empty() {
+                slug.push('-');
+            }
+            separate = false;
+            match c {
+                'ß' => slug.push_str("ss"),
+                'æ' => slug.push_str("ae"),
+                'œ' => slug.push_str("oe"),
+                'ø' => slug.push('o'),
+                _ => slug.push(c),
+            }
+        } else {
+            separate = true;
+        }
+    }
+    slug
+}
+
+/// Distribute the words of `text` to lines with at most `width` characters
+/// (unless a single word is longer). Paragraphs separated by blank lines are kept.
+pub fn wrap(text: &str, width: usize) -> String {
+    let mut paragraphs = vec![];
+    let mut paragraph: Vec<&str> = vec![];
+    for line in text.lines().chain([""]) {
+        if !line.trim().is_empty() {
+            paragraph.extend(line.split_whitespace());
+            continue;
+        }
+        if paragraph.is_empty() {
+            continue;
+        }
+
+        let mut lines: Vec<String> = vec![];
+        let mut current = String::new();
+        let mut current_width = 0;
+        for word in paragraph.drain(..) {
+            let word_width = word.chars().count();
+            if current_width > 0 && current_width + 1 + word_width > width {
+                lines.push(std::mem::take(&mut current));
+                current_width = 0;
+            }
+            if current_width > 0 {
+                current.push(' ');
+                current_width += 1;
+            }
+            current.push_str(word);
+            current_width += word_width;
+        }
+        lines.push(current);
+        paragraphs.push(lines.join("\n"));
+    }
+    paragraphs.join("\n\n")
+}
+
+/// `InvalidUtf8` determines how invalid UTF-8 sequences of text documents are handled
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+pub enum InvalidUtf8 {
+    /// fail at the first invalid sequence
+    #[default]
+    Error,
+    /// replace every invalid sequence by U+FFFD REPLACEMENT CHARACTER
+    Replace,
+    /// remove every invalid sequence
+    Strip,
+}
+
+/// Decode `bytes` as UTF-8 handling invalid sequences according to `policy`.
+/// Returns the text and the byte offsets of the invalid sequences within `bytes`.
+pub fn decode_utf8(bytes: &[u8], policy: InvalidUtf8) -> Result<(String, Vec<usize>), str::Utf8Error> {
+    if policy == InvalidUtf8::Error {
+        return str::from_utf8(bytes).map(|text| (text.to_owned(), vec![]));
+    }
+    let mut text = String::with_capacity(bytes.len());
+    let mut invalid = vec![];
+    let mut offset = 0;
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        offset += chunk.valid().len();
+        if !chunk.invalid().is_empty() {
+            invalid.push(offset);
+            if policy == InvalidUtf8::Replace {
+                text.push(char::REPLACEMENT_CHARACTER);
+            }
+            offset += chunk.invalid().len();
+        }
+    }
+    Ok((text, invalid))
+}
+
+/// `Newlines` determines the line terminators of written text
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+pub enum Newlines {
+    /// keep the line terminators as they are
+    #[default]
+    Keep,
+    /// terminate lines with U+000A LINE FEED
+    Lf,
+    /// terminate lines with U+000D CARRIAGE RETURN and U+000A LINE FEED
+    CrLf,
+}
+
+impl Newlines {
+    /// Returns `bytes` with every line terminator `\n` or `\r\n` replaced by
+    /// the terminator of this style (other bytes, even invalid UTF-8, are kept)
+    pub fn apply(&self, bytes: &[u8]) -> Vec<u8> {
+        let newline: &[u8] = match self {
+            Newlines::Keep => return bytes.to_vec(),
+            Newlines::Lf => b"\n",
+            Newlines::CrLf => b"\r\n",
+        };
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            match (bytes[i], bytes.get(i + 1)) {
+                (b'\r', Some(b'\n')) => {
+                    out.extend_from_slice(newline);
+                    i += 2;
+                },
+                (b'\n', _) => {
+                    out.extend_from_slice(newline);
+                    i += 1;
+                },
+                (byte, _) => {
+                    out.push(byte);
+                    i += 1;
+                },
+            }
+        }
+        out
+    }
+}
+
+/// Replace every placeholder `{name}` of `template` by the value `lookup` returns for
+/// `name` (`{{` and `}}` stand for literal braces). Fails for unknown placeholders.
+pub fn render_template<F: Fn(&str) -> Option<String>>(template: &str, lookup: F) -> Result<String, String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(index) = rest.find(['{', '}']) {
+        out.push_str(&rest[..index]);
+        let tail = &rest[index..];
+        if let Some(after) = tail.strip_prefix("{{").or_else(|| tail.strip_prefix("}}")) {
+            out.push_str(&tail[..1]);
+            rest = after;
+            continue;
+        }
+        let end = match (tail.starts_with('{'), tail.find('}')) {
+            (true, Some(end)) => end,
+            _ => return Err(format!("unbalanced brace at byte {} of template '{template}'", template.len() - tail.len())),
+        };
+        let name = &tail[1..end];
+        match lookup(name) {
+            Some(value) => out.push_str(&value),
+            None => return Err(format!("unknown placeholder '{{{name}}}' in template '{template}'")),
+        }
+        rest = &tail[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unicode_aware_string_utilities() {
+        assert_eq!(trim("\u{a0} text\u{2003}\n"), "text");
+        assert_eq!(split("a,b,,c", Some(",")), vec!["a", "b", "", "c"]);
+        assert_eq!(split(" a\u{a0}b \n c ", None), vec!["a", "b", "c"]);
+        assert_eq!(split("äb", Some("")), vec!["ä", "b"]);
+        assert_eq!(dedent("    if x:\n  \n\t\n      y\n    z"), "if x:\n\n\n  y\nz");
+        assert_eq!(slugify(" Größe & Maß: Ærø déjà-vu! "), "grosse-mass-aero-deja-vu");
+        assert_eq!(slugify("Ελληνικά 2"), "ελληνικα-2");
+        assert_eq!(wrap("Größe und Maß sind\nwichtig.\n\n  Ende", 10), "Größe und\nMaß sind\nwichtig.\n\nEnde");
+        assert_eq!(wrap("unbreakablewords here", 5), "unbreakablewords\nhere");
+    }
+
+    #[test]
+    fn render_name_template() {
+        let lookup = |name: &str| match name {
+            "stem" => Some("intro".to_owned()),
+            "ext" => Some("lit".to_owned()),
+            _ => None,
+        };
+        assert_eq!(render_template("{stem}.html", lookup), Ok("intro.html".to_owned()));
+        assert_eq!(render_template("{{{stem}}}-{ext}", lookup), Ok("{intro}-lit".to_owned()));
+        assert!(render_template("{name}.html", lookup).unwrap_err().contains("unknown placeholder '{name}'"));
+        assert!(render_template("{stem.html", lookup).is_err());
+    }
+
+    #[test]
+    fn decode_invalid_utf8() {
+        let bytes = b"a\xffb\xe2\x82c\xe2\x82\xac";
+        assert!(decode_utf8(bytes, InvalidUtf8::Error).is_err());
+        assert_eq!(decode_utf8(bytes, InvalidUtf8::Replace), Ok(("a\u{FFFD}b\u{FFFD}c€".to_owned(), vec![1, 3])));
+        assert_eq!(decode_utf8(bytes, InvalidUtf8::Strip), Ok(("abc€".to_owned(), vec![1, 3])));
+        assert_eq!(decode_utf8(b"ok", InvalidUtf8::Error), Ok(("ok".to_owned(), vec![])));
+    }
+
+    #[test]
+    fn newline_styles() {
+        let text = b"a\r\nb\nc\r\xff\n";
+        assert_eq!(Newlines::Keep.apply(text), text);
+        assert_eq!(Newlines::Lf.apply(text), b"a\nb\nc\r\xff\n");
+        assert_eq!(Newlines::CrLf.apply(text), b"a\r\nb\r\nc\r\xff\r\n");
+    }
+
+    #[test]
+    fn single_line_string() {
+        let text = "Hello world!";
+        let mut iter = text.lines_indices();
+        assert_eq!(iter.next(), Some((0, "Hello world!")));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn simple_newline_split() {
+        let text = "Hello world\nfoo\nbar!";
+        let mut iter = text.lines_indices();
+        assert_eq!(iter.next(), Some((0, "Hello world")));
+        assert_eq!(iter.next(), Some((12, "foo")));
+        assert_eq!(iter.next(), Some((16, "bar!")));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn simple_newline_split_with_trailing_line() {
+        let text = "Hello world\nfoo\nbar!\n";
+        let mut iter = text.lines_indices();
+        assert_eq!(iter.next(), Some((0, "Hello world")));
+        assert_eq!(iter.next(), Some((12, "foo")));
+        assert_eq!(iter.next(), Some((16, "bar!")));
+        assert_eq!(iter.next(), Some((21, "")));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn split_with_various_line_terminators() {
+        let text = "Knock\u{000D}\u{000A}knock\u{000A}…\u{000B}who's\u{000C}there\u{000D}?\u{2028}Knock\u{2029}Ness\u{0085}!";
+        let mut iter = text.lines_indices();
+        assert_eq!(iter.next(), Some((0, "Knock")));
+        assert_eq!(iter.next(), Some((7, "knock")));
+        assert_eq!(iter.next(), Some((13, "…")));
+        assert_eq!(iter.next(), Some((17, "who's")));
+        assert_eq!(iter.next(), Some((23, "there")));
+        assert_eq!(iter.next(), Some((29, "?")));
+        assert_eq!(iter.next(), Some((33, "Knock")));
+        assert_eq!(iter.next(), Some((41, "Ness")));
+        assert_eq!(iter.next(), Some((47, "!")));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn many_empty_lines() {
+        let text = "A\n\nB\n  \nC\r\n D \n";
+        let mut iter = text.lines_indices();
+        assert_eq!(iter.next(), Some((0, "A")));
+        assert_eq!(iter.next(), Some((2, "")));
+        assert_eq!(iter.next(), Some((3, "B")));
+        assert_eq!(iter.next(), Some((5, "  ")));
+        assert_eq!(iter.next(), Some((8, "C")));
+        assert_eq!(iter.next(), Some((11, " D ")));
+        assert_eq!(iter.next(), Some((15, "")));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn invalid_terminator() {
+        // the standardized sequence is (U+000D, U+000A), not the other way around
+        let text = "Knock\u{000A}\u{000D}knock";
+        let mut iter = text.lines_indices();
+        assert_eq!(iter.next(), Some((0, "Knock")));
+        assert_eq!(iter.next(), Some((6, "")));
+        assert_eq!(iter.next(), Some((7, "knock")));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn finish_with_carriage_return() {
+        let text = "line 1\u{000A}line 2\u{000A}";
+        let mut iter = text.lines_indices();
+        assert_eq!(iter.next(), Some((0, "line 1")));
+        assert_eq!(iter.next(), Some((7, "line 2")));
+        assert_eq!(iter.next(), Some((14, "")));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn byte_range_of_line() {
+        let text = "first\r\nsecond\n\nläst";
+        assert_eq!(line_range(text, 0), Some(0..5));
+        assert_eq!(line_range(text, 1), Some(7..13));
+        assert_eq!(line_range(text, 2), Some(14..14));
+        assert_eq!(line_range(text, 3).map(|r| &text[r]), Some("läst"));
+        assert_eq!(line_range(text, 4), None);
+    }
+
+    #[test]
+    fn indexed_line_lookup() {
+        let text = "first\r\nsecond\n\nläst";
+        let lines = LineIndex::new(text);
+        assert_eq!(lines.line_count(), 4);
+        assert_eq!((0..=text.len()).map(|offset| lines.line_of(offset)).collect::<Vec<_>>(),
+            vec![0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 2, 3, 3, 3, 3, 3, 3]);
+        assert_eq!(lines.line_range(3).map(|r| &text[r]), Some("läst"));
+        assert_eq!(lines.line_column(text, 19), (4, 4));
+        assert_eq!(lines.line_column(text, 6), (1, 6));
+        assert_eq!(lines.line_column(text, 99), (4, 5));
+        assert_eq!(LineIndex::new("").line_column("", 0), (1, 1));
+    }
+}
@@ -0,0 +1,111 @@
+//! Rewriting the token stream between lexer and parser
+//!
+//! A `TokenFilter` receives the tokens emitted by the lexer (or by the
+//! previous filter) and returns the tokens the parser consumes. Filters are
+//! registered with `pipeline::Pipeline::token_filter` and run in the order of
+//! registration. They may drop, reorder, or inject tokens, but tokens only
+//! carry byte offsets, so every emitted token must refer to the source code of
+//! the document and the stream must remain admissible for the parser.
+//!
+//! The CLI runs built-in filters by name (see `builtin` and `--token-filter`).
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::errors;
+use crate::lexer;
+use crate::messages::Message;
+
+/// `TokenStream` is a stream of tokens of one text document
+pub type TokenStream<'s> = Box<dyn Iterator<Item = Result<lexer::Token, errors::Error>> + 's>;
+
+/// `TokenFilter` rewrites the token stream of a text document
+pub trait TokenFilter: Send + Sync {
+    /// Name of the filter (used for debugging and by the CLI)
+    fn name(&self) -> &str;
+
+    /// Returns the rewritten stream of `tokens` of the source code `src`
+    fn filter<'s>(&self, src: &'s str, tokens: TokenStream<'s>) -> TokenStream<'s>;
+}
+
+impl fmt::Debug for dyn TokenFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TokenFilter({})", self.name())
+    }
+}
+
+impl PartialEq for dyn TokenFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+/// Call name of comments removed by `StripComments`
+pub const COMMENT_CALL: &str = "comment";
+
+/// `StripComments` removes calls named `COMMENT_CALL` (including their arguments and content)
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+pub struct StripComments;
+
+impl TokenFilter for StripComments {
+    fn name(&self) -> &str {
+        "strip-comments"
+    }
+
+    fn filter<'s>(&self, src: &'s str, tokens: TokenStream<'s>) -> TokenStream<'s> {
+        let mut tokens = tokens.peekable();
+        Box::new(std::iter::from_fn(move || loop {
+            let token = tokens.next()?;
+            let is_comment = matches!(token, Ok(lexer::Token::BeginFunction(_)))
+                && matches!(tokens.peek(), Some(Ok(lexer::Token::Call(range))) if src.get(range.clone()) == Some(COMMENT_CALL));
+            if !is_comment {
+                return Some(token);
+            }
+
+            // NOTE: skip the tokens up to the matching EndFunction
+            let mut depth = 0usize;
+            for token in tokens.by_ref() {
+                match token {
+                    Ok(lexer::Token::BeginFunction(_)) => depth += 1,
+                    Ok(lexer::Token::EndFunction(_)) if depth == 0 => break,
+                    Ok(lexer::Token::EndFunction(_)) => depth -= 1,
+                    Ok(lexer::Token::EndOfFile(_)) => return Some(Err(errors::Error::UnexpectedEOF(Message::new("P0001", vec![])))),
+                    Err(_) => return Some(token),
+                    Ok(_) => {},
+                }
+            }
+        }))
+    }
+}
+
+/// Names and descriptions of the built-in filters
+pub const BUILTIN: [(&str, &str); 1] = [
+    ("strip-comments", "remove calls named 'comment' before parsing"),
+];
+
+/// Returns the built-in filter named `name`
+pub fn builtin(name: &str) -> Option<Arc<dyn TokenFilter>> {
+    match name {
+        "strip-comments" => Some(Arc::new(StripComments)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::Pipeline;
+    use std::path;
+
+    #[test]
+    fn strip_comments() -> Result<(), errors::Error> {
+        let pipeline = Pipeline::new().token_filter(builtin("strip-comments").expect("built-in filter"));
+        let tree = pipeline.parse(path::Path::new(""), "a {comment[x=y] b {comment} {c}} d {p e{comment}{comment}}")?;
+        assert_eq!(tree.to_litua_syntax(), "a  d {p e}");
+        let chunks = pipeline.chunks(path::Path::new(""), "{comment a}{p b}").collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(chunks.len(), 1);
+        assert!(pipeline.parse(path::Path::new(""), "{comment {b}").is_err());
+        assert!(builtin("unknown").is_none());
+        Ok(())
+    }
+}
@@ -1,6 +1,120 @@
 //! Tree structure of a litua text document
 
 use std::collections::HashMap;
+use std::ops;
+
+/// Argument keys starting with this character are reserved for litua's
+/// internal bookkeeping. They cannot be written in litua input syntax.
+pub const RESERVED_ARG_PREFIX: char = '=';
+/// Argument key storing the byte range of a call within the source document.
+/// Its value consists of two text elements (start and end byte offset).
+pub const SPAN_ARG: &str = "=span";
+/// Argument key storing the whitespace after the call name (or arguments)
+pub const WHITESPACE_ARG: &str = "=whitespace";
+/// Argument key storing the whitespace before the end of a raw string
+/// (or a call parsed with `Parser::lossless`)
+pub const WHITESPACE_AFTER_ARG: &str = "=whitespace-after";
+/// Argument key storing the filepath of the text document a call was read from
+/// (only set for calls of documents included by `include::resolve_includes`)
+pub const FILE_ARG: &str = "=file";
+/// Argument key storing the language tag of a raw string like `lua` in `{<<lua …>>}`
+pub const LANGUAGE_ARG: &str = "=language";
+/// Argument key storing the types declared for arguments like `int` in `[width:int=300]`.
+/// Its value consists of one text element `key:type` per typed argument.
+pub const TYPES_ARG: &str = "=types";
+/// All reserved argument keys known to litua. Other keys starting with
+/// `RESERVED_ARG_PREFIX` are rejected when reading a tree from Lua.
+pub const RESERVED_ARGS: [&str; 6] = [SPAN_ARG, WHITESPACE_ARG, WHITESPACE_AFTER_ARG, FILE_ARG, LANGUAGE_ARG, TYPES_ARG];
+
+/// Is `key` an argument key reserved for litua (i.e. starting with `RESERVED_ARG_PREFIX`)?
+pub fn is_reserved_arg(key: &str) -> bool {
+    key.starts_with(RESERVED_ARG_PREFIX)
+}
+
+/// Character separating an argument key from its declared type like in `[width:int=300]`
+pub const TYPE_SEPARATOR: char = ':';
+
+/// Character separating the namespace from the local name of a call like `{html:div …}`
+pub const NAMESPACE_SEPARATOR: char = ':';
+
+/// Splits the call name `call` into its namespace and local name like `html:div`
+/// into `(Some("html"), "div")`. Call names without separator, or with an empty
+/// namespace or local name (like `:div`), have no namespace.
+pub fn split_call_name(call: &str) -> (Option<&str>, &str) {
+    match call.split_once(NAMESPACE_SEPARATOR) {
+        Some((namespace, local_name)) if !namespace.is_empty() && !local_name.is_empty() => (Some(namespace), local_name),
+        _ => (None, call),
+    }
+}
+
+/// `ArgType` is the declared type of an argument value. Hooks receive
+/// values of typed arguments as Lua numbers or booleans instead of strings.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+pub enum ArgType {
+    /// 64-bit signed integer like `300` (declared as `int`)
+    Int,
+    /// floating-point number like `0.5` (declared as `float`)
+    Float,
+    /// `true` or `false` (declared as `bool`)
+    Bool,
+}
+
+impl ArgType {
+    /// Returns the type declared as `name` (like `int`)
+    pub fn from_name(name: &str) -> Option<ArgType> {
+        match name {
+            "int" => Some(ArgType::Int),
+            "float" => Some(ArgType::Float),
+            "bool" => Some(ArgType::Bool),
+            _ => None,
+        }
+    }
+
+    /// Returns the name declaring this type
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArgType::Int => "int",
+            ArgType::Float => "float",
+            ArgType::Bool => "bool",
+        }
+    }
+
+    /// Splits the argument key `key` like `width:int` into the key and its
+    /// declared type. Keys without a known type suffix (like `xml:lang`) are kept.
+    pub fn split_key(key: &str) -> (&str, Option<ArgType>) {
+        match key.rsplit_once(TYPE_SEPARATOR) {
+            Some((name, suffix)) if !name.is_empty() => match ArgType::from_name(suffix) {
+                Some(arg_type) => (name, Some(arg_type)),
+                None => (key, None),
+            },
+            _ => (key, None),
+        }
+    }
+
+    /// Does `text` (ignoring surrounding whitespace) represent a value of this type?
+    pub fn admits(&self, text: &str) -> bool {
+        let text = text.trim();
+        match self {
+            ArgType::Int => text.parse::<i64>().is_ok(),
+            ArgType::Float => text.parse::<f64>().is_ok_and(f64::is_finite),
+            ArgType::Bool => text == "true" || text == "false",
+        }
+    }
+
+    /// Returns the Lua value of `text` of this type (or `None` if it does not admit `text`)
+    fn lua_value<'lua>(self, text: &str) -> Option<mlua::Value<'lua>> {
+        let text = text.trim();
+        match self {
+            ArgType::Int => text.parse::<i64>().ok().map(mlua::Value::Integer),
+            ArgType::Float => text.parse::<f64>().ok().filter(|f| f.is_finite()).map(mlua::Value::Number),
+            ArgType::Bool => match text {
+                "true" => Some(mlua::Value::Boolean(true)),
+                "false" => Some(mlua::Value::Boolean(false)),
+                _ => None,
+            },
+        }
+    }
+}
 
 /// `DocumentTree` represents the root element of the Abstract Syntax Tree
 #[derive(Clone,Debug,PartialEq)]
@@ -18,18 +132,157 @@ impl DocumentTree {
     }
 }
 
+impl DocumentTree {
+    /// Represent this tree in litua input syntax. The root call itself
+    /// is not represented, only its content. Arguments with a key
+    /// starting with `=` are not represented except for `=whitespace`
+    /// and `=whitespace-after`, which determine the whitespace after the
+    /// call name (or arguments) and before the end of raw strings (or calls
+    /// parsed with `Parser::lossless`).
+    ///
+    /// Text containing delimiters like `{` is represented as-is,
+    /// thus parsing the result does not necessarily yield the same tree.
+    pub fn to_litua_syntax(&self) -> String {
+        self.to_litua_syntax_with(&crate::lexer::LexerOptions::default())
+    }
+
+    /// Represent this tree in litua input syntax like `to_litua_syntax`,
+    /// but delimit raw strings as configured by `options`
+    pub fn to_litua_syntax_with(&self, options: &crate::lexer::LexerOptions) -> String {
+        let mut out = String::new();
+        match &self.0 {
+            DocumentElement::Function(root) => {
+                for element in root.content.iter() {
+                    element.write_litua_syntax(&mut out, options);
+                }
+            },
+            DocumentElement::Text(text) => out.push_str(text),
+        }
+        out
+    }
+}
+
+impl DocumentTree {
+    /// Returns the element at `path` or `None` if the path does not exist in this tree
+    pub fn get_path(&self, path: &NodePath) -> Option<&DocumentElement> {
+        let mut element = &self.0;
+        for step in path.0.iter() {
+            let func = match element {
+                DocumentElement::Function(func) => func,
+                DocumentElement::Text(_) => return None,
+            };
+            element = match step {
+                NodeStep::Content(index) => func.content.get(*index)?,
+                NodeStep::Arg(key, index) => func.args.get(key)?.get(*index)?,
+            };
+        }
+        Some(element)
+    }
+
+    /// Returns all calls (including the root call) satisfying `predicate`
+    /// in document order. Argument values are visited before the content
+    /// and in lexicographic order of their keys.
+    pub fn find_all<P: Fn(&DocumentFunction) -> bool>(&self, predicate: P) -> Vec<&DocumentFunction> {
+        let mut found = Vec::new();
+        if let DocumentElement::Function(root) = &self.0 {
+            root.collect_matching(&predicate, &mut found);
+        }
+        found
+    }
+
+    /// Returns the first call in document order whose argument `key`
+    /// consists of the text `value`, e.g. ``find_by_arg("id", "intro")``
+    pub fn find_by_arg(&self, key: &str, value: &str) -> Option<&DocumentFunction> {
+        self.find_all(|func| func.text_arg(key) == Some(value)).into_iter().next()
+    }
+}
+
 impl Default for DocumentTree {
     fn default() -> Self {
         Self::new()
     }
 }
 
+impl IntoIterator for DocumentTree {
+    type Item = DocumentElement;
+    type IntoIter = IntoIter;
+
+    /// Consume the tree yielding its elements in depth-first order
+    /// (starting with the root call). Calls are yielded with empty content,
+    /// because their content elements are yielded right afterwards.
+    /// Arguments remain part of their call.
+    fn into_iter(self) -> IntoIter {
+        IntoIter { stack: vec![vec![self.0].into_iter()] }
+    }
+}
+
+/// `IntoIter` is the owning depth-first iterator over the elements of a `DocumentTree`
+#[derive(Clone,Debug)]
+pub struct IntoIter {
+    /// iterators over the remaining elements of the current call and its ancestors
+    stack: Vec<std::vec::IntoIter<DocumentElement>>,
+}
+
+impl Iterator for IntoIter {
+    type Item = DocumentElement;
+
+    fn next(&mut self) -> Option<DocumentElement> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(DocumentElement::Function(mut func)) => {
+                    self.stack.push(std::mem::take(&mut func.content).into_iter());
+                    return Some(DocumentElement::Function(func));
+                },
+                Some(text) => return Some(text),
+                None => { self.stack.pop(); },
+            }
+        }
+    }
+}
+
+impl std::iter::FusedIterator for IntoIter {}
+
+#[cfg(feature = "rayon")]
+impl rayon::iter::IntoParallelIterator for DocumentTree {
+    type Item = DocumentElement;
+    type Iter = rayon::vec::IntoIter<DocumentElement>;
+
+    /// Consume the tree yielding the top-level children (i.e. the content
+    /// of the root call) in parallel
+    fn into_par_iter(self) -> Self::Iter {
+        match self.0 {
+            DocumentElement::Function(root) => root.content.into_par_iter(),
+            DocumentElement::Text(_) => Vec::new().into_par_iter(),
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<'t> rayon::iter::IntoParallelIterator for &'t DocumentTree {
+    type Item = &'t DocumentElement;
+    type Iter = rayon::slice::Iter<'t, DocumentElement>;
+
+    /// Yield the top-level children (i.e. the content of the root call) in parallel
+    fn into_par_iter(self) -> Self::Iter {
+        match &self.0 {
+            DocumentElement::Function(root) => root.content.as_slice().into_par_iter(),
+            DocumentElement::Text(_) => [].as_slice().into_par_iter(),
+        }
+    }
+}
+
 impl<'lua> mlua::ToLua<'lua> for &DocumentTree {
     fn to_lua(self, lua: &'lua mlua::Lua) -> mlua::Result<mlua::Value<'lua>> {
         self.0.to_lua(lua)
     }
 }
 
+impl<'lua> mlua::FromLua<'lua> for DocumentTree {
+    fn from_lua(value: mlua::Value<'lua>, lua: &'lua mlua::Lua) -> mlua::Result<Self> {
+        Ok(DocumentTree(DocumentElement::from_lua(value, lua)?))
+    }
+}
+
 /// `DocumentFunction` is a function call in the text document. For example,
 /// ``{text[style=bold] message}`` is a `DocumentFunction` with `name` “text”,
 /// `args` such that `style` is associated with `DocumentNode::Text` “bold”
@@ -51,6 +304,142 @@ impl DocumentFunction {
     pub fn empty_element() -> DocumentElement {
         DocumentElement::Function(Self::new())
     }
+
+    /// Returns the byte range of this call within the source document
+    /// if the parser recorded it in the `=span` argument
+    pub fn span(&self) -> Option<ops::Range<usize>> {
+        match self.args.get(SPAN_ARG)?.as_slice() {
+            [DocumentElement::Text(start), DocumentElement::Text(end)] => {
+                Some(start.parse().ok()?..end.parse().ok()?)
+            },
+            _ => None,
+        }
+    }
+
+    /// Stores the byte range of this call within the source document in the `=span` argument
+    pub fn set_span(&mut self, span: ops::Range<usize>) {
+        self.args.insert(SPAN_ARG.to_owned(), vec![
+            DocumentElement::Text(span.start.to_string()),
+            DocumentElement::Text(span.end.to_string()),
+        ]);
+    }
+
+    /// Returns the namespace of the call name like `html` for `html:div` (see `split_call_name`)
+    pub fn namespace(&self) -> Option<&str> {
+        split_call_name(&self.call).0
+    }
+
+    /// Returns the call name without namespace like `div` for `html:div` (see `split_call_name`)
+    pub fn local_name(&self) -> &str {
+        split_call_name(&self.call).1
+    }
+
+    /// Returns the type declared for argument `key` (see `TYPES_ARG`)
+    pub fn arg_type(&self, key: &str) -> Option<ArgType> {
+        self.args.get(TYPES_ARG)?.iter().find_map(|element| match element {
+            DocumentElement::Text(declared) => match declared.rsplit_once(TYPE_SEPARATOR) {
+                Some((name, arg_type)) if name == key => ArgType::from_name(arg_type),
+                _ => None,
+            },
+            DocumentElement::Function(_) => None,
+        })
+    }
+
+    /// Declares the type `arg_type` for argument `key` (see `TYPES_ARG`)
+    pub fn set_arg_type(&mut self, key: &str, arg_type: ArgType) {
+        let declared = self.args.entry(TYPES_ARG.to_owned()).or_default();
+        declared.retain(|element| !matches!(element, DocumentElement::Text(text) if text.rsplit_once(TYPE_SEPARATOR).is_some_and(|(name, _)| name == key)));
+        declared.push(DocumentElement::Text(format!("{key}{TYPE_SEPARATOR}{}", arg_type.name())));
+        // NOTE: sorted, so the content hash does not depend on the order of arguments
+        declared.sort_by_key(|element| match element {
+            DocumentElement::Text(text) => text.clone(),
+            DocumentElement::Function(_) => String::new(),
+        });
+    }
+
+    /// Removes the `=span` argument of this call and all nested calls
+    /// (e.g. because the spans do not refer to the source document)
+    pub fn remove_spans(&mut self) {
+        self.args.remove(SPAN_ARG);
+        for element in self.args.values_mut().flatten().chain(self.content.iter_mut()) {
+            if let DocumentElement::Function(func) = element {
+                func.remove_spans();
+            }
+        }
+    }
+}
+
+impl DocumentFunction {
+    /// Returns the text of the single text element of argument `key`
+    pub(crate) fn text_arg(&self, key: &str) -> Option<&str> {
+        match self.args.get(key)?.as_slice() {
+            [DocumentElement::Text(text)] => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Push this call and all nested calls satisfying `predicate` to `found`
+    fn collect_matching<'t, P: Fn(&DocumentFunction) -> bool>(&'t self, predicate: &P, found: &mut Vec<&'t DocumentFunction>) {
+        if predicate(self) {
+            found.push(self);
+        }
+
+        let mut keys: Vec<&String> = self.args.keys().collect();
+        keys.sort();
+        let arg_elements = keys.into_iter().flat_map(|key| self.args[key].iter());
+        for element in arg_elements.chain(self.content.iter()) {
+            if let DocumentElement::Function(func) = element {
+                func.collect_matching(predicate, found);
+            }
+        }
+    }
+
+    /// Append the representation of this call in litua input syntax to `out`
+    fn write_litua_syntax(&self, out: &mut String, options: &crate::lexer::LexerOptions) {
+        let whitespace = self.text_arg(WHITESPACE_ARG).unwrap_or(" ");
+
+        if self.call.starts_with(crate::lexer::OPEN_RAW) {
+            out.push(crate::lexer::OPEN_FUNCTION);
+            out.extend(self.call.chars().map(|_| options.open_raw));
+            out.push_str(self.text_arg(LANGUAGE_ARG).unwrap_or_default());
+            out.push_str(whitespace);
+            for element in self.content.iter() {
+                element.write_litua_syntax(out, options);
+            }
+            out.push_str(self.text_arg(WHITESPACE_AFTER_ARG).unwrap_or(" "));
+            out.extend(self.call.chars().map(|_| options.close_raw));
+            out.push(crate::lexer::CLOSE_FUNCTION);
+            return;
+        }
+
+        out.push(crate::lexer::OPEN_FUNCTION);
+        out.push_str(&self.call);
+
+        let mut keys: Vec<&String> = self.args.keys().filter(|k| !is_reserved_arg(k)).collect();
+        keys.sort();
+        for key in keys {
+            out.push(crate::lexer::OPEN_ARG);
+            out.push_str(key);
+            if let Some(arg_type) = self.arg_type(key) {
+                out.push(TYPE_SEPARATOR);
+                out.push_str(arg_type.name());
+            }
+            out.push(crate::lexer::ASSIGN);
+            for element in self.args[key].iter() {
+                element.write_litua_syntax(out, options);
+            }
+            out.push(crate::lexer::CLOSE_ARG);
+        }
+
+        if !self.content.is_empty() || self.text_arg(WHITESPACE_ARG).is_some() {
+            out.push_str(whitespace);
+            for element in self.content.iter() {
+                element.write_litua_syntax(out, options);
+            }
+        }
+        out.push_str(self.text_arg(WHITESPACE_AFTER_ARG).unwrap_or(""));
+        out.push(crate::lexer::CLOSE_FUNCTION);
+    }
 }
 
 impl Default for DocumentFunction {
@@ -71,8 +460,15 @@ impl<'lua> mlua::ToLua<'lua> for &DocumentFunction {
         let args = lua.create_table()?;
         for (arg, elements) in self.args.iter() {
             let lua_value = lua.create_table()?;
-            for (i, element) in elements.iter().enumerate() {
-                lua_value.set(i + 1, element)?;
+            let typed = match (self.arg_type(arg), elements.as_slice()) {
+                (Some(arg_type), [DocumentElement::Text(text)]) => arg_type.lua_value(text),
+                _ => None,
+            };
+            match typed {
+                Some(value) => lua_value.set(1, value)?,
+                None => for (i, element) in elements.iter().enumerate() {
+                    lua_value.set(i + 1, element)?;
+                },
             }
             args.set(arg.as_str(), lua_value)?;
         }
@@ -97,6 +493,167 @@ pub enum DocumentElement {
     Text(String),
 }
 
+impl DocumentElement {
+    /// Append the representation of this element in litua input syntax to `out`
+    fn write_litua_syntax(&self, out: &mut String, options: &crate::lexer::LexerOptions) {
+        match self {
+            DocumentElement::Function(func) => func.write_litua_syntax(out, options),
+            DocumentElement::Text(text) => out.push_str(text),
+        }
+    }
+}
+
+impl DocumentElement {
+    /// Returns a structural hash of this element and all nested elements.
+    /// Arguments with a key starting with `=` (like `=whitespace` or `=span`)
+    /// are ignored except for `=language` and `=types`. The hash does not depend on the order of arguments and
+    /// is stable across runs and platforms, thus it can be used as a key to
+    /// cache the transformed output of unchanged subtrees.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        self.hash_structure(&mut hasher);
+        hasher.0
+    }
+
+    fn hash_structure(&self, hasher: &mut StableHasher) {
+        match self {
+            DocumentElement::Text(text) => {
+                hasher.write_tag(b'T');
+                hasher.write_str(text);
+            },
+            DocumentElement::Function(func) => {
+                hasher.write_tag(b'F');
+                hasher.write_str(&func.call);
+
+                // NOTE: language tags of raw strings and argument types are the only reserved arguments affecting the output
+                let mut keys: Vec<&String> = func.args.keys().filter(|k| !is_reserved_arg(k) || *k == LANGUAGE_ARG || *k == TYPES_ARG).collect();
+                keys.sort();
+                hasher.write_len(keys.len());
+                for key in keys {
+                    hasher.write_str(key);
+                    hash_node(&func.args[key], hasher);
+                }
+                hash_node(&func.content, hasher);
+            },
+        }
+    }
+}
+
+impl DocumentTree {
+    /// Represent this tree as JSON value (see `DocumentElement::to_json`)
+    pub fn to_json(&self) -> serde_json::Value {
+        self.0.to_json()
+    }
+
+    /// Create a tree from its JSON representation (see `DocumentElement::from_json`)
+    pub fn from_json(value: &serde_json::Value) -> Result<DocumentTree, String> {
+        Ok(DocumentTree(DocumentElement::from_json(value)?))
+    }
+}
+
+impl DocumentElement {
+    /// Represent this element as JSON value. A text is represented as string
+    /// and a call as object with keys `call`, `args` (mapping keys to arrays of
+    /// elements), and `content` (array of elements). Arguments with a key
+    /// starting with `=` are not represented.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            DocumentElement::Text(text) => serde_json::Value::from(text.as_str()),
+            DocumentElement::Function(func) => {
+                let args: serde_json::Map<String, serde_json::Value> = func.args.iter()
+                    .filter(|(key, _)| !is_reserved_arg(key))
+                    .map(|(key, value)| (key.to_owned(), value.iter().map(|e| e.to_json()).collect()))
+                    .collect();
+                serde_json::json!({
+                    "call": func.call,
+                    "args": args,
+                    "content": func.content.iter().map(|e| e.to_json()).collect::<Vec<serde_json::Value>>(),
+                })
+            },
+        }
+    }
+
+    /// Create an element from its JSON representation (see `DocumentElement::to_json`).
+    /// Keys `args` and `content` of calls are optional.
+    pub fn from_json(value: &serde_json::Value) -> Result<DocumentElement, String> {
+        fn node_from_json(value: Option<&serde_json::Value>, what: &str) -> Result<DocumentNode, String> {
+            match value {
+                None => Ok(vec![]),
+                Some(serde_json::Value::Array(elements)) => elements.iter().map(DocumentElement::from_json).collect(),
+                Some(_) => Err(format!("{what} must be an array of elements")),
+            }
+        }
+
+        let object = match value {
+            serde_json::Value::String(text) => return Ok(DocumentElement::Text(text.to_owned())),
+            serde_json::Value::Object(object) => object,
+            _ => return Err(format!("an element must be a string or an object, not '{value}'")),
+        };
+        let call = object.get("call").and_then(serde_json::Value::as_str)
+            .ok_or_else(|| "a call must have a key 'call' with a string value".to_owned())?;
+        let mut args = HashMap::new();
+        match object.get("args") {
+            None => {},
+            Some(serde_json::Value::Object(entries)) => {
+                for (key, arg) in entries.iter() {
+                    args.insert(key.to_owned(), node_from_json(Some(arg), &format!("argument '{key}' of call '{call}'"))?);
+                }
+            },
+            Some(_) => return Err(format!("the arguments of call '{call}' must be an object")),
+        }
+        let content = node_from_json(object.get("content"), &format!("the content of call '{call}'"))?;
+        Ok(DocumentElement::Function(DocumentFunction { call: call.to_owned(), args, content }))
+    }
+}
+
+/// Returns the 64-bit FNV-1a hash of `bytes`, which is stable across runs and platforms
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write(bytes);
+    hasher.0
+}
+
+fn hash_node(node: &DocumentNode, hasher: &mut StableHasher) {
+    hasher.write_len(node.len());
+    for element in node.iter() {
+        element.hash_structure(hasher);
+    }
+}
+
+/// 64-bit FNV-1a hasher. Unlike `std::collections::hash_map::DefaultHasher`,
+/// its output is specified and thus does not change between Rust releases.
+struct StableHasher(u64);
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    fn new() -> StableHasher {
+        StableHasher(Self::OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn write_tag(&mut self, tag: u8) {
+        self.write(&[tag]);
+    }
+
+    fn write_len(&mut self, len: usize) {
+        self.write(&(len as u64).to_le_bytes());
+    }
+
+    /// Strings are prefixed by their length such that concatenations are unambiguous
+    fn write_str(&mut self, text: &str) {
+        self.write_len(text.len());
+        self.write(text.as_bytes());
+    }
+}
+
 impl<'lua> mlua::ToLua<'lua> for &DocumentElement {
     /// Lua representation of a `DocumentElement`.
     fn to_lua(self, lua: &'lua mlua::Lua) -> mlua::Result<mlua::Value<'lua>> {
@@ -107,6 +664,286 @@ impl<'lua> mlua::ToLua<'lua> for &DocumentElement {
     }
 }
 
+impl<'lua> mlua::FromLua<'lua> for DocumentElement {
+    /// Read a `DocumentElement` from its Lua representation, i.e. a table with
+    /// keys `call`, `args`, and `content` (like `Litua.Node`) or a string
+    fn from_lua(value: mlua::Value<'lua>, lua: &'lua mlua::Lua) -> mlua::Result<Self> {
+        let node = match value {
+            mlua::Value::Table(node) => node,
+            // NOTE: values of arguments declared as `bool` (see `ArgType`)
+            mlua::Value::Boolean(value) => return Ok(DocumentElement::Text(value.to_string())),
+            other => return Ok(DocumentElement::Text(String::from_lua(other, lua)?)),
+        };
+
+        let call: String = node.get("call")?;
+
+        let mut args = HashMap::new();
+        for pair in node.get::<_, mlua::Table>("args")?.pairs::<String, mlua::Table>() {
+            let (key, values) = pair?;
+            if is_reserved_arg(&key) && !RESERVED_ARGS.contains(&key.as_str()) {
+                return Err(mlua::Error::FromLuaConversionError {
+                    from: "table",
+                    to: "DocumentElement",
+                    message: Some(format!("argument key '{key}' of call '{call}' is reserved for litua")),
+                });
+            }
+            args.insert(key, values.sequence_values::<DocumentElement>().collect::<mlua::Result<DocumentNode>>()?);
+        }
+
+        let content = node.get::<_, mlua::Table>("content")?
+            .sequence_values::<DocumentElement>()
+            .collect::<mlua::Result<DocumentNode>>()?;
+
+        Ok(DocumentElement::Function(DocumentFunction { call, args, content }))
+    }
+}
+
 /// `DocumentNode` is a node establishing a tree.
 /// Each node consists of zero or more elements constituting its children.
 pub type DocumentNode = Vec<DocumentElement>;
+
+/// `NodeStep` selects one child element of a call
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub enum NodeStep {
+    /// element at the given index of the content
+    Content(usize),
+    /// element at the given index of the value of the argument with the given key
+    Arg(String, usize),
+}
+
+/// `NodePath` locates an element within a `DocumentTree` by the
+/// sequence of steps starting at the root call. The empty path
+/// refers to the root call itself.
+#[derive(Clone,Debug,Default,PartialEq,Eq,Hash)]
+pub struct NodePath(pub Vec<NodeStep>);
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_tree {
+    //! `arbitrary::Arbitrary` implementation for `DocumentTree` generating
+    //! trees which can be represented in litua input syntax. Hence, parsing
+    //! `tree.to_litua_syntax()` yields `tree` again (ignoring `=`-prefixed args).
+
+    use std::collections::HashMap;
+
+    use arbitrary::{Arbitrary, Result, Unstructured};
+
+    use super::*;
+
+    /// maximum nesting depth of generated calls
+    const MAX_DEPTH: usize = 4;
+    /// characters of generated call names and argument keys
+    const NAME_CHARS: &str = "abcdefghijklmnopqrstuvwxyz0123456789-_./:";
+
+    fn name(u: &mut Unstructured<'_>) -> Result<String> {
+        let length = u.int_in_range(1..=8)?;
+        let chars: Vec<char> = NAME_CHARS.chars().collect();
+        (0..length).map(|_| Ok(*u.choose(&chars)?)).collect()
+    }
+
+    fn text(u: &mut Unstructured<'_>, forbidden: &[char]) -> Result<String> {
+        let text: String = String::arbitrary(u)?.chars().filter(|c| !forbidden.contains(c)).collect();
+        Ok(if text.is_empty() { "x".to_owned() } else { text })
+    }
+
+    /// Generate a non-empty node without adjacent text elements
+    fn node(u: &mut Unstructured<'_>, depth: usize, forbidden: &[char]) -> Result<DocumentNode> {
+        let mut node = DocumentNode::new();
+        let length = u.int_in_range(1..=4)?;
+        for _ in 0..length {
+            let previous_is_text = matches!(node.last(), Some(DocumentElement::Text(_)));
+            if depth < MAX_DEPTH && (previous_is_text || bool::arbitrary(u)?) {
+                node.push(DocumentElement::Function(function(u, depth + 1)?));
+            } else if !previous_is_text {
+                node.push(DocumentElement::Text(text(u, forbidden)?));
+            }
+        }
+        Ok(node)
+    }
+
+    fn function(u: &mut Unstructured<'_>, depth: usize) -> Result<DocumentFunction> {
+        let mut args = HashMap::new();
+        for _ in 0..u.int_in_range(0..=2)? {
+            // NOTE: keys like `width:int` declare the type of their value instead
+            let key = name(u)?;
+            if ArgType::split_key(&key).1.is_none() {
+                args.insert(key, node(u, depth, &['{', '}', ']'])?);
+            }
+        }
+        let mut content = if bool::arbitrary(u)? { node(u, depth, &['{', '}'])? } else { DocumentNode::new() };
+        // NOTE: leading whitespace of the content would be read as part of the separating whitespace
+        if let Some(DocumentElement::Text(text)) = content.first_mut() {
+            let trimmed = text.trim_start();
+            *text = if trimmed.is_empty() { "x".to_owned() } else { trimmed.to_owned() };
+        }
+        Ok(DocumentFunction { call: name(u)?, args, content })
+    }
+
+    impl<'a> Arbitrary<'a> for DocumentTree {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            let content = if bool::arbitrary(u)? { node(u, 0, &['{', '}'])? } else { DocumentNode::new() };
+            Ok(DocumentTree(DocumentElement::Function(DocumentFunction {
+                call: "document".to_owned(),
+                args: HashMap::new(),
+                content,
+            })))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Remove all arguments with a key starting with `=` recursively
+    fn strip_internal_args(elements: &mut DocumentNode) {
+        for element in elements.iter_mut() {
+            if let DocumentElement::Function(func) = element {
+                func.args.retain(|key, _| !key.starts_with('='));
+                for value in func.args.values_mut() {
+                    strip_internal_args(value);
+                }
+                strip_internal_args(&mut func.content);
+            }
+        }
+    }
+
+    fn content_of(tree: DocumentTree) -> DocumentNode {
+        match tree.0 {
+            DocumentElement::Function(mut root) => {
+                strip_internal_args(&mut root.content);
+                root.content
+            },
+            DocumentElement::Text(_) => panic!("expected the document root to be a function"),
+        }
+    }
+
+    #[test]
+    fn litua_syntax_round_trip() {
+        let src = "A {b[x=1{c}][y=2]\tz {<< {d} >>}} {e}";
+        let tree = crate::parse_str(src).expect("valid litua syntax");
+        assert_eq!(tree.to_litua_syntax(), src);
+
+        let reparsed = crate::parse_str(&tree.to_litua_syntax()).expect("valid litua syntax");
+        assert_eq!(content_of(reparsed), content_of(tree));
+    }
+
+    #[test]
+    fn lua_round_trip() -> mlua::Result<()> {
+        use mlua::{FromLua, ToLua};
+
+        let tree = crate::parse_str("A {b[x=1{c}] z} {e}").expect("valid litua syntax");
+        let lua = mlua::Lua::new();
+        let value = (&tree).to_lua(&lua)?;
+        assert_eq!(DocumentTree::from_lua(value, &lua)?, tree);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unknown_reserved_args() -> mlua::Result<()> {
+        use mlua::FromLua;
+
+        let lua = mlua::Lua::new();
+        let known: mlua::Value = lua.load(r#"{ call = "a", args = { ["=whitespace"] = { " " } }, content = {} }"#).eval()?;
+        assert!(DocumentElement::from_lua(known, &lua).is_ok());
+        let unknown: mlua::Value = lua.load(r#"{ call = "a", args = { ["=mine"] = { "x" } }, content = {} }"#).eval()?;
+        assert!(DocumentElement::from_lua(unknown, &lua).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn lookup_by_path_and_arg() {
+        let tree = crate::parse_str("A {p[id=intro] x{b y}} {p[id=main][n={q[id=note]}] z}").expect("valid litua syntax");
+
+        let path = NodePath(vec![NodeStep::Content(1), NodeStep::Content(1)]);
+        match tree.get_path(&path) {
+            Some(DocumentElement::Function(func)) => assert_eq!(func.call, "b"),
+            other => panic!("expected call b, got {other:?}"),
+        }
+        let path = NodePath(vec![NodeStep::Content(3), NodeStep::Arg("n".to_owned(), 0)]);
+        assert!(matches!(tree.get_path(&path), Some(DocumentElement::Function(func)) if func.call == "q"));
+        assert_eq!(tree.get_path(&NodePath(vec![NodeStep::Content(9)])), None);
+        assert_eq!(tree.get_path(&NodePath::default()), Some(&tree.0));
+
+        let calls: Vec<&str> = tree.find_all(|f| f.call != "b").iter().map(|f| f.call.as_str()).collect();
+        assert_eq!(calls, vec!["document", "p", "p", "q"]);
+        assert_eq!(tree.find_by_arg("id", "intro").map(|f| f.content.len()), Some(2));
+        assert_eq!(tree.find_by_arg("id", "note").map(|f| f.call.as_str()), Some("q"));
+        assert!(tree.find_by_arg("id", "missing").is_none());
+    }
+
+    #[test]
+    fn split_namespaced_calls() {
+        let tree = crate::parse_str("{html:div[class=x] {svg:rect}} {:a} {b:} {c}").expect("valid litua syntax");
+        let names: Vec<(Option<&str>, &str)> = tree.find_all(|f| f.call != "document").iter().map(|f| (f.namespace(), f.local_name())).collect();
+        assert_eq!(names, vec![(Some("html"), "div"), (Some("svg"), "rect"), (None, ":a"), (None, "b:"), (None, "c")]);
+        assert_eq!(split_call_name("a:b:c"), (Some("a"), "b:c"));
+    }
+
+    #[test]
+    fn represent_as_json() {
+        let tree = crate::parse_str("A {p[id=x] b {i c}}").expect("valid litua syntax");
+        let expected = serde_json::json!({
+            "call": "document",
+            "args": { "filepath": [""] },
+            "content": ["A ", { "call": "p", "args": { "id": ["x"] }, "content": ["b ", { "call": "i", "args": {}, "content": ["c"] }] }],
+        });
+        assert_eq!(tree.to_json(), expected);
+        assert_eq!(DocumentTree::from_json(&expected).map(|tree| tree.to_json()), Ok(expected));
+        assert!(DocumentTree::from_json(&serde_json::json!({ "call": "p", "content": [1] })).is_err());
+    }
+
+    #[test]
+    fn content_hash_ignores_volatile_args() {
+        let hash_of = |src: &str| crate::parse_str(src).expect("valid litua syntax").0.content_hash();
+
+        let hash = hash_of("{p[id=1][class=x] a {b c}}");
+        assert_eq!(hash_of("{p[class=x][id=1]\t\ta {b   c}}"), hash);
+        assert_ne!(hash_of("\n{p[class=x][id=1] a {b c}}"), hash);
+        assert_ne!(hash_of("{p[id=1][class=x] a {b d}}"), hash);
+        assert_ne!(hash_of("{p[id=1][class=x] a{b c}}"), hash);
+        assert_ne!(hash_of("{p[id=1][class=xa] {b c}}"), hash);
+        // NOTE: the hash must not change between releases, otherwise caches are invalidated
+        assert_eq!(DocumentElement::Text("".to_owned()).content_hash(), 0xa995da0242913763);
+    }
+
+    #[test]
+    fn owned_depth_first_iteration() {
+        let tree = crate::parse_str("A {b[x={y}] c {d}}{e}").expect("valid litua syntax");
+        let items: Vec<String> = tree.into_iter().map(|element| match element {
+            DocumentElement::Function(func) => {
+                assert!(func.content.is_empty());
+                format!("{{{}}}", func.call)
+            },
+            DocumentElement::Text(text) => text,
+        }).collect();
+        assert_eq!(items, vec!["{document}", "A ", "{b}", "c ", "{d}", "{e}"]);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_top_level_iteration() {
+        use rayon::prelude::*;
+
+        let tree = crate::parse_str("{a x}{b}{c y}").expect("valid litua syntax");
+        let calls: Vec<String> = tree.par_iter().filter_map(|element| match element {
+            DocumentElement::Function(func) => Some(func.call.clone()),
+            DocumentElement::Text(_) => None,
+        }).collect();
+        assert_eq!(calls, vec!["a", "b", "c"]);
+        assert_eq!(tree.into_par_iter().count(), 3);
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_trees_round_trip() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        for seed in 0..200u32 {
+            let data: Vec<u8> = (0..512u32).map(|i| (seed.wrapping_mul(2654435761).wrapping_add(i * 40503) >> 7) as u8).collect();
+            let tree = DocumentTree::arbitrary(&mut Unstructured::new(&data)).expect("enough data");
+            let src = tree.to_litua_syntax();
+            let parsed = crate::parse_str(&src).unwrap_or_else(|e| panic!("cannot parse {src:?}: {e:?}"));
+            assert_eq!(content_of(parsed), content_of(tree), "round trip of {src:?}");
+        }
+    }
+}
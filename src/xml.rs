@@ -0,0 +1,195 @@
+//! Generic XML representation of litua text documents
+//!
+//! Every call becomes an element named after the call and arguments with
+//! only text become attributes. Call names and argument keys which are not
+//! admissible in XML as well as arguments containing calls are represented
+//! by `litua:call[name=…]` and `litua:arg[key=…]` elements of namespace
+//! `NAMESPACE`, so `from_xml` recovers the original tree. `from_xml` also
+//! reads arbitrary XML documents: the root element becomes the root call,
+//! comments and processing instructions are skipped, and namespaces of other
+//! elements and attributes are ignored. Reading requires feature `import`.
+
+use std::collections::HashMap;
+
+use crate::tree;
+
+/// XML namespace of the elements representing calls and arguments which cannot be represented otherwise
+pub const NAMESPACE: &str = "urn:litua";
+
+/// Escape `text` for use in XML text content
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('\r', "&#13;")
+}
+
+/// Escape `text` for use in XML attribute values (preserving whitespace characters)
+fn escape_attr(text: &str) -> String {
+    escape_text(text).replace('"', "&quot;").replace('\n', "&#10;").replace('\t', "&#9;")
+}
+
+/// Is `name` admissible as XML element or attribute name without namespace prefix?
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let starts_name = |chr: char| chr.is_alphabetic() || chr == '_';
+    chars.next().is_some_and(starts_name)
+        && chars.all(|chr| chr.is_alphanumeric() || matches!(chr, '-' | '_' | '.'))
+        && !name.to_ascii_lowercase().starts_with("xml")
+}
+
+/// Returns the text of `node` if it does not contain calls
+fn text_only(node: &tree::DocumentNode) -> Option<String> {
+    node.iter().map(|element| match element {
+        tree::DocumentElement::Text(text) => Some(text.as_str()),
+        tree::DocumentElement::Function(_) => None,
+    }).collect()
+}
+
+/// Returns the tree as XML document
+pub fn to_xml(tree: &tree::DocumentTree) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    match &tree.0 {
+        tree::DocumentElement::Function(root) => write_call(root, true, &mut out),
+        tree::DocumentElement::Text(text) => write_call(&tree::DocumentFunction {
+            call: "document".to_owned(),
+            args: HashMap::new(),
+            content: vec![tree::DocumentElement::Text(text.to_owned())],
+        }, true, &mut out),
+    }
+    out.push('\n');
+    out
+}
+
+fn write_node(node: &tree::DocumentNode, out: &mut String) {
+    for element in node {
+        match element {
+            tree::DocumentElement::Text(text) => out.push_str(&escape_text(text)),
+            tree::DocumentElement::Function(func) => write_call(func, false, out),
+        }
+    }
+}
+
+fn write_call(func: &tree::DocumentFunction, is_root: bool, out: &mut String) {
+    let name = if is_xml_name(&func.call) { func.call.clone() } else { "litua:call".to_owned() };
+    out.push('<');
+    out.push_str(&name);
+    if is_root {
+        out.push_str(&format!(" xmlns:litua=\"{NAMESPACE}\""));
+    }
+    if !is_xml_name(&func.call) {
+        out.push_str(&format!(" name=\"{}\"", escape_attr(&func.call)));
+    }
+
+    let mut keys: Vec<&String> = func.args.keys().filter(|key| !tree::is_reserved_arg(key)).collect();
+    keys.sort();
+    let mut nested = vec![];
+    for key in keys {
+        match text_only(&func.args[key]) {
+            // NOTE: `name` is the attribute of `litua:call` elements
+            Some(value) if is_xml_name(key) && (is_xml_name(&func.call) || key != "name") => {
+                out.push_str(&format!(" {key}=\"{}\"", escape_attr(&value)));
+            },
+            _ => nested.push(key),
+        }
+    }
+
+    if nested.is_empty() && func.content.is_empty() {
+        out.push_str("/>");
+        return;
+    }
+    out.push('>');
+    for key in nested {
+        out.push_str(&format!("<litua:arg key=\"{}\">", escape_attr(key)));
+        write_node(&func.args[key], out);
+        out.push_str("</litua:arg>");
+    }
+    write_node(&func.content, out);
+    out.push_str(&format!("</{name}>"));
+}
+
+/// Create a tree from the XML document `src`
+#[cfg(feature = "import")]
+pub fn from_xml(src: &str) -> Result<tree::DocumentTree, String> {
+    let document = roxmltree::Document::parse(src).map_err(|e| e.to_string())?;
+    Ok(tree::DocumentTree(tree::DocumentElement::Function(read_call(document.root_element()))))
+}
+
+/// Create a tree from an XML document, which requires feature `import`
+#[cfg(not(feature = "import"))]
+pub fn from_xml(_src: &str) -> Result<tree::DocumentTree, String> {
+    Err("cannot read XML documents, because litua was built without feature 'import'".to_owned())
+}
+
+#[cfg(feature = "import")]
+fn is_litua_element(node: &roxmltree::Node, name: &str) -> bool {
+    node.is_element() && node.tag_name().namespace() == Some(NAMESPACE) && node.tag_name().name() == name
+}
+
+#[cfg(feature = "import")]
+fn read_call(element: roxmltree::Node) -> tree::DocumentFunction {
+    let is_call = is_litua_element(&element, "call");
+    let mut func = tree::DocumentFunction::new();
+    func.call = match is_call {
+        true => element.attribute("name").unwrap_or_default().to_owned(),
+        false => element.tag_name().name().to_owned(),
+    };
+    for attr in element.attributes() {
+        if !(is_call && attr.name() == "name" && attr.namespace().is_none()) {
+            func.args.insert(attr.name().to_owned(), vec![tree::DocumentElement::Text(attr.value().to_owned())]);
+        }
+    }
+    for child in element.children() {
+        if is_litua_element(&child, "arg") {
+            let key = child.attribute("key").unwrap_or_default().to_owned();
+            func.args.insert(key, read_node(child));
+        } else {
+            push_element(&mut func.content, child);
+        }
+    }
+    func
+}
+
+#[cfg(feature = "import")]
+fn read_node(element: roxmltree::Node) -> tree::DocumentNode {
+    let mut node = vec![];
+    for child in element.children() {
+        push_element(&mut node, child);
+    }
+    node
+}
+
+/// Append the XML node `child` to `node` merging adjacent texts
+#[cfg(feature = "import")]
+fn push_element(node: &mut tree::DocumentNode, child: roxmltree::Node) {
+    if child.is_element() {
+        node.push(tree::DocumentElement::Function(read_call(child)));
+    } else if let Some(text) = child.text().filter(|_| child.is_text()) {
+        match node.last_mut() {
+            Some(tree::DocumentElement::Text(previous)) => previous.push_str(text),
+            _ => node.push(tree::DocumentElement::Text(text.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_xml() {
+        let tree = crate::parse_str("A & {p[id=x][t={b y}] b\n{2col c}}{br}").expect("valid document");
+        assert_eq!(to_xml(&tree), "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <document xmlns:litua=\"urn:litua\" filepath=\"\">A &amp; \
+            <p id=\"x\"><litua:arg key=\"t\"><b>y</b></litua:arg>b\n<litua:call name=\"2col\">c</litua:call></p><br/></document>\n");
+    }
+
+    #[cfg(feature = "import")]
+    #[test]
+    fn read_xml() -> Result<(), String> {
+        let tree = crate::parse_str("A & {p[id=x\ny][t={b y}] b <!-- {2col c}}{br}").expect("valid document");
+        assert_eq!(from_xml(&to_xml(&tree))?.to_json(), tree.to_json());
+
+        let foreign = from_xml("<?xml version=\"1.0\"?><html xmlns=\"http://www.w3.org/1999/xhtml\"><!-- x --><p class=\"a\">b<![CDATA[<c>]]></p></html>")?;
+        assert_eq!(foreign.to_litua_syntax(), "{p[class=a] b<c>}");
+        assert!(from_xml("<p>").is_err());
+        Ok(())
+    }
+}
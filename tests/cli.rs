@@ -0,0 +1,458 @@
+//! End-to-end tests running the litua executable on small documents with hook files
+
+use std::fs;
+use std::path;
+use std::process;
+
+/// Create an empty directory for the test `name` containing the files `files` (name and content)
+fn setup(name: &str, files: &[(&str, &str)]) -> path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("litua-cli-{name}-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("create test directory");
+    for (filename, content) in files {
+        let filepath = dir.join(filename);
+        fs::create_dir_all(filepath.parent().expect("parent directory")).expect("create test subdirectory");
+        fs::write(filepath, content).expect("write test file");
+    }
+    dir
+}
+
+/// Run litua with the arguments `args` in directory `dir` and return whether it succeeded and its stderr
+fn litua(dir: &path::Path, args: &[&str]) -> (bool, String) {
+    let output = process::Command::new(env!("CARGO_BIN_EXE_litua"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("run litua");
+    (output.status.success(), String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+/// Build `doc.lit` in directory `dir` and return the content of `doc.out`
+fn build(dir: &path::Path) -> String {
+    let (success, stderr) = litua(dir, &["build", "doc.lit"]);
+    assert!(success, "litua failed: {stderr}");
+    fs::read_to_string(dir.join("doc.out")).expect("read output")
+}
+
+#[test]
+fn footnote_hooks_take_precedence_over_the_footnote_pass() {
+    let dir = setup("footnote-hooks", &[
+        ("doc.lit", "a{footnote b}"),
+        ("hooks.lua", r#"Litua.convert_node_to_string("footnote", function (node) return "[" .. Litua.concat_table_values(node.content) .. "]" end)"#),
+    ]);
+    assert_eq!(build(&dir), "a[b]");
+
+    fs::remove_file(dir.join("hooks.lua")).expect("remove hook file");
+    assert!(build(&dir).starts_with("a{footnote-ref[id=footnote-ref-1]"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn var_hooks_take_precedence_over_variable_substitution() {
+    let dir = setup("var-hooks", &[
+        ("doc.lit", "{var x} {$y}"),
+        ("hooks.lua", concat!(
+            r#"Litua.on_setup(function () Litua.vars.y = "Y" end)"#, "\n",
+            r#"Litua.convert_node_to_string("var", function (node) return "<var>" .. Litua.concat_table_values(node.content) .. "</var>" end)"#,
+        )),
+    ]);
+    assert_eq!(build(&dir), "<var>x</var> Y");
+
+    fs::write(dir.join("hooks.lua"), "").expect("write hook file");
+    let (success, stderr) = litua(&dir, &["build", "doc.lit"]);
+    assert!(!success);
+    assert!(stderr.contains("unknown variable"), "unexpected error: {stderr}");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn index_hooks_take_precedence_over_the_index_pass() {
+    let dir = setup("index-hooks", &[
+        ("doc.lit", "{index a}{printindex}"),
+        ("hooks.lua", concat!(
+            r#"Litua.read_new_node("index", function (node) assert(node.args.id == nil, "id was injected") end)"#, "\n",
+            r#"Litua.convert_node_to_string("index", function (node) return "<" .. Litua.concat_table_values(node.content) .. ">" end)"#, "\n",
+            r#"Litua.convert_node_to_string("printindex", function (node) return "[index]" end)"#,
+        )),
+    ]);
+    assert_eq!(build(&dir), "<a>[index]");
+
+    fs::write(dir.join("hooks.lua"), "").expect("write hook file");
+    assert!(build(&dir).starts_with("{index[id=index-1] a}{index-group[letter=A]"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn highlight_uses_lexer_options_and_header_directives() {
+    let dir = setup("highlight-options", &[
+        ("doc.lit", "{p {| a |}}"),
+        ("header.lit", "%!litua raw-delimiters=|\n{p {| a |}}"),
+    ]);
+    let (success, _) = litua(&dir, &["highlight", "--format", "html", "-o", "doc.html", "doc.lit"]);
+    assert!(!success);
+    for args in [&["--raw-delimiters", "|", "doc.lit"][..], &["header.lit"][..]] {
+        let (success, stderr) = litua(&dir, &[&["highlight", "--format", "html", "-o", "out.html"][..], args].concat());
+        assert!(success, "litua failed: {stderr}");
+        let html = fs::read_to_string(dir.join("out.html")).expect("read output");
+        assert!(html.contains("<span class=\"litua-raw\"> a </span>"), "unexpected output: {html}");
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn require_api_rejects_newer_minor_versions() {
+    let dir = setup("require-api", &[
+        ("doc.lit", "a"),
+        ("hooks.lua", r#"Litua.require_api("2.99")"#),
+    ]);
+    let (success, stderr) = litua(&dir, &["build", "doc.lit"]);
+    assert!(!success);
+    assert!(stderr.contains("2.99"), "unexpected error: {stderr}");
+
+    fs::write(dir.join("hooks.lua"), r#"Litua.require_api("2.24") assert(Litua.api_version == "2.24")"#).expect("write hook file");
+    assert_eq!(build(&dir), "a");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn returned_nodes_run_through_the_node_hooks() {
+    let dir = setup("returned-nodes", &[
+        ("doc.lit", "{a}{b}{c}"),
+        ("hooks.lua", concat!(
+            "seen = 0\n",
+            r#"Litua.read_new_node("img", function (node) seen = seen + 1 end)"#, "\n",
+            r#"Litua.modify_node("img", function (node) node.content = { Litua.concat_table_values(node.content):upper() } return node end)"#, "\n",
+            r#"Litua.convert_node_to_string("img", function (node) return "<img " .. Litua.concat_table_values(node.content) .. ">" end)"#, "\n",
+            r#"Litua.convert_node_to_string("a", function (node) return Litua.Node.init("img", {}, { "x" }) end)"#, "\n",
+            r#"Litua.convert_node_to_string("b", function (node) return { "(", Litua.Node.init("img", {}, { "y" }), ")" } end)"#, "\n",
+            r#"Litua.convert_node_to_string("c", function (node) return tostring(seen) end)"#,
+        )),
+    ]);
+    assert_eq!(build(&dir), "<img X>(<img Y>)2");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn redispatch_depth_is_limited_and_restored() {
+    let dir = setup("redispatch-depth", &[
+        ("doc.lit", "{d}"),
+        ("hooks.lua", concat!(
+            r#"Litua.convert_node_to_string("d", function (node)"#, "\n",
+            r#"    local ok = pcall(Litua.redispatch_to_string, { {} }, 0, "convert_node_to_string", "/d", { src = "test" })"#, "\n",
+            r#"    return tostring(ok) .. Litua.redispatch_depth"#, "\n",
+            "end)",
+        )),
+    ]);
+    assert_eq!(build(&dir), "false0");
+
+    fs::write(dir.join("doc.lit"), "{loop}").expect("write document");
+    fs::write(dir.join("hooks.lua"), r#"Litua.convert_node_to_string("loop", function (node) return Litua.Node.init("loop", {}, {}) end)"#).expect("write hook file");
+    let (success, stderr) = litua(&dir, &["build", "doc.lit"]);
+    assert!(!success);
+    assert!(stderr.contains("nested more than 100 times"), "unexpected error: {stderr}");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn backup_keeps_the_previous_output() {
+    let dir = setup("backup", &[("doc.lit", "new"), ("doc.out", "old")]);
+    let (success, stderr) = litua(&dir, &["build", "--backup", "doc.lit"]);
+    assert!(success, "litua failed: {stderr}");
+    assert_eq!(fs::read_to_string(dir.join("doc.out")).expect("read output"), "new");
+    assert_eq!(fs::read_to_string(dir.join("doc.out.bak")).expect("read backup"), "old");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn output_keeps_permissions_and_symlinks() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = setup("permissions", &[("doc.lit", "#!/bin/sh\necho hi\n"), ("target.sh", "old")]);
+    fs::set_permissions(dir.join("target.sh"), fs::Permissions::from_mode(0o755)).expect("set permissions");
+    std::os::unix::fs::symlink("target.sh", dir.join("doc.out")).expect("create symlink");
+
+    let (success, stderr) = litua(&dir, &["build", "--backup", "doc.lit"]);
+    assert!(success, "litua failed: {stderr}");
+    assert!(fs::symlink_metadata(dir.join("doc.out")).expect("stat output").file_type().is_symlink());
+    assert_eq!(fs::read_to_string(dir.join("target.sh")).expect("read target"), "echo hi\n");
+    assert_eq!(fs::read_to_string(dir.join("doc.out.bak")).expect("read backup"), "old");
+    let mode = fs::metadata(dir.join("target.sh")).expect("stat target").permissions().mode();
+    assert_eq!(mode & 0o777, 0o755);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn split_call_name_uses_the_namespace_separator() {
+    let dir = setup("namespace-separator", &[
+        ("doc.lit", "{html:div}{x}"),
+        ("hooks.lua", concat!(
+            r#"Litua.convert_node_to_string("x", function (node)"#, "\n",
+            r#"    local parts = {}"#, "\n",
+            r#"    for _, call in ipairs({ "html:div", ":div", "a::b", "svg.rect" }) do"#, "\n",
+            r#"        local namespace, local_name = Litua.split_call_name(call)"#, "\n",
+            r#"        table.insert(parts, tostring(namespace) .. "|" .. local_name)"#, "\n",
+            r#"    end"#, "\n",
+            r#"    Litua.namespace_separator = "." "#, "\n",
+            r#"    local namespace, local_name = Litua.split_call_name("svg.rect")"#, "\n",
+            r#"    Litua.namespace_separator = ":""#, "\n",
+            r#"    return table.concat(parts, " ") .. " " .. namespace .. "|" .. local_name"#, "\n",
+            "end)\n",
+            r#"Litua.convert_node_to_string("html:div", function (node) return node.namespace .. "|" .. node.local_name .. " " end)"#,
+        )),
+    ]);
+    assert_eq!(build(&dir), "html|div html|div nil|:div a|:b nil|svg.rect svg|rect");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn fmt_applies_header_directives() {
+    let dir = setup("fmt-directives", &[("doc.lit", "%!litua raw-delimiters=|\n{p[z=1][a=2]   {| a { |}}\n")]);
+    let (success, stderr) = litua(&dir, &["fmt", "doc.lit"]);
+    assert!(success, "litua fmt failed: {stderr}");
+    assert_eq!(fs::read_to_string(dir.join("doc.lit")).expect("read document"), "%!litua raw-delimiters=|\n{p[a=2][z=1] {| a { |}}\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn lint_applies_header_directives() {
+    let dir = setup("lint-directives", &[("doc.lit", "%!litua raw-delimiters=|\n{p {| a |}}\n")]);
+    let (success, stderr) = litua(&dir, &["lint", "doc.lit"]);
+    assert!(success, "litua lint failed: {stderr}");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn convert_applies_header_directives() {
+    let dir = setup("convert-directives", &[("doc.lit", "%!litua raw-delimiters=|\n{p {| a {b} |}}\n")]);
+    let (success, stderr) = litua(&dir, &["convert", "--to", "xml", "-o", "doc.xml", "doc.lit"]);
+    assert!(success, "litua convert failed: {stderr}");
+    assert!(fs::read_to_string(dir.join("doc.xml")).expect("read output").contains("a {b}"));
+
+    let (success, stderr) = litua(&dir, &["convert", "--to", "litua", "-o", "out.lit", "doc.lit"]);
+    assert!(success, "litua convert failed: {stderr}");
+    assert_eq!(fs::read_to_string(dir.join("out.lit")).expect("read output"), "%!litua raw-delimiters=|\n{p {| a {b} |}}\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn chunks_start_with_the_global_state_of_the_setup() {
+    let dir = setup("chunked-enumeration", &[
+        ("doc.lit", include_str!("../examples/enumeration/doc.lit")),
+        ("hooks.enumeration.lua", include_str!("../examples/enumeration/hooks.enumeration.lua")),
+    ]);
+    let (success, stderr) = litua(&dir, &["build", "--chunked", "doc.lit"]);
+    assert!(success, "litua failed: {stderr}");
+    // NOTE: every {item} is a chunk of its own and counts from the value set by on_setup
+    assert_eq!(fs::read_to_string(dir.join("doc.out")).expect("read output"),
+        "Hello World!\n\nThis is an ordered list:\n\n(1) foo\n(1) bar\n(1) baz\n(1) caz\n(1) car\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn chunk_cache_reports_chunks_with_binary_output() {
+    let dir = setup("chunk-cache-binary", &[
+        ("doc.lit", "{text}{binary}"),
+        ("hooks.lua", r#"
+Litua.convert_node_to_string("text", function (node) return "text" end)
+Litua.convert_node_to_string("binary", function (node) return "\xff" end)
+"#),
+    ]);
+    let (success, stderr) = litua(&dir, &["build", "--chunked", "--chunk-cache", "cache.json", "doc.lit"]);
+    assert!(success, "litua failed: {stderr}");
+    assert!(stderr.contains("1 chunk was not cached"), "unexpected stderr: {stderr}");
+    assert_eq!(fs::read(dir.join("doc.out")).expect("read output"), b"text\xff");
+    let cache = fs::read_to_string(dir.join("cache.json")).expect("read cache");
+    assert!(cache.contains("\"output\":\"text\""), "unexpected cache: {cache}");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn links_are_checked_in_the_tree_given_to_the_hooks() {
+    let dir = setup("links-conditionals", &[
+        ("doc.lit", "{p[id=intro] text}{if[flag=draft] {ref todo}}{ref intro}"),
+    ]);
+    let (success, stderr) = litua(&dir, &["build", "."]);
+    assert!(success, "litua failed: {stderr}");
+    assert!(!stderr.contains("todo"), "unexpected stderr: {stderr}");
+    let (success, stderr) = litua(&dir, &["build", "--define", "draft", "."]);
+    assert!(!success && stderr.contains("broken link target 'todo'"), "unexpected stderr: {stderr}");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn check_watch_serves_all_diagnostics_and_keeps_unchanged_documents() {
+    use std::io::{BufRead, Read, Write};
+
+    let dir = setup("check-watch", &[
+        ("doc.lit", "text \n{p[class=] x}\n"),
+    ]);
+    /// `Watcher` stops litua when the test ends, even if it fails
+    struct Watcher(process::Child);
+    impl Drop for Watcher {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+    let mut child = Watcher(process::Command::new(env!("CARGO_BIN_EXE_litua"))
+        .args(["check", "--watch", "--serve", "127.0.0.1:0", "doc.lit"])
+        .current_dir(&dir)
+        .stderr(process::Stdio::piped())
+        .spawn()
+        .expect("run litua"));
+    let (sender, lines) = std::sync::mpsc::channel();
+    let stderr = child.0.stderr.take().expect("stderr of litua");
+    std::thread::spawn(move || {
+        for line in std::io::BufReader::new(stderr).lines().map_while(Result::ok) {
+            let _ = sender.send(line);
+        }
+    });
+    let wait_for = |needle: &str| loop {
+        let line = lines.recv_timeout(std::time::Duration::from_secs(10)).expect("line of litua");
+        if let Some(index) = line.find(needle) {
+            break line[index + needle.len()..].to_owned();
+        }
+    };
+
+    let addr = wait_for("Diagnostics served at http://");
+    let addr = addr.trim_end_matches("/diagnostics").to_owned();
+    let fetch = || {
+        let mut stream = std::net::TcpStream::connect(&addr).expect("connect to litua");
+        stream.write_all(b"GET /diagnostics HTTP/1.0\r\n\r\n").expect("send request");
+        let mut response = String::new();
+        stream.read_to_string(&mut response).expect("read response");
+        response
+    };
+    // NOTE: the diagnostics are published after the check finished
+    let mut response = fetch();
+    for _ in 0..100 {
+        if !response.contains(r#""checked_at":null"#) {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        response = fetch();
+    }
+    assert!(response.contains(r#""code":"W0001""#) && response.contains(r#""code":"W0002""#), "unexpected response: {response}");
+    assert!(response.contains(r#""ok":true"#), "unexpected response: {response}");
+
+    // NOTE: a new modification time with the same content does not parse the document again
+    fs::File::options().write(true).open(dir.join("doc.lit")).expect("open document")
+        .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(1)).expect("touch document");
+    wait_for("source file 'doc.lit' unchanged");
+
+    drop(child);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn diff_exits_like_diff_utility() {
+    let dir = setup("diff-exit-code", &[
+        ("doc.lit", "{x}"),
+        ("hooks.lua", r#"Litua.convert_node_to_string("x", function (node) return "new" end)"#),
+    ]);
+    let diff = |dir: &path::Path| process::Command::new(env!("CARGO_BIN_EXE_litua"))
+        .args(["build", "--diff", "doc.lit"])
+        .current_dir(dir)
+        .output()
+        .expect("run litua")
+        .status.code();
+    fs::write(dir.join("doc.out"), "new").expect("write output");
+    assert_eq!(diff(&dir), Some(0));
+    fs::write(dir.join("doc.out"), "old").expect("write output");
+    assert_eq!(diff(&dir), Some(1));
+    fs::write(dir.join("hooks.lua"), r#"Litua.convert_node_to_string("x", function (node) error("broken") end)"#).expect("write hook file");
+    assert_eq!(diff(&dir), Some(2));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn language_server_completes_calls_of_the_hooks() {
+    use std::io::Write;
+
+    let dir = setup("lsp", &[
+        ("hooks.lua", "--- A section with a heading\nLitua.convert_node_to_string(\"section\", function (node)\n  return node.args.title[1]\nend)\n"),
+    ]);
+    let messages = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///doc.lit","text":"{section[]}"}}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/completion","params":{"textDocument":{"uri":"file:///doc.lit"},"position":{"line":0,"character":9}}}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///doc.lit"},"position":{"line":0,"character":2}}}"#,
+        r#"{"jsonrpc":"2.0","id":4,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+    ];
+    let mut child = process::Command::new(env!("CARGO_BIN_EXE_litua"))
+        .arg("lsp")
+        .current_dir(&dir)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()
+        .expect("run litua");
+    let mut stdin = child.stdin.take().expect("stdin of litua");
+    for message in messages {
+        write!(stdin, "Content-Length: {}\r\n\r\n{message}", message.len()).expect("send message");
+    }
+    drop(stdin);
+    let output = child.wait_with_output().expect("wait for litua");
+    assert!(output.status.success(), "litua failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    // NOTE: log lines must not disturb the messages on stdout
+    let stdout = String::from_utf8(output.stdout).expect("UTF-8 output");
+    let responses: Vec<serde_json::Value> = stdout.split("Content-Length: ").skip(1)
+        .map(|framed| serde_json::from_str(framed.split_once("\r\n\r\n").expect("header").1).expect("JSON response"))
+        .collect();
+    assert!(stdout.starts_with("Content-Length: "), "unexpected stdout: {stdout}");
+    assert_eq!(responses.len(), 4, "unexpected stdout: {stdout}");
+    assert_eq!(responses[1]["result"][0]["label"], "title");
+    assert!(responses[2]["result"]["contents"]["value"].as_str().is_some_and(|hover| hover.contains("A section with a heading")));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cases_only_use_the_hooks_of_their_directory() {
+    let dir = setup("test-hermetic", &[
+        ("a.lit", "{x}"),
+        ("a.expected", "A"),
+        ("b.lit", "{x}{x}"),
+        ("b.expected", "AA"),
+        ("hooks.lua", r#"Litua.convert_node_to_string("x", function (node) return "A" end)"#),
+        ("shared/hooks.shared.lua", r#"Litua.convert_node_to_string("document", function (node) return "shared" end)"#),
+    ]);
+    let output = process::Command::new(env!("CARGO_BIN_EXE_litua"))
+        .args(["test", "."])
+        .current_dir(&dir)
+        .env("LITUA_HOOKS_PATH", dir.join("shared"))
+        .env("XDG_CONFIG_HOME", dir.join("shared"))
+        .output()
+        .expect("run litua");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "litua test failed: {stdout}");
+    assert!(stdout.contains("2 tests run, 0 failed"), "{stdout}");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn reproducible_builds_keep_the_source_and_normalize_the_output() {
+    let dir = setup("reproducible-newlines", &[
+        ("doc.lit", "a\r\nb"),
+        ("hooks.lua", r#"Litua.convert_node_to_string("document", function (node) return node.content[1] .. "|" .. (node.content[1]:find("\r") and "crlf" or "lf") end)"#),
+    ]);
+    let (success, stderr) = litua(&dir, &["build", "--reproducible", "doc.lit"]);
+    assert!(success, "litua failed: {stderr}");
+    assert_eq!(fs::read_to_string(dir.join("doc.out")).expect("read output"), "a\nb|crlf");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn sourcemap_of_binary_output() {
+    let dir = setup("sourcemap-binary", &[
+        ("doc.lit", "{b x}"),
+        ("hooks.lua", r#"Litua.convert_node_to_string("b", function (node) return "\255<b>" .. node.content[1] .. "</b>" end)"#),
+    ]);
+    let (success, stderr) = litua(&dir, &["build", "--sourcemap", "doc.map", "doc.lit"]);
+    assert!(success, "litua failed: {stderr}");
+    let map = fs::read_to_string(dir.join("doc.map")).expect("read source map");
+    assert!(map.contains(r#""call": "b""#), "{map}");
+    let _ = fs::remove_dir_all(&dir);
+}